@@ -1,11 +1,24 @@
 //! Building applications linker
 
+use std::env;
 use std::fs::{read_dir, File};
 use std::io::{Result, Write};
 
 fn main() {
     println!("cargo:rerun-if-changed=../user/src/");
     println!("cargo:rerun-if-changed={}", TARGET_PATH);
+    // where the firmware of the board loads the kernel, see src/board
+    let base_address = if env::var_os("CARGO_FEATURE_BOARD_K210").is_some() {
+        "0x80020000"
+    } else if env::var_os("CARGO_FEATURE_BOARD_VISIONFIVE2").is_some() {
+        "0x40200000"
+    } else {
+        "0x80200000"
+    };
+    println!(
+        "cargo:rustc-link-arg=--defsym=BASE_ADDRESS={}",
+        base_address
+    );
     insert_app_data().unwrap();
 }
 
@@ -24,29 +37,48 @@ fn insert_app_data() -> Result<()> {
         })
         .collect();
     apps.sort();
+    // the app table is read as an array of usize. The table and the images
+    // are never written, so they go with the read-only data, leaving a warm
+    // reboot only the kernel's own data to restore.
+    let word = match env::var("CARGO_CFG_TARGET_POINTER_WIDTH").as_deref() {
+        Ok("32") => ".word",
+        _ => ".quad",
+    };
 
     writeln!(
         f,
         r#"
     .align 3
-    .section .data
+    .section .rodata.apps
     .global _num_app
 _num_app:
-    .quad {}"#,
+    {} {}"#,
+        word,
         apps.len()
     )?;
 
     for i in 0..apps.len() {
-        writeln!(f, r#"    .quad app_{}_start"#, i)?;
+        writeln!(f, r#"    {} app_{}_start"#, word, i)?;
+    }
+    writeln!(f, r#"    {} app_{}_end"#, word, apps.len() - 1)?;
+
+    // names in the order of the table, NUL-terminated
+    writeln!(
+        f,
+        r#"
+    .global _app_names
+_app_names:"#
+    )?;
+    for app in apps.iter() {
+        writeln!(f, r#"    .string "{}""#, app)?;
     }
-    writeln!(f, r#"    .quad app_{}_end"#, apps.len() - 1)?;
 
     for (idx, app) in apps.iter().enumerate() {
         println!("app_{}: {}", idx, app);
         writeln!(
             f,
             r#"
-    .section .data
+    .section .rodata.apps
     .global app_{0}_start
     .global app_{0}_end
     .align 3
@@ -0,0 +1,67 @@
+//! Process accounting
+//!
+//! When a task exits, an [`AcctRecord`] of what it was and what it used is
+//! appended to a [`RecordLog`], so that what ran during a batch of tests
+//! can be looked at afterwards. `sys_acct_read` reads it from any position,
+//! for root only.
+
+use crate::config::{ACCT_LOG_RECORDS, CLOCK_FREQ, TASK_NAME_LEN};
+use crate::record_log::RecordLog;
+use crate::task::TaskControlBlock;
+use crate::timer::get_time_us;
+use alloc::vec::Vec;
+use lazy_static::*;
+
+/// A record as read by user space
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct AcctRecord {
+    /// nul-padded
+    pub name: [u8; TASK_NAME_LEN + 1],
+    pub exit_time_us: usize,
+    /// CPU time in user space
+    pub utime_us: usize,
+    /// CPU time in the kernel on behalf of the task
+    pub stime_us: usize,
+    pub peak_resident_pages: usize,
+    /// syscalls made, of all kinds
+    pub syscalls: usize,
+    pub pid: u32,
+    pub uid: u32,
+    pub gid: u32,
+    /// negative if the kernel killed the task
+    pub exit_code: i32,
+}
+
+lazy_static! {
+    static ref LOG: RecordLog<AcctRecord> = RecordLog::new("accounting", ACCT_LOG_RECORDS);
+}
+
+fn ticks_to_us(ticks: usize) -> usize {
+    (ticks as u64 * 1_000_000 / CLOCK_FREQ as u64) as usize
+}
+
+/// Append a record of task `pid`, which has just exited with `exit_code`.
+pub fn record(pid: usize, task: &TaskControlBlock, exit_code: i32) {
+    let mut name = [0u8; TASK_NAME_LEN + 1];
+    name[..task.name.len()].copy_from_slice(task.name.as_bytes());
+    let counters = &task.counters;
+    let record = AcctRecord {
+        name,
+        exit_time_us: get_time_us(),
+        utime_us: ticks_to_us(counters.cpu_time - counters.system_time),
+        stime_us: ticks_to_us(counters.system_time),
+        peak_resident_pages: counters.peak_resident_pages,
+        syscalls: task.syscall_times.iter().map(|&n| n as usize).sum(),
+        pid: pid as u32,
+        uid: task.uid,
+        gid: task.gid,
+        exit_code,
+    };
+    LOG.append(record);
+}
+
+/// Copy up to `count` records from the `start`th on.
+pub fn read(start: usize, count: usize) -> Vec<AcctRecord> {
+    LOG.read(start, count)
+}
@@ -0,0 +1,67 @@
+//! Security audit log
+//!
+//! Sensitive operations and refused permission checks are appended to a
+//! [`RecordLog`] of [`AuditRecord`]s, with the time and the task, user and
+//! syscall behind them, so what happened first stays on record.
+//! `sys_audit_read` reads it from any position, for root only.
+//!
+//! There is no filesystem to mount yet, so there are no mount records.
+
+use crate::config::AUDIT_LOG_RECORDS;
+use crate::record_log::RecordLog;
+use crate::task::{current_credentials, current_syscall_id, current_task_id};
+use crate::timer::get_time_us;
+use alloc::vec::Vec;
+use lazy_static::*;
+
+/// Events audited, with the meaning of their arguments
+#[derive(Copy, Clone)]
+#[repr(u32)]
+pub enum AuditEvent {
+    /// a signal was sent: the target task and the signal number
+    Kill = 0,
+    /// the task changed user: the old and the new uid
+    SetUid = 1,
+    /// the task changed group: the old and the new gid
+    SetGid = 2,
+    /// memory was made executable: its start and length
+    MmapExec = 3,
+    /// a permission check failed: the target task or file mode, if any,
+    /// and what was asked for
+    Denied = 4,
+}
+
+/// A record as read by user space
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct AuditRecord {
+    pub time_us: usize,
+    pub event: u32,
+    pub pid: u32,
+    pub uid: u32,
+    /// syscall that caused the event
+    pub syscall: u32,
+    pub args: [usize; 2],
+}
+
+lazy_static! {
+    static ref LOG: RecordLog<AuditRecord> = RecordLog::new("audit", AUDIT_LOG_RECORDS);
+}
+
+/// Append `event` of the current task to the log.
+pub fn record(event: AuditEvent, args: [usize; 2]) {
+    let record = AuditRecord {
+        time_us: get_time_us(),
+        event: event as u32,
+        pid: current_task_id() as u32,
+        uid: current_credentials().0,
+        syscall: current_syscall_id() as u32,
+        args,
+    };
+    LOG.append(record);
+}
+
+/// Copy up to `count` records from the `start`th on.
+pub fn read(start: usize, count: usize) -> Vec<AuditRecord> {
+    LOG.read(start, count)
+}
@@ -0,0 +1,373 @@
+//! Flattened device tree parser
+//!
+//! Only walks the structure block once, collecting what [`BoardInfo`] needs
+//! and the kernel command line, then reads the memory reservation block.
+//! It runs before paging is enabled and without allocating, so the blob can
+//! sit anywhere in physical memory.
+
+use super::{BoardInfo, Extensions, MmioDevice, MAX_VIRTIO};
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+/// oldest format version whose layout we understand
+const FDT_MIN_VERSION: u32 = 16;
+
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+
+/// deepest node nesting handled
+const MAX_DEPTH: usize = 16;
+
+#[derive(Copy, Clone, PartialEq)]
+enum Kind {
+    Unknown,
+    Memory,
+    /// a child of `/reserved-memory`
+    Reserved,
+    Cpu,
+    Uart,
+    Plic,
+    Virtio,
+    TestFinisher,
+}
+
+/// What has been seen of a node whose end is not reached yet
+#[derive(Copy, Clone)]
+struct Node<'a> {
+    /// cells of the `reg` entries of the children
+    address_cells: usize,
+    size_cells: usize,
+    kind: Kind,
+    /// the `reg` value, with the address and size cells of the parent
+    reg: Option<(&'a [u8], usize, usize)>,
+    irq: usize,
+    reg_shift: usize,
+    /// the node is `/chosen`
+    chosen: bool,
+    /// the node is `/reserved-memory`
+    reserved_memory: bool,
+    /// `status = "disabled"`
+    disabled: bool,
+    /// of memory, only to be used once added
+    hotpluggable: bool,
+    /// of a cpu, from `riscv,isa` or `riscv,isa-extensions`
+    extensions: Option<Extensions>,
+}
+
+impl<'a> Node<'a> {
+    fn new() -> Self {
+        Self {
+            // defaults from the devicetree specification
+            address_cells: 2,
+            size_cells: 1,
+            kind: Kind::Unknown,
+            reg: None,
+            irq: 0,
+            reg_shift: 0,
+            chosen: false,
+            reserved_memory: false,
+            disabled: false,
+            hotpluggable: false,
+            extensions: None,
+        }
+    }
+    /// The (base, size) entries of `reg`, a size of 0 where the parent has
+    /// no size cells.
+    fn regs(&self) -> impl Iterator<Item = (usize, usize)> + 'a {
+        let (value, address_cells, size_cells) = self.reg.unwrap_or((&[], 1, 0));
+        value
+            .chunks_exact((address_cells + size_cells).max(1) * 4)
+            .map(move |entry| {
+                let base = read_cells(entry, address_cells).unwrap_or(0);
+                let size = read_cells(&entry[address_cells * 4..], size_cells).unwrap_or(0);
+                (base, size)
+            })
+    }
+}
+
+struct Blob<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Blob<'a> {
+    fn u32_at(&self, offset: usize) -> Option<u32> {
+        let bytes = self.data.get(offset..offset + 4)?;
+        Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+    fn u64_at(&self, offset: usize) -> Option<u64> {
+        Some((self.u32_at(offset)? as u64) << 32 | self.u32_at(offset + 4)? as u64)
+    }
+    /// The nul-terminated string at `offset`, without the nul.
+    fn str_at(&self, offset: usize) -> Option<&'a [u8]> {
+        let rest = self.data.get(offset..)?;
+        let len = rest.iter().position(|&b| b == 0)?;
+        Some(&rest[..len])
+    }
+}
+
+fn align4(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+/// Big-endian number made of `cells` 32-bit cells.
+fn read_cells(value: &[u8], cells: usize) -> Option<usize> {
+    let bytes = value.get(..cells * 4)?;
+    Some(
+        bytes
+            .iter()
+            .fold(0usize, |acc, &b| acc.wrapping_shl(8) | b as usize),
+    )
+}
+
+fn kind_of_compatible(value: &[u8]) -> Kind {
+    for compatible in value.split(|&b| b == 0) {
+        match compatible {
+            b"ns16550a" | b"snps,dw-apb-uart" => return Kind::Uart,
+            b"riscv,plic0" | b"sifive,plic-1.0.0" => return Kind::Plic,
+            b"virtio,mmio" => return Kind::Virtio,
+            b"sifive,test0" | b"sifive,test1" => return Kind::TestFinisher,
+            _ => {}
+        }
+    }
+    Kind::Unknown
+}
+
+fn extension_of_name(name: &[u8]) -> Extensions {
+    match name {
+        b"v" => Extensions::V,
+        b"h" => Extensions::H,
+        b"sstc" => Extensions::SSTC,
+        b"svpbmt" => Extensions::SVPBMT,
+        _ => Extensions::empty(),
+    }
+}
+
+/// Extensions of an ISA string such as `rv64imafdcvh_zicsr_sstc`: single
+/// letters after the base, then multi-letter names separated by `_`.
+fn extensions_of_isa(value: &[u8]) -> Extensions {
+    let isa = value.split(|&b| b == 0).next().unwrap_or(&[]);
+    let mut names = isa.split(|&b| b == b'_');
+    let mut extensions = Extensions::empty();
+    if let Some(letters) = names.next() {
+        let letters = letters.get(4..).unwrap_or(&[]);
+        // the first multi-letter name may follow the letters directly
+        for letter in letters
+            .iter()
+            .take_while(|&&b| !matches!(b.to_ascii_lowercase(), b's' | b'x' | b'z'))
+        {
+            extensions |= extension_of_name(&[letter.to_ascii_lowercase()]);
+        }
+    }
+    for name in names {
+        extensions |= extension_of_name(&name.to_ascii_lowercase());
+    }
+    extensions
+}
+
+/// Fill `info` from the device tree blob at physical address `dtb`. Returns
+/// false, leaving `info` untouched, if there is no valid blob there.
+///
+/// # Safety
+///
+/// `dtb` must be readable, which holds before paging is enabled.
+pub unsafe fn parse(dtb: usize, info: &mut BoardInfo) -> bool {
+    if dtb == 0 || dtb % 8 != 0 {
+        return false;
+    }
+    let header = Blob {
+        data: core::slice::from_raw_parts(dtb as *const u8, 40),
+    };
+    if header.u32_at(0) != Some(FDT_MAGIC) || header.u32_at(20).unwrap() < FDT_MIN_VERSION {
+        return false;
+    }
+    let total_size = header.u32_at(4).unwrap() as usize;
+    let blob = Blob {
+        data: core::slice::from_raw_parts(dtb as *const u8, total_size),
+    };
+    let structs = blob.u32_at(8).unwrap() as usize;
+    let strings = blob.u32_at(12).unwrap() as usize;
+    let reservations = blob.u32_at(16).unwrap() as usize;
+    let mut parsed = BoardInfo::empty();
+    // the blob itself is kept, for a warm reboot to read again
+    if walk(&blob, structs, strings, &mut parsed).is_none()
+        || read_reservations(&blob, reservations, &mut parsed).is_none()
+        || parsed.add_reserved(dtb, total_size).is_none()
+    {
+        return false;
+    }
+    // keep the defaults for what the tree does not describe
+    if parsed.memory_count == 0 {
+        parsed.memory = info.memory;
+        parsed.memory_count = info.memory_count;
+    }
+    if parsed.cpus == 0 {
+        parsed.cpus = info.cpus;
+        parsed.extensions = info.extensions;
+    }
+    if parsed.plic.base == 0 {
+        parsed.plic = info.plic;
+    }
+    if parsed.uart.base == 0 {
+        parsed.uart = info.uart;
+        parsed.uart_reg_shift = info.uart_reg_shift;
+    }
+    let count = parsed.memory_count;
+    parsed.memory[..count].sort_unstable();
+    let count = parsed.virtio_count;
+    parsed.virtio[..count].sort_unstable_by_key(|device| device.base);
+    *info = parsed;
+    true
+}
+
+fn walk<'a>(blob: &Blob<'a>, structs: usize, strings: usize, info: &mut BoardInfo) -> Option<()> {
+    let mut stack = [Node::new(); MAX_DEPTH];
+    let mut depth = 0usize;
+    let mut offset = structs;
+    loop {
+        let token = blob.u32_at(offset)?;
+        offset += 4;
+        match token {
+            FDT_BEGIN_NODE => {
+                let name = blob.str_at(offset)?;
+                offset = align4(offset + name.len() + 1);
+                if depth == MAX_DEPTH {
+                    return None;
+                }
+                stack[depth] = Node::new();
+                stack[depth].chosen = depth == 1 && name == b"chosen";
+                stack[depth].reserved_memory = depth == 1 && name == b"reserved-memory";
+                if depth == 2 && stack[1].reserved_memory {
+                    stack[depth].kind = Kind::Reserved;
+                }
+                depth += 1;
+            }
+            FDT_END_NODE => {
+                if depth == 0 {
+                    return None;
+                }
+                depth -= 1;
+                record(&stack[depth], info)?;
+            }
+            FDT_PROP => {
+                let len = blob.u32_at(offset)? as usize;
+                let name = blob.str_at(strings + blob.u32_at(offset + 4)? as usize)?;
+                let value = blob.data.get(offset + 8..offset + 8 + len)?;
+                offset = align4(offset + 8 + len);
+                if depth == 0 {
+                    return None;
+                }
+                let (parent_address_cells, parent_size_cells) = if depth >= 2 {
+                    (stack[depth - 2].address_cells, stack[depth - 2].size_cells)
+                } else {
+                    (2, 1)
+                };
+                let node = &mut stack[depth - 1];
+                match name {
+                    b"#address-cells" => node.address_cells = read_cells(value, 1)?,
+                    b"#size-cells" => node.size_cells = read_cells(value, 1)?,
+                    b"device_type" if value.starts_with(b"memory\0") => node.kind = Kind::Memory,
+                    b"device_type" if value.starts_with(b"cpu\0") => node.kind = Kind::Cpu,
+                    b"compatible" if node.kind == Kind::Unknown => {
+                        node.kind = kind_of_compatible(value)
+                    }
+                    b"reg" => node.reg = Some((value, parent_address_cells, parent_size_cells)),
+                    b"interrupts" => node.irq = read_cells(value, 1)?,
+                    b"reg-shift" => node.reg_shift = read_cells(value, 1)?,
+                    b"bootargs" if node.chosen => crate::cmdline::save(value),
+                    b"status" => node.disabled = value.starts_with(b"disabled\0"),
+                    b"hotpluggable" => node.hotpluggable = true,
+                    // the list, where there is one, is the more precise
+                    b"riscv,isa-extensions" => {
+                        node.extensions = Some(
+                            value
+                                .split(|&b| b == 0)
+                                .fold(Extensions::empty(), |all, name| {
+                                    all | extension_of_name(name)
+                                }),
+                        )
+                    }
+                    b"riscv,isa" if node.extensions.is_none() => {
+                        node.extensions = Some(extensions_of_isa(value))
+                    }
+                    _ => {}
+                }
+            }
+            FDT_NOP => {}
+            FDT_END => return Some(()),
+            _ => return None,
+        }
+    }
+}
+
+/// Entries of the memory reservation block at `offset`, which ends with an
+/// empty one.
+fn read_reservations(blob: &Blob, mut offset: usize, info: &mut BoardInfo) -> Option<()> {
+    loop {
+        let base = blob.u64_at(offset)? as usize;
+        let size = blob.u64_at(offset + 8)? as usize;
+        if base == 0 && size == 0 {
+            return Some(());
+        }
+        info.add_reserved(base, size)?;
+        offset += 16;
+    }
+}
+
+/// Add a fully parsed node to `info`. Fails if a reserved region does not
+/// fit, as its memory would be handed out.
+fn record(node: &Node, info: &mut BoardInfo) -> Option<()> {
+    match node.kind {
+        Kind::Memory if !node.disabled => {
+            for (base, size) in node.regs() {
+                info.add_memory(base, size);
+                if node.hotpluggable {
+                    info.add_hotpluggable(base, size);
+                }
+            }
+            return Some(());
+        }
+        Kind::Reserved => {
+            for (base, size) in node.regs() {
+                info.add_reserved(base, size)?;
+            }
+            return Some(());
+        }
+        _ => {}
+    }
+    let (base, size) = match node.regs().next() {
+        Some(reg) => reg,
+        None if node.kind == Kind::Cpu => (0, 0),
+        None => return Some(()),
+    };
+    let device = MmioDevice {
+        base,
+        size,
+        irq: node.irq,
+    };
+    match node.kind {
+        Kind::Cpu if node.disabled => {}
+        Kind::Cpu => {
+            let extensions = node.extensions.unwrap_or_else(Extensions::empty);
+            info.extensions = if info.cpus == 0 {
+                extensions
+            } else {
+                info.extensions & extensions
+            };
+            info.cpus += 1;
+        }
+        Kind::Uart if info.uart.base == 0 => {
+            info.uart = device;
+            info.uart_reg_shift = node.reg_shift;
+        }
+        Kind::Plic if info.plic.base == 0 => info.plic = device,
+        Kind::TestFinisher if info.test_finisher.base == 0 => info.test_finisher = device,
+        Kind::Virtio if info.virtio_count < MAX_VIRTIO => {
+            info.virtio[info.virtio_count] = device;
+            info.virtio_count += 1;
+        }
+        _ => {}
+    }
+    Some(())
+}
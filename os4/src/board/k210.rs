@@ -0,0 +1,32 @@
+//! Kendryte K210
+//!
+//! The K210 implements the 1.9.1 privileged spec; RustSBI emulates `satp`
+//! and `sfence.vma` on top of it, so paging works as on a newer core. Its
+//! console is the UARTHS, which is not a 16550, so console input goes
+//! through the SBI. It loads the kernel at `0x8002_0000`.
+
+use super::{Board, BoardInfo, MmioDevice};
+
+const MEMORY_START: usize = 0x8000_0000;
+const PLIC_BASE: usize = 0x0c00_0000;
+
+pub struct K210;
+
+impl Board for K210 {
+    const NAME: &'static str = "Kendryte K210";
+    /// the 403 MHz core clock divided by 62
+    const CLOCK_FREQ: usize = 403000000 / 62;
+    /// the 6 MiB of general-purpose SRAM, leaving the AI SRAM alone
+    const MEMORY_END: usize = 0x8060_0000;
+    fn default_info() -> BoardInfo {
+        let mut info = BoardInfo::empty();
+        info.add_memory(MEMORY_START, Self::MEMORY_END - MEMORY_START);
+        info.cpus = 2;
+        info.plic = MmioDevice {
+            base: PLIC_BASE,
+            size: 0x40_0000,
+            irq: 0,
+        };
+        info
+    }
+}
@@ -0,0 +1,234 @@
+//! Platform description
+//!
+//! The board the kernel is built for is chosen by cargo feature:
+//! `board-k210` for the Kendryte K210, `board-visionfive2` for the StarFive
+//! VisionFive 2, and QEMU virt without either. Each implements [`Board`],
+//! giving what must be known before anything is probed: the timer
+//! frequency, the device layout to assume without a device tree, and how
+//! PLIC contexts map to harts. The usual load address goes to the linker
+//! from `build.rs`; the kernel relocates itself when loaded elsewhere.
+//!
+//! What the kernel knows of the machine is then read from the device tree
+//! the firmware passes at boot, falling back to the board's layout. There is
+//! no block driver, so nothing describes an SD card yet.
+
+mod fdt;
+#[cfg(feature = "board-k210")]
+mod k210;
+#[cfg(not(any(feature = "board-k210", feature = "board-visionfive2")))]
+mod qemu;
+#[cfg(feature = "board-visionfive2")]
+mod visionfive2;
+
+#[cfg(all(feature = "board-k210", feature = "board-visionfive2"))]
+compile_error!("at most one board feature may be enabled");
+
+#[cfg(feature = "board-k210")]
+pub use k210::K210 as CurrentBoard;
+#[cfg(not(any(feature = "board-k210", feature = "board-visionfive2")))]
+pub use qemu::QemuVirt as CurrentBoard;
+#[cfg(feature = "board-visionfive2")]
+pub use visionfive2::VisionFive2 as CurrentBoard;
+
+use crate::drivers::TargetPriority;
+use crate::sync::UPSafeCell;
+use alloc::vec::Vec;
+use lazy_static::*;
+
+/// What differs between the boards the kernel runs on
+pub trait Board {
+    const NAME: &'static str;
+    /// frequency of the `time` CSR
+    const CLOCK_FREQ: usize;
+    /// end of the RAM assumed without a device tree
+    const MEMORY_END: usize;
+    /// The layout assumed without a device tree.
+    fn default_info() -> BoardInfo;
+    /// The PLIC context of `hart` at `priority`.
+    fn plic_context(hart: usize, priority: TargetPriority) -> usize {
+        hart * TargetPriority::supported_number() + priority as usize
+    }
+}
+
+/// most virtio-mmio slots recorded
+pub const MAX_VIRTIO: usize = 16;
+/// most memory banks recorded
+pub const MAX_MEMORY_REGIONS: usize = 8;
+/// most reserved regions recorded
+pub const MAX_RESERVED_REGIONS: usize = 16;
+
+bitflags! {
+    /// ISA extensions the kernel makes use of, as the device tree lists them
+    /// for the harts
+    pub struct Extensions: u32 {
+        /// vectors
+        const V = 1 << 0;
+        /// the hypervisor extension
+        const H = 1 << 1;
+        /// supervisor timer compare, `stimecmp`
+        const SSTC = 1 << 2;
+        /// page-based memory types
+        const SVPBMT = 1 << 3;
+    }
+}
+
+/// An MMIO device and its PLIC source
+#[derive(Copy, Clone, Debug, Default)]
+pub struct MmioDevice {
+    pub base: usize,
+    pub size: usize,
+    pub irq: usize,
+}
+
+#[derive(Copy, Clone)]
+pub struct BoardInfo {
+    /// (base, size) of the memory banks, in address order
+    pub memory: [(usize, usize); MAX_MEMORY_REGIONS],
+    pub memory_count: usize,
+    /// (base, size) of the banks marked `hotpluggable`, left to be added
+    /// after boot
+    pub hotpluggable: [(usize, usize); MAX_MEMORY_REGIONS],
+    pub hotpluggable_count: usize,
+    /// (base, size) of the memory the firmware keeps for itself or for
+    /// devices, from `/reserved-memory` and the blob's reservation block
+    pub reserved: [(usize, usize); MAX_RESERVED_REGIONS],
+    pub reserved_count: usize,
+    pub cpus: usize,
+    pub plic: MmioDevice,
+    pub uart: MmioDevice,
+    pub virtio: [MmioDevice; MAX_VIRTIO],
+    pub virtio_count: usize,
+    /// the SiFive test finisher, which ends a QEMU run
+    pub test_finisher: MmioDevice,
+    /// the UART registers are `1 << uart_reg_shift` bytes apart
+    pub uart_reg_shift: usize,
+    /// the hart the kernel booted on
+    pub boot_hart: usize,
+    /// the device tree the kernel was passed, kept for a warm reboot
+    pub dtb: usize,
+    /// extensions all enabled harts have
+    pub extensions: Extensions,
+}
+
+impl BoardInfo {
+    fn empty() -> Self {
+        Self {
+            memory: [(0, 0); MAX_MEMORY_REGIONS],
+            memory_count: 0,
+            hotpluggable: [(0, 0); MAX_MEMORY_REGIONS],
+            hotpluggable_count: 0,
+            reserved: [(0, 0); MAX_RESERVED_REGIONS],
+            reserved_count: 0,
+            cpus: 0,
+            plic: MmioDevice::default(),
+            uart: MmioDevice::default(),
+            virtio: [MmioDevice::default(); MAX_VIRTIO],
+            virtio_count: 0,
+            test_finisher: MmioDevice::default(),
+            uart_reg_shift: 0,
+            boot_hart: 0,
+            dtb: 0,
+            extensions: Extensions::empty(),
+        }
+    }
+    /// Record a memory bank, dropping it if there are too many.
+    fn add_memory(&mut self, base: usize, size: usize) {
+        if size != 0 && self.memory_count < MAX_MEMORY_REGIONS {
+            self.memory[self.memory_count] = (base, size);
+            self.memory_count += 1;
+        }
+    }
+    /// Record a memory bank that is only to be used once added, dropping
+    /// it if there are too many.
+    fn add_hotpluggable(&mut self, base: usize, size: usize) {
+        if size != 0 && self.hotpluggable_count < MAX_MEMORY_REGIONS {
+            self.hotpluggable[self.hotpluggable_count] = (base, size);
+            self.hotpluggable_count += 1;
+        }
+    }
+    /// Record a reserved region. There must be room for all of them, as
+    /// handing one out would corrupt what the firmware keeps there.
+    fn add_reserved(&mut self, base: usize, size: usize) -> Option<()> {
+        if size != 0 {
+            *self.reserved.get_mut(self.reserved_count)? = (base, size);
+            self.reserved_count += 1;
+        }
+        Some(())
+    }
+    pub fn memory_regions(&self) -> &[(usize, usize)] {
+        &self.memory[..self.memory_count]
+    }
+    pub fn hotpluggable_regions(&self) -> &[(usize, usize)] {
+        &self.hotpluggable[..self.hotpluggable_count]
+    }
+    pub fn reserved_regions(&self) -> &[(usize, usize)] {
+        &self.reserved[..self.reserved_count]
+    }
+    pub fn virtio_devices(&self) -> &[MmioDevice] {
+        &self.virtio[..self.virtio_count]
+    }
+    /// (base, size) of every MMIO region to map in kernel space, page-aligned.
+    pub fn mmio_regions(&self) -> Vec<(usize, usize)> {
+        let mut regions = Vec::new();
+        for device in [self.plic, self.uart, self.test_finisher]
+            .iter()
+            .chain(self.virtio_devices())
+        {
+            // a board may have no device of a kind
+            if device.base != 0 {
+                regions.push((device.base, device.size));
+            }
+        }
+        regions
+    }
+}
+
+lazy_static! {
+    static ref BOARD_INFO: UPSafeCell<BoardInfo> =
+        unsafe { UPSafeCell::new(CurrentBoard::default_info()) };
+}
+
+/// Read the device tree at physical address `dtb`, passed to hart
+/// `hart_id`. Must run before paging is enabled.
+pub fn init(hart_id: usize, dtb: usize) {
+    let mut info = BOARD_INFO.exclusive_access();
+    if unsafe { fdt::parse(dtb, &mut info) } {
+        info!("[kernel] {}, device tree at {:#x}", CurrentBoard::NAME, dtb);
+    } else {
+        warn!(
+            "[kernel] no valid device tree at {:#x}, assuming {}",
+            dtb,
+            CurrentBoard::NAME
+        );
+    }
+    info.boot_hart = hart_id;
+    info.dtb = dtb;
+    for &(base, size) in info.memory_regions() {
+        info!("[kernel] memory [{:#x}, {:#x})", base, base + size);
+    }
+    for &(base, size) in info.hotpluggable_regions() {
+        info!("[kernel] hotpluggable [{:#x}, {:#x})", base, base + size);
+    }
+    for &(base, size) in info.reserved_regions() {
+        info!("[kernel] reserved [{:#x}, {:#x})", base, base + size);
+    }
+    info!("[kernel] {} cpu(s)", info.cpus);
+    info!(
+        "[kernel] plic {:#x}, uart {:#x} (irq {}), {} virtio-mmio slot(s)",
+        info.plic.base, info.uart.base, info.uart.irq, info.virtio_count
+    );
+    info!("[kernel] ISA extensions: {:?}", info.extensions);
+}
+
+/// What is known of the machine.
+pub fn board_info() -> BoardInfo {
+    *BOARD_INFO.exclusive_access()
+}
+
+/// Whether the harts have all of `extensions`.
+pub fn has_extensions(extensions: Extensions) -> bool {
+    BOARD_INFO
+        .exclusive_access()
+        .extensions
+        .contains(extensions)
+}
@@ -0,0 +1,51 @@
+//! QEMU virt
+
+use super::{Board, BoardInfo, MmioDevice};
+
+const MEMORY_START: usize = 0x8000_0000;
+const TEST_FINISHER_BASE: usize = 0x10_0000;
+const PLIC_BASE: usize = 0x0c00_0000;
+const UART_BASE: usize = 0x1000_0000;
+const UART_IRQ: usize = 10;
+/// eight virtio-mmio slots, slot `i` raising IRQ `VIRTIO_IRQ_BASE + i`
+const VIRTIO_BASE: usize = 0x1000_1000;
+const VIRTIO_SLOTS: usize = 8;
+const VIRTIO_SLOT_SIZE: usize = 0x1000;
+const VIRTIO_IRQ_BASE: usize = 1;
+
+pub struct QemuVirt;
+
+impl Board for QemuVirt {
+    const NAME: &'static str = "QEMU virt";
+    const CLOCK_FREQ: usize = 12500000;
+    const MEMORY_END: usize = 0x8080_0000;
+    fn default_info() -> BoardInfo {
+        let mut info = BoardInfo::empty();
+        info.add_memory(MEMORY_START, Self::MEMORY_END - MEMORY_START);
+        info.cpus = 1;
+        info.plic = MmioDevice {
+            base: PLIC_BASE,
+            size: 0x40_0000,
+            irq: 0,
+        };
+        info.test_finisher = MmioDevice {
+            base: TEST_FINISHER_BASE,
+            size: 0x1000,
+            irq: 0,
+        };
+        info.uart = MmioDevice {
+            base: UART_BASE,
+            size: 0x100,
+            irq: UART_IRQ,
+        };
+        for slot in 0..VIRTIO_SLOTS {
+            info.virtio[slot] = MmioDevice {
+                base: VIRTIO_BASE + slot * VIRTIO_SLOT_SIZE,
+                size: VIRTIO_SLOT_SIZE,
+                irq: VIRTIO_IRQ_BASE + slot,
+            };
+        }
+        info.virtio_count = VIRTIO_SLOTS;
+        info
+    }
+}
@@ -0,0 +1,49 @@
+//! StarFive VisionFive 2 (JH7110)
+//!
+//! Hart 0 is an S7 monitor core without supervisor mode; the kernel boots
+//! on one of the U74 harts 1 to 4. The console is a DesignWare UART, which
+//! is 16550-compatible with its registers 4 bytes apart. It loads the
+//! kernel at `0x4020_0000`.
+
+use super::{Board, BoardInfo, MmioDevice};
+use crate::drivers::TargetPriority;
+
+const MEMORY_START: usize = 0x4000_0000;
+const PLIC_BASE: usize = 0x0c00_0000;
+const UART_BASE: usize = 0x1000_0000;
+const UART_IRQ: usize = 32;
+
+pub struct VisionFive2;
+
+impl Board for VisionFive2 {
+    const NAME: &'static str = "StarFive VisionFive 2";
+    const CLOCK_FREQ: usize = 4000000;
+    /// the first 128 MiB of the 4 or 8 GiB of DRAM
+    const MEMORY_END: usize = 0x4800_0000;
+    fn default_info() -> BoardInfo {
+        let mut info = BoardInfo::empty();
+        info.add_memory(MEMORY_START, Self::MEMORY_END - MEMORY_START);
+        info.cpus = 4;
+        info.plic = MmioDevice {
+            base: PLIC_BASE,
+            size: 0x400_0000,
+            irq: 0,
+        };
+        info.uart = MmioDevice {
+            base: UART_BASE,
+            size: 0x1_0000,
+            irq: UART_IRQ,
+        };
+        info.uart_reg_shift = 2;
+        info.boot_hart = 1;
+        info
+    }
+    /// Hart 0 only has a machine-mode context, so hart `h > 0` has contexts
+    /// `2 * h - 1` and `2 * h`.
+    fn plic_context(hart: usize, priority: TargetPriority) -> usize {
+        match hart {
+            0 => 0,
+            _ => 2 * hart - 1 + priority as usize,
+        }
+    }
+}
@@ -0,0 +1,243 @@
+//! Kernel command line
+//!
+//! The firmware passes the command line as `/chosen/bootargs` in the device
+//! tree (`-append` under QEMU). It is a list of space-separated `key=value`
+//! options:
+//!
+//! - `loglevel=`: the default level of the kernel log, `0` (off) to `5`
+//!   (trace) as for `sys_log_level`, or a level name such as `debug`
+//! - `sched=`: the scheduler, `rr` (round robin) or `stride`, by default
+//!   the one the `sched-stride` feature selects
+//! - `init=`: the name of the application run first, as root
+//! - `harness=`: the name of an application run alone, as root, to spawn
+//!   the others as tests and report their results with `sys_test_report`
+//! - `test=on`: run the kernel unit tests at boot; `test=exit` to exit
+//!   afterwards, with a status telling whether they passed
+//! - `assert=`: what a failed `debug_assert_kernel!` does, `panic` or
+//!   `kill` the current task, by default as `KILL_ON_ASSERT` says
+//! - `guest=`: the name of the application run as a guest kernel, with the
+//!   `hypervisor` feature
+//! - `schedlog=on`: record task switches, or replay those the previous
+//!   boot recorded if it did, in RAM kept across a reboot
+//! - `mem=`: how much memory the frame allocator gets at boot, in bytes or
+//!   with a `K`, `M` or `G` suffix; the rest is held back, like hotpluggable
+//!   memory banks, until added with `sys_memory_hot_add`
+//! - `console=framed`: tag each line the kernel and the tasks write to the
+//!   SBI console with the stream it belongs to, see [`crate::console`];
+//!   `console=plain`, the default, leaves them as they are
+//! - `idle=`: the deepest state the CPU idles in, `poll`, `wfi` or
+//!   `tickless`, the default, see [`crate::power`]
+//!
+//! The line is saved while the device tree is read, and parsed once the
+//! heap is up; records logged before that follow the `LOG` build variable.
+//! Unknown options are reported and ignored.
+
+use crate::config::{KILL_ON_ASSERT, STRIDE_SCHEDULER};
+use crate::sync::UPSafeCell;
+use alloc::string::String;
+use lazy_static::*;
+use log::LevelFilter;
+
+/// longest command line kept, in bytes
+const MAX_BOOTARGS_LEN: usize = 256;
+
+/// How the next task to run is picked
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Scheduler {
+    /// the next ready task after the current one
+    RoundRobin,
+    /// the ready task that has run least, weighted by priority
+    Stride,
+}
+
+/// What the command line selects
+struct BootArgs {
+    log_level: Option<LevelFilter>,
+    scheduler: Scheduler,
+    init: Option<String>,
+    harness: Option<String>,
+    test: bool,
+    /// exit once the tests passed rather than boot
+    test_exit: bool,
+    kill_on_assert: bool,
+    guest: Option<String>,
+    sched_log: bool,
+}
+
+lazy_static! {
+    /// the line as read from the device tree, and its length
+    static ref SAVED: UPSafeCell<([u8; MAX_BOOTARGS_LEN], usize)> =
+        unsafe { UPSafeCell::new(([0; MAX_BOOTARGS_LEN], 0)) };
+    static ref BOOT_ARGS: UPSafeCell<BootArgs> = unsafe {
+        UPSafeCell::new(BootArgs {
+            log_level: None,
+            scheduler: if STRIDE_SCHEDULER {
+                Scheduler::Stride
+            } else {
+                Scheduler::RoundRobin
+            },
+            init: None,
+            harness: None,
+            test: false,
+            test_exit: false,
+            kill_on_assert: KILL_ON_ASSERT,
+            guest: None,
+            sched_log: false,
+        })
+    };
+}
+
+/// Keep the `bootargs` property `value` until [`init`]. Does not allocate.
+pub fn save(value: &[u8]) {
+    // the property is NUL-terminated
+    let value = value.split(|&b| b == 0).next().unwrap_or(&[]);
+    let mut saved = SAVED.exclusive_access();
+    let len = value.len().min(MAX_BOOTARGS_LEN);
+    saved.0[..len].copy_from_slice(&value[..len]);
+    saved.1 = len;
+}
+
+fn parse_log_level(value: &str) -> Option<LevelFilter> {
+    match value {
+        "0" | "off" => Some(LevelFilter::Off),
+        "1" | "error" => Some(LevelFilter::Error),
+        "2" | "warn" => Some(LevelFilter::Warn),
+        "3" | "info" => Some(LevelFilter::Info),
+        "4" | "debug" => Some(LevelFilter::Debug),
+        "5" | "trace" => Some(LevelFilter::Trace),
+        _ => None,
+    }
+}
+
+/// A size in bytes, maybe with a `K`, `M` or `G` suffix.
+fn parse_size(value: &str) -> Option<usize> {
+    let (digits, shift) = match value.as_bytes().last()? {
+        b'K' | b'k' => (&value[..value.len() - 1], 10),
+        b'M' | b'm' => (&value[..value.len() - 1], 20),
+        b'G' | b'g' => (&value[..value.len() - 1], 30),
+        _ => (value, 0),
+    };
+    digits.parse::<usize>().ok()?.checked_mul(1 << shift)
+}
+
+/// The size given with `mem=`. It is read from the saved line, as the frame
+/// allocator is set up before [`init`] parses it.
+pub fn memory_limit() -> Option<usize> {
+    let saved = SAVED.exclusive_access();
+    let line = core::str::from_utf8(&saved.0[..saved.1]).ok()?;
+    line.split_ascii_whitespace()
+        .filter_map(|option| option.strip_prefix("mem="))
+        .last()
+        .and_then(parse_size)
+}
+
+/// Parse the saved command line and apply the log level it selects.
+pub fn init() {
+    let saved = SAVED.exclusive_access();
+    let line = match core::str::from_utf8(&saved.0[..saved.1]) {
+        Ok(line) => line,
+        Err(_) => {
+            warn!("[kernel] command line is not UTF-8, ignored");
+            return;
+        }
+    };
+    if !line.is_empty() {
+        info!("[kernel] command line: {}", line);
+    }
+    let mut args = BOOT_ARGS.exclusive_access();
+    for option in line.split_ascii_whitespace() {
+        let (key, value) = option.split_once('=').unwrap_or((option, ""));
+        match key {
+            "loglevel" => match parse_log_level(value) {
+                Some(level) => args.log_level = Some(level),
+                None => warn!("[kernel] bad log level {:?}", value),
+            },
+            "sched" => match value {
+                "rr" => args.scheduler = Scheduler::RoundRobin,
+                "stride" => args.scheduler = Scheduler::Stride,
+                _ => warn!("[kernel] unknown scheduler {:?}", value),
+            },
+            "init" if !value.is_empty() => args.init = Some(String::from(value)),
+            "harness" if !value.is_empty() => args.harness = Some(String::from(value)),
+            "test" => match value {
+                "on" | "off" => {
+                    args.test = value == "on";
+                    args.test_exit = false;
+                }
+                "exit" => {
+                    args.test = true;
+                    args.test_exit = true;
+                }
+                _ => warn!("[kernel] bad test mode {:?}", value),
+            },
+            "assert" => match value {
+                "panic" | "kill" => args.kill_on_assert = value == "kill",
+                _ => warn!("[kernel] bad assertion mode {:?}", value),
+            },
+            "guest" if !value.is_empty() => args.guest = Some(String::from(value)),
+            "schedlog" => args.sched_log = value == "on",
+            "console" => match value {
+                "framed" | "plain" => crate::console::set_framed(value == "framed"),
+                _ => warn!("[kernel] bad console mode {:?}", value),
+            },
+            "idle" => match crate::power::IdleState::from_name(value) {
+                Some(state) => crate::power::set_idle_limit(state),
+                None => warn!("[kernel] bad idle state {:?}", value),
+            },
+            // applied by the frame allocator, see `memory_limit`
+            "mem" => {
+                if parse_size(value).is_none() {
+                    warn!("[kernel] bad memory size {:?}", value);
+                }
+            }
+            _ => warn!("[kernel] unknown boot option {:?}", option),
+        }
+    }
+    if let Some(level) = args.log_level {
+        crate::logging::set_log_level("", Some(level));
+    }
+    if cfg!(not(feature = "hypervisor")) && args.guest.is_some() {
+        warn!("[kernel] guest= needs the hypervisor feature, ignored");
+    }
+}
+
+/// The scheduler selected with `sched=`.
+pub fn scheduler() -> Scheduler {
+    BOOT_ARGS.exclusive_access().scheduler
+}
+
+/// The name of the application selected with `init=`, if any.
+pub fn init_app() -> Option<String> {
+    BOOT_ARGS.exclusive_access().init.clone()
+}
+
+/// The name of the application selected with `harness=`, if any.
+pub fn harness_app() -> Option<String> {
+    BOOT_ARGS.exclusive_access().harness.clone()
+}
+
+/// The name of the application selected with `guest=`, if any.
+#[cfg(feature = "hypervisor")]
+pub fn guest_app() -> Option<String> {
+    BOOT_ARGS.exclusive_access().guest.clone()
+}
+
+/// Whether `test=on` or `test=exit` was given.
+pub fn test_mode() -> bool {
+    BOOT_ARGS.exclusive_access().test
+}
+
+/// Whether `schedlog=on` was given.
+pub fn sched_log() -> bool {
+    BOOT_ARGS.exclusive_access().sched_log
+}
+
+/// Whether `test=exit` was given.
+pub fn test_exit() -> bool {
+    BOOT_ARGS.exclusive_access().test_exit
+}
+
+/// Whether a failed kernel assertion kills the current task, see `assert=`.
+pub fn kill_on_assert() -> bool {
+    BOOT_ARGS.exclusive_access().kill_on_assert
+}
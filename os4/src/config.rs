@@ -1,13 +1,78 @@
 //! Constants used in rCore
+//!
+//! Grouped by subsystem. What an experiment usually changes is selected by
+//! cargo feature rather than by editing this file:
+//!
+//! - `board-k210`, `board-visionfive2`: the board, see [`crate::board`]
+//! - `sched-stride`: stride scheduling unless `sched=` says otherwise
+//! - `lazy-mmap`: `mmap` gives frames on first touch instead of at once
+//! - `debug`: the leak detector, heap redzones, page poisoning, every
+//!   tracepoint enabled from boot, and kernel assertions that panic
+//! - `kcov`, `leak-detector`, `heap-redzone`, `page-poison`: each piece of
+//!   instrumentation on its own
+//! - `syscall-fuzz`: make random syscalls as a sacrificial task at boot
+//! - `fault-inject`: fail frame and heap allocations and reads as
+//!   `sys_fault_inject` says
+//! - `hypervisor`: run the application named by `guest=` as a guest kernel
+//!
+//! There is no hart count to choose: the kernel runs on the boot hart only.
+//! The combinations are checked at the end of this file, so that a bad one
+//! fails the build instead of the boot.
+
+use crate::board::{Board, CurrentBoard};
+
+// Memory
 
-pub const USER_STACK_SIZE: usize = 4096 * 2;
-pub const KERNEL_STACK_SIZE: usize = 4096 * 2;
-pub const KERNEL_HEAP_SIZE: usize = 0x30_0000;
-pub const MEMORY_END: usize = 0x80800000;
 pub const PAGE_SIZE: usize = 0x1000;
 pub const PAGE_SIZE_BITS: usize = 0xc;
-pub const MAX_SYSCALL_NUM: usize = 500;
+pub const KERNEL_HEAP_SIZE: usize = 0x30_0000;
+/// RAM at the end of the memory bank holding the kernel kept out of the
+/// frame allocator for the crash dump, so that it survives a warm reboot
+pub const CRASH_DUMP_SIZE: usize = 0x4000;
+/// RAM below the crash dump kept for the scheduling log, which a reboot
+/// replays
+pub const SCHED_LOG_SIZE: usize = 0x1_0000;
+/// Room kept for a copy of the kernel's initialized data as it was at boot,
+/// which a warm reboot restores
+pub const WARM_REBOOT_DATA_SIZE: usize = 0x8000;
+/// Whether frames are cleared when freed as well as when allocated, so that
+/// free memory never holds what a task left there. `page-poison` fills them
+/// with poison instead.
+pub const ZERO_FRAMES_ON_FREE: bool = true;
+/// Whether `mmap` only reserves the pages, giving each a frame when it is
+/// first touched
+pub const LAZY_MMAP: bool = cfg!(feature = "lazy-mmap");
+/// Memory kept from boot for the contiguous, aligned buffers of drivers,
+/// and the alignment of its start
+pub const CMA_SIZE: usize = 0x20_0000;
+pub const CMA_ALIGN: usize = 0x1_0000;
+/// Free frames below which memory is under low and critical pressure,
+/// until root sets other watermarks
+pub const MEMORY_LOW_WATERMARK_PAGES: usize = 1024;
+pub const MEMORY_MIN_WATERMARK_PAGES: usize = 256;
+
+// Address spaces
 
+pub const USER_STACK_SIZE: usize = 4096 * 2;
+pub const KERNEL_STACK_SIZE: usize = 4096 * 2;
+/// Whether each trap starts at a random offset below the top of the kernel
+/// stack, and the largest such offset
+pub const KSTACK_RANDOMIZE: bool = true;
+pub const KSTACK_MAX_OFFSET: usize = 0x200;
+/// End of the lower half of Sv39, where all user mappings live. Addresses
+/// above it alias the upper half in the page table, as only the low 39 bits
+/// index it.
+#[cfg(target_pointer_width = "64")]
+pub const USER_SPACE_END: usize = 1 << 38;
+/// Sv32 translates all 32 bits, so user mappings may go up to the trap
+/// context.
+#[cfg(target_pointer_width = "32")]
+pub const USER_SPACE_END: usize = TRAP_CONTEXT;
+/// Where Linux `mmap` looks for room first when given no address, well
+/// above the program and the heap `brk` grows
+pub const LINUX_MMAP_BASE: usize = 0x2000_0000;
+/// Ranges a sandbox profile may allow mappings in
+pub const MAX_SANDBOX_RANGES: usize = 4;
 pub const TRAMPOLINE: usize = usize::MAX - PAGE_SIZE + 1;
 pub const TRAP_CONTEXT: usize = TRAMPOLINE - PAGE_SIZE;
 /// Return (bottom, top) of a kernel stack in kernel space.
@@ -17,4 +82,160 @@ pub fn kernel_stack_position(app_id: usize) -> (usize, usize) {
     (bottom, top)
 }
 
-pub const CLOCK_FREQ: usize = 12500000;
+// Tasks
+
+pub const MAX_SYSCALL_NUM: usize = 500;
+/// Tasks there may be at once, each with a kernel stack below the
+/// trampoline
+pub const MAX_TASKS: usize = 64;
+/// Whether the stride scheduler is used unless `sched=` selects another
+pub const STRIDE_SCHEDULER: bool = cfg!(feature = "sched-stride");
+/// Stride scheduling: the pass a task of priority 1 would advance by, and
+/// the priority tasks start with
+pub const BIG_STRIDE: usize = 0x10_0000;
+pub const DEFAULT_PRIORITY: usize = 16;
+/// Control groups there may be at once, the root group included
+pub const MAX_CGROUPS: usize = 32;
+/// Range of the CPU weight of a control group, and the weight groups start
+/// with, as `cpu.weight` in Linux
+pub const MAX_CGROUP_WEIGHT: usize = 10_000;
+pub const DEFAULT_CGROUP_WEIGHT: usize = 100;
+/// Pages a long kernel operation such as `munmap` handles between two
+/// points where it lets other tasks run
+pub const RESCHED_BATCH_PAGES: usize = 256;
+/// Range of nice values, as in Linux; lower is favoured
+pub const MIN_NICE: isize = -20;
+pub const MAX_NICE: isize = 19;
+/// Credentials of every task but init, which runs as root
+pub const USER_UID: u32 = 1000;
+pub const USER_GID: u32 = 1000;
+/// Longest task name, in bytes, as `TASK_COMM_LEN` less the nul in Linux
+pub const TASK_NAME_LEN: usize = 15;
+/// File descriptors a task may have open unless it changes its limit
+pub const DEFAULT_NOFILE_LIMIT: usize = 64;
+/// Bytes a pipe holds before its writers wait
+pub const PIPE_BUFFER_SIZE: usize = 0x1000;
+/// Longest line edited on the console in cooked mode, as Linux
+/// `MAX_CANON`; what is typed past it is dropped
+pub const MAX_CANON: usize = 255;
+
+// Time
+
+pub const CLOCK_FREQ: usize = <CurrentBoard as Board>::CLOCK_FREQ;
+/// How long the kernel may go without showing progress before the
+/// watchdog resets the machine
+pub const WATCHDOG_TIMEOUT_MS: usize = 5000;
+/// CPU usage is averaged over about this many seconds, the latest ones
+/// weighing most
+pub const CPU_USAGE_WINDOW_SECS: usize = 5;
+
+// Diagnostics
+
+/// Size of the ring keeping the latest kernel log lines for `dmesg`
+pub const LOG_BUFFER_SIZE: usize = 0x8000;
+/// Distinct pcs the profiler keeps per task
+pub const MAX_PROFILE_PCS: usize = 512;
+/// Records the trace ring holds before overwriting the oldest
+pub const TRACE_BUFFER_RECORDS: usize = 4096;
+/// Whether every tracepoint is enabled from boot, before user space can
+/// enable any
+pub const TRACE_AT_BOOT: bool = cfg!(feature = "debug");
+/// Whether a failed `debug_assert_kernel!` kills the current task rather
+/// than panic, unless `assert=` says otherwise
+pub const KILL_ON_ASSERT: bool = cfg!(not(feature = "debug"));
+/// Records the audit log holds before dropping new ones
+pub const AUDIT_LOG_RECORDS: usize = 1024;
+/// Records the accounting log holds before dropping new ones
+pub const ACCT_LOG_RECORDS: usize = 256;
+/// Live heap allocations the leak detector can track
+#[cfg(feature = "leak-detector")]
+pub const MAX_TRACKED_ALLOCATIONS: usize = 4096;
+/// Return addresses recorded as the call site of a heap allocation
+#[cfg(feature = "leak-detector")]
+pub const HEAP_SITE_DEPTH: usize = 4;
+/// Bytes checked on each side of a heap allocation
+#[cfg(feature = "heap-redzone")]
+pub const HEAP_REDZONE_SIZE: usize = 16;
+/// Latest heap frees remembered, to tell a double free
+#[cfg(feature = "heap-redzone")]
+pub const HEAP_FREED_RECORDS: usize = 256;
+/// Where `sys_kcov` maps the coverage buffer in user space
+#[cfg(feature = "kcov")]
+pub const KCOV_VADDR: usize = 0x7000_0000;
+/// Random syscalls the sacrificial task makes
+#[cfg(feature = "syscall-fuzz")]
+pub const SYSCALL_FUZZ_ITERATIONS: usize = 10000;
+
+// Devices
+
+/// The GPU framebuffer is clipped to this size to spare physical memory
+pub const FB_MAX_WIDTH: usize = 640;
+pub const FB_MAX_HEIGHT: usize = 480;
+/// Where `sys_framebuffer` maps the framebuffer in user space
+pub const FB_VADDR: usize = 0x6000_0000;
+
+// Network
+
+/// Address of the network interface when no DHCP server answers, matching
+/// QEMU user networking
+pub const NET_IP: [u8; 4] = [10, 0, 2, 15];
+pub const NET_PREFIX_LEN: u8 = 24;
+pub const NET_GATEWAY: [u8; 4] = [10, 0, 2, 2];
+pub const NET_DNS: [u8; 4] = [10, 0, 2, 3];
+
+// Guests
+
+/// Guest physical address and size of the RAM of a guest kernel
+#[cfg(feature = "hypervisor")]
+pub const GUEST_RAM_BASE: usize = 0x8000_0000;
+#[cfg(feature = "hypervisor")]
+pub const GUEST_RAM_SIZE: usize = 0x10_0000;
+
+// Checks
+
+const _: () = assert!(PAGE_SIZE == 1 << PAGE_SIZE_BITS, "PAGE_SIZE_BITS");
+const _: () = assert!(
+    CRASH_DUMP_SIZE % PAGE_SIZE == 0 && SCHED_LOG_SIZE % PAGE_SIZE == 0,
+    "memory is managed in whole pages"
+);
+const _: () = assert!(
+    KSTACK_MAX_OFFSET >= 16 && KSTACK_MAX_OFFSET % 16 == 0,
+    "kernel stack offsets are multiples of 16"
+);
+const _: () = assert!(
+    KSTACK_MAX_OFFSET <= KERNEL_STACK_SIZE / 4,
+    "the kernel stack offset leaves too little stack"
+);
+const _: () = assert!(
+    CMA_SIZE % PAGE_SIZE == 0 && CMA_ALIGN % PAGE_SIZE == 0 && CMA_ALIGN.is_power_of_two(),
+    "the contiguous memory area is made of whole, aligned pages"
+);
+const _: () = assert!(
+    MEMORY_MIN_WATERMARK_PAGES <= MEMORY_LOW_WATERMARK_PAGES,
+    "the critical watermark is below the low one"
+);
+const _: () = assert!(DEFAULT_PRIORITY >= 2, "priorities start at 2");
+const _: () = assert!(
+    BIG_STRIDE / DEFAULT_PRIORITY > 0,
+    "BIG_STRIDE too small for the default priority"
+);
+const _: () = assert!(
+    BIG_STRIDE / MAX_CGROUP_WEIGHT > 0,
+    "BIG_STRIDE too small for the largest group weight"
+);
+const _: () = assert!(
+    MIN_NICE == -20 && MAX_NICE == 19,
+    "nice values index the scheduler's weight table"
+);
+const _: () = assert!(
+    FB_VADDR + FB_MAX_WIDTH * FB_MAX_HEIGHT * 4 <= USER_SPACE_END,
+    "the framebuffer mapping leaves user space"
+);
+#[cfg(feature = "hypervisor")]
+const _: () = assert!(
+    GUEST_RAM_BASE % PAGE_SIZE == 0 && GUEST_RAM_SIZE % PAGE_SIZE == 0,
+    "guest RAM must be whole pages"
+);
+#[cfg(feature = "kcov")]
+const _: () = assert!(KCOV_VADDR < USER_SPACE_END && KCOV_VADDR % PAGE_SIZE == 0);
+const _: () = assert!(LINUX_MMAP_BASE < USER_SPACE_END && LINUX_MMAP_BASE % PAGE_SIZE == 0);
@@ -1,15 +1,83 @@
 //! SBI console driver, for text output
+//!
+//! The kernel's own output and the output of tasks that has no virtio
+//! console port to go to share the SBI console. With `console=framed` on
+//! the command line, each line on it starts with a tag telling which
+//! stream it belongs to: `K ` for the kernel, `U ` for tasks. A line of one
+//! stream cut short by the other is ended there and goes on in a line
+//! tagged `k ` or `u `, so that joining those to the line before gives the
+//! streams back as they were written.
 
 use crate::sbi::console_putchar;
 use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
-struct Stdout;
+/// Which output a line of the console belongs to
+#[repr(usize)]
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Stream {
+    Kernel = 0,
+    User = 1,
+}
+
+/// whether lines are tagged with their stream
+static FRAMED: AtomicBool = AtomicBool::new(false);
+/// the stream written last
+static STREAM: AtomicUsize = AtomicUsize::new(Stream::Kernel as usize);
+/// whether the next byte starts a line
+static AT_LINE_START: AtomicBool = AtomicBool::new(true);
+/// per stream, whether its last line was cut short by the other stream
+static CUT: [AtomicBool; 2] = [AtomicBool::new(false), AtomicBool::new(false)];
+
+/// Tag each line with its stream from now on, or stop.
+pub fn set_framed(framed: bool) {
+    FRAMED.store(framed, Ordering::Relaxed);
+}
+
+/// Write `bytes` of `stream`, tagging its lines if framing is on.
+fn write_stream(stream: Stream, bytes: &[u8]) {
+    if !FRAMED.load(Ordering::Relaxed) {
+        for &byte in bytes {
+            console_putchar(byte as usize);
+        }
+        return;
+    }
+    for &byte in bytes {
+        let last = STREAM.swap(stream as usize, Ordering::Relaxed);
+        if last != stream as usize && !AT_LINE_START.load(Ordering::Relaxed) {
+            console_putchar(b'\n' as usize);
+            CUT[last].store(true, Ordering::Relaxed);
+            AT_LINE_START.store(true, Ordering::Relaxed);
+        }
+        if AT_LINE_START.swap(false, Ordering::Relaxed) {
+            let cut = CUT[stream as usize].swap(false, Ordering::Relaxed);
+            let tag = match (stream, cut) {
+                (Stream::Kernel, false) => b'K',
+                (Stream::Kernel, true) => b'k',
+                (Stream::User, false) => b'U',
+                (Stream::User, true) => b'u',
+            };
+            console_putchar(tag as usize);
+            console_putchar(b' ' as usize);
+        }
+        console_putchar(byte as usize);
+        if byte == b'\n' {
+            AT_LINE_START.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Write output of a task.
+pub fn write_user(bytes: &[u8]) {
+    write_stream(Stream::User, bytes);
+}
+
+/// The console as a [`Write`], for the kernel's own output
+pub struct Stdout;
 
 impl Write for Stdout {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        for c in s.chars() {
-            console_putchar(c as usize);
-        }
+        write_stream(Stream::Kernel, s.as_bytes());
         Ok(())
     }
 }
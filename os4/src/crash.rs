@@ -0,0 +1,197 @@
+//! Crash dumps
+//!
+//! On panic, [`dump`] writes the panic message, the trap registers, a
+//! backtrace, the task table and the end of the kernel log to the console
+//! between marker lines, so that a headless run keeps a record of the
+//! crash that a script can cut out of its output.
+//!
+//! There is no block device in this kernel, so the dump is also copied to
+//! the [`CRASH_DUMP_SIZE`] bytes of RAM at the end of the memory bank
+//! holding the kernel, kept out of the frame allocator. That RAM survives a
+//! warm reboot, after which [`init`] prints the dump again at boot and
+//! forgets it. Where the bank ends is only known once the device tree is
+//! read; a panic before that is only written to the console.
+//!
+//! Nothing here allocates, as the heap may be what failed.
+
+use crate::config::{kernel_stack_position, CRASH_DUMP_SIZE, MAX_TASKS, TRAMPOLINE};
+use crate::console::Stdout;
+use crate::mm::crash_dump_base;
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use riscv::register::{scause, sepc, sstatus, stval};
+
+/// marks a dump saved by a previous boot, "CRASHDMP"
+const MAGIC: u64 = 0x504d_4448_5341_5243;
+/// frames the backtrace follows at most
+const MAX_BACKTRACE_DEPTH: usize = 32;
+/// bytes of kernel log in a dump
+const LOG_TAIL_LEN: usize = 0x1000;
+
+/// Start of the saved dump; the text follows it
+#[repr(C)]
+struct Header {
+    magic: u64,
+    len: u64,
+}
+
+/// Set by the first panic, so that a panic while dumping does not dump again
+static PANICKING: AtomicBool = AtomicBool::new(false);
+/// Where the dump is saved, 0 until [`init`]
+static DUMP_BASE: AtomicUsize = AtomicUsize::new(0);
+
+fn header() -> Option<&'static mut Header> {
+    match DUMP_BASE.load(Ordering::Relaxed) {
+        0 => None,
+        base => Some(unsafe { &mut *(base as *mut Header) }),
+    }
+}
+
+fn saved_text() -> &'static mut [u8] {
+    let base = DUMP_BASE.load(Ordering::Relaxed);
+    if base == 0 {
+        return &mut [];
+    }
+    let start = base + core::mem::size_of::<Header>();
+    let end = base + CRASH_DUMP_SIZE;
+    unsafe { core::slice::from_raw_parts_mut(start as *mut u8, end - start) }
+}
+
+/// Writes to the console and to the saved dump, dropping what does not fit
+/// in the latter
+struct DumpWriter {
+    len: usize,
+}
+
+impl Write for DumpWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        Stdout.write_str(s)?;
+        let text = saved_text();
+        let len = s.len().min(text.len() - self.len);
+        text[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
+
+/// Follow the frame pointers up from the caller of this function, calling
+/// `f` with each return address until it returns false or the chain leaves
+/// the kernel.
+#[inline(never)]
+pub fn walk_frames(mut f: impl FnMut(usize) -> bool) {
+    extern "C" {
+        fn stext();
+        fn etext();
+        fn ekernel();
+    }
+    const WORD: usize = core::mem::size_of::<usize>();
+    let (stacks_bottom, _) = kernel_stack_position(MAX_TASKS);
+    let mut fp: usize;
+    unsafe {
+        core::arch::asm!("mv {}, s0", out(reg) fp);
+    }
+    for _ in 0..MAX_BACKTRACE_DEPTH {
+        // the frame record is right below where fp points, on the boot stack
+        // in the kernel image or on a kernel stack
+        let in_image = fp >= stext as usize + 2 * WORD && fp <= ekernel as usize;
+        let in_stacks = fp >= stacks_bottom + 2 * WORD && fp <= TRAMPOLINE;
+        if fp % WORD != 0 || !(in_image || in_stacks) {
+            break;
+        }
+        let (ra, prev_fp) = unsafe {
+            (
+                *((fp - WORD) as *const usize),
+                *((fp - 2 * WORD) as *const usize),
+            )
+        };
+        if ra < stext as usize || ra >= etext as usize || !f(ra) {
+            break;
+        }
+        fp = prev_fp;
+    }
+}
+
+fn backtrace(out: &mut dyn Write) -> fmt::Result {
+    let mut result = Ok(());
+    walk_frames(|ra| {
+        result = writeln!(out, "  {:#x}", ra);
+        result.is_ok()
+    });
+    result
+}
+
+/// Where a panic was raised and its message, each if known, as
+/// " at file:line: message"
+struct PanicDescription<'a>(&'a PanicInfo<'a>);
+
+impl fmt::Display for PanicDescription<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(location) = self.0.location() {
+            write!(f, " at {}:{}", location.file(), location.line())?;
+        }
+        match self.0.message() {
+            Some(message) => write!(f, ": {}", message),
+            None => Ok(()),
+        }
+    }
+}
+
+fn write_dump(out: &mut dyn Write, info: &PanicInfo) -> fmt::Result {
+    writeln!(out, "panicked{}", PanicDescription(info))?;
+    writeln!(
+        out,
+        "last trap: scause {:#x} sepc {:#x} stval {:#x} sstatus {:#x}",
+        scause::read().bits(),
+        sepc::read(),
+        stval::read(),
+        sstatus::read().bits()
+    )?;
+    writeln!(out, "backtrace:")?;
+    backtrace(out)?;
+    writeln!(out, "tasks:")?;
+    crate::task::write_tasks(out)?;
+    writeln!(out, "log:")?;
+    crate::logging::write_kernel_log_tail(out, LOG_TAIL_LEN)
+}
+
+/// Write the crash dump for the panic `info`, or only its message if the
+/// kernel panicked again while dumping.
+pub fn dump(info: &PanicInfo) {
+    if PANICKING.swap(true, Ordering::Relaxed) {
+        println!("[kernel] panicked while dumping{}", PanicDescription(info));
+        return;
+    }
+    println!("---[ crash dump begin ]---");
+    let mut writer = DumpWriter { len: 0 };
+    // the console is written even if the dump is cut short
+    let _ = write_dump(&mut writer, info);
+    if let Some(header) = header() {
+        header.len = writer.len as u64;
+        header.magic = MAGIC;
+    }
+    println!("---[ crash dump end ]---");
+}
+
+/// Find the saved dump at the end of the kernel's memory bank. Print the
+/// one saved by a crash before the last warm reboot, if there is one, and
+/// forget it.
+pub fn init() {
+    DUMP_BASE.store(crash_dump_base(), Ordering::Relaxed);
+    let header = match header() {
+        Some(header) if header.magic == MAGIC => header,
+        _ => return,
+    };
+    header.magic = 0;
+    let len = (header.len as usize).min(saved_text().len());
+    let text = &saved_text()[..len];
+    // the dump may have been cut in the middle of a character
+    let text = match core::str::from_utf8(text) {
+        Ok(text) => text,
+        Err(err) => core::str::from_utf8(&text[..err.valid_up_to()]).unwrap(),
+    };
+    println!("[kernel] the previous boot crashed:");
+    println!("---[ previous crash dump begin ]---");
+    print!("{}", text);
+    println!("---[ previous crash dump end ]---");
+}
@@ -0,0 +1,93 @@
+//! Character devices
+//!
+//! The user console and the kernel log go to separate ports of a virtio
+//! console when there is one. Otherwise the console is the UART for input
+//! and the SBI console for output, and the log is mixed into it, told apart
+//! only if `console=framed` tags the lines (see [`crate::console`]). On a
+//! board without a 16550 UART, input comes from the SBI console too.
+
+mod ns16550a;
+
+use crate::board::board_info;
+use crate::drivers::virtio::VirtIOConsole;
+use crate::sbi::console_getchar;
+use crate::sync::UPSafeCell;
+use lazy_static::*;
+pub use ns16550a::NS16550a;
+
+/// port of the virtio console carrying the user console
+const CONSOLE_PORT: usize = 0;
+/// port of the virtio console carrying the kernel log
+const LOG_PORT: usize = 1;
+
+lazy_static! {
+    /// the console UART, if the board has one
+    pub static ref UART: Option<NS16550a> = {
+        let info = board_info();
+        (info.uart.base != 0).then(|| NS16550a::new(info.uart.base, info.uart_reg_shift))
+    };
+    /// the virtio console, if any
+    pub static ref VIRTIO_CONSOLE: UPSafeCell<Option<VirtIOConsole>> =
+        unsafe { UPSafeCell::new(None) };
+}
+
+/// Take one byte of input from the UART, or from the SBI console without one.
+fn uart_read() -> Option<u8> {
+    if let Some(uart) = UART.as_ref() {
+        return uart.read();
+    }
+    match console_getchar() {
+        // the legacy call returns -1 when there is no input
+        usize::MAX => None,
+        c => Some(c as u8),
+    }
+}
+
+/// Interrupt handler of the virtio console.
+pub fn virtio_console_irq_handler() {
+    if let Some(console) = VIRTIO_CONSOLE.exclusive_access().as_mut() {
+        console.handle_irq();
+    }
+}
+
+/// Write user console output to the virtio console. Returns false if there
+/// is none, leaving the output to the SBI console.
+pub fn console_write(data: &[u8]) -> bool {
+    VIRTIO_CONSOLE
+        .exclusive_access()
+        .as_mut()
+        .map_or(false, |console| console.write(CONSOLE_PORT, data))
+}
+
+/// Take one byte of user console input, if any.
+pub fn console_read() -> Option<u8> {
+    match VIRTIO_CONSOLE.exclusive_access().as_mut() {
+        Some(console) if console.ports() > CONSOLE_PORT => console.read(CONSOLE_PORT),
+        _ => uart_read(),
+    }
+}
+
+/// Whether user console input raises an interrupt, which it does unless it
+/// comes from the SBI console.
+pub fn console_has_irq() -> bool {
+    UART.is_some()
+        || VIRTIO_CONSOLE
+            .exclusive_access()
+            .as_ref()
+            .map_or(false, |console| console.ports() > CONSOLE_PORT)
+}
+
+/// Whether the kernel log has a channel of its own.
+pub fn has_log_channel() -> bool {
+    VIRTIO_CONSOLE
+        .exclusive_access()
+        .as_ref()
+        .map_or(false, |console| console.ports() > LOG_PORT)
+}
+
+/// Write to the kernel log channel, if there is one.
+pub fn log_write(data: &[u8]) {
+    if let Some(console) = VIRTIO_CONSOLE.exclusive_access().as_mut() {
+        console.write(LOG_PORT, data);
+    }
+}
@@ -0,0 +1,143 @@
+//! NS16550a UART driver
+//!
+//! Received bytes are moved into a ring buffer by the RX interrupt handler,
+//! so readers never have to poll the SBI for input. Output still goes
+//! through the SBI console.
+//!
+//! A break makes the byte after it a [`crate::sysrq`] command instead of
+//! input.
+
+use crate::sync::UPSafeCell;
+use alloc::collections::VecDeque;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// receive buffer register (read)
+const RBR: usize = 0;
+/// interrupt enable register
+const IER: usize = 1;
+/// FIFO control register (write)
+const FCR: usize = 2;
+/// line control register
+const LCR: usize = 3;
+/// modem control register
+const MCR: usize = 4;
+/// line status register
+const LSR: usize = 5;
+
+bitflags! {
+    /// interrupt enable register
+    struct IerFlags: u8 {
+        const RX_AVAILABLE = 1 << 0;
+        const TX_EMPTY = 1 << 1;
+    }
+    /// line status register
+    struct LsrFlags: u8 {
+        const DATA_AVAILABLE = 1 << 0;
+        const BREAK_INTERRUPT = 1 << 4;
+        const THR_EMPTY = 1 << 5;
+    }
+    /// modem control register
+    struct McrFlags: u8 {
+        const DATA_TERMINAL_READY = 1 << 0;
+        const REQUEST_TO_SEND = 1 << 1;
+        const AUX_OUTPUT1 = 1 << 2;
+        /// gates the interrupt line of the UART
+        const AUX_OUTPUT2 = 1 << 3;
+    }
+}
+
+/// capacity of the receive ring
+const RX_BUFFER_SIZE: usize = 256;
+
+pub struct NS16550a {
+    base: usize,
+    /// registers are `1 << reg_shift` bytes apart
+    reg_shift: usize,
+    rx_buffer: UPSafeCell<VecDeque<u8>>,
+    /// a break was received, so the next byte is a SysRq key
+    sysrq_armed: AtomicBool,
+}
+
+impl NS16550a {
+    pub fn new(base: usize, reg_shift: usize) -> Self {
+        Self {
+            base,
+            reg_shift,
+            rx_buffer: unsafe { UPSafeCell::new(VecDeque::with_capacity(RX_BUFFER_SIZE)) },
+            sysrq_armed: AtomicBool::new(false),
+        }
+    }
+    /// Registers 4 bytes apart are accessed 4 bytes wide, as the
+    /// DesignWare UART requires.
+    fn read_reg(&self, reg: usize) -> u8 {
+        let addr = self.base + (reg << self.reg_shift);
+        unsafe {
+            if self.reg_shift >= 2 {
+                (addr as *const u32).read_volatile() as u8
+            } else {
+                (addr as *const u8).read_volatile()
+            }
+        }
+    }
+    fn write_reg(&self, reg: usize, value: u8) {
+        let addr = self.base + (reg << self.reg_shift);
+        unsafe {
+            if self.reg_shift >= 2 {
+                (addr as *mut u32).write_volatile(value as u32);
+            } else {
+                (addr as *mut u8).write_volatile(value);
+            }
+        }
+    }
+    /// Enable the FIFOs and the RX interrupt, keeping the line settings
+    /// programmed by the firmware.
+    pub fn init(&self) {
+        self.write_reg(FCR, 1);
+        let lcr = self.read_reg(LCR);
+        self.write_reg(LCR, lcr & 0x7f);
+        self.write_reg(
+            MCR,
+            (McrFlags::DATA_TERMINAL_READY | McrFlags::REQUEST_TO_SEND | McrFlags::AUX_OUTPUT2)
+                .bits(),
+        );
+        self.write_reg(IER, IerFlags::RX_AVAILABLE.bits());
+    }
+    /// Move every byte waiting in the hardware FIFO into the ring buffer,
+    /// dropping input once the ring is full, then run the SysRq command
+    /// received if there is one.
+    fn drain_fifo(&self) {
+        let mut rx_buffer = self.rx_buffer.exclusive_access();
+        let mut sysrq = None;
+        loop {
+            let lsr = LsrFlags::from_bits_truncate(self.read_reg(LSR));
+            if !lsr.contains(LsrFlags::DATA_AVAILABLE) {
+                break;
+            }
+            let byte = self.read_reg(RBR);
+            if lsr.contains(LsrFlags::BREAK_INTERRUPT) {
+                // the break itself reads as a NUL
+                self.sysrq_armed.store(true, Ordering::Relaxed);
+            } else if self.sysrq_armed.swap(false, Ordering::Relaxed) {
+                sysrq = Some(byte);
+            } else if rx_buffer.len() < RX_BUFFER_SIZE {
+                rx_buffer.push_back(byte);
+            }
+        }
+        drop(rx_buffer);
+        if let Some(key) = sysrq {
+            crate::sysrq::handle(key);
+        }
+    }
+    /// RX interrupt handler
+    pub fn handle_irq(&self) {
+        self.drain_fifo();
+    }
+    /// Take one received byte, if any.
+    ///
+    /// External interrupts are only taken in user mode, so a reader waiting
+    /// inside the kernel also drains the FIFO itself.
+    pub fn read(&self) -> Option<u8> {
+        self.drain_fifo();
+        self.rx_buffer.exclusive_access().pop_front()
+    }
+}
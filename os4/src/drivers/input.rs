@@ -0,0 +1,64 @@
+//! Input devices
+//!
+//! Events of every keyboard and pointer are merged into one queue, read by
+//! user programs through `/dev/input`.
+
+use super::virtio::{InputEvent, VirtIOInput};
+use crate::sync::UPSafeCell;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use lazy_static::*;
+
+/// events kept before new ones are dropped
+const EVENT_QUEUE_SIZE: usize = 256;
+
+pub struct InputHub {
+    devices: UPSafeCell<Vec<VirtIOInput>>,
+    events: UPSafeCell<VecDeque<InputEvent>>,
+}
+
+lazy_static! {
+    /// all input devices of the system
+    pub static ref INPUT: InputHub = InputHub {
+        devices: unsafe { UPSafeCell::new(Vec::new()) },
+        events: unsafe { UPSafeCell::new(VecDeque::with_capacity(EVENT_QUEUE_SIZE)) },
+    };
+}
+
+impl InputHub {
+    pub fn add_device(&self, device: VirtIOInput) {
+        info!("[kernel] input device: {}", device.name());
+        self.devices.exclusive_access().push(device);
+    }
+    pub fn has_devices(&self) -> bool {
+        !self.devices.exclusive_access().is_empty()
+    }
+    /// Move the events delivered by the devices into the queue.
+    fn collect(&self) {
+        let mut devices = self.devices.exclusive_access();
+        let mut events = self.events.exclusive_access();
+        for device in devices.iter_mut() {
+            while let Some(event) = device.pop_event() {
+                if events.len() < EVENT_QUEUE_SIZE {
+                    events.push_back(event);
+                }
+            }
+        }
+    }
+    pub fn handle_irq(&self) {
+        for device in self.devices.exclusive_access().iter() {
+            device.ack_interrupt();
+        }
+        self.collect();
+    }
+    /// Take the oldest queued event. Interrupts are only taken in user
+    /// mode, so the devices are checked here as well.
+    pub fn read_event(&self) -> Option<InputEvent> {
+        self.collect();
+        self.events.exclusive_access().pop_front()
+    }
+    pub fn has_event(&self) -> bool {
+        self.collect();
+        !self.events.exclusive_access().is_empty()
+    }
+}
@@ -0,0 +1,150 @@
+//! Device drivers
+//!
+//! Devices are reached through MMIO regions identity-mapped into kernel
+//! space (see [`crate::board`]). Interrupt-driven devices register a
+//! handler for their PLIC source with [`register_irq_handler`]; external
+//! interrupts are then claimed and dispatched by [`irq_handler`]. The
+//! [`test_finisher`] needs no initialization, so that it works from the
+//! start.
+
+pub mod chardev;
+pub mod input;
+pub mod net;
+mod plic;
+pub mod test_finisher;
+pub mod virtio;
+
+use crate::board::board_info;
+use crate::sync::UPSafeCell;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use chardev::{virtio_console_irq_handler, UART, VIRTIO_CONSOLE};
+use input::INPUT;
+use lazy_static::*;
+use net::{net_irq_handler, NetDevice, NET_DEVICE};
+pub use plic::{Plic, TargetPriority};
+use riscv::register::scause;
+use virtio::{DeviceType, VirtIOConsole, VirtIOGpu, VirtIOInput, VirtIONet};
+
+lazy_static! {
+    /// the PLIC of the platform
+    pub static ref PLIC: Plic = Plic::new(board_info().plic.base);
+    /// irq -> handler of the driver owning that source
+    static ref IRQ_HANDLERS: UPSafeCell<BTreeMap<usize, fn()>> =
+        unsafe { UPSafeCell::new(BTreeMap::new()) };
+    /// the first virtio-gpu found, if any
+    pub static ref GPU_DEVICE: UPSafeCell<Option<VirtIOGpu>> =
+        unsafe { UPSafeCell::new(None) };
+}
+
+/// Route PLIC source `irq` to `handler` and enable it for supervisor mode on
+/// the boot hart, replacing any previous handler.
+pub fn register_irq_handler(irq: usize, handler: fn()) {
+    IRQ_HANDLERS.exclusive_access().insert(irq, handler);
+    PLIC.set_priority(irq, 1);
+    PLIC.enable(board_info().boot_hart, TargetPriority::Supervisor, irq);
+}
+
+/// Stop delivering PLIC source `irq` and forget its handler.
+#[allow(unused)]
+pub fn unregister_irq_handler(irq: usize) {
+    PLIC.disable(board_info().boot_hart, TargetPriority::Supervisor, irq);
+    IRQ_HANDLERS.exclusive_access().remove(&irq);
+}
+
+/// Initialize the devices and enable their interrupts on this hart.
+pub fn init() {
+    let hart = board_info().boot_hart;
+    PLIC.set_threshold(hart, TargetPriority::Machine, 1);
+    PLIC.set_threshold(hart, TargetPriority::Supervisor, 0);
+    if let Some(uart) = UART.as_ref() {
+        uart.init();
+        register_irq_handler(board_info().uart.irq, || {
+            UART.as_ref().unwrap().handle_irq()
+        });
+    }
+    for device in virtio::probe() {
+        match device.device_type {
+            DeviceType::Gpu if GPU_DEVICE.exclusive_access().is_none() => {
+                let gpu = VirtIOGpu::new(device.header);
+                if gpu.is_none() {
+                    warn!("[kernel] virtio-gpu: initialization failed");
+                }
+                *GPU_DEVICE.exclusive_access() = gpu;
+            }
+            DeviceType::Input => match VirtIOInput::new(device.header) {
+                Some(input) => {
+                    INPUT.add_device(input);
+                    register_irq_handler(device.irq, || INPUT.handle_irq());
+                }
+                None => warn!("[kernel] virtio-input: initialization failed"),
+            },
+            DeviceType::Network if NET_DEVICE.exclusive_access().is_none() => {
+                match VirtIONet::new(device.header) {
+                    Some(net) => {
+                        let mac = net.mac();
+                        info!(
+                            "[kernel] virtio-net: MAC {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+                            mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]
+                        );
+                        *NET_DEVICE.exclusive_access() = Some(Box::new(net));
+                        register_irq_handler(device.irq, net_irq_handler);
+                    }
+                    None => warn!("[kernel] virtio-net: initialization failed"),
+                }
+            }
+            DeviceType::Console if VIRTIO_CONSOLE.exclusive_access().is_none() => {
+                match VirtIOConsole::new(device.header) {
+                    Some(console) => {
+                        let ports = console.ports();
+                        *VIRTIO_CONSOLE.exclusive_access() = Some(console);
+                        register_irq_handler(device.irq, virtio_console_irq_handler);
+                        info!("[kernel] virtio-console: {} port(s)", ports);
+                    }
+                    None => warn!("[kernel] virtio-console: initialization failed"),
+                }
+            }
+            device_type => info!("[kernel] ignoring virtio device {:?}", device_type),
+        }
+    }
+}
+
+/// Quiesce the devices before a warm reboot: reset every virtio device, so
+/// that none writes to memory the next boot hands out, and stop delivering
+/// their interrupts. [`init`] sets them up again.
+pub fn shutdown() {
+    let info = board_info();
+    let hart = info.boot_hart;
+    if info.uart.base != 0 {
+        PLIC.disable(hart, TargetPriority::Supervisor, info.uart.irq);
+    }
+    for device in virtio::probe() {
+        device.header.reset();
+        PLIC.disable(hart, TargetPriority::Supervisor, device.irq);
+    }
+}
+
+/// Handle a supervisor external interrupt: claim every pending source and
+/// run its driver's handler.
+pub fn irq_handler() {
+    let hart = board_info().boot_hart;
+    loop {
+        let irq = PLIC.claim(hart, TargetPriority::Supervisor);
+        if irq == 0 {
+            break;
+        }
+        trace_event!(
+            Irq,
+            crate::task::current_task_id(),
+            scause::read().code(),
+            irq
+        );
+        // copy the handler out so that it may register others
+        let handler = IRQ_HANDLERS.exclusive_access().get(&irq).copied();
+        match handler {
+            Some(handler) => handler(),
+            None => warn!("[kernel] unhandled external interrupt {}", irq),
+        }
+        PLIC.complete(hart, TargetPriority::Supervisor, irq);
+    }
+}
@@ -0,0 +1,38 @@
+//! Network devices
+//!
+//! A network stack only sees [`NetDevice`]: a link that sends and receives
+//! whole Ethernet frames.
+
+use crate::sync::UPSafeCell;
+use alloc::boxed::Box;
+use lazy_static::*;
+
+/// A link sending and receiving Ethernet frames
+#[allow(unused)]
+pub trait NetDevice: Send {
+    fn mac(&self) -> [u8; 6];
+    /// Largest frame `send_frame` accepts.
+    fn max_frame_size(&self) -> usize;
+    fn can_send(&mut self) -> bool;
+    fn can_recv(&mut self) -> bool;
+    /// Queue `frame` for transmission, returning false if the link is busy
+    /// or the frame is too large.
+    fn send_frame(&mut self, frame: &[u8]) -> bool;
+    /// Copy the next received frame into `buf`, truncated to its size, and
+    /// return the length of the frame.
+    fn recv_frame(&mut self, buf: &mut [u8]) -> Option<usize>;
+    fn handle_irq(&mut self);
+}
+
+lazy_static! {
+    /// the network device of the system, if any
+    pub static ref NET_DEVICE: UPSafeCell<Option<Box<dyn NetDevice>>> =
+        unsafe { UPSafeCell::new(None) };
+}
+
+/// Interrupt handler of the network device.
+pub fn net_irq_handler() {
+    if let Some(device) = NET_DEVICE.exclusive_access().as_mut() {
+        device.handle_irq();
+    }
+}
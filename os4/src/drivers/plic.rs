@@ -0,0 +1,106 @@
+//! Platform-Level Interrupt Controller
+//!
+//! Every hart has one PLIC context per privilege level able to take
+//! external interrupts. Which contexts a hart owns depends on the board, see
+//! [`Board::plic_context`].
+
+use crate::board::{Board, CurrentBoard};
+
+/// number of interrupt sources the PLIC can address
+const MAX_IRQ: usize = 1024;
+
+#[derive(Copy, Clone, Debug)]
+/// privilege level of a PLIC context
+pub enum TargetPriority {
+    Machine = 0,
+    Supervisor = 1,
+}
+
+impl TargetPriority {
+    pub fn supported_number() -> usize {
+        2
+    }
+}
+
+pub struct Plic {
+    base: usize,
+}
+
+impl Plic {
+    pub fn new(base: usize) -> Self {
+        Self { base }
+    }
+    fn context(hart_id: usize, target_priority: TargetPriority) -> usize {
+        CurrentBoard::plic_context(hart_id, target_priority)
+    }
+    fn priority_ptr(&self, irq: usize) -> *mut u32 {
+        assert!(irq > 0 && irq < MAX_IRQ, "invalid irq {}", irq);
+        (self.base + irq * 4) as *mut u32
+    }
+    fn enable_ptr(
+        &self,
+        hart_id: usize,
+        target_priority: TargetPriority,
+        irq: usize,
+    ) -> (*mut u32, usize) {
+        let context = Self::context(hart_id, target_priority);
+        let ptr = (self.base + 0x2000 + context * 0x80 + irq / 32 * 4) as *mut u32;
+        (ptr, irq % 32)
+    }
+    fn threshold_ptr(&self, hart_id: usize, target_priority: TargetPriority) -> *mut u32 {
+        let context = Self::context(hart_id, target_priority);
+        (self.base + 0x20_0000 + context * 0x1000) as *mut u32
+    }
+    fn claim_complete_ptr(&self, hart_id: usize, target_priority: TargetPriority) -> *mut u32 {
+        let context = Self::context(hart_id, target_priority);
+        (self.base + 0x20_0004 + context * 0x1000) as *mut u32
+    }
+    /// Priority 0 disables the source; higher values win arbitration.
+    pub fn set_priority(&self, irq: usize, priority: u32) {
+        assert!(priority < 8);
+        unsafe {
+            self.priority_ptr(irq).write_volatile(priority);
+        }
+    }
+    #[allow(unused)]
+    pub fn get_priority(&self, irq: usize) -> u32 {
+        unsafe { self.priority_ptr(irq).read_volatile() & 7 }
+    }
+    pub fn enable(&self, hart_id: usize, target_priority: TargetPriority, irq: usize) {
+        let (ptr, shift) = self.enable_ptr(hart_id, target_priority, irq);
+        unsafe {
+            ptr.write_volatile(ptr.read_volatile() | 1 << shift);
+        }
+    }
+    pub fn disable(&self, hart_id: usize, target_priority: TargetPriority, irq: usize) {
+        let (ptr, shift) = self.enable_ptr(hart_id, target_priority, irq);
+        unsafe {
+            ptr.write_volatile(ptr.read_volatile() & !(1 << shift));
+        }
+    }
+    /// Interrupts with a priority not above `threshold` are masked for the
+    /// context.
+    pub fn set_threshold(&self, hart_id: usize, target_priority: TargetPriority, threshold: u32) {
+        assert!(threshold < 8);
+        unsafe {
+            self.threshold_ptr(hart_id, target_priority)
+                .write_volatile(threshold);
+        }
+    }
+    /// Claim the highest-priority pending interrupt of the context, 0 if
+    /// there is none.
+    pub fn claim(&self, hart_id: usize, target_priority: TargetPriority) -> usize {
+        unsafe {
+            self.claim_complete_ptr(hart_id, target_priority)
+                .read_volatile() as usize
+        }
+    }
+    /// Signal that the context finished handling `irq`, so that it can be
+    /// raised again.
+    pub fn complete(&self, hart_id: usize, target_priority: TargetPriority, irq: usize) {
+        unsafe {
+            self.claim_complete_ptr(hart_id, target_priority)
+                .write_volatile(irq as u32);
+        }
+    }
+}
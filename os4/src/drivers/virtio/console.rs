@@ -0,0 +1,254 @@
+//! VirtIO console driver with multiport support
+//!
+//! Port 0 is the interactive console. With `VIRTIO_CONSOLE_F_MULTIPORT` the
+//! device offers more ports, announced on the control queues; port 1 is used
+//! as the kernel log channel. Ports are opened once at initialization.
+
+use super::{VirtIOHeader, VirtQueue};
+use crate::config::PAGE_SIZE;
+use crate::mm::DmaBuffer;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::mem::size_of;
+use core::ops::Range;
+
+const F_MULTIPORT: u32 = 1 << 1;
+
+const QUEUE_SIZE: u16 = 16;
+/// size of one receive buffer; a page holds `QUEUE_SIZE` of them
+const RX_BUFFER_SIZE: usize = PAGE_SIZE / QUEUE_SIZE as usize;
+/// ports driven, the console and the log channel
+const MAX_PORTS: usize = 2;
+/// bytes kept per port before new input is dropped
+const INPUT_BUFFER_SIZE: usize = 256;
+
+const CONTROL_DEVICE_READY: u16 = 0;
+const CONTROL_DEVICE_ADD: u16 = 1;
+const CONTROL_PORT_READY: u16 = 3;
+const CONTROL_PORT_OPEN: u16 = 6;
+
+#[repr(C)]
+struct ConsoleConfig {
+    _cols: u16,
+    _rows: u16,
+    max_nr_ports: u32,
+    _emerg_wr: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct ControlMessage {
+    id: u32,
+    event: u16,
+    value: u16,
+}
+
+/// A receive and a transmit queue with their buffers
+struct Channel {
+    rx: VirtQueue,
+    tx: VirtQueue,
+    rx_buffers: DmaBuffer,
+    tx_buffer: DmaBuffer,
+    /// token of an in-flight receive buffer -> its slot
+    slot_of_token: [u16; QUEUE_SIZE as usize],
+}
+
+impl Channel {
+    /// Set up receive queue `rx_idx` and the transmit queue right after it.
+    fn new(header: &VirtIOHeader, rx_idx: u16) -> Option<Self> {
+        Some(Self {
+            rx: VirtQueue::new(header, rx_idx, QUEUE_SIZE)?,
+            tx: VirtQueue::new(header, rx_idx + 1, QUEUE_SIZE)?,
+            rx_buffers: DmaBuffer::new(PAGE_SIZE)?,
+            tx_buffer: DmaBuffer::new(PAGE_SIZE)?,
+            slot_of_token: [0; QUEUE_SIZE as usize],
+        })
+    }
+    fn rx_slot(slot: u16) -> Range<usize> {
+        let start = slot as usize * RX_BUFFER_SIZE;
+        start..start + RX_BUFFER_SIZE
+    }
+    fn post_rx(&mut self, slot: u16) {
+        let region = self.rx_buffers.region(Self::rx_slot(slot));
+        if let Some(token) = self.rx.add(&[], &[region]) {
+            self.slot_of_token[token as usize] = slot;
+        }
+    }
+    fn post_all_rx(&mut self, header: &VirtIOHeader) {
+        for slot in 0..QUEUE_SIZE {
+            self.post_rx(slot);
+        }
+        self.rx.notify(header);
+    }
+    /// Hand every received chunk to `f`, then give the buffers back.
+    fn receive(&mut self, header: &VirtIOHeader, mut f: impl FnMut(&[u8])) {
+        let mut received = false;
+        while let Some((token, len)) = self.rx.pop_used() {
+            let slot = self.slot_of_token[token as usize];
+            let buf = &self.rx_buffers.as_slice()[Self::rx_slot(slot)];
+            f(&buf[..(len as usize).min(RX_BUFFER_SIZE)]);
+            self.post_rx(slot);
+            received = true;
+        }
+        if received {
+            self.rx.notify(header);
+        }
+    }
+    /// Send `data`, waiting for the device to take each chunk.
+    fn send(&mut self, header: &VirtIOHeader, data: &[u8]) {
+        for chunk in data.chunks(PAGE_SIZE) {
+            self.tx_buffer.as_mut_slice()[..chunk.len()].copy_from_slice(chunk);
+            let region = self.tx_buffer.region(0..chunk.len());
+            self.tx.add_notify_wait_pop(header, &[region], &[]);
+        }
+    }
+}
+
+struct Port {
+    channel: Channel,
+    input: VecDeque<u8>,
+    /// announced by the device and acknowledged
+    ready: bool,
+}
+
+pub struct VirtIOConsole {
+    header: VirtIOHeader,
+    control: Option<Channel>,
+    ports: Vec<Port>,
+}
+
+impl VirtIOConsole {
+    pub fn new(header: VirtIOHeader) -> Option<Self> {
+        let features = header.begin_init(F_MULTIPORT);
+        let multiport = features & F_MULTIPORT != 0;
+        let max_ports = if multiport {
+            let config = header.config::<ConsoleConfig>();
+            unsafe { core::ptr::addr_of!((*config).max_nr_ports).read_volatile() as usize }
+        } else {
+            1
+        };
+        // port 0 uses queues 0 and 1, the control queues are 2 and 3, and
+        // port n > 0 uses queues 2n + 2 and 2n + 3
+        let mut ports = Vec::new();
+        let mut control = None;
+        for id in 0..max_ports.min(MAX_PORTS) {
+            let rx_idx = if id == 0 { 0 } else { 2 * id as u16 + 2 };
+            let channel = match Channel::new(&header, rx_idx) {
+                Some(channel) => channel,
+                None => break,
+            };
+            ports.push(Port {
+                channel,
+                input: VecDeque::with_capacity(INPUT_BUFFER_SIZE),
+                // without multiport, the only port is there from the start
+                ready: !multiport,
+            });
+            if id == 0 && multiport {
+                control = Channel::new(&header, 2);
+                if control.is_none() {
+                    break;
+                }
+            }
+        }
+        if ports.is_empty() || (multiport && control.is_none()) {
+            header.fail();
+            return None;
+        }
+        header.finish_init();
+        let mut console = Self {
+            header,
+            control,
+            ports,
+        };
+        for port in console.ports.iter_mut() {
+            port.channel.post_all_rx(&console.header);
+        }
+        if let Some(control) = console.control.as_mut() {
+            control.post_all_rx(&console.header);
+        }
+        console.send_control(0, CONTROL_DEVICE_READY, 1);
+        console.handle_control();
+        Some(console)
+    }
+    fn send_control(&mut self, id: u32, event: u16, value: u16) {
+        let message = ControlMessage { id, event, value };
+        let bytes = unsafe {
+            core::slice::from_raw_parts(
+                &message as *const ControlMessage as *const u8,
+                size_of::<ControlMessage>(),
+            )
+        };
+        if let Some(control) = self.control.as_mut() {
+            control.send(&self.header, bytes);
+        }
+    }
+    /// Answer the messages on the control queue. The device handles our
+    /// replies synchronously, so their follow-ups are seen in the same call.
+    fn handle_control(&mut self) {
+        loop {
+            let mut messages = Vec::new();
+            if let Some(control) = self.control.as_mut() {
+                control.receive(&self.header, |buf| {
+                    if buf.len() >= size_of::<ControlMessage>() {
+                        messages.push(unsafe {
+                            (buf.as_ptr() as *const ControlMessage).read_unaligned()
+                        });
+                    }
+                });
+            }
+            if messages.is_empty() {
+                break;
+            }
+            for message in messages {
+                let id = message.id as usize;
+                if message.event != CONTROL_DEVICE_ADD {
+                    continue;
+                }
+                if id < self.ports.len() {
+                    self.ports[id].ready = true;
+                    self.send_control(message.id, CONTROL_PORT_READY, 1);
+                    self.send_control(message.id, CONTROL_PORT_OPEN, 1);
+                } else {
+                    self.send_control(message.id, CONTROL_PORT_READY, 0);
+                }
+            }
+        }
+    }
+    /// Number of ports ready for use; port 0 is the console.
+    pub fn ports(&self) -> usize {
+        self.ports.iter().take_while(|port| port.ready).count()
+    }
+    /// Move received data into the input buffers of the ports.
+    fn receive(&mut self) {
+        for port in self.ports.iter_mut() {
+            let input = &mut port.input;
+            port.channel.receive(&self.header, |buf| {
+                for &byte in buf {
+                    if input.len() < INPUT_BUFFER_SIZE {
+                        input.push_back(byte);
+                    }
+                }
+            });
+        }
+    }
+    pub fn handle_irq(&mut self) {
+        self.header.ack_interrupt();
+        self.handle_control();
+        self.receive();
+    }
+    /// Send `data` on `port`, returning false if the port is not ready.
+    pub fn write(&mut self, port: usize, data: &[u8]) -> bool {
+        match self.ports.get_mut(port).filter(|port| port.ready) {
+            Some(port) => {
+                port.channel.send(&self.header, data);
+                true
+            }
+            None => false,
+        }
+    }
+    /// Take one byte received on `port`, if any.
+    pub fn read(&mut self, port: usize) -> Option<u8> {
+        self.receive();
+        self.ports.get_mut(port)?.input.pop_front()
+    }
+}
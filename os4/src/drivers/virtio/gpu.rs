@@ -0,0 +1,300 @@
+//! VirtIO GPU driver, 2D mode only
+//!
+//! One host resource backed by a physically contiguous framebuffer is
+//! attached to scanout 0. Drawing is done directly in the framebuffer;
+//! [`VirtIOGpu::flush`] then copies a rectangle to the host and redisplays
+//! it.
+
+use super::{VirtIOHeader, VirtQueue};
+use crate::config::{FB_MAX_HEIGHT, FB_MAX_WIDTH, PAGE_SIZE};
+use crate::mm::{DmaBuffer, PhysPageNum};
+use core::mem::size_of;
+
+const QUEUE_CONTROL: u16 = 0;
+const QUEUE_SIZE: u16 = 16;
+
+const CMD_GET_DISPLAY_INFO: u32 = 0x0100;
+const CMD_RESOURCE_CREATE_2D: u32 = 0x0101;
+const CMD_SET_SCANOUT: u32 = 0x0103;
+const CMD_RESOURCE_FLUSH: u32 = 0x0104;
+const CMD_TRANSFER_TO_HOST_2D: u32 = 0x0105;
+const CMD_RESOURCE_ATTACH_BACKING: u32 = 0x0106;
+const RESP_OK_NODATA: u32 = 0x1100;
+const RESP_OK_DISPLAY_INFO: u32 = 0x1101;
+
+/// 32 bits per pixel, bytes in memory order B, G, R, A
+const FORMAT_B8G8R8A8_UNORM: u32 = 1;
+const BYTES_PER_PIXEL: usize = 4;
+const MAX_SCANOUTS: usize = 16;
+const RESOURCE_ID: u32 = 0xbabe;
+const SCANOUT_ID: u32 = 0;
+
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct CtrlHeader {
+    hdr_type: u32,
+    flags: u32,
+    fence_id: u64,
+    ctx_id: u32,
+    padding: u32,
+}
+
+impl CtrlHeader {
+    fn with_type(hdr_type: u32) -> Self {
+        Self {
+            hdr_type,
+            ..Default::default()
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct Rect {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct DisplayOne {
+    rect: Rect,
+    enabled: u32,
+    flags: u32,
+}
+
+#[repr(C)]
+struct RespDisplayInfo {
+    header: CtrlHeader,
+    pmodes: [DisplayOne; MAX_SCANOUTS],
+}
+
+#[repr(C)]
+struct ResourceCreate2D {
+    header: CtrlHeader,
+    resource_id: u32,
+    format: u32,
+    width: u32,
+    height: u32,
+}
+
+#[repr(C)]
+struct ResourceAttachBacking {
+    header: CtrlHeader,
+    resource_id: u32,
+    nr_entries: u32,
+    addr: u64,
+    length: u32,
+    padding: u32,
+}
+
+#[repr(C)]
+struct SetScanout {
+    header: CtrlHeader,
+    rect: Rect,
+    scanout_id: u32,
+    resource_id: u32,
+}
+
+#[repr(C)]
+struct TransferToHost2D {
+    header: CtrlHeader,
+    rect: Rect,
+    offset: u64,
+    resource_id: u32,
+    padding: u32,
+}
+
+#[repr(C)]
+struct ResourceFlush {
+    header: CtrlHeader,
+    rect: Rect,
+    resource_id: u32,
+    padding: u32,
+}
+
+/// Framebuffer geometry, as reported to user space
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct FbInfo {
+    pub width: u32,
+    pub height: u32,
+    /// bytes per row
+    pub stride: u32,
+    /// `FORMAT_B8G8R8A8_UNORM`
+    pub format: u32,
+}
+
+pub struct VirtIOGpu {
+    header: VirtIOHeader,
+    control: VirtQueue,
+    /// one page the commands and responses are copied through, since
+    /// callers' buffers may live on kernel stacks that are not identity-mapped
+    dma: DmaBuffer,
+    framebuffer: Option<DmaBuffer>,
+    info: FbInfo,
+}
+
+impl VirtIOGpu {
+    /// Bring up the device and attach a framebuffer to its first scanout.
+    pub fn new(header: VirtIOHeader) -> Option<Self> {
+        header.begin_init(0);
+        let control = match VirtQueue::new(&header, QUEUE_CONTROL, QUEUE_SIZE) {
+            Some(queue) => queue,
+            None => {
+                header.fail();
+                return None;
+            }
+        };
+        header.finish_init();
+        let dma = DmaBuffer::new(PAGE_SIZE)?;
+        let mut gpu = Self {
+            header,
+            control,
+            dma,
+            framebuffer: None,
+            info: FbInfo {
+                width: 0,
+                height: 0,
+                stride: 0,
+                format: FORMAT_B8G8R8A8_UNORM,
+            },
+        };
+        if gpu.setup_framebuffer().is_none() {
+            gpu.header.fail();
+            return None;
+        }
+        Some(gpu)
+    }
+    /// Send `req` and return the response of type `Resp`, or `None` if the
+    /// device did not answer with `expected`.
+    fn request<Req, Resp>(&mut self, req: Req, expected: u32) -> Option<Resp> {
+        assert!(size_of::<Req>() + size_of::<Resp>() <= PAGE_SIZE);
+        let req_size = size_of::<Req>();
+        let resp_range = req_size..req_size + size_of::<Resp>();
+        let page = self.dma.as_mut_slice();
+        unsafe {
+            (page.as_mut_ptr() as *mut Req).write_unaligned(req);
+        }
+        page[resp_range.clone()].fill(0);
+        let inputs = [self.dma.region(0..req_size)];
+        let outputs = [self.dma.region(resp_range.clone())];
+        self.control
+            .add_notify_wait_pop(&self.header, &inputs, &outputs)?;
+        let resp_buf = &self.dma.as_slice()[resp_range];
+        let resp = unsafe { (resp_buf.as_ptr() as *const Resp).read_unaligned() };
+        let header = unsafe { (resp_buf.as_ptr() as *const CtrlHeader).read_unaligned() };
+        if header.hdr_type == expected {
+            Some(resp)
+        } else {
+            warn!(
+                "[kernel] virtio-gpu: command failed with {:#x}",
+                header.hdr_type
+            );
+            None
+        }
+    }
+    fn setup_framebuffer(&mut self) -> Option<()> {
+        let display: RespDisplayInfo = self.request(
+            CtrlHeader::with_type(CMD_GET_DISPLAY_INFO),
+            RESP_OK_DISPLAY_INFO,
+        )?;
+        let mode = display.pmodes[SCANOUT_ID as usize];
+        let width = (mode.rect.width as usize).min(FB_MAX_WIDTH);
+        let height = (mode.rect.height as usize).min(FB_MAX_HEIGHT);
+        if width == 0 || height == 0 {
+            return None;
+        }
+        let size = width * height * BYTES_PER_PIXEL;
+        let framebuffer = DmaBuffer::new(size)?;
+        let framebuffer_addr = framebuffer.paddr().0;
+        self.framebuffer = Some(framebuffer);
+        self.info = FbInfo {
+            width: width as u32,
+            height: height as u32,
+            stride: (width * BYTES_PER_PIXEL) as u32,
+            format: FORMAT_B8G8R8A8_UNORM,
+        };
+        let rect = Rect {
+            x: 0,
+            y: 0,
+            width: width as u32,
+            height: height as u32,
+        };
+        self.request::<_, CtrlHeader>(
+            ResourceCreate2D {
+                header: CtrlHeader::with_type(CMD_RESOURCE_CREATE_2D),
+                resource_id: RESOURCE_ID,
+                format: FORMAT_B8G8R8A8_UNORM,
+                width: width as u32,
+                height: height as u32,
+            },
+            RESP_OK_NODATA,
+        )?;
+        self.request::<_, CtrlHeader>(
+            ResourceAttachBacking {
+                header: CtrlHeader::with_type(CMD_RESOURCE_ATTACH_BACKING),
+                resource_id: RESOURCE_ID,
+                nr_entries: 1,
+                addr: framebuffer_addr as u64,
+                length: size as u32,
+                padding: 0,
+            },
+            RESP_OK_NODATA,
+        )?;
+        self.request::<_, CtrlHeader>(
+            SetScanout {
+                header: CtrlHeader::with_type(CMD_SET_SCANOUT),
+                rect,
+                scanout_id: SCANOUT_ID,
+                resource_id: RESOURCE_ID,
+            },
+            RESP_OK_NODATA,
+        )?;
+        info!("[kernel] virtio-gpu: {}x{} framebuffer", width, height);
+        self.flush(0, 0, width as u32, height as u32)
+    }
+    pub fn info(&self) -> FbInfo {
+        self.info
+    }
+    /// First frame and number of frames of the framebuffer.
+    pub fn framebuffer(&self) -> (PhysPageNum, usize) {
+        let framebuffer = self.framebuffer.as_ref().unwrap();
+        (framebuffer.paddr().floor(), framebuffer.pages())
+    }
+    /// Push the given rectangle of the framebuffer to the display. The
+    /// rectangle is clipped to the screen; `None` if the device refused.
+    pub fn flush(&mut self, x: u32, y: u32, width: u32, height: u32) -> Option<()> {
+        if x >= self.info.width || y >= self.info.height {
+            return Some(());
+        }
+        let rect = Rect {
+            x,
+            y,
+            width: width.min(self.info.width - x),
+            height: height.min(self.info.height - y),
+        };
+        self.request::<_, CtrlHeader>(
+            TransferToHost2D {
+                header: CtrlHeader::with_type(CMD_TRANSFER_TO_HOST_2D),
+                rect,
+                offset: (y * self.info.stride + x * BYTES_PER_PIXEL as u32) as u64,
+                resource_id: RESOURCE_ID,
+                padding: 0,
+            },
+            RESP_OK_NODATA,
+        )?;
+        self.request::<_, CtrlHeader>(
+            ResourceFlush {
+                header: CtrlHeader::with_type(CMD_RESOURCE_FLUSH),
+                rect,
+                resource_id: RESOURCE_ID,
+                padding: 0,
+            },
+            RESP_OK_NODATA,
+        )?;
+        Some(())
+    }
+}
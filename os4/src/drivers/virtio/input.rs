@@ -0,0 +1,118 @@
+//! VirtIO input driver
+//!
+//! The event queue is kept full of one-event buffers; every buffer the
+//! device hands back carries an evdev-style event and is posted again once
+//! it has been read.
+
+use super::{VirtIOHeader, VirtQueue};
+use crate::mm::DmaBuffer;
+use alloc::string::String;
+use core::mem::size_of;
+
+const QUEUE_EVENT: u16 = 0;
+const QUEUE_SIZE: u16 = 32;
+
+/// config select value for the device name
+const CFG_ID_NAME: u8 = 0x01;
+
+#[repr(C)]
+struct InputConfig {
+    select: u8,
+    subsel: u8,
+    size: u8,
+    _reserved: [u8; 5],
+    data: [u8; 128],
+}
+
+/// One input event, layout of `struct virtio_input_event` (and of the
+/// Linux evdev `type`/`code`/`value` triple)
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct InputEvent {
+    pub event_type: u16,
+    pub code: u16,
+    pub value: u32,
+}
+
+pub struct VirtIOInput {
+    header: VirtIOHeader,
+    event_queue: VirtQueue,
+    /// the event buffers, slot `i` at offset `8 * i`
+    buffers: DmaBuffer,
+    /// token of an in-flight buffer -> its slot
+    slot_of_token: [u16; QUEUE_SIZE as usize],
+    name: String,
+}
+
+impl VirtIOInput {
+    pub fn new(header: VirtIOHeader) -> Option<Self> {
+        header.begin_init(0);
+        let event_queue = match VirtQueue::new(&header, QUEUE_EVENT, QUEUE_SIZE) {
+            Some(queue) => queue,
+            None => {
+                header.fail();
+                return None;
+            }
+        };
+        header.finish_init();
+        let buffers = DmaBuffer::new(QUEUE_SIZE as usize * size_of::<InputEvent>())?;
+        let mut input = Self {
+            name: read_name(&header),
+            header,
+            event_queue,
+            buffers,
+            slot_of_token: [0; QUEUE_SIZE as usize],
+        };
+        for slot in 0..QUEUE_SIZE {
+            if !input.post(slot) {
+                break;
+            }
+        }
+        input.event_queue.notify(&input.header);
+        Some(input)
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    /// Hand event buffer `slot` to the device.
+    fn post(&mut self, slot: u16) -> bool {
+        let start = slot as usize * size_of::<InputEvent>();
+        let region = self.buffers.region(start..start + size_of::<InputEvent>());
+        match self.event_queue.add(&[], &[region]) {
+            Some(token) => {
+                self.slot_of_token[token as usize] = slot;
+                true
+            }
+            None => false,
+        }
+    }
+    /// Acknowledge the interrupt of this device, if it raised one.
+    pub fn ack_interrupt(&self) -> bool {
+        self.header.ack_interrupt()
+    }
+    /// Take the oldest event the device has delivered, if any.
+    pub fn pop_event(&mut self) -> Option<InputEvent> {
+        let (token, _) = self.event_queue.pop_used()?;
+        let slot = self.slot_of_token[token as usize];
+        let start = slot as usize * size_of::<InputEvent>();
+        let event = unsafe {
+            (self.buffers.as_slice()[start..].as_ptr() as *const InputEvent).read_volatile()
+        };
+        self.post(slot);
+        self.event_queue.notify(&self.header);
+        Some(event)
+    }
+}
+
+fn read_name(header: &VirtIOHeader) -> String {
+    let config = header.config::<InputConfig>();
+    unsafe {
+        core::ptr::addr_of_mut!((*config).select).write_volatile(CFG_ID_NAME);
+        core::ptr::addr_of_mut!((*config).subsel).write_volatile(0);
+        let size = core::ptr::addr_of!((*config).size).read_volatile() as usize;
+        let data = core::ptr::addr_of!((*config).data) as *const u8;
+        (0..size.min(128))
+            .map(|i| data.add(i).read_volatile() as char)
+            .collect()
+    }
+}
@@ -0,0 +1,195 @@
+//! VirtIO over MMIO
+//!
+//! Only the legacy (version 1) register layout is implemented, which is what
+//! QEMU's virtio-mmio transport exposes by default. Queue rings and device
+//! buffers are handed to the device by physical address, as regions of a
+//! [`crate::mm::DmaBuffer`].
+
+mod console;
+mod gpu;
+mod input;
+mod net;
+mod queue;
+
+use crate::board::board_info;
+use alloc::vec::Vec;
+pub use console::VirtIOConsole;
+pub use gpu::{FbInfo, VirtIOGpu};
+pub use input::{InputEvent, VirtIOInput};
+pub use net::VirtIONet;
+pub use queue::VirtQueue;
+
+/// "virt" in little endian
+const MAGIC_VALUE: u32 = 0x7472_6976;
+/// the legacy interface
+const LEGACY_VERSION: u32 = 1;
+
+const REG_MAGIC_VALUE: usize = 0x000;
+const REG_VERSION: usize = 0x004;
+const REG_DEVICE_ID: usize = 0x008;
+const REG_HOST_FEATURES: usize = 0x010;
+const REG_HOST_FEATURES_SEL: usize = 0x014;
+const REG_GUEST_FEATURES: usize = 0x020;
+const REG_GUEST_FEATURES_SEL: usize = 0x024;
+const REG_GUEST_PAGE_SIZE: usize = 0x028;
+const REG_QUEUE_SEL: usize = 0x030;
+const REG_QUEUE_NUM_MAX: usize = 0x034;
+const REG_QUEUE_NUM: usize = 0x038;
+const REG_QUEUE_ALIGN: usize = 0x03c;
+const REG_QUEUE_PFN: usize = 0x040;
+const REG_QUEUE_NOTIFY: usize = 0x050;
+const REG_INTERRUPT_STATUS: usize = 0x060;
+const REG_INTERRUPT_ACK: usize = 0x064;
+const REG_STATUS: usize = 0x070;
+const REG_CONFIG: usize = 0x100;
+
+bitflags! {
+    /// device status register
+    struct DeviceStatus: u32 {
+        const ACKNOWLEDGE = 1;
+        const DRIVER = 2;
+        const DRIVER_OK = 4;
+        const FAILED = 128;
+    }
+}
+
+/// Device types, as reported in the DeviceID register
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum DeviceType {
+    Network,
+    Block,
+    Console,
+    Gpu,
+    Input,
+    Other(u32),
+}
+
+impl From<u32> for DeviceType {
+    fn from(id: u32) -> Self {
+        match id {
+            1 => Self::Network,
+            2 => Self::Block,
+            3 => Self::Console,
+            16 => Self::Gpu,
+            18 => Self::Input,
+            id => Self::Other(id),
+        }
+    }
+}
+
+/// Registers of one virtio-mmio device
+pub struct VirtIOHeader {
+    base: usize,
+}
+
+impl VirtIOHeader {
+    /// # Safety
+    ///
+    /// `base` must be the identity-mapped address of a virtio-mmio slot.
+    pub unsafe fn new(base: usize) -> Self {
+        Self { base }
+    }
+    fn read(&self, reg: usize) -> u32 {
+        unsafe { ((self.base + reg) as *const u32).read_volatile() }
+    }
+    fn write(&self, reg: usize, value: u32) {
+        unsafe {
+            ((self.base + reg) as *mut u32).write_volatile(value);
+        }
+    }
+    /// Whether a legacy device is plugged into this slot.
+    pub fn is_present(&self) -> bool {
+        self.read(REG_MAGIC_VALUE) == MAGIC_VALUE
+            && self.read(REG_VERSION) == LEGACY_VERSION
+            && self.read(REG_DEVICE_ID) != 0
+    }
+    pub fn device_type(&self) -> DeviceType {
+        self.read(REG_DEVICE_ID).into()
+    }
+    /// Reset the device and negotiate features: the driver accepts the
+    /// offered features in `supported` (only the low 32 bits exist for
+    /// legacy devices) and gets back what was accepted.
+    pub fn begin_init(&self, supported: u32) -> u32 {
+        self.write(REG_STATUS, 0);
+        self.write(REG_STATUS, DeviceStatus::ACKNOWLEDGE.bits());
+        self.write(
+            REG_STATUS,
+            (DeviceStatus::ACKNOWLEDGE | DeviceStatus::DRIVER).bits(),
+        );
+        self.write(REG_HOST_FEATURES_SEL, 0);
+        let features = self.read(REG_HOST_FEATURES) & supported;
+        self.write(REG_GUEST_FEATURES_SEL, 0);
+        self.write(REG_GUEST_FEATURES, features);
+        self.write(REG_GUEST_PAGE_SIZE, crate::config::PAGE_SIZE as u32);
+        features
+    }
+    /// Tell the device the driver is ready; queues must be set up by now.
+    pub fn finish_init(&self) {
+        self.write(
+            REG_STATUS,
+            (DeviceStatus::ACKNOWLEDGE | DeviceStatus::DRIVER | DeviceStatus::DRIVER_OK).bits(),
+        );
+    }
+    /// Reset the device, which stops it using the queues it was given.
+    pub fn reset(&self) {
+        self.write(REG_STATUS, 0);
+    }
+    /// Mark the device unusable after a failed initialization.
+    pub fn fail(&self) {
+        self.write(REG_STATUS, DeviceStatus::FAILED.bits());
+    }
+    /// Largest size the device accepts for `queue`, 0 if it does not exist.
+    fn queue_max_size(&self, queue: u16) -> u16 {
+        self.write(REG_QUEUE_SEL, queue as u32);
+        self.read(REG_QUEUE_NUM_MAX) as u16
+    }
+    /// Hand the rings of `queue` to the device.
+    fn queue_set(&self, queue: u16, size: u16, align: u32, pfn: u32) {
+        self.write(REG_QUEUE_SEL, queue as u32);
+        self.write(REG_QUEUE_NUM, size as u32);
+        self.write(REG_QUEUE_ALIGN, align);
+        self.write(REG_QUEUE_PFN, pfn);
+    }
+    fn queue_notify(&self, queue: u16) {
+        self.write(REG_QUEUE_NOTIFY, queue as u32);
+    }
+    /// Acknowledge a device interrupt, returning whether one was pending.
+    pub fn ack_interrupt(&self) -> bool {
+        let status = self.read(REG_INTERRUPT_STATUS);
+        if status != 0 {
+            self.write(REG_INTERRUPT_ACK, status);
+        }
+        status != 0
+    }
+    /// Device-specific configuration space.
+    pub fn config<T>(&self) -> *mut T {
+        (self.base + REG_CONFIG) as *mut T
+    }
+}
+
+/// A device found while probing the virtio-mmio slots
+pub struct ProbedDevice {
+    pub header: VirtIOHeader,
+    pub device_type: DeviceType,
+    pub irq: usize,
+}
+
+/// Find the devices plugged into the virtio-mmio slots of the board.
+pub fn probe() -> Vec<ProbedDevice> {
+    board_info()
+        .virtio_devices()
+        .iter()
+        .filter_map(|slot| {
+            let header = unsafe { VirtIOHeader::new(slot.base) };
+            if !header.is_present() {
+                return None;
+            }
+            let device_type = header.device_type();
+            Some(ProbedDevice {
+                header,
+                device_type,
+                irq: slot.irq,
+            })
+        })
+        .collect()
+}
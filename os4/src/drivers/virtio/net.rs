@@ -0,0 +1,178 @@
+//! VirtIO network driver
+//!
+//! Both queues use fixed slots of [`BUFFER_SIZE`] bytes, each holding the
+//! legacy `virtio_net_hdr` followed by one Ethernet frame. Receive slots are
+//! all posted up front and reposted as soon as their frame is read.
+
+use super::{VirtIOHeader, VirtQueue};
+use crate::drivers::net::NetDevice;
+use crate::mm::{DmaBuffer, DmaRegion};
+use alloc::vec::Vec;
+
+const QUEUE_RECEIVE: u16 = 0;
+const QUEUE_TRANSMIT: u16 = 1;
+const QUEUE_SIZE: u16 = 16;
+
+/// the device reports its MAC address in the config space
+const F_MAC: u32 = 1 << 5;
+
+/// `virtio_net_hdr` without `num_buffers`, as mergeable buffers are not
+/// negotiated
+const HEADER_SIZE: usize = 10;
+/// header plus the largest Ethernet frame, rounded up
+const BUFFER_SIZE: usize = 2048;
+/// largest frame that fits in a slot
+const MAX_FRAME_SIZE: usize = BUFFER_SIZE - HEADER_SIZE;
+
+#[repr(C)]
+struct NetConfig {
+    mac: [u8; 6],
+    _status: u16,
+}
+
+/// Fixed-size buffers carved out of one DMA buffer
+struct Slots {
+    buffer: DmaBuffer,
+    /// token of an in-flight slot -> slot
+    slot_of_token: [u16; QUEUE_SIZE as usize],
+}
+
+impl Slots {
+    fn new() -> Option<Self> {
+        Some(Self {
+            buffer: DmaBuffer::new(QUEUE_SIZE as usize * BUFFER_SIZE)?,
+            slot_of_token: [0; QUEUE_SIZE as usize],
+        })
+    }
+    fn get(&self, slot: u16) -> &[u8] {
+        let start = slot as usize * BUFFER_SIZE;
+        &self.buffer.as_slice()[start..start + BUFFER_SIZE]
+    }
+    fn get_mut(&mut self, slot: u16) -> &mut [u8] {
+        let start = slot as usize * BUFFER_SIZE;
+        &mut self.buffer.as_mut_slice()[start..start + BUFFER_SIZE]
+    }
+    /// The first `len` bytes of `slot`, for the device.
+    fn region(&self, slot: u16, len: usize) -> DmaRegion {
+        let start = slot as usize * BUFFER_SIZE;
+        self.buffer.region(start..start + len)
+    }
+}
+
+pub struct VirtIONet {
+    header: VirtIOHeader,
+    mac: [u8; 6],
+    rx_queue: VirtQueue,
+    tx_queue: VirtQueue,
+    rx_slots: Slots,
+    tx_slots: Slots,
+    /// transmit slots not in flight
+    tx_free: Vec<u16>,
+}
+
+impl VirtIONet {
+    pub fn new(header: VirtIOHeader) -> Option<Self> {
+        let features = header.begin_init(F_MAC);
+        let queues = VirtQueue::new(&header, QUEUE_RECEIVE, QUEUE_SIZE).zip(VirtQueue::new(
+            &header,
+            QUEUE_TRANSMIT,
+            QUEUE_SIZE,
+        ));
+        let slots = Slots::new().zip(Slots::new());
+        let ((rx_queue, tx_queue), (rx_slots, tx_slots)) = match queues.zip(slots) {
+            Some(parts) => parts,
+            None => {
+                header.fail();
+                return None;
+            }
+        };
+        header.finish_init();
+        let mac = if features & F_MAC != 0 {
+            let config = header.config::<NetConfig>();
+            let mut mac = [0u8; 6];
+            for (i, byte) in mac.iter_mut().enumerate() {
+                *byte = unsafe {
+                    (core::ptr::addr_of!((*config).mac) as *const u8)
+                        .add(i)
+                        .read_volatile()
+                };
+            }
+            mac
+        } else {
+            // locally administered fallback
+            [0x52, 0x54, 0x00, 0x12, 0x34, 0x56]
+        };
+        let mut net = Self {
+            header,
+            mac,
+            rx_queue,
+            tx_queue,
+            rx_slots,
+            tx_slots,
+            tx_free: (0..QUEUE_SIZE).rev().collect(),
+        };
+        for slot in 0..QUEUE_SIZE {
+            net.post_rx(slot);
+        }
+        net.rx_queue.notify(&net.header);
+        Some(net)
+    }
+    fn post_rx(&mut self, slot: u16) {
+        let region = self.rx_slots.region(slot, BUFFER_SIZE);
+        if let Some(token) = self.rx_queue.add(&[], &[region]) {
+            self.rx_slots.slot_of_token[token as usize] = slot;
+        }
+    }
+    /// Take back the transmit slots the device is done with.
+    fn reclaim_tx(&mut self) {
+        while let Some((token, _)) = self.tx_queue.pop_used() {
+            self.tx_free
+                .push(self.tx_slots.slot_of_token[token as usize]);
+        }
+    }
+}
+
+impl NetDevice for VirtIONet {
+    fn mac(&self) -> [u8; 6] {
+        self.mac
+    }
+    fn max_frame_size(&self) -> usize {
+        MAX_FRAME_SIZE
+    }
+    fn can_send(&mut self) -> bool {
+        self.reclaim_tx();
+        !self.tx_free.is_empty()
+    }
+    fn can_recv(&mut self) -> bool {
+        self.rx_queue.can_pop()
+    }
+    fn send_frame(&mut self, frame: &[u8]) -> bool {
+        if frame.len() > MAX_FRAME_SIZE || !self.can_send() {
+            return false;
+        }
+        let slot = self.tx_free.pop().unwrap();
+        let buf = self.tx_slots.get_mut(slot);
+        buf[..HEADER_SIZE].fill(0);
+        buf[HEADER_SIZE..HEADER_SIZE + frame.len()].copy_from_slice(frame);
+        let region = self.tx_slots.region(slot, HEADER_SIZE + frame.len());
+        let token = self.tx_queue.add(&[region], &[]).unwrap();
+        self.tx_slots.slot_of_token[token as usize] = slot;
+        self.tx_queue.notify(&self.header);
+        true
+    }
+    fn recv_frame(&mut self, buf: &mut [u8]) -> Option<usize> {
+        let (token, len) = self.rx_queue.pop_used()?;
+        let slot = self.rx_slots.slot_of_token[token as usize];
+        let len = (len as usize).saturating_sub(HEADER_SIZE);
+        let copied = len.min(buf.len());
+        buf[..copied].copy_from_slice(&self.rx_slots.get(slot)[HEADER_SIZE..HEADER_SIZE + copied]);
+        self.post_rx(slot);
+        self.rx_queue.notify(&self.header);
+        Some(len)
+    }
+    /// Acknowledge the interrupt of the device and reclaim sent buffers.
+    fn handle_irq(&mut self) {
+        self.header.ack_interrupt();
+        self.reclaim_tx();
+    }
+}
@@ -0,0 +1,189 @@
+//! Split virtqueue in the legacy layout
+//!
+//! The descriptor table and available ring share the first pages of the
+//! queue; the used ring starts on the next page boundary.
+
+use super::VirtIOHeader;
+use crate::config::PAGE_SIZE;
+use crate::mm::{DmaBuffer, DmaRegion};
+use core::sync::atomic::{fence, Ordering};
+
+bitflags! {
+    struct DescFlags: u16 {
+        const NEXT = 1;
+        const WRITE = 2;
+    }
+}
+
+#[repr(C)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+struct UsedElem {
+    id: u32,
+    len: u32,
+}
+
+fn align_up(size: usize) -> usize {
+    (size + PAGE_SIZE - 1) & !(PAGE_SIZE - 1)
+}
+
+pub struct VirtQueue {
+    /// memory holding the rings, kept alive as long as the queue
+    _rings: DmaBuffer,
+    /// kernel addresses of the three parts
+    desc: usize,
+    avail: usize,
+    used: usize,
+    queue_idx: u16,
+    size: u16,
+    /// head of the chain of free descriptors
+    free_head: u16,
+    num_free: u16,
+    /// next slot of the available ring the driver fills
+    avail_idx: u16,
+    /// next slot of the used ring the driver has not seen yet
+    last_used_idx: u16,
+}
+
+impl VirtQueue {
+    /// Allocate queue `idx` with at most `size` entries and give it to the
+    /// device. Returns `None` if the device has no such queue or memory is
+    /// short.
+    pub fn new(header: &VirtIOHeader, idx: u16, size: u16) -> Option<Self> {
+        let size = size.min(header.queue_max_size(idx));
+        if size == 0 || !size.is_power_of_two() {
+            return None;
+        }
+        let n = size as usize;
+        let driver_part = align_up(16 * n + 6 + 2 * n);
+        let device_part = align_up(6 + 8 * n);
+        let rings = DmaBuffer::new(driver_part + device_part)?;
+        let desc = rings.vaddr().0;
+        let pfn = rings.paddr().0 / PAGE_SIZE;
+        let queue = Self {
+            _rings: rings,
+            desc,
+            avail: desc + 16 * n,
+            used: desc + driver_part,
+            queue_idx: idx,
+            size,
+            free_head: 0,
+            num_free: size,
+            avail_idx: 0,
+            last_used_idx: 0,
+        };
+        for i in 0..size - 1 {
+            unsafe { (*queue.desc_at(i)).next = i + 1 };
+        }
+        header.queue_set(idx, size, PAGE_SIZE as u32, pfn as u32);
+        Some(queue)
+    }
+    fn desc_at(&self, i: u16) -> *mut Descriptor {
+        (self.desc + 16 * i as usize) as *mut Descriptor
+    }
+    fn avail_idx_ptr(&self) -> *mut u16 {
+        (self.avail + 2) as *mut u16
+    }
+    fn avail_ring(&self, slot: u16) -> *mut u16 {
+        (self.avail + 4 + 2 * slot as usize) as *mut u16
+    }
+    fn used_idx_ptr(&self) -> *const u16 {
+        (self.used + 2) as *const u16
+    }
+    fn used_ring(&self, slot: u16) -> *const UsedElem {
+        (self.used + 4 + 8 * slot as usize) as *const UsedElem
+    }
+    /// Post a request made of device-readable `inputs` followed by
+    /// device-writable `outputs`, returning the token identifying it, or
+    /// `None` if the queue is full. The device is not notified.
+    pub fn add(&mut self, inputs: &[DmaRegion], outputs: &[DmaRegion]) -> Option<u16> {
+        let count = inputs.len() + outputs.len();
+        if count == 0 || count > self.num_free as usize {
+            return None;
+        }
+        let head = self.free_head;
+        let mut last = head;
+        let buffers = inputs
+            .iter()
+            .map(|region| (region, DescFlags::empty()))
+            .chain(outputs.iter().map(|region| (region, DescFlags::WRITE)));
+        for (region, flags) in buffers {
+            let desc = unsafe { &mut *self.desc_at(self.free_head) };
+            desc.addr = region.paddr.0 as u64;
+            desc.len = region.len as u32;
+            desc.flags = (flags | DescFlags::NEXT).bits();
+            last = self.free_head;
+            self.free_head = desc.next;
+        }
+        unsafe { (*self.desc_at(last)).flags &= !DescFlags::NEXT.bits() };
+        self.num_free -= count as u16;
+        unsafe {
+            self.avail_ring(self.avail_idx % self.size)
+                .write_volatile(head);
+        }
+        // the entry must be visible before the index that publishes it
+        fence(Ordering::SeqCst);
+        self.avail_idx = self.avail_idx.wrapping_add(1);
+        unsafe { self.avail_idx_ptr().write_volatile(self.avail_idx) };
+        Some(head)
+    }
+    pub fn notify(&self, header: &VirtIOHeader) {
+        fence(Ordering::SeqCst);
+        header.queue_notify(self.queue_idx);
+    }
+    /// Whether the device has finished a request we have not popped yet.
+    pub fn can_pop(&self) -> bool {
+        fence(Ordering::SeqCst);
+        self.last_used_idx != unsafe { self.used_idx_ptr().read_volatile() }
+    }
+    /// Take a finished request, returning its token and the number of bytes
+    /// the device wrote, and recycle its descriptors.
+    pub fn pop_used(&mut self) -> Option<(u16, u32)> {
+        if !self.can_pop() {
+            return None;
+        }
+        let elem = unsafe {
+            self.used_ring(self.last_used_idx % self.size)
+                .read_volatile()
+        };
+        self.last_used_idx = self.last_used_idx.wrapping_add(1);
+        let head = elem.id as u16;
+        let mut tail = head;
+        loop {
+            self.num_free += 1;
+            let desc = unsafe { &*self.desc_at(tail) };
+            if desc.flags & DescFlags::NEXT.bits() == 0 {
+                break;
+            }
+            tail = desc.next;
+        }
+        unsafe { (*self.desc_at(tail)).next = self.free_head };
+        self.free_head = head;
+        Some((head, elem.len))
+    }
+    /// Post a request, notify the device and spin until it is done,
+    /// returning the number of bytes written by the device.
+    pub fn add_notify_wait_pop(
+        &mut self,
+        header: &VirtIOHeader,
+        inputs: &[DmaRegion],
+        outputs: &[DmaRegion],
+    ) -> Option<u32> {
+        let token = self.add(inputs, outputs)?;
+        self.notify(header);
+        loop {
+            if let Some((used, len)) = self.pop_used() {
+                assert_eq!(used, token, "virtqueue completed out of order");
+                return Some(len);
+            }
+            crate::watchdog::check();
+            core::hint::spin_loop();
+        }
+    }
+}
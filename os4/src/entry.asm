@@ -1,12 +1,46 @@
     .section .text.entry
     .globl _start
 _start:
-    la sp, boot_stack_top
+    # a0 (hart id) and a1 (device tree) are kept for rust_main.
+    # The kernel is linked at BASE_ADDRESS but may be loaded anywhere:
+    # first add the load offset to every address stored in the image, as
+    # recorded by its R_RISCV_RELATIVE relocations. Until then nothing may
+    # be read through the GOT, hence lla rather than la.
+    lla t0, skernel
+    LOAD t1, link_base
+    sub t0, t0, t1
+    lla t1, __rela_dyn_start
+    lla t2, __rela_dyn_end
+1:
+    bgeu t1, t2, 3f
+    # Elf_Rela: r_offset, r_info, r_addend
+    LOAD t3, 1*REGBYTES(t1)
+    li t4, 3
+    bne t3, t4, 2f
+    LOAD t3, 0*REGBYTES(t1)
+    LOAD t4, 2*REGBYTES(t1)
+    add t3, t3, t0
+    add t4, t4, t0
+    STORE t4, 0(t3)
+2:
+    addi t1, t1, 3*REGBYTES
+    j 1b
+3:
+    lla sp, boot_stack_top
     call rust_main
 
+    # an absolute symbol, so the linker resolves it with no relocation
+    .balign REGBYTES
+link_base:
+    .if REGBYTES == 8
+    .quad BASE_ADDRESS
+    .else
+    .word BASE_ADDRESS
+    .endif
+
     .section .bss.stack
     .globl boot_stack
 boot_stack:
     .space 4096 * 16
     .globl boot_stack_top
-boot_stack_top:
\ No newline at end of file
+boot_stack_top:
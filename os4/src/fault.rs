@@ -0,0 +1,202 @@
+//! Fault injection, built with the `fault-inject` feature
+//!
+//! Makes frame allocations, heap allocations or file reads fail on purpose,
+//! so that tests can check that the paths handling those failures unwind
+//! cleanly: `mmap` returning -1, a lazy page fault killing the task, a read
+//! returning -1. Each [`FaultPoint`] is set up with `sys_fault_inject` as
+//! in Linux fault injection: after letting `skip` calls through, each call
+//! fails with a `probability` in thousandths, up to `times` failures. One
+//! failing on the 5th call only is skip 4, probability 1000, times 1.
+//! Failures may be restricted to the calls made while one task runs.
+//!
+//! Nothing is checked for the callers, so that the tests also find the
+//! paths that do not handle the failure: most kernel code takes frames and
+//! heap for granted, and panics when it gets none, as heap allocations do
+//! outside of `try_reserve`.
+//!
+//! The state is atomics only, as the heap allocator consults it.
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::null_mut;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// What can be made to fail
+#[repr(usize)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FaultPoint {
+    /// `frame_alloc`, returning None
+    Frame = 0,
+    /// the global allocator, returning null
+    Heap = 1,
+    /// `sys_read` of any file, returning -1, as there is no block device
+    Read = 2,
+}
+
+impl FaultPoint {
+    pub fn from_usize(point: usize) -> Option<Self> {
+        match point {
+            0 => Some(Self::Frame),
+            1 => Some(Self::Heap),
+            2 => Some(Self::Read),
+            _ => None,
+        }
+    }
+}
+
+/// `times` and `pid` meaning no limit and any task
+const ANY: usize = usize::MAX;
+
+/// How a point fails, as `sys_fault_inject` takes it
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct FaultConfig {
+    /// in thousandths, 0 never failing
+    pub probability: u32,
+    /// calls let through before failures start
+    pub skip: u32,
+    /// failures injected at most, -1 for no limit
+    pub times: i32,
+    /// the task whose calls fail, -1 for any, the kernel's own included
+    pub pid: i32,
+}
+
+struct Point {
+    probability: AtomicUsize,
+    skip: AtomicUsize,
+    times: AtomicUsize,
+    pid: AtomicUsize,
+    /// failures injected so far
+    injected: AtomicUsize,
+}
+
+impl Point {
+    const fn new() -> Self {
+        Self {
+            probability: AtomicUsize::new(0),
+            skip: AtomicUsize::new(0),
+            times: AtomicUsize::new(0),
+            pid: AtomicUsize::new(ANY),
+            injected: AtomicUsize::new(0),
+        }
+    }
+}
+
+/// indexed by [`FaultPoint`]
+static POINTS: [Point; 3] = [Point::new(), Point::new(), Point::new()];
+/// xorshift state, seeded when a point is set up
+static RNG: AtomicUsize = AtomicUsize::new(1);
+/// the task running now, `ANY` before the first one
+static RUNNING_TASK: AtomicUsize = AtomicUsize::new(ANY);
+
+/// Task switch path: `next` is about to run.
+pub fn on_switch(next: usize) {
+    RUNNING_TASK.store(next, Ordering::Relaxed);
+}
+
+/// Reaping path: task `pid` is gone, disarming the points aimed at it.
+pub fn on_reap(pid: usize) {
+    for state in POINTS.iter() {
+        if state.pid.load(Ordering::Relaxed) == pid {
+            state.probability.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+fn random() -> usize {
+    let mut x = RNG.load(Ordering::Relaxed);
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    RNG.store(x, Ordering::Relaxed);
+    x
+}
+
+/// Whether this call at `point` is to fail.
+pub fn should_fail(point: FaultPoint) -> bool {
+    let state = &POINTS[point as usize];
+    let probability = state.probability.load(Ordering::Relaxed);
+    if probability == 0 {
+        return false;
+    }
+    let pid = state.pid.load(Ordering::Relaxed);
+    if pid != ANY && pid != RUNNING_TASK.load(Ordering::Relaxed) {
+        return false;
+    }
+    if state
+        .skip
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |skip| {
+            skip.checked_sub(1)
+        })
+        .is_ok()
+    {
+        return false;
+    }
+    if probability < 1000 && random() % 1000 >= probability {
+        return false;
+    }
+    let failed = state
+        .times
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |times| match times {
+            ANY => Some(ANY),
+            times => times.checked_sub(1),
+        })
+        .is_ok();
+    if failed {
+        state.injected.fetch_add(1, Ordering::Relaxed);
+    }
+    failed
+}
+
+/// Set up how `point` fails, returning the failures it injected so far. A
+/// probability above 1000 is taken as 1000.
+pub fn configure(point: FaultPoint, config: FaultConfig) -> usize {
+    let state = &POINTS[point as usize];
+    // off while the rest changes
+    state.probability.store(0, Ordering::Relaxed);
+    let mut seed = [0; core::mem::size_of::<usize>()];
+    crate::random::fill_random(&mut seed);
+    // xorshift never leaves 0
+    RNG.store(usize::from_ne_bytes(seed) | 1, Ordering::Relaxed);
+    state.skip.store(config.skip as usize, Ordering::Relaxed);
+    state.times.store(
+        if config.times < 0 {
+            ANY
+        } else {
+            config.times as usize
+        },
+        Ordering::Relaxed,
+    );
+    state.pid.store(
+        if config.pid < 0 {
+            ANY
+        } else {
+            config.pid as usize
+        },
+        Ordering::Relaxed,
+    );
+    let injected = state.injected.swap(0, Ordering::Relaxed);
+    state
+        .probability
+        .store(config.probability.min(1000) as usize, Ordering::Relaxed);
+    injected
+}
+
+/// The failures `point` injected since it was last set up.
+pub fn injected(point: FaultPoint) -> usize {
+    POINTS[point as usize].injected.load(Ordering::Relaxed)
+}
+
+/// A global allocator failing as [`FaultPoint::Heap`] says
+pub struct FaultyAlloc(pub &'static (dyn GlobalAlloc + Sync));
+
+unsafe impl GlobalAlloc for FaultyAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if should_fail(FaultPoint::Heap) {
+            return null_mut();
+        }
+        self.0.alloc(layout)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.0.dealloc(ptr, layout)
+    }
+}
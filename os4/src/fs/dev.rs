@@ -0,0 +1,32 @@
+//! Device files
+//!
+//! There is no filesystem yet: besides the `/proc` files, the only paths
+//! that can be opened are the device files below, looked up by name.
+
+use super::{File, FileMode, InputEvents, Urandom};
+use crate::drivers::input::INPUT;
+use alloc::sync::Arc;
+
+/// Owner and mode of the device file at `path`. Only root may inject
+/// input events.
+pub fn device_mode(path: &str) -> Option<FileMode> {
+    let mode = match path {
+        "/dev/input" => 0o644,
+        "/dev/urandom" => 0o666,
+        _ => return None,
+    };
+    Some(FileMode {
+        uid: 0,
+        gid: 0,
+        mode,
+    })
+}
+
+/// Open the device file at `path`, if such a device is present.
+pub fn open_device(path: &str) -> Option<Arc<dyn File + Send + Sync>> {
+    match path {
+        "/dev/input" if INPUT.has_devices() => Some(Arc::new(InputEvents::new())),
+        "/dev/urandom" => Some(Arc::new(Urandom::new())),
+        _ => None,
+    }
+}
@@ -0,0 +1,97 @@
+//! `/dev/input`: the merged event queue of the input devices
+
+use super::{File, PollEvents};
+use crate::drivers::input::INPUT;
+use crate::drivers::virtio::InputEvent;
+use crate::mm::UserBuffer;
+use crate::task::{current_signal_interrupted, WaitQueue};
+use core::mem::size_of;
+use core::sync::atomic::{AtomicBool, Ordering};
+use lazy_static::*;
+
+lazy_static! {
+    /// tasks waiting for an input event
+    static ref READERS: WaitQueue = WaitQueue::new();
+}
+
+/// Device interrupt: wake the readers if an event came.
+pub fn input_interrupt() {
+    if !READERS.is_empty() && INPUT.has_event() {
+        READERS.wake_all();
+    }
+}
+
+/// Whether a task waits for an input event, which only an interrupt brings.
+pub fn input_has_readers() -> bool {
+    !READERS.is_empty()
+}
+
+/// Reads yield whole [`InputEvent`]s, 8 bytes each
+pub struct InputEvents {
+    nonblocking: AtomicBool,
+}
+
+impl InputEvents {
+    pub fn new() -> Self {
+        Self {
+            nonblocking: AtomicBool::new(false),
+        }
+    }
+}
+
+impl File for InputEvents {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        false
+    }
+    /// Wait for an event unless non-blocking, then return as many queued
+    /// events as fit in `user_buf`. A buffer smaller than one event reads
+    /// nothing.
+    fn read(&self, user_buf: UserBuffer) -> usize {
+        let capacity = user_buf.len() / size_of::<InputEvent>();
+        let mut events = alloc::vec::Vec::with_capacity(capacity);
+        while events.len() < capacity {
+            match INPUT.read_event() {
+                Some(event) => events.push(event),
+                None if !events.is_empty()
+                    || self.nonblocking()
+                    || current_signal_interrupted() =>
+                {
+                    break
+                }
+                None => READERS.block_current_and_run_next(),
+            }
+        }
+        let bytes = unsafe {
+            core::slice::from_raw_parts(
+                events.as_ptr() as *const u8,
+                events.len() * size_of::<InputEvent>(),
+            )
+        };
+        for (byte_ref, byte) in user_buf.into_iter().zip(bytes.iter()) {
+            unsafe {
+                byte_ref.write_volatile(*byte);
+            }
+        }
+        bytes.len()
+    }
+    fn write(&self, _user_buf: UserBuffer) -> usize {
+        0
+    }
+    fn poll(&self, events: PollEvents) -> PollEvents {
+        if INPUT.has_event() {
+            events & PollEvents::POLLIN
+        } else {
+            PollEvents::empty()
+        }
+    }
+    fn nonblocking(&self) -> bool {
+        self.nonblocking.load(Ordering::Relaxed)
+    }
+    fn set_nonblocking(&self, nonblocking: bool) -> bool {
+        self.nonblocking.store(nonblocking, Ordering::Relaxed);
+        true
+    }
+}
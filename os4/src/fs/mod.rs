@@ -0,0 +1,265 @@
+//! File abstractions
+//!
+//! Every resource a task can reach through a file descriptor implements
+//! [`File`]. Each task keeps its own descriptor table in its
+//! [`crate::task::TaskControlBlock`], with stdin, stdout and stderr opened at
+//! creation.
+
+mod dev;
+mod input;
+mod pipe;
+mod pressure;
+mod proc;
+mod signalfd;
+mod stdio;
+mod tcp;
+mod tty;
+mod udp;
+mod urandom;
+
+use crate::mm::UserBuffer;
+use crate::task::{block_until, current_signal_interrupted, WaitQueue};
+use crate::timer::{deadline_after_ms, get_time};
+use alloc::string::String;
+use alloc::vec::Vec;
+use lazy_static::*;
+
+/// The common abstraction of all IO resources
+pub trait File: Send + Sync {
+    fn readable(&self) -> bool;
+    fn writable(&self) -> bool;
+    fn read(&self, buf: UserBuffer) -> usize;
+    fn write(&self, buf: UserBuffer) -> usize;
+    /// Report which of `events` can be satisfied right now without blocking.
+    fn poll(&self, events: PollEvents) -> PollEvents {
+        let mut ready = PollEvents::empty();
+        if self.readable() {
+            ready |= PollEvents::POLLIN;
+        }
+        if self.writable() {
+            ready |= PollEvents::POLLOUT;
+        }
+        ready & events
+    }
+    /// Whether reads and writes fail instead of waiting, see
+    /// [`wait_ready`].
+    fn nonblocking(&self) -> bool {
+        false
+    }
+    /// Switch non-blocking mode. Returns false if the file has no such
+    /// mode.
+    fn set_nonblocking(&self, _nonblocking: bool) -> bool {
+        false
+    }
+    /// How long, in milliseconds, a read may wait before failing.
+    fn recv_timeout(&self) -> Option<usize> {
+        None
+    }
+    /// The queue woken when the file may have become ready, for
+    /// [`wait_ready`]. Without one, the pollers' queue is waited on.
+    fn wait_queue(&self) -> Option<&WaitQueue> {
+        None
+    }
+    /// Downcast hook for the syscalls that only apply to signalfds.
+    fn as_signalfd(&self) -> Option<&SignalFd> {
+        None
+    }
+    /// Downcast hooks for the socket syscalls.
+    fn as_udp_socket(&self) -> Option<&UdpSocket> {
+        None
+    }
+    fn as_tcp_socket(&self) -> Option<&TcpSocket> {
+        None
+    }
+    /// Whether the file is the console, which the terminal syscalls apply
+    /// to.
+    fn is_tty(&self) -> bool {
+        false
+    }
+}
+
+bitflags! {
+    /// Events of interest in `ppoll`, same values as Linux
+    pub struct PollEvents: u16 {
+        const POLLIN   = 0x001;
+        const POLLPRI  = 0x002;
+        const POLLOUT  = 0x004;
+        const POLLERR  = 0x008;
+        const POLLHUP  = 0x010;
+        const POLLNVAL = 0x020;
+    }
+}
+
+/// Options of a socket, set with `setsockopt` and `fcntl`
+#[derive(Copy, Clone, Default)]
+pub struct SocketOptions {
+    pub nonblocking: bool,
+    /// `SO_RCVTIMEO`, in milliseconds
+    pub recv_timeout: Option<usize>,
+    /// `SO_REUSEADDR`: TCP may bind a port its old connections still use
+    pub reuse_addr: bool,
+}
+
+/// Why [`wait_ready`] gave up
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WaitError {
+    /// the file is non-blocking or the receive timeout passed
+    WouldBlock,
+    Interrupted,
+}
+
+/// Wait until `file` is ready for `events`, or has hung up or failed, so
+/// that the operation that follows does not block. Files that are neither
+/// non-blocking nor have a receive timeout are left to wait on their own.
+pub fn wait_ready(file: &dyn File, events: PollEvents) -> Result<(), WaitError> {
+    let timeout = match events.contains(PollEvents::POLLIN) {
+        true => file.recv_timeout(),
+        false => None,
+    };
+    if !file.nonblocking() && timeout.is_none() {
+        return Ok(());
+    }
+    let deadline = timeout.map(deadline_after_ms);
+    let events = events | PollEvents::POLLHUP | PollEvents::POLLERR;
+    loop {
+        if !file.poll(events).is_empty() {
+            return Ok(());
+        }
+        if file.nonblocking() || deadline.map_or(false, |deadline| get_time() >= deadline) {
+            return Err(WaitError::WouldBlock);
+        }
+        if current_signal_interrupted() {
+            return Err(WaitError::Interrupted);
+        }
+        match file.wait_queue() {
+            Some(queue) => block_until(queue, deadline),
+            None => wait_for_poll(deadline),
+        }
+    }
+}
+
+lazy_static! {
+    /// tasks in `ppoll`, waiting for a file to become ready
+    static ref POLLERS: WaitQueue = WaitQueue::new();
+}
+
+/// Something may have made a file ready: wake the tasks in `ppoll` to look
+/// again. A poll waits on many files at once, so device interrupts, pipes
+/// and signals wake the pollers all, and each tick does for the rest.
+pub fn wake_pollers() {
+    POLLERS.wake_all();
+}
+
+/// Whether a task waits in `ppoll`, for a device interrupt maybe.
+pub fn has_pollers() -> bool {
+    !POLLERS.is_empty()
+}
+
+/// Whether a task waits for what an interrupt may bring: a device, a
+/// signal from the console, or a tick for the pollers.
+pub fn has_interrupt_waiters() -> bool {
+    has_pollers() || tty_has_readers() || input_has_readers() || signalfd_has_readers()
+}
+
+/// Wait in `ppoll` until [`wake_pollers`], or until the timer reaches
+/// `deadline` if there is one.
+pub fn wait_for_poll(deadline: Option<usize>) {
+    block_until(&POLLERS, deadline);
+}
+
+/// Owner and permission bits of a file, as in Unix
+#[derive(Copy, Clone)]
+pub struct FileMode {
+    pub uid: u32,
+    pub gid: u32,
+    /// `rwxrwxrwx` bits for the owner, the group and everyone else
+    pub mode: u16,
+}
+
+bitflags! {
+    /// Access asked for when opening a file
+    pub struct Access: u16 {
+        const READ  = 0o4;
+        const WRITE = 0o2;
+    }
+}
+
+impl FileMode {
+    /// Whether a task running as `uid`/`gid` may access the file as asked.
+    /// Root may access any file.
+    pub fn allows(&self, uid: u32, gid: u32, access: Access) -> bool {
+        let bits = if uid == 0 {
+            return true;
+        } else if uid == self.uid {
+            self.mode >> 6
+        } else if gid == self.gid {
+            self.mode >> 3
+        } else {
+            self.mode
+        };
+        bits & access.bits() == access.bits()
+    }
+}
+
+/// Owner and mode of the file at `path`, if there is one.
+pub fn file_mode(path: &str) -> Option<FileMode> {
+    dev::device_mode(path).or_else(|| proc::proc_mode(path))
+}
+
+/// The directories holding the device and `/proc` files, there being no
+/// other
+const DIRECTORIES: [&str; 4] = ["/", "/dev", "/proc", "/proc/net"];
+
+/// Whether `path`, absolute and normalized, is a directory.
+pub fn is_dir(path: &str) -> bool {
+    DIRECTORIES.contains(&path)
+}
+
+/// The absolute path `path` names when relative to the directory `cwd`,
+/// without `.`, `..` or repeated slashes. `..` of the root is the root.
+pub fn resolve_path(cwd: &str, path: &str) -> String {
+    let mut components: Vec<&str> = Vec::new();
+    let base = if path.starts_with('/') { "" } else { cwd };
+    for component in base.split('/').chain(path.split('/')) {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            component => components.push(component),
+        }
+    }
+    let mut resolved = String::new();
+    for component in components {
+        resolved.push('/');
+        resolved.push_str(component);
+    }
+    if resolved.is_empty() {
+        resolved.push('/');
+    }
+    resolved
+}
+
+/// The poll request of one descriptor, layout compatible with `struct pollfd`
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct PollFd {
+    pub fd: i32,
+    pub events: u16,
+    pub revents: u16,
+}
+
+pub use dev::open_device;
+pub use input::{input_has_readers, input_interrupt, InputEvents};
+pub use pipe::Pipe;
+pub use pressure::{pressure_tick, PressureFd};
+pub use proc::open_proc;
+pub use signalfd::{signalfd_has_readers, wake_signalfd_readers, SignalFd};
+pub use stdio::{Stdin, Stdout};
+pub use tcp::TcpSocket;
+pub use tty::{
+    tty_foreground, tty_has_readers, tty_interrupt, tty_needs_tick, tty_set_foreground,
+    tty_set_termios, tty_termios, Termios,
+};
+pub use udp::UdpSocket;
+pub use urandom::Urandom;
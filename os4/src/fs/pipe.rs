@@ -0,0 +1,197 @@
+//! Pipes
+//!
+//! `pipe2` makes a read end and a write end sharing a buffer of
+//! `PIPE_BUFFER_SIZE` bytes. Each end has its own non-blocking mode, as it
+//! is its own open file. Reading with no writer left gets what is buffered
+//! and then end of file; writing with no reader left raises `SIGPIPE` and
+//! fails with `EPIPE`. A reader waiting for bytes and a writer waiting for
+//! room block on queues of their own, woken from the other end.
+
+use super::{wake_pollers, File, PollEvents};
+use crate::config::PIPE_BUFFER_SIZE;
+use crate::mm::UserBuffer;
+use crate::sync::UPSafeCell;
+use crate::task::{
+    current_signal_interrupted, current_task_id, send_signal, SignalFlags, WaitQueue,
+};
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+struct PipeBuffer {
+    bytes: VecDeque<u8>,
+    /// ends of each kind still open
+    readers: usize,
+    writers: usize,
+}
+
+/// What both ends of a pipe share
+struct PipeShared {
+    buffer: UPSafeCell<PipeBuffer>,
+    /// readers waiting for bytes or for the last writer to go
+    read_waiters: WaitQueue,
+    /// writers waiting for room or for the last reader to go
+    write_waiters: WaitQueue,
+}
+
+/// One end of a pipe
+pub struct Pipe {
+    writer: bool,
+    shared: Arc<PipeShared>,
+    nonblocking: AtomicBool,
+}
+
+impl Pipe {
+    /// Make a pipe, returning its read end and its write end.
+    pub fn new(nonblocking: bool) -> (Self, Self) {
+        let shared = Arc::new(PipeShared {
+            buffer: unsafe {
+                UPSafeCell::new(PipeBuffer {
+                    bytes: VecDeque::with_capacity(PIPE_BUFFER_SIZE),
+                    readers: 1,
+                    writers: 1,
+                })
+            },
+            read_waiters: WaitQueue::new(),
+            write_waiters: WaitQueue::new(),
+        });
+        let end = |writer| Self {
+            writer,
+            shared: shared.clone(),
+            nonblocking: AtomicBool::new(nonblocking),
+        };
+        (end(false), end(true))
+    }
+    /// Whether a blocked call should give up instead of waiting.
+    fn should_stop_waiting(&self) -> bool {
+        self.nonblocking() || current_signal_interrupted()
+    }
+}
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        let mut buffer = self.shared.buffer.exclusive_access();
+        if self.writer {
+            buffer.writers -= 1;
+        } else {
+            buffer.readers -= 1;
+        }
+        let (readers, writers) = (buffer.readers, buffer.writers);
+        drop(buffer);
+        // the other end hung up once the last of these goes
+        if writers == 0 {
+            self.shared.read_waiters.wake_all();
+        }
+        if readers == 0 {
+            self.shared.write_waiters.wake_all();
+        }
+        wake_pollers();
+    }
+}
+
+impl File for Pipe {
+    fn readable(&self) -> bool {
+        !self.writer
+    }
+    fn writable(&self) -> bool {
+        self.writer
+    }
+    /// Read what is buffered, waiting for some unless the end is
+    /// non-blocking. 0 at end of file, once every write end is closed.
+    fn read(&self, user_buf: UserBuffer) -> usize {
+        let mut read_size = 0;
+        let mut user_bytes = user_buf.into_iter();
+        loop {
+            {
+                let mut buffer = self.shared.buffer.exclusive_access();
+                if !buffer.bytes.is_empty() || buffer.writers == 0 {
+                    for byte_ref in user_bytes.by_ref() {
+                        let byte = match buffer.bytes.pop_front() {
+                            Some(byte) => byte,
+                            None => break,
+                        };
+                        unsafe {
+                            byte_ref.write_volatile(byte);
+                        }
+                        read_size += 1;
+                    }
+                    drop(buffer);
+                    // room for the writers
+                    self.shared.write_waiters.wake_all();
+                    wake_pollers();
+                    return read_size;
+                }
+            }
+            if self.should_stop_waiting() {
+                return 0;
+            }
+            self.shared.read_waiters.block_current_and_run_next();
+        }
+    }
+    /// Buffer all of `user_buf`, waiting for room unless the end is
+    /// non-blocking. Returns how much was buffered, which falls short with
+    /// `SIGPIPE` raised once no reader is left.
+    fn write(&self, user_buf: UserBuffer) -> usize {
+        let len = user_buf.len();
+        let mut written = 0;
+        let mut user_bytes = user_buf.into_iter();
+        while written < len {
+            {
+                let mut buffer = self.shared.buffer.exclusive_access();
+                if buffer.readers == 0 {
+                    drop(buffer);
+                    send_signal(current_task_id(), SignalFlags::SIGPIPE);
+                    break;
+                }
+                while buffer.bytes.len() < PIPE_BUFFER_SIZE {
+                    match user_bytes.next() {
+                        Some(byte_ref) => buffer.bytes.push_back(unsafe { *byte_ref }),
+                        None => break,
+                    }
+                    written += 1;
+                }
+            }
+            // something for the readers
+            self.shared.read_waiters.wake_all();
+            wake_pollers();
+            if written == len || self.should_stop_waiting() {
+                break;
+            }
+            self.shared.write_waiters.block_current_and_run_next();
+        }
+        written
+    }
+    fn poll(&self, events: PollEvents) -> PollEvents {
+        let buffer = self.shared.buffer.exclusive_access();
+        let mut ready = PollEvents::empty();
+        if self.writer {
+            if buffer.readers == 0 {
+                ready |= PollEvents::POLLERR;
+            } else if buffer.bytes.len() < PIPE_BUFFER_SIZE {
+                ready |= PollEvents::POLLOUT;
+            }
+        } else {
+            if !buffer.bytes.is_empty() {
+                ready |= PollEvents::POLLIN;
+            }
+            if buffer.writers == 0 {
+                ready |= PollEvents::POLLHUP;
+            }
+        }
+        // hangups and errors are reported whether asked for or not
+        ready & (events | PollEvents::POLLHUP | PollEvents::POLLERR)
+    }
+    fn nonblocking(&self) -> bool {
+        self.nonblocking.load(Ordering::Relaxed)
+    }
+    fn set_nonblocking(&self, nonblocking: bool) -> bool {
+        self.nonblocking.store(nonblocking, Ordering::Relaxed);
+        true
+    }
+    fn wait_queue(&self) -> Option<&WaitQueue> {
+        Some(match self.writer {
+            true => &self.shared.write_waiters,
+            false => &self.shared.read_waiters,
+        })
+    }
+}
@@ -0,0 +1,100 @@
+//! Memory pressure as readable file events
+//!
+//! A [`PressureFd`] becomes readable each time memory pressure rises past a
+//! watermark, see [`PressureLevel`]. Reading it returns the level, as a
+//! `u32`, of the latest rise since the previous read. Rises that happen
+//! before the descriptor is created are not reported.
+//!
+//! Pressure rises as frames are allocated, often with the task manager in
+//! use, so the readers are not woken there but by the next tick.
+
+use super::{File, PollEvents};
+use crate::mm::{pressure_events, PressureLevel, UserBuffer};
+use crate::sync::UPSafeCell;
+use crate::task::{current_signal_interrupted, WaitQueue};
+use core::mem::size_of;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use lazy_static::*;
+
+lazy_static! {
+    /// tasks waiting for pressure to rise
+    static ref READERS: WaitQueue = WaitQueue::new();
+}
+
+/// pressure events the readers were last woken for
+static WOKEN_EVENTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Tick: wake the readers if pressure rose since they were last woken.
+pub fn pressure_tick() {
+    let events = pressure_events().0;
+    if WOKEN_EVENTS.swap(events, Ordering::Relaxed) != events {
+        READERS.wake_all();
+    }
+}
+
+pub struct PressureFd {
+    /// pressure events already reported
+    seen: UPSafeCell<usize>,
+    nonblocking: AtomicBool,
+}
+
+impl PressureFd {
+    pub fn new(nonblocking: bool) -> Self {
+        Self {
+            seen: unsafe { UPSafeCell::new(pressure_events().0) },
+            nonblocking: AtomicBool::new(nonblocking),
+        }
+    }
+}
+
+impl File for PressureFd {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        false
+    }
+    /// Block until pressure rises, then return its level. Returns 0 if
+    /// `buf` cannot hold the level, if the descriptor is non-blocking and
+    /// pressure did not rise, or if the wait was interrupted by a signal.
+    fn read(&self, buf: UserBuffer) -> usize {
+        if buf.len() < size_of::<u32>() {
+            return 0;
+        }
+        let level: PressureLevel = loop {
+            let (events, level) = pressure_events();
+            if events != *self.seen.exclusive_access() {
+                *self.seen.exclusive_access() = events;
+                break level;
+            }
+            if self.nonblocking() || current_signal_interrupted() {
+                return 0;
+            }
+            READERS.block_current_and_run_next();
+        };
+        let bytes = (level as u32).to_ne_bytes();
+        for (byte, value) in buf.into_iter().zip(bytes.iter()) {
+            unsafe {
+                *byte = *value;
+            }
+        }
+        bytes.len()
+    }
+    fn write(&self, _buf: UserBuffer) -> usize {
+        0
+    }
+    fn poll(&self, events: PollEvents) -> PollEvents {
+        if pressure_events().0 != *self.seen.exclusive_access() {
+            events & PollEvents::POLLIN
+        } else {
+            PollEvents::empty()
+        }
+    }
+    fn nonblocking(&self) -> bool {
+        self.nonblocking.load(Ordering::Relaxed)
+    }
+    fn set_nonblocking(&self, nonblocking: bool) -> bool {
+        self.nonblocking.store(nonblocking, Ordering::Relaxed);
+        true
+    }
+}
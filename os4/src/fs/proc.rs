@@ -0,0 +1,349 @@
+//! `/proc` files
+//!
+//! Each file is a text snapshot taken when it is opened, read like a
+//! regular file until its end. The formats follow Linux where it has the
+//! same file; `/proc/cgroupstats`, `/proc/cpuidle` and `/proc/faults` are
+//! this kernel's own.
+
+use super::{File, FileMode};
+use crate::config::CLOCK_FREQ;
+use crate::mm::UserBuffer;
+use crate::net::{interface_stats, neighbors, protocol_stats, tcp_sockets, udp_sockets};
+use crate::net::{Ipv4Addr, MacDisplay, NetStats, TcpState};
+use crate::sync::UPSafeCell;
+use crate::task::{cgroup_stats, task_usage};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// name of the interface on the network device
+const NET_DEVICE_NAME: &str = "eth0";
+
+pub struct ProcFile {
+    contents: String,
+    /// how much has been read
+    offset: UPSafeCell<usize>,
+    /// kept for `F_GETFL`, reads never blocking
+    nonblocking: AtomicBool,
+}
+
+impl ProcFile {
+    fn new(contents: String) -> Self {
+        Self {
+            contents,
+            offset: unsafe { UPSafeCell::new(0) },
+            nonblocking: AtomicBool::new(false),
+        }
+    }
+}
+
+impl File for ProcFile {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        false
+    }
+    fn read(&self, mut user_buf: UserBuffer) -> usize {
+        let mut offset = self.offset.exclusive_access();
+        let start = *offset;
+        for buffer in user_buf.buffers.iter_mut() {
+            let rest = &self.contents.as_bytes()[*offset..];
+            let len = buffer.len().min(rest.len());
+            buffer[..len].copy_from_slice(&rest[..len]);
+            *offset += len;
+        }
+        *offset - start
+    }
+    fn write(&self, _user_buf: UserBuffer) -> usize {
+        0
+    }
+    fn nonblocking(&self) -> bool {
+        self.nonblocking.load(Ordering::Relaxed)
+    }
+    fn set_nonblocking(&self, nonblocking: bool) -> bool {
+        self.nonblocking.store(nonblocking, Ordering::Relaxed);
+        true
+    }
+}
+
+/// `/proc/net/arp`: the neighbor cache, incomplete entries with flags 0
+fn net_arp() -> String {
+    let mut text = String::from(
+        "IP address       HW type     Flags       HW address            Mask     Device\n",
+    );
+    for neighbor in neighbors() {
+        let (flags, mac) = match neighbor.mac {
+            Some(mac) => (0x2, mac),
+            None => (0x0, [0; 6]),
+        };
+        let ip = neighbor.ip.to_string();
+        let mac = MacDisplay(mac).to_string();
+        writeln!(
+            text,
+            "{:<16} 0x1         {:<#11x} {:<21} *        {}",
+            ip, flags, mac, NET_DEVICE_NAME
+        )
+        .unwrap();
+    }
+    text
+}
+
+/// `/proc/net/dev`: packet counters per interface
+fn net_dev() -> String {
+    let mut text = String::from(
+        "Inter-|   Receive                   |  Transmit\n \
+         face |      bytes  packets   drop |      bytes  packets   drop\n",
+    );
+    let mut line = |name: &str, stats: NetStats| {
+        writeln!(
+            text,
+            "{:>6}:{:>11} {:>8} {:>6}  {:>11} {:>8} {:>6}",
+            name,
+            stats.rx_bytes,
+            stats.rx_packets,
+            stats.rx_dropped,
+            stats.tx_bytes,
+            stats.tx_packets,
+            stats.tx_dropped
+        )
+        .unwrap();
+    };
+    let (loopback, device) = interface_stats();
+    line("lo", loopback);
+    if let Some(device) = device {
+        line(NET_DEVICE_NAME, device);
+    }
+    text
+}
+
+/// An address as in `/proc/net/tcp`: the bytes in memory order read as a
+/// little-endian word, then the port
+fn socket_address(ip: Ipv4Addr, port: u16) -> String {
+    format!("{:08X}:{:04X}", u32::from_le_bytes(ip.0), port)
+}
+
+/// State numbers of `/proc/net/tcp`, same as Linux
+fn tcp_state_number(state: Option<TcpState>) -> u8 {
+    match state {
+        Some(TcpState::Established) => 0x01,
+        Some(TcpState::SynSent) => 0x02,
+        Some(TcpState::SynReceived) => 0x03,
+        Some(TcpState::FinWait1) => 0x04,
+        Some(TcpState::FinWait2) => 0x05,
+        Some(TcpState::TimeWait) => 0x06,
+        Some(TcpState::Closed) => 0x07,
+        Some(TcpState::CloseWait) => 0x08,
+        Some(TcpState::LastAck) => 0x09,
+        None => 0x0a,
+        Some(TcpState::Closing) => 0x0b,
+    }
+}
+
+/// `/proc/net/tcp`: listeners, then connections, with their queued bytes
+/// and retransmissions
+fn net_tcp() -> String {
+    let mut text =
+        String::from("  sl  local_address rem_address   st tx_queue rx_queue retrnsmt\n");
+    for (i, socket) in tcp_sockets().iter().enumerate() {
+        writeln!(
+            text,
+            "{:>4}: {} {} {:02X} {:08X}:{:08X} {:08X}",
+            i,
+            socket_address(socket.local, socket.local_port),
+            socket_address(socket.remote, socket.remote_port),
+            tcp_state_number(socket.state),
+            socket.send_queue,
+            socket.recv_queue,
+            socket.retries
+        )
+        .unwrap();
+    }
+    text
+}
+
+/// `/proc/net/udp`: bound ports with their queued bytes and drops
+fn net_udp() -> String {
+    let mut text = String::from("  sl  local_address rem_address   st tx_queue rx_queue drops\n");
+    for (i, socket) in udp_sockets().iter().enumerate() {
+        writeln!(
+            text,
+            "{:>4}: {} {} 07 00000000:{:08X} {}",
+            i,
+            socket_address(Ipv4Addr::UNSPECIFIED, socket.port),
+            socket_address(Ipv4Addr::UNSPECIFIED, 0),
+            socket.recv_queue,
+            socket.drops
+        )
+        .unwrap();
+    }
+    text
+}
+
+/// `/proc/net/snmp`: a line of names, then a line of values, per protocol
+fn net_snmp() -> String {
+    let mut text = String::new();
+    let stats = match protocol_stats() {
+        Some(stats) => stats,
+        None => return text,
+    };
+    let mut protocol = |name: &str, counters: &[(&str, usize)]| {
+        write!(text, "{}:", name).unwrap();
+        for (counter, _) in counters {
+            write!(text, " {}", counter).unwrap();
+        }
+        write!(text, "\n{}:", name).unwrap();
+        for (_, value) in counters {
+            write!(text, " {}", value).unwrap();
+        }
+        text.push('\n');
+    };
+    let ip = stats.ip;
+    protocol(
+        "Ip",
+        &[
+            ("InReceives", ip.in_receives),
+            ("InHdrErrors", ip.in_hdr_errors),
+            ("InAddrErrors", ip.in_addr_errors),
+            ("InUnknownProtos", ip.in_unknown_protos),
+            ("InDelivers", ip.in_delivers),
+            ("OutRequests", ip.out_requests),
+            ("OutDiscards", ip.out_discards),
+        ],
+    );
+    let icmp = stats.icmp;
+    protocol(
+        "Icmp",
+        &[
+            ("InMsgs", icmp.in_msgs),
+            ("InErrors", icmp.in_errors),
+            ("InEchos", icmp.in_echos),
+            ("InEchoReps", icmp.in_echo_reps),
+            ("OutMsgs", icmp.out_msgs),
+            ("OutEchos", icmp.out_echos),
+            ("OutEchoReps", icmp.out_echo_reps),
+        ],
+    );
+    let tcp = stats.tcp;
+    protocol(
+        "Tcp",
+        &[
+            ("ActiveOpens", tcp.active_opens),
+            ("PassiveOpens", tcp.passive_opens),
+            ("AttemptFails", tcp.attempt_fails),
+            ("EstabResets", tcp.estab_resets),
+            ("CurrEstab", tcp.curr_estab),
+            ("InSegs", tcp.in_segs),
+            ("OutSegs", tcp.out_segs),
+            ("RetransSegs", tcp.retrans_segs),
+            ("InErrs", tcp.in_errs),
+            ("OutRsts", tcp.out_rsts),
+        ],
+    );
+    let udp = stats.udp;
+    protocol(
+        "Udp",
+        &[
+            ("InDatagrams", udp.in_datagrams),
+            ("NoPorts", udp.no_ports),
+            ("InErrors", udp.in_errors),
+            ("OutDatagrams", udp.out_datagrams),
+            ("RcvbufErrors", udp.rcvbuf_errors),
+        ],
+    );
+    text
+}
+
+/// `/proc/faults`: the page faults of each task, by kind
+fn faults() -> String {
+    let mut text = String::new();
+    let columns = ("pid", "name", "minflt", "majflt", "lazy", "cow");
+    writeln!(
+        text,
+        "{:>5} {:<16} {:>8} {:>8} {:>8} {:>8}",
+        columns.0, columns.1, columns.2, columns.3, columns.4, columns.5
+    )
+    .unwrap();
+    for task in task_usage() {
+        let counters = task.counters;
+        writeln!(
+            text,
+            "{:>5} {:<16} {:>8} {:>8} {:>8} {:>8}",
+            task.pid,
+            task.name,
+            counters.minor_faults,
+            counters.major_faults,
+            counters.lazy_faults,
+            counters.cow_faults
+        )
+        .unwrap();
+    }
+    text
+}
+
+/// `/proc/cpuidle`: how often the CPU entered each idle state and
+/// suspended, and for how long, in microseconds
+fn cpuidle() -> String {
+    let mut text = format!("{:<9} {:>10} {:>16}\n", "state", "usage", "time");
+    for (name, usage, time) in crate::power::residency() {
+        writeln!(text, "{:<9} {:>10} {:>16}", name, usage, time).unwrap();
+    }
+    text
+}
+
+/// `/proc/cgroupstats`: each control group with its CPU weight, the tasks
+/// directly in it, and the CPU time it used with its descendants, in
+/// microseconds
+fn cgroupstats() -> String {
+    let mut text = format!(
+        "{:>3} {:<24} {:>6} {:>6} {:>14}\n",
+        "id", "path", "weight", "tasks", "usage_us"
+    );
+    for group in cgroup_stats() {
+        writeln!(
+            text,
+            "{:>3} {:<24} {:>6} {:>6} {:>14}",
+            group.id,
+            group.path,
+            group.weight,
+            group.tasks,
+            group.cpu_time / (CLOCK_FREQ / 1_000_000)
+        )
+        .unwrap();
+    }
+    text
+}
+
+/// Owner and mode of the `/proc` file at `path`: all are read-only and
+/// readable by everyone.
+pub fn proc_mode(path: &str) -> Option<FileMode> {
+    match path {
+        "/proc/cgroupstats" | "/proc/cpuidle" | "/proc/faults" | "/proc/net/arp"
+        | "/proc/net/dev" | "/proc/net/snmp" | "/proc/net/tcp" | "/proc/net/udp" => {
+            Some(FileMode {
+                uid: 0,
+                gid: 0,
+                mode: 0o444,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Open the `/proc` file at `path`, if there is one.
+pub fn open_proc(path: &str) -> Option<Arc<dyn File + Send + Sync>> {
+    let contents = match path {
+        "/proc/cgroupstats" => cgroupstats(),
+        "/proc/cpuidle" => cpuidle(),
+        "/proc/faults" => faults(),
+        "/proc/net/arp" => net_arp(),
+        "/proc/net/dev" => net_dev(),
+        "/proc/net/snmp" => net_snmp(),
+        "/proc/net/tcp" => net_tcp(),
+        "/proc/net/udp" => net_udp(),
+        _ => return None,
+    };
+    Some(Arc::new(ProcFile::new(contents)))
+}
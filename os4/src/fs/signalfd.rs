@@ -0,0 +1,145 @@
+//! Signals delivered as readable file events
+//!
+//! A [`SignalFd`] turns the signals in its mask into [`SignalfdSiginfo`]
+//! records: reading it consumes the matching pending signals of the reading
+//! task instead of running their default action. The signals should also be
+//! blocked with `sigprocmask`, otherwise they may be acted upon before the
+//! descriptor is read.
+
+use super::{File, PollEvents};
+use crate::mm::UserBuffer;
+use crate::sync::UPSafeCell;
+use crate::task::{
+    current_pending_signals, current_signal_interrupted, take_current_signal, SignalFlags,
+    WaitQueue,
+};
+use core::mem::size_of;
+use core::sync::atomic::{AtomicBool, Ordering};
+use lazy_static::*;
+
+lazy_static! {
+    /// tasks waiting in a signalfd read
+    static ref READERS: WaitQueue = WaitQueue::new();
+}
+
+/// A signal was sent: wake the readers to look for it. Signals in their
+/// mask are blocked, so sending one does not wake them by itself.
+pub fn wake_signalfd_readers() {
+    READERS.wake_all();
+}
+
+/// Whether a task waits in a signalfd read, maybe for a signal from the
+/// console.
+pub fn signalfd_has_readers() -> bool {
+    !READERS.is_empty()
+}
+
+/// The record returned by reading a signalfd, layout compatible with Linux
+#[repr(C)]
+pub struct SignalfdSiginfo {
+    pub ssi_signo: u32,
+    pub ssi_errno: i32,
+    pub ssi_code: i32,
+    pub ssi_pid: u32,
+    pub ssi_uid: u32,
+    _pad: [u8; 108],
+}
+
+/// `ssi_code` of a signal sent by `kill`
+const SI_USER: i32 = 0;
+
+impl SignalfdSiginfo {
+    fn new(signo: usize) -> Self {
+        Self {
+            ssi_signo: signo as u32,
+            ssi_errno: 0,
+            ssi_code: SI_USER,
+            ssi_pid: 0,
+            ssi_uid: 0,
+            _pad: [0; 108],
+        }
+    }
+}
+
+/// A file that reports the pending signals in `mask`
+pub struct SignalFd {
+    mask: UPSafeCell<SignalFlags>,
+    nonblocking: AtomicBool,
+}
+
+impl SignalFd {
+    pub fn new(mask: SignalFlags, nonblocking: bool) -> Self {
+        Self {
+            mask: unsafe { UPSafeCell::new(mask - SignalFlags::unblockable()) },
+            nonblocking: AtomicBool::new(nonblocking),
+        }
+    }
+    /// Replace the set of signals reported by this descriptor.
+    pub fn set_mask(&self, mask: SignalFlags) {
+        *self.mask.exclusive_access() = mask - SignalFlags::unblockable();
+    }
+    fn mask(&self) -> SignalFlags {
+        *self.mask.exclusive_access()
+    }
+}
+
+impl File for SignalFd {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        false
+    }
+    /// Block until at least one signal in the mask is pending, then return as
+    /// many whole records as fit in `buf`. Returns 0 if `buf` cannot hold a
+    /// single record, if the descriptor is non-blocking and no signal is
+    /// pending, or if the wait was interrupted by another signal.
+    fn read(&self, buf: UserBuffer) -> usize {
+        let record_size = size_of::<SignalfdSiginfo>();
+        let capacity = buf.len() / record_size;
+        if capacity == 0 {
+            return 0;
+        }
+        let mut buf_iter = buf.into_iter();
+        let mut read_records = 0usize;
+        while read_records < capacity {
+            if let Some(signum) = take_current_signal(self.mask()) {
+                let info = SignalfdSiginfo::new(signum);
+                let bytes = unsafe {
+                    core::slice::from_raw_parts(&info as *const _ as *const u8, record_size)
+                };
+                for byte in bytes {
+                    unsafe {
+                        *buf_iter.next().unwrap() = *byte;
+                    }
+                }
+                read_records += 1;
+            } else if read_records > 0 || self.nonblocking() || current_signal_interrupted() {
+                break;
+            } else {
+                READERS.block_current_and_run_next();
+            }
+        }
+        read_records * record_size
+    }
+    fn write(&self, _buf: UserBuffer) -> usize {
+        0
+    }
+    fn poll(&self, events: PollEvents) -> PollEvents {
+        if current_pending_signals().intersects(self.mask()) {
+            events & PollEvents::POLLIN
+        } else {
+            PollEvents::empty()
+        }
+    }
+    fn nonblocking(&self) -> bool {
+        self.nonblocking.load(Ordering::Relaxed)
+    }
+    fn set_nonblocking(&self, nonblocking: bool) -> bool {
+        self.nonblocking.store(nonblocking, Ordering::Relaxed);
+        true
+    }
+    fn as_signalfd(&self) -> Option<&SignalFd> {
+        Some(self)
+    }
+}
@@ -0,0 +1,99 @@
+//! Console-backed standard input and output
+
+use super::tty::{tty_read, tty_read_ready};
+use super::{File, PollEvents};
+use crate::console::write_user;
+use crate::drivers::chardev::console_write;
+use crate::mm::UserBuffer;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// The standard input
+pub struct Stdin {
+    nonblocking: AtomicBool,
+}
+/// The standard output, never blocking but keeping the mode it is set to
+pub struct Stdout {
+    nonblocking: AtomicBool,
+}
+
+impl Stdin {
+    pub fn new() -> Self {
+        Self {
+            nonblocking: AtomicBool::new(false),
+        }
+    }
+}
+
+impl Stdout {
+    pub fn new() -> Self {
+        Self {
+            nonblocking: AtomicBool::new(false),
+        }
+    }
+}
+
+impl File for Stdin {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        false
+    }
+    /// Wait for input unless non-blocking, then return what the line
+    /// discipline hands over, up to the size of `user_buf`.
+    fn read(&self, user_buf: UserBuffer) -> usize {
+        tty_read(user_buf, self.nonblocking())
+    }
+    fn write(&self, _user_buf: UserBuffer) -> usize {
+        0
+    }
+    fn poll(&self, events: PollEvents) -> PollEvents {
+        if tty_read_ready() {
+            events & PollEvents::POLLIN
+        } else {
+            PollEvents::empty()
+        }
+    }
+    fn nonblocking(&self) -> bool {
+        self.nonblocking.load(Ordering::Relaxed)
+    }
+    fn set_nonblocking(&self, nonblocking: bool) -> bool {
+        self.nonblocking.store(nonblocking, Ordering::Relaxed);
+        true
+    }
+    fn is_tty(&self) -> bool {
+        true
+    }
+}
+
+impl File for Stdout {
+    fn readable(&self) -> bool {
+        false
+    }
+    fn writable(&self) -> bool {
+        true
+    }
+    fn read(&self, _user_buf: UserBuffer) -> usize {
+        0
+    }
+    fn write(&self, user_buf: UserBuffer) -> usize {
+        for buffer in user_buf.buffers.iter() {
+            if !console_write(buffer) {
+                write_user(buffer);
+            }
+            // the firmware console takes a while for each character
+            crate::trap::cond_resched();
+        }
+        user_buf.len()
+    }
+    fn nonblocking(&self) -> bool {
+        self.nonblocking.load(Ordering::Relaxed)
+    }
+    fn set_nonblocking(&self, nonblocking: bool) -> bool {
+        self.nonblocking.store(nonblocking, Ordering::Relaxed);
+        true
+    }
+    fn is_tty(&self) -> bool {
+        true
+    }
+}
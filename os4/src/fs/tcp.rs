@@ -0,0 +1,304 @@
+//! TCP sockets
+//!
+//! A socket is either idle, listening on its port, or attached to one
+//! connection of the network stack. Dropping it closes the connection
+//! gracefully; the stack finishes the handshake on its own.
+//!
+//! A blocked call waits on the queue of its socket, which the stack wakes
+//! whenever something happens on the port of the socket.
+
+use super::{File, PollEvents, SocketOptions};
+use crate::mm::UserBuffer;
+use crate::net::{self, source_address, with_tcp, Endpoint, Ipv4Addr};
+use crate::net::{TcpError, TcpHandle, TcpState};
+use crate::sync::UPSafeCell;
+use crate::task::{current_signal_interrupted, WaitQueue};
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::RefMut;
+
+/// largest chunk moved between user space and a connection at once
+const CHUNK_SIZE: usize = 4096;
+
+#[derive(Copy, Clone)]
+enum Mode {
+    Idle,
+    Listening,
+    Connected(TcpHandle),
+}
+
+struct SocketState {
+    /// port reserved by this socket, 0 if none
+    port: u16,
+    mode: Mode,
+    options: SocketOptions,
+}
+
+pub struct TcpSocket {
+    state: UPSafeCell<SocketState>,
+    /// tasks blocked in a call on this socket
+    waiters: Arc<WaitQueue>,
+}
+
+impl TcpSocket {
+    pub fn new(nonblocking: bool) -> Self {
+        Self::with_mode(Mode::Idle, nonblocking)
+    }
+    fn with_mode(mode: Mode, nonblocking: bool) -> Self {
+        Self {
+            state: unsafe {
+                UPSafeCell::new(SocketState {
+                    port: 0,
+                    mode,
+                    options: SocketOptions {
+                        nonblocking,
+                        ..SocketOptions::default()
+                    },
+                })
+            },
+            waiters: Arc::new(WaitQueue::new()),
+        }
+    }
+    pub fn options(&self) -> RefMut<'_, SocketOptions> {
+        RefMut::map(self.state.exclusive_access(), |state| &mut state.options)
+    }
+    fn handle(&self) -> Option<TcpHandle> {
+        match self.state.exclusive_access().mode {
+            Mode::Connected(handle) => Some(handle),
+            _ => None,
+        }
+    }
+    /// Whether a blocked call should give up instead of waiting.
+    fn should_stop_waiting(&self) -> bool {
+        self.nonblocking() || current_signal_interrupted()
+    }
+    /// Block until the stack changes something on the port of the socket.
+    fn wait(&self) {
+        self.waiters.block_current_and_run_next();
+    }
+    /// Bind to `port`, an ephemeral one if 0. Fails if the socket is already
+    /// bound or in use, or the port is taken, including by connections that
+    /// linger after their socket closed unless `SO_REUSEADDR` is set.
+    pub fn bind(&self, port: u16) -> bool {
+        let mut state = self.state.exclusive_access();
+        if state.port != 0 || !matches!(state.mode, Mode::Idle) {
+            return false;
+        }
+        let reuse = state.options.reuse_addr;
+        match with_tcp(|tcp| tcp.reserve(port, reuse)).flatten() {
+            Some(port) => {
+                state.port = port;
+                net::watch(Endpoint::Tcp(port), &self.waiters);
+                true
+            }
+            None => false,
+        }
+    }
+    /// The reserved port, binding an ephemeral one first if needed.
+    fn local_port(&self) -> Option<u16> {
+        let port = self.state.exclusive_access().port;
+        if port != 0 || self.bind(0) {
+            Some(self.state.exclusive_access().port)
+        } else {
+            None
+        }
+    }
+    /// Start accepting connections, at most `backlog` of them waiting.
+    pub fn listen(&self, backlog: usize) -> bool {
+        if !matches!(self.state.exclusive_access().mode, Mode::Idle) {
+            return false;
+        }
+        let port = match self.local_port() {
+            Some(port) => port,
+            None => return false,
+        };
+        if with_tcp(|tcp| tcp.listen(port, backlog)) != Some(true) {
+            return false;
+        }
+        self.state.exclusive_access().mode = Mode::Listening;
+        true
+    }
+    /// Take an established connection, waiting for one unless the socket is
+    /// non-blocking. Returns the new socket and the address of the peer.
+    pub fn accept(&self) -> Option<(TcpSocket, Ipv4Addr, u16)> {
+        if !matches!(self.state.exclusive_access().mode, Mode::Listening) {
+            return None;
+        }
+        let port = self.state.exclusive_access().port;
+        loop {
+            let accepted = with_tcp(|tcp| {
+                let handle = tcp.accept(port)?;
+                Some((handle, tcp.remote(handle)?))
+            })?;
+            if let Some((handle, (ip, remote_port))) = accepted {
+                let socket = Self::with_mode(Mode::Connected(handle), false);
+                net::watch(Endpoint::Tcp(port), &socket.waiters);
+                return Some((socket, ip, remote_port));
+            }
+            if self.should_stop_waiting() {
+                return None;
+            }
+            self.wait();
+        }
+    }
+    /// Connect to `ip`:`port`. A non-blocking socket returns false at once
+    /// with the connection in progress; poll for `POLLOUT` to see it done.
+    pub fn connect(&self, ip: Ipv4Addr, port: u16) -> bool {
+        if !matches!(self.state.exclusive_access().mode, Mode::Idle) {
+            return false;
+        }
+        let local_port = match self.local_port() {
+            Some(port) => port,
+            None => return false,
+        };
+        let local = match source_address(ip) {
+            Some(local) => local,
+            None => return false,
+        };
+        let handle = match with_tcp(|tcp| tcp.connect(local, local_port, ip, port)) {
+            Some(handle) => handle,
+            None => return false,
+        };
+        self.state.exclusive_access().mode = Mode::Connected(handle);
+        loop {
+            match with_tcp(|tcp| tcp.state(handle)) {
+                Some(TcpState::SynSent) => {}
+                Some(TcpState::Closed) | None => return false,
+                Some(_) => return true,
+            }
+            if self.should_stop_waiting() {
+                return false;
+            }
+            self.wait();
+        }
+    }
+    /// Whether a connection is still being opened.
+    pub fn connecting(&self) -> bool {
+        match self.handle() {
+            Some(handle) => with_tcp(|tcp| tcp.state(handle)) == Some(TcpState::SynSent),
+            None => false,
+        }
+    }
+    /// Shut down the reading and/or writing side of the connection.
+    pub fn shutdown(&self, read: bool, write: bool) -> bool {
+        match self.handle() {
+            Some(handle) => with_tcp(|tcp| tcp.shutdown(handle, read, write)).is_some(),
+            None => false,
+        }
+    }
+}
+
+impl Drop for TcpSocket {
+    fn drop(&mut self) {
+        let state = self.state.exclusive_access();
+        let (port, mode) = (state.port, state.mode);
+        drop(state);
+        with_tcp(|tcp| {
+            match mode {
+                Mode::Listening => tcp.unlisten(port),
+                Mode::Connected(handle) => tcp.close(handle),
+                Mode::Idle => {}
+            }
+            if port != 0 {
+                tcp.release(port);
+            }
+        });
+    }
+}
+
+impl File for TcpSocket {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        true
+    }
+    /// Read what has arrived, waiting for some data unless the socket is
+    /// non-blocking. 0 at the end of the stream.
+    fn read(&self, user_buf: UserBuffer) -> usize {
+        let handle = match self.handle() {
+            Some(handle) => handle,
+            None => return 0,
+        };
+        let mut buf = vec![0u8; user_buf.len().min(CHUNK_SIZE)];
+        let len = loop {
+            match with_tcp(|tcp| tcp.recv(handle, &mut buf)) {
+                Some(Ok(len)) => break len,
+                Some(Err(TcpError::WouldBlock)) if !self.should_stop_waiting() => self.wait(),
+                _ => return 0,
+            }
+        };
+        for (byte_ref, byte) in user_buf.into_iter().zip(buf[..len].iter()) {
+            unsafe {
+                byte_ref.write_volatile(*byte);
+            }
+        }
+        len
+    }
+    /// Queue all of `user_buf` for sending, waiting for buffer space unless
+    /// the socket is non-blocking. Returns how much was queued.
+    fn write(&self, user_buf: UserBuffer) -> usize {
+        let handle = match self.handle() {
+            Some(handle) => handle,
+            None => return 0,
+        };
+        let data: Vec<u8> = user_buf.into_iter().map(|byte| unsafe { *byte }).collect();
+        let mut written = 0;
+        while written < data.len() {
+            let end = data.len().min(written + CHUNK_SIZE);
+            match with_tcp(|tcp| tcp.send(handle, &data[written..end])) {
+                Some(Ok(len)) => written += len,
+                Some(Err(TcpError::WouldBlock)) if !self.should_stop_waiting() => self.wait(),
+                _ => break,
+            }
+        }
+        written
+    }
+    fn poll(&self, events: PollEvents) -> PollEvents {
+        let (port, mode) = {
+            let state = self.state.exclusive_access();
+            (state.port, state.mode)
+        };
+        let ready = with_tcp(|tcp| match mode {
+            Mode::Idle => PollEvents::POLLHUP,
+            Mode::Listening if tcp.can_accept(port) => PollEvents::POLLIN,
+            Mode::Listening => PollEvents::empty(),
+            Mode::Connected(handle) => {
+                let mut ready = PollEvents::empty();
+                if tcp.can_recv(handle) {
+                    ready |= PollEvents::POLLIN;
+                }
+                if tcp.can_send(handle) {
+                    ready |= PollEvents::POLLOUT;
+                }
+                if tcp.state(handle) == TcpState::Closed {
+                    ready |= PollEvents::POLLHUP;
+                    if tcp.was_reset(handle) {
+                        ready |= PollEvents::POLLERR;
+                    }
+                }
+                ready
+            }
+        })
+        .unwrap_or(PollEvents::POLLERR);
+        // errors and hangups are reported whether asked for or not
+        ready & (events | PollEvents::POLLHUP | PollEvents::POLLERR)
+    }
+    fn nonblocking(&self) -> bool {
+        self.options().nonblocking
+    }
+    fn set_nonblocking(&self, nonblocking: bool) -> bool {
+        self.options().nonblocking = nonblocking;
+        true
+    }
+    fn recv_timeout(&self) -> Option<usize> {
+        self.options().recv_timeout
+    }
+    fn wait_queue(&self) -> Option<&WaitQueue> {
+        Some(&self.waiters)
+    }
+    fn as_tcp_socket(&self) -> Option<&TcpSocket> {
+        Some(self)
+    }
+}
@@ -0,0 +1,335 @@
+//! Console line discipline
+//!
+//! Input typed on the console goes through the [`Termios`] settings, as on
+//! a Linux terminal, before a read of the standard input gets it. In cooked
+//! mode (`ICANON`) a line is edited until it is ended: the erase character
+//! deletes the last character, the kill character the whole line, and the
+//! end-of-file character ends the line without a newline, or makes the
+//! read return 0 on an empty line. A read then returns one line at most. In
+//! raw mode every byte is handed over as it comes. `ECHO` writes what is
+//! typed back to the console, and `ICRNL` reads the carriage return most
+//! terminals send as a newline. Output is not processed.
+//!
+//! With `ISIG`, the interrupt, quit and suspend characters (Ctrl-C, Ctrl-\
+//! and Ctrl-Z) discard the input not read yet and send `SIGINT`, `SIGQUIT`
+//! and `SIGTSTP` to the foreground process group of the console, which a
+//! shell sets with `ioctl` and `TIOCSPGRP`. Until one is set, they are read
+//! as any other character. Job-control stops are not supported, so
+//! `SIGTSTP` only reaches the tasks that take it through a signalfd.
+//!
+//! The console starts raw without echo, as the bundled shells edit their
+//! lines themselves; `ioctl` with `TCSETS` switches modes. Input is
+//! processed when a task reads or polls the console, and on console
+//! interrupts, so that the foreground group is signalled while it does not
+//! read. A reader waiting for input blocks until an interrupt brings some,
+//! or the tick when input comes from the SBI console, which raises none.
+
+use crate::config::MAX_CANON;
+use crate::console::write_user;
+use crate::drivers::chardev::{console_has_irq, console_read, console_write};
+use crate::mm::UserBuffer;
+use crate::sync::UPSafeCell;
+use crate::task::{
+    current_signal_interrupted, signal_group, task_table_in_use, SignalFlags, WaitQueue,
+};
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use lazy_static::*;
+
+/// control characters in a [`Termios`], as in Linux
+pub const NCCS: usize = 19;
+const VINTR: usize = 0;
+const VQUIT: usize = 1;
+const VERASE: usize = 2;
+const VKILL: usize = 3;
+const VEOF: usize = 4;
+const VMIN: usize = 6;
+const VSUSP: usize = 10;
+
+bitflags! {
+    /// `c_iflag` of a [`Termios`], the input modes handled
+    pub struct InputFlags: u32 {
+        /// carriage return read as newline
+        const ICRNL = 0o400;
+    }
+}
+
+bitflags! {
+    /// `c_lflag` of a [`Termios`], the local modes handled
+    pub struct LocalFlags: u32 {
+        /// send signals for the interrupt, quit and suspend characters
+        const ISIG = 0o1;
+        /// edit lines before handing them over
+        const ICANON = 0o2;
+        /// write input back
+        const ECHO = 0o10;
+        /// echo erasing a character as erasing it from the screen
+        const ECHOE = 0o20;
+    }
+}
+
+/// Terminal settings, as Linux `struct termios` for `TCGETS` and `TCSETS`.
+/// Flags not handled are kept as they are set.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct Termios {
+    pub c_iflag: u32,
+    pub c_oflag: u32,
+    pub c_cflag: u32,
+    pub c_lflag: u32,
+    pub c_line: u8,
+    pub c_cc: [u8; NCCS],
+}
+
+impl Termios {
+    /// Raw mode without echo but with signals, with the usual control
+    /// characters.
+    const fn new() -> Self {
+        let mut c_cc = [0; NCCS];
+        c_cc[VINTR] = 0x03;
+        c_cc[VQUIT] = 0x1c;
+        c_cc[VERASE] = 0x7f;
+        c_cc[VKILL] = 0x15;
+        c_cc[VEOF] = 0x04;
+        c_cc[VMIN] = 1;
+        c_cc[VSUSP] = 0x1a;
+        Self {
+            c_iflag: 0,
+            c_oflag: 0,
+            c_cflag: 0,
+            c_lflag: LocalFlags::ISIG.bits(),
+            c_line: 0,
+            c_cc,
+        }
+    }
+    fn local_flags(&self) -> LocalFlags {
+        LocalFlags::from_bits_truncate(self.c_lflag)
+    }
+}
+
+struct Tty {
+    termios: Termios,
+    /// the line being edited in cooked mode
+    line: Vec<u8>,
+    /// input to be read
+    ready: VecDeque<u8>,
+    /// end of file typed on an empty line, for the next read to return
+    eof: bool,
+    /// the process group the signals typed go to
+    foreground: Option<usize>,
+}
+
+lazy_static! {
+    static ref TTY: UPSafeCell<Tty> = unsafe {
+        UPSafeCell::new(Tty {
+            termios: Termios::new(),
+            line: Vec::new(),
+            ready: VecDeque::new(),
+            eof: false,
+            foreground: None,
+        })
+    };
+    /// tasks waiting for console input
+    static ref READERS: WaitQueue = WaitQueue::new();
+}
+
+/// Write `bytes` back to the console.
+fn echo(bytes: &[u8]) {
+    if !console_write(bytes) {
+        write_user(bytes);
+    }
+}
+
+impl Tty {
+    fn cooked(&self) -> bool {
+        self.termios.local_flags().contains(LocalFlags::ICANON)
+    }
+    /// The signal `byte` stands for, if signals are on.
+    fn signal_for(&self, byte: u8) -> Option<SignalFlags> {
+        if !self.termios.local_flags().contains(LocalFlags::ISIG) {
+            return None;
+        }
+        let c_cc = self.termios.c_cc;
+        if byte == c_cc[VINTR] {
+            Some(SignalFlags::SIGINT)
+        } else if byte == c_cc[VQUIT] {
+            Some(SignalFlags::SIGQUIT)
+        } else if byte == c_cc[VSUSP] {
+            Some(SignalFlags::SIGTSTP)
+        } else {
+            None
+        }
+    }
+    /// Discard the input not read yet.
+    fn flush(&mut self) {
+        self.line.clear();
+        self.ready.clear();
+        self.eof = false;
+    }
+    /// Take in the console input received so far.
+    fn receive(&mut self) {
+        while let Some(byte) = console_read() {
+            self.receive_byte(byte);
+        }
+    }
+    fn receive_byte(&mut self, mut byte: u8) {
+        let input_flags = InputFlags::from_bits_truncate(self.termios.c_iflag);
+        let local_flags = self.termios.local_flags();
+        if byte == b'\r' && input_flags.contains(InputFlags::ICRNL) {
+            byte = b'\n';
+        }
+        if let (Some(signal), Some(pgid)) = (self.signal_for(byte), self.foreground) {
+            self.flush();
+            signal_group(pgid, signal);
+            return;
+        }
+        let echoing = local_flags.contains(LocalFlags::ECHO);
+        if !local_flags.contains(LocalFlags::ICANON) {
+            self.ready.push_back(byte);
+            if echoing {
+                echo(&[byte]);
+            }
+            return;
+        }
+        let c_cc = self.termios.c_cc;
+        let erase = |count: usize| {
+            if echoing && local_flags.contains(LocalFlags::ECHOE) {
+                for _ in 0..count {
+                    echo(b"\x08 \x08");
+                }
+            } else if echoing {
+                echo(&[byte]);
+            }
+        };
+        if byte == c_cc[VERASE] {
+            if self.line.pop().is_some() {
+                erase(1);
+            }
+        } else if byte == c_cc[VKILL] {
+            erase(self.line.len());
+            self.line.clear();
+        } else if byte == c_cc[VEOF] {
+            if self.line.is_empty() {
+                self.eof = true;
+            }
+            self.ready.extend(self.line.drain(..));
+        } else if byte == b'\n' {
+            self.line.push(byte);
+            self.ready.extend(self.line.drain(..));
+            if echoing {
+                echo(b"\n");
+            }
+        } else if self.line.len() < MAX_CANON {
+            self.line.push(byte);
+            if echoing {
+                echo(&[byte]);
+            }
+        }
+    }
+}
+
+/// Read console input into `user_buf`, as the line discipline hands it
+/// over, waiting for some unless `nonblocking`. 0 at end of file.
+pub fn tty_read(user_buf: UserBuffer, nonblocking: bool) -> usize {
+    loop {
+        {
+            let mut tty = TTY.exclusive_access();
+            tty.receive();
+            if !tty.ready.is_empty() {
+                let cooked = tty.cooked();
+                let mut read_size = 0;
+                for byte_ref in user_buf.into_iter() {
+                    let byte = match tty.ready.pop_front() {
+                        Some(byte) => byte,
+                        None => break,
+                    };
+                    unsafe {
+                        byte_ref.write_volatile(byte);
+                    }
+                    read_size += 1;
+                    if cooked && byte == b'\n' {
+                        break;
+                    }
+                }
+                return read_size;
+            }
+            if tty.eof {
+                tty.eof = false;
+                return 0;
+            }
+        }
+        if nonblocking || current_signal_interrupted() {
+            return 0;
+        }
+        READERS.block_current_and_run_next();
+    }
+}
+
+/// Whether a task waits for console input, which only an interrupt brings.
+pub fn tty_has_readers() -> bool {
+    !READERS.is_empty()
+}
+
+/// Whether the tick is to look for console input, there being a reader but
+/// no interrupt to bring it.
+pub fn tty_needs_tick() -> bool {
+    tty_has_readers() && !console_has_irq()
+}
+
+/// Whether a read of the console would not wait.
+pub fn tty_read_ready() -> bool {
+    let mut tty = TTY.exclusive_access();
+    tty.receive();
+    !tty.ready.is_empty() || tty.eof
+}
+
+/// The console settings.
+pub fn tty_termios() -> Termios {
+    TTY.exclusive_access().termios
+}
+
+/// Change the console settings, discarding the input not read yet if
+/// `flush`. A line being edited is handed over as it is when leaving cooked
+/// mode.
+pub fn tty_set_termios(termios: Termios, flush: bool) {
+    let mut tty = TTY.exclusive_access();
+    if flush {
+        tty.receive();
+        tty.flush();
+    }
+    tty.termios = termios;
+    if !tty.cooked() {
+        let line = core::mem::take(&mut tty.line);
+        tty.ready.extend(line);
+    }
+}
+
+/// Console interrupt, after the UART or virtio console took in what it
+/// received: process the input and wake the readers if there is some to
+/// read, unless the console or the task table is in use, leaving it for
+/// later.
+pub fn tty_interrupt() {
+    if task_table_in_use() {
+        return;
+    }
+    let readable = match TTY.try_exclusive_access() {
+        Some(mut tty) => {
+            tty.receive();
+            !tty.ready.is_empty() || tty.eof
+        }
+        None => return,
+    };
+    if readable {
+        READERS.wake_all();
+    }
+}
+
+/// The foreground process group of the console, if one was set.
+pub fn tty_foreground() -> Option<usize> {
+    TTY.exclusive_access().foreground
+}
+
+/// Make `pgid` the foreground process group of the console.
+pub fn tty_set_foreground(pgid: usize) {
+    TTY.exclusive_access().foreground = Some(pgid);
+}
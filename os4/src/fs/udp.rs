@@ -0,0 +1,157 @@
+//! UDP sockets
+//!
+//! A socket owns its bound port in the network stack until it is dropped.
+//! Plain `read` takes the next datagram without its source address;
+//! `recvfrom` and `sendto` are the full interface. A receive waits on the
+//! queue of its socket, which the stack wakes when a datagram arrives.
+
+use super::{File, PollEvents, SocketOptions};
+use crate::mm::UserBuffer;
+use crate::net::{self, Datagram, Endpoint, Ipv4Addr};
+use crate::sync::UPSafeCell;
+use crate::task::{current_signal_interrupted, WaitQueue};
+use alloc::sync::Arc;
+use core::cell::RefMut;
+
+struct SocketState {
+    /// bound local port, 0 before the first bind or send
+    port: u16,
+    options: SocketOptions,
+}
+
+pub struct UdpSocket {
+    state: UPSafeCell<SocketState>,
+    /// tasks blocked receiving on this socket
+    waiters: Arc<WaitQueue>,
+}
+
+impl UdpSocket {
+    pub fn new(nonblocking: bool) -> Self {
+        Self {
+            state: unsafe {
+                UPSafeCell::new(SocketState {
+                    port: 0,
+                    options: SocketOptions {
+                        nonblocking,
+                        ..SocketOptions::default()
+                    },
+                })
+            },
+            waiters: Arc::new(WaitQueue::new()),
+        }
+    }
+    /// `SO_REUSEADDR` is kept but has no effect: UDP ports are never
+    /// shared.
+    pub fn options(&self) -> RefMut<'_, SocketOptions> {
+        RefMut::map(self.state.exclusive_access(), |state| &mut state.options)
+    }
+    /// Bind to `port`, an ephemeral one if 0. Fails if already bound or the
+    /// port is taken.
+    pub fn bind(&self, port: u16) -> bool {
+        let mut state = self.state.exclusive_access();
+        if state.port != 0 {
+            return false;
+        }
+        match net::udp_bind(port) {
+            Some(port) => {
+                state.port = port;
+                net::watch(Endpoint::Udp(port), &self.waiters);
+                true
+            }
+            None => false,
+        }
+    }
+    /// The bound port, binding an ephemeral one first if needed.
+    fn local_port(&self) -> Option<u16> {
+        let port = self.state.exclusive_access().port;
+        if port != 0 || self.bind(0) {
+            Some(self.state.exclusive_access().port)
+        } else {
+            None
+        }
+    }
+    pub fn send_to(&self, data: &[u8], dst: Ipv4Addr, dst_port: u16) -> bool {
+        match self.local_port() {
+            Some(port) => net::udp_send(port, dst, dst_port, data),
+            None => false,
+        }
+    }
+    /// Take the next datagram, waiting for one unless the socket is
+    /// non-blocking or `dont_wait` is set. `None` if there is none or the
+    /// wait was interrupted by a signal.
+    pub fn recv_from(&self, dont_wait: bool) -> Option<Datagram> {
+        let port = self.local_port()?;
+        let dont_wait = dont_wait || self.nonblocking();
+        loop {
+            if let Some(datagram) = net::udp_recv(port) {
+                return Some(datagram);
+            }
+            if dont_wait || current_signal_interrupted() {
+                return None;
+            }
+            self.waiters.block_current_and_run_next();
+        }
+    }
+}
+
+impl Drop for UdpSocket {
+    fn drop(&mut self) {
+        let port = self.state.exclusive_access().port;
+        if port != 0 {
+            net::udp_unbind(port);
+        }
+    }
+}
+
+impl File for UdpSocket {
+    fn readable(&self) -> bool {
+        true
+    }
+    /// There is no connected peer to write to; use `sendto`.
+    fn writable(&self) -> bool {
+        false
+    }
+    /// Read the next datagram, truncated to the buffer.
+    fn read(&self, user_buf: UserBuffer) -> usize {
+        let datagram = match self.recv_from(false) {
+            Some(datagram) => datagram,
+            None => return 0,
+        };
+        let mut len = 0;
+        for (byte_ref, byte) in user_buf.into_iter().zip(datagram.data.iter()) {
+            unsafe {
+                byte_ref.write_volatile(*byte);
+            }
+            len += 1;
+        }
+        len
+    }
+    fn write(&self, _user_buf: UserBuffer) -> usize {
+        0
+    }
+    /// Readable once a datagram is queued; sends never wait.
+    fn poll(&self, events: PollEvents) -> PollEvents {
+        let port = self.state.exclusive_access().port;
+        let mut ready = PollEvents::POLLOUT;
+        if port != 0 && net::udp_can_recv(port) {
+            ready |= PollEvents::POLLIN;
+        }
+        ready & events
+    }
+    fn nonblocking(&self) -> bool {
+        self.options().nonblocking
+    }
+    fn set_nonblocking(&self, nonblocking: bool) -> bool {
+        self.options().nonblocking = nonblocking;
+        true
+    }
+    fn recv_timeout(&self) -> Option<usize> {
+        self.options().recv_timeout
+    }
+    fn wait_queue(&self) -> Option<&WaitQueue> {
+        Some(&self.waiters)
+    }
+    fn as_udp_socket(&self) -> Option<&UdpSocket> {
+        Some(self)
+    }
+}
@@ -0,0 +1,44 @@
+//! `/dev/urandom`: the kernel random number generator
+
+use super::File;
+use crate::mm::UserBuffer;
+use crate::random::fill_random;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Reads never block; writes are accepted and discarded
+pub struct Urandom {
+    nonblocking: AtomicBool,
+}
+
+impl Urandom {
+    pub fn new() -> Self {
+        Self {
+            nonblocking: AtomicBool::new(false),
+        }
+    }
+}
+
+impl File for Urandom {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        true
+    }
+    fn read(&self, mut user_buf: UserBuffer) -> usize {
+        for buffer in user_buf.buffers.iter_mut() {
+            fill_random(buffer);
+        }
+        user_buf.len()
+    }
+    fn write(&self, user_buf: UserBuffer) -> usize {
+        user_buf.len()
+    }
+    fn nonblocking(&self) -> bool {
+        self.nonblocking.load(Ordering::Relaxed)
+    }
+    fn set_nonblocking(&self, nonblocking: bool) -> bool {
+        self.nonblocking.store(nonblocking, Ordering::Relaxed);
+        true
+    }
+}
@@ -0,0 +1,66 @@
+//! G-stage translation, from guest physical to host physical addresses
+//!
+//! Sv39x4 is Sv39 with two more address bits at the root, whose 2048
+//! entries take four pages aligned to 16 KiB. Every leaf has `U` set, as
+//! the G stage treats all guest accesses as user accesses.
+
+use crate::mm::{frame_alloc, frame_alloc_contiguous, FrameTracker};
+use crate::mm::{PTEFlags, PageTableEntry, PhysPageNum};
+use alloc::vec::Vec;
+
+/// pages of the root table
+const ROOT_PAGES: usize = 4;
+/// `MODE` field of `hgatp` selecting Sv39x4
+const HGATP_MODE: usize = 8 << 60;
+
+/// A G-stage page table, owning its tables but not the frames it maps
+pub struct GStage {
+    root: Vec<FrameTracker>,
+    frames: Vec<FrameTracker>,
+}
+
+impl GStage {
+    pub fn new() -> Option<Self> {
+        // four aligned pages are among any seven contiguous ones; the others
+        // are freed again
+        let mut frames = frame_alloc_contiguous(2 * ROOT_PAGES - 1)?;
+        let first = frames
+            .iter()
+            .position(|frame| frame.ppn.0 % ROOT_PAGES == 0)
+            .unwrap();
+        let root = frames.drain(first..first + ROOT_PAGES).collect();
+        Some(Self {
+            root,
+            frames: Vec::new(),
+        })
+    }
+    /// The value of `hgatp` selecting this table.
+    pub fn hgatp(&self) -> usize {
+        HGATP_MODE | self.root[0].ppn.0
+    }
+    /// Map the guest physical page `gpn` to the frame `ppn`, readable,
+    /// writable and executable. Fails if a table cannot be allocated.
+    pub fn map(&mut self, gpn: usize, ppn: PhysPageNum) -> Option<()> {
+        let root = (gpn >> 18) & (ROOT_PAGES * 512 - 1);
+        let mut pte = &mut PhysPageNum(self.root[0].ppn.0 + root / 512).get_pte_array()[root % 512];
+        for index in [(gpn >> 9) & 511, gpn & 511] {
+            if !pte.is_valid() {
+                let frame = frame_alloc()?;
+                *pte = PageTableEntry::new(frame.ppn, PTEFlags::V);
+                self.frames.push(frame);
+            }
+            pte = &mut pte.ppn().get_pte_array()[index];
+        }
+        *pte = PageTableEntry::new(
+            ppn,
+            PTEFlags::V
+                | PTEFlags::R
+                | PTEFlags::W
+                | PTEFlags::X
+                | PTEFlags::U
+                | PTEFlags::A
+                | PTEFlags::D,
+        );
+        Some(())
+    }
+}
@@ -0,0 +1,235 @@
+//! Guest kernels, built with the `hypervisor` feature
+//!
+//! An experiment with the H extension: the application named by `guest=`
+//! on the command line runs as a small guest kernel in VS-mode instead of
+//! as a user program. Otherwise it is a task like any other, scheduled,
+//! preempted and killed as the others are.
+//!
+//! - Memory: its `PT_LOAD` segments are copied into [`GUEST_RAM_SIZE`]
+//!   bytes of guest RAM at guest physical address [`GUEST_RAM_BASE`],
+//!   which a G-stage table maps. It starts with `vsatp` bare, at its entry
+//!   point, with `a0` the hart id and `a1` 0 as there is no device tree.
+//! - Traps: its own page faults, breakpoints and user ecalls, and its VS
+//!   interrupts, are delegated to it, as KVM does. Everything else comes to
+//!   the kernel through the trap path of user tasks: the task's address
+//!   space holds only the trampoline and the trap context, where the guest
+//!   registers are saved.
+//! - SBI: its ecalls are emulated, see [`sbi`]. The timer it sets raises
+//!   its VS timer interrupt on the first return to it past the deadline, so
+//!   no sooner than the next kernel tick.
+//!
+//! There is one guest at most, with one hart and no devices. The VS CSRs
+//! are not switched, as nothing else uses them, and its memory is kept
+//! until shutdown. A trap that is not emulated, such as a guest page fault
+//! outside its RAM or a virtual instruction, ends it.
+
+mod gstage;
+mod sbi;
+
+#[cfg(target_pointer_width = "32")]
+compile_error!("the hypervisor feature supports riscv64 only");
+
+use crate::board::{has_extensions, Extensions};
+use crate::config::{GUEST_RAM_BASE, GUEST_RAM_SIZE, PAGE_SIZE};
+use crate::mm::{frame_alloc_contiguous, FrameTracker, PhysAddr};
+use crate::sync::UPSafeCell;
+use crate::timer::get_time;
+use crate::trap::TrapContext;
+use alloc::vec::Vec;
+use core::arch::asm;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use gstage::GStage;
+use lazy_static::*;
+
+/// `hstatus.SPV`: `sret` enters VS-mode
+const HSTATUS_SPV: usize = 1 << 7;
+/// `hvip.VSTIP`: the VS timer interrupt is pending
+const HVIP_VSTIP: usize = 1 << 6;
+/// Exceptions of VS- and VU-mode the guest handles itself: misaligned
+/// fetches, breakpoints, user ecalls and page faults
+const HEDELEG: usize = 1 << 0 | 1 << 3 | 1 << 8 | 1 << 12 | 1 << 13 | 1 << 15;
+/// VS software, timer and external interrupts
+const HIDELEG: usize = 1 << 2 | 1 << 6 | 1 << 10;
+/// The guest may read `cycle`, `time` and `instret`
+const HCOUNTEREN: usize = 0b111;
+
+const CAUSE_VIRTUAL_SUPERVISOR_ECALL: usize = 10;
+const CAUSE_INSTRUCTION_GUEST_PAGE_FAULT: usize = 20;
+const CAUSE_LOAD_GUEST_PAGE_FAULT: usize = 21;
+const CAUSE_VIRTUAL_INSTRUCTION: usize = 22;
+const CAUSE_STORE_GUEST_PAGE_FAULT: usize = 23;
+
+/// The task running the guest, `usize::MAX` if none
+static GUEST_TASK: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// State of the guest kept by the kernel
+pub struct Guest {
+    /// owned for as long as the guest may run
+    #[allow(unused)]
+    gstage: GStage,
+    #[allow(unused)]
+    ram: Vec<FrameTracker>,
+    /// when its timer interrupt is due, in `time` ticks
+    timer: Option<usize>,
+}
+
+lazy_static! {
+    static ref GUEST: UPSafeCell<Option<Guest>> = unsafe { UPSafeCell::new(None) };
+}
+
+impl Guest {
+    fn set_timer(&mut self, deadline: usize) {
+        self.timer = Some(deadline);
+        unsafe {
+            asm!("csrc 0x645, {}", in(reg) HVIP_VSTIP);
+        }
+    }
+}
+
+/// Copy the segments of the guest kernel `elf_data` into a new guest RAM.
+/// Returns the entry point, or None if the harts lack the H extension or the
+/// image does not fit. The guest runs once [`set_guest_task`] names its task.
+pub fn load(elf_data: &[u8]) -> Option<usize> {
+    if !has_extensions(Extensions::H) {
+        warn!("[kernel] no H extension, cannot run a guest");
+        return None;
+    }
+    let elf = xmas_elf::ElfFile::new(elf_data).ok()?;
+    let ram = frame_alloc_contiguous(GUEST_RAM_SIZE / PAGE_SIZE)?;
+    let host_base = PhysAddr::from(ram[0].ppn).0;
+    for ph in elf.program_iter() {
+        if ph.get_type() != Ok(xmas_elf::program::Type::Load) {
+            continue;
+        }
+        let start = ph.physical_addr() as usize;
+        let end = start + ph.mem_size() as usize;
+        if start < GUEST_RAM_BASE || end > GUEST_RAM_BASE + GUEST_RAM_SIZE {
+            warn!(
+                "[kernel] guest segment {:#x}..{:#x} outside its RAM",
+                start, end
+            );
+            return None;
+        }
+        let data = &elf.input[ph.offset() as usize..(ph.offset() + ph.file_size()) as usize];
+        // RAM is identity-mapped in the kernel, and cleared
+        let dst = host_base + start - GUEST_RAM_BASE;
+        unsafe {
+            core::slice::from_raw_parts_mut(dst as *mut u8, data.len()).copy_from_slice(data);
+        }
+    }
+    let mut gstage = GStage::new()?;
+    for (i, frame) in ram.iter().enumerate() {
+        gstage.map(GUEST_RAM_BASE / PAGE_SIZE + i, frame.ppn)?;
+    }
+    unsafe {
+        asm!(
+            "csrw 0x602, {hedeleg}",
+            "csrw 0x603, {hideleg}",
+            "csrw 0x606, {hcounteren}",
+            // htimedelta: the guest sees the time of the host
+            "csrw 0x605, zero",
+            "csrw 0x680, {hgatp}",
+            ".word 0x62000073", // hfence.gvma zero, zero
+            hedeleg = in(reg) HEDELEG,
+            hideleg = in(reg) HIDELEG,
+            hcounteren = in(reg) HCOUNTEREN,
+            hgatp = in(reg) gstage.hgatp(),
+        );
+    }
+    *GUEST.exclusive_access() = Some(Guest {
+        gstage,
+        ram,
+        timer: None,
+    });
+    Some(elf.header.pt2.entry_point() as usize)
+}
+
+/// Make task `task`, created with the entry point [`load`] returned, run
+/// the guest.
+pub fn set_guest_task(task: usize) {
+    GUEST_TASK.store(task, Ordering::Relaxed);
+    info!(
+        "[kernel] task {} is a guest, {:#x} bytes of RAM at {:#x}",
+        task, GUEST_RAM_SIZE, GUEST_RAM_BASE
+    );
+}
+
+/// Whether task `task` runs the guest.
+pub fn is_guest(task: usize) -> bool {
+    GUEST_TASK.load(Ordering::Relaxed) == task
+}
+
+/// Reaping path: task `task` is gone, taking the guest RAM with it if it ran
+/// the guest.
+pub fn on_reap(task: usize) {
+    if GUEST_TASK
+        .compare_exchange(task, usize::MAX, Ordering::Relaxed, Ordering::Relaxed)
+        .is_ok()
+    {
+        *GUEST.exclusive_access() = None;
+    }
+}
+
+/// Return path to task `task`: make `sret` enter VS-mode if it runs the
+/// guest, raising its timer interrupt if due, and U-mode otherwise.
+pub fn prepare_entry(task: usize) {
+    let guest_task = GUEST_TASK.load(Ordering::Relaxed);
+    if guest_task == usize::MAX {
+        return;
+    }
+    if task != guest_task {
+        unsafe {
+            asm!("csrc 0x600, {}", in(reg) HSTATUS_SPV);
+        }
+        return;
+    }
+    let due = GUEST
+        .exclusive_access()
+        .as_ref()
+        .and_then(|guest| guest.timer)
+        .map_or(false, |deadline| get_time() >= deadline);
+    unsafe {
+        asm!("csrs 0x600, {}", in(reg) HSTATUS_SPV);
+        if due {
+            asm!("csrs 0x645, {}", in(reg) HVIP_VSTIP);
+        }
+    }
+}
+
+/// Handle the exception of cause `scause` the guest took, with registers in
+/// `cx`. Returns false if the guest must end.
+pub fn handle_exception(cx: &mut TrapContext, scause: usize, stval: usize) -> bool {
+    match scause {
+        CAUSE_VIRTUAL_SUPERVISOR_ECALL => {
+            cx.sepc += 4;
+            let mut guest = GUEST.exclusive_access();
+            sbi::emulate(cx, guest.as_mut().unwrap())
+        }
+        CAUSE_INSTRUCTION_GUEST_PAGE_FAULT
+        | CAUSE_LOAD_GUEST_PAGE_FAULT
+        | CAUSE_STORE_GUEST_PAGE_FAULT => {
+            let htval: usize;
+            unsafe {
+                asm!("csrr {}, 0x643", out(reg) htval);
+            }
+            // htval holds the guest physical address shifted right by 2
+            error!(
+                "[kernel] guest page fault at {:#x}, pc {:#x}",
+                htval << 2 | stval & 3,
+                cx.sepc
+            );
+            false
+        }
+        CAUSE_VIRTUAL_INSTRUCTION => {
+            error!("[kernel] guest virtual instruction at {:#x}", cx.sepc);
+            false
+        }
+        _ => {
+            error!(
+                "[kernel] guest exception {}, stval {:#x}, pc {:#x}",
+                scause, stval, cx.sepc
+            );
+            false
+        }
+    }
+}
@@ -0,0 +1,85 @@
+//! SBI calls of the guest, emulated by the kernel
+//!
+//! The guest sees an SBI 2.0 implementation with the base, TIME, SRST and
+//! DBCN extensions, and the legacy timer, console and shutdown calls. Its
+//! console is the kernel console; it reads no input.
+
+use super::Guest;
+use crate::trap::TrapContext;
+
+const SBI_SUCCESS: isize = 0;
+const SBI_ERR_NOT_SUPPORTED: isize = -2;
+
+const LEGACY_SET_TIMER: usize = 0x00;
+const LEGACY_CONSOLE_PUTCHAR: usize = 0x01;
+const LEGACY_CONSOLE_GETCHAR: usize = 0x02;
+const LEGACY_SHUTDOWN: usize = 0x08;
+const EXT_BASE: usize = 0x10;
+const EXT_TIME: usize = 0x5449_4d45;
+const EXT_SRST: usize = 0x5352_5354;
+const EXT_DBCN: usize = 0x4442_434e;
+
+/// SBI 2.0
+const SPEC_VERSION: usize = 2 << 24;
+/// not a registered implementation id: "rcor"
+const IMPL_ID: usize = 0x7263_6f72;
+
+/// Emulate the SBI call the guest made with `ecall`, whose registers are in
+/// `cx`. Returns false if the guest asked to be shut down.
+pub fn emulate(cx: &mut TrapContext, guest: &mut Guest) -> bool {
+    let (eid, fid, arg0) = (cx.x[17], cx.x[16], cx.x[10]);
+    let legacy = match eid {
+        LEGACY_SET_TIMER => {
+            guest.set_timer(arg0);
+            Some(SBI_SUCCESS)
+        }
+        LEGACY_CONSOLE_PUTCHAR => {
+            print!("{}", arg0 as u8 as char);
+            Some(SBI_SUCCESS)
+        }
+        LEGACY_CONSOLE_GETCHAR => Some(-1),
+        LEGACY_SHUTDOWN => return false,
+        _ => None,
+    };
+    // legacy calls return a single value in a0
+    if let Some(value) = legacy {
+        cx.x[10] = value as usize;
+        return true;
+    }
+    let (error, value) = match (eid, fid) {
+        (EXT_BASE, 0) => (SBI_SUCCESS, SPEC_VERSION),
+        (EXT_BASE, 1) => (SBI_SUCCESS, IMPL_ID),
+        (EXT_BASE, 2) => (SBI_SUCCESS, 0),
+        (EXT_BASE, 3) => (SBI_SUCCESS, probe(arg0) as usize),
+        // mvendorid, marchid, mimpid
+        (EXT_BASE, 4..=6) => (SBI_SUCCESS, 0),
+        (EXT_TIME, 0) => {
+            guest.set_timer(arg0);
+            (SBI_SUCCESS, 0)
+        }
+        (EXT_SRST, 0) => return false,
+        (EXT_DBCN, 2) => {
+            print!("{}", arg0 as u8 as char);
+            (SBI_SUCCESS, 0)
+        }
+        _ => (SBI_ERR_NOT_SUPPORTED, 0),
+    };
+    cx.x[10] = error as usize;
+    cx.x[11] = value;
+    true
+}
+
+/// Whether the extension `eid` is emulated.
+fn probe(eid: usize) -> bool {
+    matches!(
+        eid,
+        LEGACY_SET_TIMER
+            | LEGACY_CONSOLE_PUTCHAR
+            | LEGACY_CONSOLE_GETCHAR
+            | LEGACY_SHUTDOWN
+            | EXT_BASE
+            | EXT_TIME
+            | EXT_SRST
+            | EXT_DBCN
+    )
+}
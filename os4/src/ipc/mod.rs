@@ -0,0 +1,23 @@
+//! System V style inter-process communication
+//!
+//! IPC objects live in kernel-global registries and are named by a user
+//! chosen key; [`IPC_PRIVATE`] always creates a fresh object.
+
+mod sem;
+
+pub use sem::{sem_exit, semctl, semget, semop, SemBuf, SemOpError, SEMOPM};
+
+/// key that always creates a new object
+pub const IPC_PRIVATE: usize = 0;
+
+bitflags! {
+    /// flags of the `*get` and `*op` calls
+    pub struct IpcFlags: u32 {
+        /// create the object if the key does not exist
+        const IPC_CREAT  = 0o1000;
+        /// fail if the key already exists
+        const IPC_EXCL   = 0o2000;
+        /// return an error instead of waiting
+        const IPC_NOWAIT = 0o4000;
+    }
+}
@@ -0,0 +1,250 @@
+//! Semaphore sets
+//!
+//! A set holds `nsems` counting semaphores that [`semop`] updates as a
+//! group: either every operation of a call is applied, or the caller blocks
+//! until some semaphore changes (or fails with `IPC_NOWAIT`) and none is.
+//! Operations flagged `SEM_UNDO` are recorded per task and reverted by
+//! [`sem_exit`] when the task exits.
+
+use super::{IpcFlags, IPC_PRIVATE};
+use crate::sync::UPSafeCell;
+use crate::task::{current_signal_interrupted, WaitQueue};
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+use lazy_static::*;
+
+/// max number of semaphores in a set
+const SEMMSL: usize = 250;
+/// max number of operations in one `semop`
+pub const SEMOPM: usize = 32;
+/// max value of a semaphore
+const SEMVMX: i32 = 32767;
+
+/// undo the operation when the task exits
+const SEM_UNDO: i16 = 0x1000;
+
+/// `semctl` commands
+const IPC_RMID: usize = 0;
+const GETVAL: usize = 12;
+const SETVAL: usize = 16;
+
+/// One operation of a `semop` call, layout compatible with `struct sembuf`
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct SemBuf {
+    pub sem_num: u16,
+    pub sem_op: i16,
+    pub sem_flg: i16,
+}
+
+struct SemSet {
+    key: usize,
+    values: Vec<i32>,
+}
+
+struct SemRegistry {
+    sets: BTreeMap<usize, SemSet>,
+    next_id: usize,
+    /// pid -> (semid, sem_num) -> adjustment applied at exit
+    undo: BTreeMap<usize, BTreeMap<(usize, usize), i32>>,
+}
+
+lazy_static! {
+    static ref SEM_REGISTRY: UPSafeCell<SemRegistry> = unsafe {
+        UPSafeCell::new(SemRegistry {
+            sets: BTreeMap::new(),
+            next_id: 0,
+            undo: BTreeMap::new(),
+        })
+    };
+    /// tasks whose `semop` would block, woken whenever a value changes or a
+    /// set is removed
+    static ref SEM_WAITERS: WaitQueue = WaitQueue::new();
+}
+
+/// Why [`semop`] failed
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SemOpError {
+    /// no such set, a semaphore out of range or a value out of bounds
+    Invalid,
+    /// the operations would wait and one of them has `IPC_NOWAIT`
+    WouldBlock,
+    Interrupted,
+}
+
+/// The result of trying a whole `semop` call at once
+enum SemOpResult {
+    Done,
+    WouldBlock,
+    Invalid,
+}
+
+impl SemRegistry {
+    fn try_semop(&mut self, pid: usize, semid: usize, sops: &[SemBuf]) -> SemOpResult {
+        let set = match self.sets.get_mut(&semid) {
+            Some(set) => set,
+            None => return SemOpResult::Invalid,
+        };
+        // check the operations against a scratch copy so that a call is
+        // applied atomically or not at all
+        let mut values = set.values.clone();
+        for sop in sops {
+            let value = match values.get_mut(sop.sem_num as usize) {
+                Some(value) => value,
+                None => return SemOpResult::Invalid,
+            };
+            let op = sop.sem_op as i32;
+            if op == 0 {
+                if *value != 0 {
+                    return SemOpResult::WouldBlock;
+                }
+            } else if *value + op < 0 {
+                return SemOpResult::WouldBlock;
+            } else if *value + op > SEMVMX {
+                return SemOpResult::Invalid;
+            } else {
+                *value += op;
+            }
+        }
+        set.values = values;
+        for sop in sops.iter().filter(|sop| sop.sem_flg & SEM_UNDO != 0) {
+            *self
+                .undo
+                .entry(pid)
+                .or_insert_with(BTreeMap::new)
+                .entry((semid, sop.sem_num as usize))
+                .or_insert(0) -= sop.sem_op as i32;
+        }
+        SemOpResult::Done
+    }
+
+    /// Forget the undo adjustments of `semid` (`sem_num` `None` for all).
+    fn clear_undo(&mut self, semid: usize, sem_num: Option<usize>) {
+        for adjustments in self.undo.values_mut() {
+            adjustments.retain(|(id, num), _| {
+                *id != semid || sem_num.map_or(false, |sem_num| *num != sem_num)
+            });
+        }
+    }
+}
+
+/// Get the id of the semaphore set named `key`, creating a set of `nsems`
+/// zeroed semaphores if asked to.
+pub fn semget(key: usize, nsems: usize, flags: IpcFlags) -> isize {
+    let mut registry = SEM_REGISTRY.exclusive_access();
+    if key != IPC_PRIVATE {
+        if let Some((&semid, set)) = registry.sets.iter().find(|(_, set)| set.key == key) {
+            if flags.contains(IpcFlags::IPC_CREAT | IpcFlags::IPC_EXCL) || nsems > set.values.len()
+            {
+                return -1;
+            }
+            return semid as isize;
+        }
+        if !flags.contains(IpcFlags::IPC_CREAT) {
+            return -1;
+        }
+    }
+    if nsems == 0 || nsems > SEMMSL {
+        return -1;
+    }
+    let semid = registry.next_id;
+    registry.next_id += 1;
+    registry.sets.insert(
+        semid,
+        SemSet {
+            key,
+            values: vec![0; nsems],
+        },
+    );
+    semid as isize
+}
+
+/// Apply all of `sops` to set `semid` atomically on behalf of task `pid`,
+/// waiting until they can all proceed unless one of them has `IPC_NOWAIT`.
+pub fn semop(pid: usize, semid: usize, sops: &[SemBuf]) -> Result<(), SemOpError> {
+    if sops.is_empty() || sops.len() > SEMOPM {
+        return Err(SemOpError::Invalid);
+    }
+    let nowait = sops
+        .iter()
+        .any(|sop| sop.sem_flg as u32 & IpcFlags::IPC_NOWAIT.bits() != 0);
+    loop {
+        let result = SEM_REGISTRY.exclusive_access().try_semop(pid, semid, sops);
+        match result {
+            SemOpResult::Done => {
+                SEM_WAITERS.wake_all();
+                return Ok(());
+            }
+            SemOpResult::Invalid => return Err(SemOpError::Invalid),
+            SemOpResult::WouldBlock => {
+                if nowait {
+                    return Err(SemOpError::WouldBlock);
+                }
+                if current_signal_interrupted() {
+                    return Err(SemOpError::Interrupted);
+                }
+                SEM_WAITERS.block_current_and_run_next();
+            }
+        }
+    }
+}
+
+/// Control operations on set `semid`: `GETVAL`, `SETVAL` and `IPC_RMID`.
+pub fn semctl(semid: usize, sem_num: usize, cmd: usize, arg: usize) -> isize {
+    let mut registry = SEM_REGISTRY.exclusive_access();
+    if !registry.sets.contains_key(&semid) {
+        return -1;
+    }
+    match cmd {
+        IPC_RMID => {
+            registry.sets.remove(&semid);
+            registry.clear_undo(semid, None);
+            SEM_WAITERS.wake_all();
+            0
+        }
+        GETVAL => match registry.sets[&semid].values.get(sem_num) {
+            Some(value) => *value as isize,
+            None => -1,
+        },
+        SETVAL => {
+            let value = arg as i32;
+            if !(0..=SEMVMX).contains(&value) {
+                return -1;
+            }
+            match registry
+                .sets
+                .get_mut(&semid)
+                .unwrap()
+                .values
+                .get_mut(sem_num)
+            {
+                Some(slot) => *slot = value,
+                None => return -1,
+            }
+            registry.clear_undo(semid, Some(sem_num));
+            SEM_WAITERS.wake_all();
+            0
+        }
+        _ => -1,
+    }
+}
+
+/// Revert the `SEM_UNDO` operations of exiting task `pid`.
+pub fn sem_exit(pid: usize) {
+    let mut registry = SEM_REGISTRY.exclusive_access();
+    let adjustments = match registry.undo.remove(&pid) {
+        Some(adjustments) => adjustments,
+        None => return,
+    };
+    for ((semid, sem_num), adjustment) in adjustments {
+        if let Some(value) = registry
+            .sets
+            .get_mut(&semid)
+            .and_then(|set| set.values.get_mut(sem_num))
+        {
+            *value = (*value + adjustment).clamp(0, SEMVMX);
+        }
+    }
+    SEM_WAITERS.wake_all();
+}
@@ -0,0 +1,49 @@
+//! Kernel assertions that need not bring the kernel down
+//!
+//! [`debug_assert_kernel!`] checks a condition like `assert!`, but what a
+//! failure does is chosen at boot: with `assert=panic` the kernel panics,
+//! with `assert=kill` the failure is logged and the current task killed
+//! with exit code -4, so that a bug in one syscall path ends one task and
+//! not a long stress run. The default is to panic with the `debug`
+//! feature, to kill otherwise, see `KILL_ON_ASSERT`.
+//!
+//! A task is killed from wherever the assertion failed, so what the code
+//! there holds is never dropped. Frames and heap it allocated leak, and a
+//! shared cell it borrowed stays borrowed: the failure panics anyway if no
+//! task runs yet or the task table is borrowed, the kill needing both.
+
+use crate::task::{
+    current_task_id, current_task_name, exit_current_and_run_next, task_table_in_use, tasks_started,
+};
+use core::fmt::Arguments;
+
+/// Check `cond`, panicking or killing the current task if it is false, with
+/// a message formatted as for `assert!`.
+#[macro_export]
+macro_rules! debug_assert_kernel {
+    ($cond: expr) => {
+        $crate::debug_assert_kernel!($cond, "{}", stringify!($cond))
+    };
+    ($cond: expr, $($arg: tt)+) => {
+        if !$cond {
+            $crate::kassert::failed(file!(), line!(), format_args!($($arg)+));
+        }
+    };
+}
+
+/// Failure path of [`debug_assert_kernel!`].
+pub fn failed(file: &str, line: u32, message: Arguments) -> ! {
+    if !crate::cmdline::kill_on_assert() || !tasks_started() || task_table_in_use() {
+        panic!("assertion failed at {}:{}: {}", file, line, message);
+    }
+    error!(
+        "[kernel] assertion failed at {}:{}: {}, killing application {} (pid {})",
+        file,
+        line,
+        message,
+        current_task_name(),
+        current_task_id()
+    );
+    exit_current_and_run_next(-4);
+    unreachable!("killed task ran again");
+}
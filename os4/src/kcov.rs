@@ -0,0 +1,154 @@
+//! Kernel coverage collection, built with the `kcov` feature
+//!
+//! `make KCOV=1` builds the kernel with LLVM's `trace-pc` sanitizer
+//! coverage, which calls `__sanitizer_cov_trace_pc` at the start of every
+//! basic block. While one task collects coverage, the call appends its
+//! return address to a buffer mapped into that task, so that a fuzzer or a
+//! test can see which kernel code its syscalls reached.
+//!
+//! The buffer is an array of words: the first counts the pcs that follow
+//! it. The collecting task resets it to 0 itself, as with Linux kcov. Only
+//! the syscalls of that task are traced, not interrupts nor other tasks.
+//!
+//! The callback is written in assembly, as Rust code would be instrumented
+//! and call itself.
+
+use crate::config::{KCOV_VADDR, PAGE_SIZE};
+use crate::mm::{frame_alloc_contiguous, FrameTracker, MapPermission, PhysAddr};
+use crate::sync::UPSafeCell;
+use crate::task::{current_map_linear, current_task_id, task_user_token};
+use alloc::vec::Vec;
+use core::mem::size_of;
+use lazy_static::*;
+
+/// Where pcs are appended, 0 when nothing is traced. Kept in `.data` as it
+/// is read before `.bss` is cleared.
+#[no_mangle]
+#[link_section = ".data.kcov"]
+static mut KCOV_AREA: usize = 0;
+/// pcs that fit in the area after the count
+#[no_mangle]
+#[link_section = ".data.kcov"]
+static mut KCOV_CAPACITY: usize = 0;
+
+global_asm_xlen!(
+    "
+    .section .text
+    .globl __sanitizer_cov_trace_pc
+__sanitizer_cov_trace_pc:
+    la t0, KCOV_AREA
+    LOAD t0, 0(t0)
+    beqz t0, 1f
+    LOAD t1, 0(t0)
+    la t2, KCOV_CAPACITY
+    LOAD t2, 0(t2)
+    bgeu t1, t2, 1f
+    addi t1, t1, 1
+    STORE t1, 0(t0)
+    slli t1, t1, LOG_REGBYTES
+    add t1, t0, t1
+    STORE ra, 0(t1)
+1:
+    ret
+    "
+);
+
+struct Kcov {
+    /// task the buffer is mapped into
+    owner: Option<usize>,
+    buffer: Vec<FrameTracker>,
+    enabled: bool,
+    /// whether the owner is in a syscall, traced if enabled
+    in_syscall: bool,
+}
+
+lazy_static! {
+    static ref KCOV: UPSafeCell<Kcov> = unsafe {
+        UPSafeCell::new(Kcov {
+            owner: None,
+            buffer: Vec::new(),
+            enabled: false,
+            in_syscall: false,
+        })
+    };
+}
+
+impl Kcov {
+    /// Point the callback at the buffer if `pid` is being traced.
+    fn update(&self, pid: usize) {
+        let traced = self.owner == Some(pid) && self.enabled && self.in_syscall;
+        let (area, capacity) = match self.buffer.first() {
+            Some(frame) if traced => (
+                PhysAddr::from(frame.ppn).0,
+                self.buffer.len() * PAGE_SIZE / size_of::<usize>() - 1,
+            ),
+            _ => (0, 0),
+        };
+        unsafe {
+            KCOV_CAPACITY = capacity;
+            KCOV_AREA = area;
+        }
+    }
+}
+
+/// Allocate a buffer of `pages` pages and map it at `KCOV_VADDR` in the
+/// current task, which becomes the one collecting coverage. Fails if
+/// another task that has not exited already collects it.
+pub fn init(pages: usize) -> Option<usize> {
+    let mut kcov = KCOV.exclusive_access();
+    let pid = current_task_id();
+    // the owner has the buffer mapped already
+    let taken = matches!(kcov.owner, Some(owner) if task_user_token(owner).is_some());
+    if pages == 0 || taken {
+        return None;
+    }
+    let buffer = frame_alloc_contiguous(pages)?;
+    let perm = MapPermission::R | MapPermission::W | MapPermission::U;
+    if current_map_linear(KCOV_VADDR, buffer[0].ppn, pages, perm) != 0 {
+        return None;
+    }
+    kcov.owner = Some(pid);
+    kcov.buffer = buffer;
+    kcov.enabled = false;
+    // the new owner is in this very syscall
+    kcov.in_syscall = true;
+    kcov.update(pid);
+    Some(KCOV_VADDR)
+}
+
+/// Start or stop collecting for the current task, if it owns the buffer.
+pub fn enable(enabled: bool) -> bool {
+    let mut kcov = KCOV.exclusive_access();
+    let pid = current_task_id();
+    if kcov.owner != Some(pid) {
+        return false;
+    }
+    kcov.enabled = enabled;
+    kcov.update(pid);
+    true
+}
+
+/// Syscall path: task `pid` enters (`true`) or leaves a syscall.
+pub fn on_syscall(pid: usize, entering: bool) {
+    let mut kcov = KCOV.exclusive_access();
+    if kcov.owner == Some(pid) {
+        kcov.in_syscall = entering;
+    }
+    kcov.update(pid);
+}
+
+/// Reaping path: task `pid` is gone, freeing the buffer if it was the
+/// owner's.
+pub fn on_reap(pid: usize) {
+    let mut kcov = KCOV.exclusive_access();
+    if kcov.owner == Some(pid) {
+        kcov.owner = None;
+        kcov.buffer.clear();
+        kcov.enabled = false;
+    }
+}
+
+/// Task switch path: `next` is about to run.
+pub fn on_switch(next: usize) {
+    KCOV.exclusive_access().update(next);
+}
@@ -0,0 +1,105 @@
+//! Kernel unit tests, and the results of user tests
+//!
+//! Each module lists its tests as a slice of [`KernelTest`]s, built with
+//! [`test_case!`], and [`run`] runs them all with `test=on` or `test=exit`
+//! on the command line, once memory management is up and before devices
+//! are. A test passes by returning and fails by panicking: as a panic
+//! cannot be recovered from, the first failure ends the run, the panic
+//! handler naming the test and exiting with [`ExitCode::Failure`] rather
+//! than [`ExitCode::Panic`]. With `test=exit`, a run where all tests passed
+//! exits with status 0 instead of going on to boot, so that tests can run
+//! headlessly under QEMU.
+//!
+//! User tests report their results with `sys_test_report`, typically from
+//! the harness `harness=` names, which spawns them one by one. Once all
+//! tasks are done, [`print_reports`] prints them, one `[test] PASS name` or
+//! `[test] FAIL name` line each and a `[test] result:` line, and the kernel
+//! exits with status 0 if they all passed, 1 otherwise.
+
+use crate::cmdline;
+use crate::power::{exit_qemu, ExitCode};
+use crate::sync::UPSafeCell;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ptr::null_mut;
+use core::sync::atomic::{AtomicPtr, Ordering};
+use lazy_static::*;
+
+/// A test, and the name it is reported under
+pub struct KernelTest {
+    pub name: &'static str,
+    pub run: fn(),
+}
+
+/// A [`KernelTest`] running the function at `path`, named after it.
+macro_rules! test_case {
+    ($path: path) => {
+        $crate::ktest::KernelTest {
+            name: stringify!($path),
+            run: $path,
+        }
+    };
+}
+
+/// the test running, null between tests
+static RUNNING: AtomicPtr<KernelTest> = AtomicPtr::new(null_mut());
+
+lazy_static! {
+    /// name and result of each user test reported, in order
+    static ref REPORTS: UPSafeCell<Vec<(String, bool)>> = unsafe { UPSafeCell::new(Vec::new()) };
+}
+
+/// Run the tests of every module, returning if they all pass and `test=on`
+/// was given.
+pub fn run() {
+    let suites: [(&str, &[KernelTest]); 2] =
+        [("mm", crate::mm::TESTS), ("task", crate::task::TESTS)];
+    let count: usize = suites.iter().map(|(_, tests)| tests.len()).sum();
+    println!("[kernel] running {} kernel tests", count);
+    for (module, tests) in suites.iter() {
+        for test in tests.iter() {
+            RUNNING.store(test as *const _ as *mut _, Ordering::Relaxed);
+            (test.run)();
+            RUNNING.store(null_mut(), Ordering::Relaxed);
+            println!("[kernel] test {}::{} ... ok", module, test.name);
+        }
+    }
+    println!("[kernel] test result: ok. {} passed", count);
+    if cmdline::test_exit() {
+        exit_qemu(ExitCode::Success);
+    }
+}
+
+/// Panic path: report the test that panicked, if one was running, and exit
+/// with the failure status.
+pub fn on_panic() {
+    let test = RUNNING.load(Ordering::Relaxed);
+    if !test.is_null() {
+        println!("[kernel] test {} ... FAILED", unsafe { (*test).name });
+        exit_qemu(ExitCode::Failure);
+    }
+}
+
+/// Record the result of the user test `name`.
+pub fn report(name: String, passed: bool) {
+    REPORTS.exclusive_access().push((name, passed));
+}
+
+/// Print the results of the user tests, returning whether they all passed,
+/// or None if none was reported.
+pub fn print_reports() -> Option<bool> {
+    let reports = REPORTS.exclusive_access();
+    if reports.is_empty() {
+        return None;
+    }
+    for (name, passed) in reports.iter() {
+        println!("[test] {} {}", if *passed { "PASS" } else { "FAIL" }, name);
+    }
+    let passed = reports.iter().filter(|(_, passed)| *passed).count();
+    println!(
+        "[test] result: {} passed, {} failed",
+        passed,
+        reports.len() - passed
+    );
+    Some(passed == reports.len())
+}
@@ -1,6 +1,6 @@
 //! The panic handler
 
-use crate::sbi::shutdown;
+use crate::power::{exit_qemu, ExitCode};
 use core::panic::PanicInfo;
 
 #[panic_handler]
@@ -16,5 +16,7 @@ fn panic(info: &PanicInfo) -> ! {
     } else {
         println!("[kernel] Panicked: {}", info.message().unwrap());
     }
-    shutdown()
+    crate::crash::dump(info);
+    crate::ktest::on_panic();
+    exit_qemu(ExitCode::Panic)
 }
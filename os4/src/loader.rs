@@ -1,5 +1,8 @@
 //! Loading user applications into memory
 
+use alloc::vec::Vec;
+use lazy_static::*;
+
 /// Get the total number of applications.
 pub fn get_num_app() -> usize {
     extern "C" {
@@ -24,3 +27,36 @@ pub fn get_app_data(app_id: usize) -> &'static [u8] {
         )
     }
 }
+
+lazy_static! {
+    /// names of the applications, in the order of their ids
+    static ref APP_NAMES: Vec<&'static str> = {
+        extern "C" {
+            fn _app_names();
+        }
+        let mut start = _app_names as usize as *const u8;
+        let mut names = Vec::new();
+        unsafe {
+            for _ in 0..get_num_app() {
+                let mut end = start;
+                while end.read_volatile() != b'\0' {
+                    end = end.add(1);
+                }
+                let slice = core::slice::from_raw_parts(start, end as usize - start as usize);
+                names.push(core::str::from_utf8(slice).unwrap());
+                start = end.add(1);
+            }
+        }
+        names
+    };
+}
+
+/// The name of application `app_id`.
+pub fn get_app_name(app_id: usize) -> &'static str {
+    APP_NAMES[app_id]
+}
+
+/// The id of the application called `name`, if there is one.
+pub fn find_app(name: &str) -> Option<usize> {
+    APP_NAMES.iter().position(|&app| app == name)
+}
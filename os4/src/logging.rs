@@ -1,16 +1,150 @@
 //! Global logger
+//!
+//! Records go to the log channel as `<ms> <level> <target>: <message>`
+//! lines when there is one, or are printed in color on the console. Which
+//! are shown starts out as selected by the `LOG` build variable and can be
+//! changed at runtime, for everything or per module (`task`, `mm::page_table`
+//! and so on). Whatever is shown, records up to [`RING_LEVEL`] are also kept
+//! in a ring of [`LOG_BUFFER_SIZE`] bytes that user space reads back with
+//! `dmesg`, unless their module is set to a lower level.
 
+use crate::config::LOG_BUFFER_SIZE;
+use crate::drivers::chardev::{has_log_channel, log_write};
+use crate::sync::UPSafeCell;
+use crate::timer::get_time_ms;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use lazy_static::*;
 use log::{self, Level, LevelFilter, Log, Metadata, Record};
 
+/// least severe records kept in the ring
+const RING_LEVEL: LevelFilter = LevelFilter::Info;
+
+static mut LOG_BUFFER: [u8; LOG_BUFFER_SIZE] = [0; LOG_BUFFER_SIZE];
+
+/// The latest log lines, oldest overwritten first
+struct LogRing {
+    /// bytes written since boot; the ring holds the last of them
+    written: usize,
+    /// bytes written before the last clear
+    cleared: usize,
+}
+
+impl Write for LogRing {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &byte in s.as_bytes() {
+            unsafe {
+                LOG_BUFFER[self.written % LOG_BUFFER_SIZE] = byte;
+            }
+            self.written += 1;
+        }
+        Ok(())
+    }
+}
+
+lazy_static! {
+    static ref LOG_RING: UPSafeCell<LogRing> = unsafe {
+        UPSafeCell::new(LogRing {
+            written: 0,
+            cleared: 0,
+        })
+    };
+    static ref FILTERS: UPSafeCell<Filters> = unsafe {
+        UPSafeCell::new(Filters {
+            default: match option_env!("LOG") {
+                Some("ERROR") => LevelFilter::Error,
+                Some("WARN") => LevelFilter::Warn,
+                Some("INFO") => LevelFilter::Info,
+                Some("DEBUG") => LevelFilter::Debug,
+                Some("TRACE") => LevelFilter::Trace,
+                _ => LevelFilter::Off,
+            },
+            modules: Vec::new(),
+        })
+    };
+}
+
+/// Least severe records shown on the console or log channel
+struct Filters {
+    default: LevelFilter,
+    /// module path below the crate root -> its level, overriding the
+    /// default for the module and its submodules
+    modules: Vec<(String, LevelFilter)>,
+}
+
+impl Filters {
+    /// The level set for the module of `target`, if any. The most specific
+    /// module wins.
+    fn module_level(&self, target: &str) -> Option<LevelFilter> {
+        // targets are module paths, starting with the crate name
+        let path = target.split_once("::").map_or("", |(_, path)| path);
+        self.modules
+            .iter()
+            .filter(|(module, _)| {
+                path.strip_prefix(module.as_str())
+                    .map_or(false, |rest| rest.is_empty() || rest.starts_with("::"))
+            })
+            .max_by_key(|(module, _)| module.len())
+            .map(|&(_, level)| level)
+    }
+    /// Whether `level` records of `target` are shown, and whether they are
+    /// kept in the ring.
+    fn check(&self, level: Level, target: &str) -> (bool, bool) {
+        match self.module_level(target) {
+            Some(module_level) => (level <= module_level, level <= module_level.min(RING_LEVEL)),
+            None => (level <= self.default, level <= RING_LEVEL),
+        }
+    }
+    /// The least severe level any record may pass with.
+    fn max_level(&self) -> LevelFilter {
+        self.modules
+            .iter()
+            .map(|&(_, level)| level)
+            .fold(self.default.max(RING_LEVEL), Ord::max)
+    }
+}
+
 /// a simple logger
 struct SimpleLogger;
 
 impl Log for SimpleLogger {
-    fn enabled(&self, _metadata: &Metadata) -> bool {
-        true
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        let (shown, kept) = FILTERS
+            .exclusive_access()
+            .check(metadata.level(), metadata.target());
+        shown || kept
     }
     fn log(&self, record: &Record) {
-        if !self.enabled(record.metadata()) {
+        let (shown, kept) = FILTERS
+            .exclusive_access()
+            .check(record.level(), record.target());
+        if kept {
+            let ms = get_time_ms();
+            writeln!(
+                LOG_RING.exclusive_access(),
+                "[{:>5}.{:03}] {:<5} {}: {}",
+                ms / 1000,
+                ms % 1000,
+                record.level(),
+                record.target(),
+                record.args()
+            )
+            .unwrap();
+        }
+        if !shown {
+            return;
+        }
+        if has_log_channel() {
+            let line = format!(
+                "{} {:<5} {}: {}\n",
+                get_time_ms(),
+                record.level(),
+                record.target(),
+                record.args()
+            );
+            log_write(line.as_bytes());
             return;
         }
         let color = match record.level() {
@@ -34,12 +168,71 @@ impl Log for SimpleLogger {
 pub fn init() {
     static LOGGER: SimpleLogger = SimpleLogger;
     log::set_logger(&LOGGER).unwrap();
-    log::set_max_level(match option_env!("LOG") {
-        Some("ERROR") => LevelFilter::Error,
-        Some("WARN") => LevelFilter::Warn,
-        Some("INFO") => LevelFilter::Info,
-        Some("DEBUG") => LevelFilter::Debug,
-        Some("TRACE") => LevelFilter::Trace,
-        _ => LevelFilter::Off,
-    });
+    log::set_max_level(FILTERS.exclusive_access().max_level());
+}
+
+/// Show records up to `level` from `module`, a module path below the crate
+/// root such as `task` or `mm::page_table`, or from every module without a
+/// level of its own if `module` is empty. `None` makes `module` follow the
+/// default again.
+pub fn set_log_level(module: &str, level: Option<LevelFilter>) {
+    let mut filters = FILTERS.exclusive_access();
+    let module = module.trim_matches(':');
+    if module.is_empty() {
+        if let Some(level) = level {
+            filters.default = level;
+        }
+    } else {
+        filters.modules.retain(|(name, _)| name != module);
+        if let Some(level) = level {
+            filters.modules.push((String::from(module), level));
+        }
+    }
+    log::set_max_level(filters.max_level());
+}
+
+/// The kernel log kept since boot or the last clear, starting at a whole
+/// line once older lines were overwritten.
+pub fn kernel_log() -> Vec<u8> {
+    let ring = LOG_RING.exclusive_access();
+    let start = ring
+        .cleared
+        .max(ring.written.saturating_sub(LOG_BUFFER_SIZE));
+    let mut log: Vec<u8> = (start..ring.written)
+        .map(|i| unsafe { LOG_BUFFER[i % LOG_BUFFER_SIZE] })
+        .collect();
+    if start > ring.cleared {
+        // the first line lost its beginning
+        let line_end = log.iter().position(|&byte| byte == b'\n');
+        log.drain(..line_end.map_or(log.len(), |end| end + 1));
+    }
+    log
+}
+
+/// Write the last `len` bytes of the kernel log to `out` without
+/// allocating, for when the heap cannot be trusted. Only notes that the
+/// ring is in use if it is.
+pub fn write_kernel_log_tail(out: &mut dyn Write, len: usize) -> fmt::Result {
+    let ring = match LOG_RING.try_exclusive_access() {
+        Some(ring) => ring,
+        None => return writeln!(out, "log ring in use"),
+    };
+    let start = ring
+        .cleared
+        .max(ring.written.saturating_sub(LOG_BUFFER_SIZE.min(len)));
+    for i in start..ring.written {
+        out.write_char(unsafe { LOG_BUFFER[i % LOG_BUFFER_SIZE] } as char)?;
+    }
+    Ok(())
+}
+
+/// Bytes the ring can hold.
+pub fn kernel_log_capacity() -> usize {
+    LOG_BUFFER_SIZE
+}
+
+/// Forget the kernel log kept so far.
+pub fn clear_kernel_log() {
+    let mut ring = LOG_RING.exclusive_access();
+    ring.cleared = ring.written;
 }
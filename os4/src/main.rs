@@ -27,21 +27,66 @@ extern crate log;
 
 extern crate alloc;
 
+/// Assemble `$asm` after the register width macros of the target, so that
+/// the same assembly builds for riscv32 and riscv64.
+macro_rules! global_asm_xlen {
+    ($($asm: expr),+) => {
+        #[cfg(target_pointer_width = "64")]
+        core::arch::global_asm!(
+            include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/src/xlen64.S")),
+            $($asm),+
+        );
+        #[cfg(target_pointer_width = "32")]
+        core::arch::global_asm!(
+            include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/src/xlen32.S")),
+            $($asm),+
+        );
+    };
+}
+
+mod acct;
+mod audit;
+mod board;
+mod cmdline;
 #[macro_use]
 mod console;
+#[macro_use]
+mod trace;
+#[macro_use]
+mod kassert;
 mod config;
+mod crash;
+mod drivers;
+#[cfg(feature = "fault-inject")]
+mod fault;
+mod fs;
+#[cfg(feature = "hypervisor")]
+mod hypervisor;
+mod ipc;
+#[cfg(feature = "kcov")]
+mod kcov;
+#[macro_use]
+mod ktest;
 mod lang_items;
 mod loader;
 mod logging;
 mod mm;
+mod net;
+mod perf;
+mod power;
+mod profile;
+mod random;
+mod record_log;
 mod sbi;
 mod sync;
+mod sysrq;
 pub mod syscall;
 pub mod task;
 mod timer;
 pub mod trap;
+mod watchdog;
 
-core::arch::global_asm!(include_str!("entry.asm"));
+global_asm_xlen!(include_str!("entry.asm"));
 core::arch::global_asm!(include_str!("link_app.S"));
 
 /// clear BSS segment
@@ -58,17 +103,31 @@ fn clear_bss() {
 
 #[no_mangle]
 /// the rust entry-point of os
-pub fn rust_main() -> ! {
+pub fn rust_main(hart_id: usize, dtb: usize) -> ! {
     clear_bss();
+    power::save_boot_data();
     logging::init();
     println!("[kernel] Hello, world!");
+    board::init(hart_id, dtb);
+    crash::init();
     mm::init();
+    cmdline::init();
     println!("[kernel] back to world!");
     mm::remap_test();
+    if cmdline::test_mode() {
+        ktest::run();
+    }
+    drivers::init();
+    net::init();
+    perf::init();
     trap::init();
     //trap::enable_interrupt();
     trap::enable_timer_interrupt();
+    trap::enable_external_interrupt();
+    timer::init();
     timer::set_next_trigger();
+    #[cfg(feature = "syscall-fuzz")]
+    syscall::add_fuzz_target();
     task::run_first_task();
     panic!("Unreachable in rust_main!");
 }
@@ -1,5 +1,6 @@
 //! Implementation of physical and virtual address and page number.
 
+use super::paging::{PAGE_TABLE_LEVELS, PTES_PER_TABLE, VPN_INDEX_BITS};
 use super::PageTableEntry;
 use crate::config::{PAGE_SIZE, PAGE_SIZE_BITS};
 use core::fmt::{self, Debug, Formatter};
@@ -140,21 +141,30 @@ impl From<PhysPageNum> for PhysAddr {
 }
 
 impl VirtPageNum {
-    pub fn indexes(&self) -> [usize; 3] {
+    pub fn indexes(&self) -> [usize; PAGE_TABLE_LEVELS] {
         let mut vpn = self.0;
-        let mut idx = [0usize; 3];
-        for i in (0..3).rev() {
-            idx[i] = vpn & 511;
-            vpn >>= 9;
+        let mut idx = [0usize; PAGE_TABLE_LEVELS];
+        for i in (0..PAGE_TABLE_LEVELS).rev() {
+            idx[i] = vpn & (PTES_PER_TABLE - 1);
+            vpn >>= VPN_INDEX_BITS;
         }
         idx
     }
 }
 
+impl PhysAddr {
+    pub fn get_ref<T>(&self) -> &'static T {
+        unsafe { (self.0 as *const T).as_ref().unwrap() }
+    }
+    pub fn get_mut<T>(&self) -> &'static mut T {
+        unsafe { (self.0 as *mut T).as_mut().unwrap() }
+    }
+}
+
 impl PhysPageNum {
     pub fn get_pte_array(&self) -> &'static mut [PageTableEntry] {
         let pa: PhysAddr = (*self).into();
-        unsafe { core::slice::from_raw_parts_mut(pa.0 as *mut PageTableEntry, 512) }
+        unsafe { core::slice::from_raw_parts_mut(pa.0 as *mut PageTableEntry, PTES_PER_TABLE) }
     }
     pub fn get_bytes_array(&self) -> &'static mut [u8] {
         let pa: PhysAddr = (*self).into();
@@ -0,0 +1,134 @@
+//! Contiguous memory area for drivers
+//!
+//! At boot, `CMA_SIZE` bytes at the end of the largest range of usable
+//! memory are kept out of the frame allocator. Drivers get physically
+//! contiguous and aligned buffers from there with [`frame_alloc_dma`],
+//! which a fragmented frame allocator may not have. The frames are ordinary
+//! [`FrameTracker`]s, going back to the area when dropped. When the area
+//! cannot satisfy a request, the frame allocator is tried instead.
+
+use super::frame_allocator::frame_alloc_contiguous;
+use super::{FrameTracker, PhysPageNum};
+use crate::config::{CMA_ALIGN, CMA_SIZE, PAGE_SIZE};
+use crate::sync::UPSafeCell;
+use alloc::vec;
+use alloc::vec::Vec;
+use lazy_static::*;
+
+struct Cma {
+    /// first frame of the area
+    start: usize,
+    /// whether each frame of the area is allocated
+    used: Vec<bool>,
+}
+
+impl Cma {
+    /// First fit of `pages` free frames starting at a multiple of `align`.
+    fn alloc(&mut self, pages: usize, align: usize) -> Option<PhysPageNum> {
+        let align_up = |ppn: usize| (ppn + align - 1) / align * align;
+        let mut first = align_up(self.start);
+        while first + pages <= self.start + self.used.len() {
+            let frames = first - self.start..first - self.start + pages;
+            match self.used[frames.clone()].iter().rposition(|&used| used) {
+                // start again past the last frame in use
+                Some(i) => first = align_up(first + i + 1),
+                None => {
+                    self.used[frames].fill(true);
+                    return Some(first.into());
+                }
+            }
+        }
+        None
+    }
+}
+
+lazy_static! {
+    static ref CMA: UPSafeCell<Cma> = unsafe {
+        UPSafeCell::new(Cma {
+            start: 0,
+            used: Vec::new(),
+        })
+    };
+}
+
+/// Take the area out of the largest of the `ranges` of usable memory, if it
+/// leaves most of that range to the frame allocator.
+pub(super) fn init(ranges: &mut [(PhysPageNum, PhysPageNum)]) {
+    let pages = CMA_SIZE / PAGE_SIZE;
+    let range = match ranges.iter_mut().max_by_key(|(start, end)| end.0 - start.0) {
+        Some(range) if range.1 .0 - range.0 .0 >= 4 * pages => range,
+        _ => {
+            warn!("[kernel] too little memory for the contiguous memory area");
+            return;
+        }
+    };
+    let align = CMA_ALIGN / PAGE_SIZE;
+    let start = (range.1 .0 - pages) / align * align;
+    let end = range.1 .0;
+    range.1 = start.into();
+    let mut cma = CMA.exclusive_access();
+    cma.start = start;
+    cma.used = vec![false; end - start];
+    info!(
+        "[kernel] contiguous memory area: [{:#x}, {:#x})",
+        start * PAGE_SIZE,
+        end * PAGE_SIZE
+    );
+}
+
+/// Give the frame `ppn` back to the area if it is from there, returning
+/// whether it was.
+pub(super) fn dealloc(ppn: PhysPageNum) -> bool {
+    let mut cma = CMA.exclusive_access();
+    if ppn.0 < cma.start || ppn.0 >= cma.start + cma.used.len() {
+        return false;
+    }
+    let i = ppn.0 - cma.start;
+    assert!(
+        cma.used[i],
+        "Frame ppn={:#x} has not been allocated!",
+        ppn.0
+    );
+    cma.used[i] = false;
+    true
+}
+
+/// Allocate `pages` physically contiguous frames for a device, the first
+/// one at a multiple of `align` pages, from the contiguous memory area or
+/// else from the frame allocator.
+pub fn frame_alloc_dma(pages: usize, align: usize) -> Option<Vec<FrameTracker>> {
+    assert!(
+        align.is_power_of_two(),
+        "DMA alignment must be a power of two"
+    );
+    if pages == 0 {
+        return None;
+    }
+    let base = CMA.exclusive_access().alloc(pages, align);
+    if let Some(base) = base {
+        return Some(
+            (0..pages)
+                .map(|i| FrameTracker::new(PhysPageNum(base.0 + i)))
+                .collect(),
+        );
+    }
+    // an aligned run is among any `pages + align - 1` frames; the others
+    // are freed again
+    let mut frames = frame_alloc_contiguous(pages + align - 1)?;
+    let first = frames
+        .iter()
+        .position(|frame| frame.ppn.0 % align == 0)
+        .unwrap();
+    Some(frames.drain(first..first + pages).collect())
+}
+
+/// Allocate aligned buffers for devices, and free them.
+pub fn cma_test() {
+    for &(pages, align) in &[(1, 1), (3, 4), (2, 16)] {
+        let frames = frame_alloc_dma(pages, align).unwrap();
+        assert_eq!(frames.len(), pages);
+        assert_eq!(frames[0].ppn.0 % align, 0);
+        assert!(frames.windows(2).all(|w| w[1].ppn.0 == w[0].ppn.0 + 1));
+    }
+    info!("cma_test passed!");
+}
@@ -0,0 +1,65 @@
+//! Buffers for device DMA
+//!
+//! A device reaches memory by physical address, the kernel through its own
+//! mapping. A [`DmaBuffer`] is physically contiguous, stays at the same
+//! frames until it is dropped, as only the frames of user pages are ever
+//! moved, and gives both addresses. Drivers hand devices parts of one as
+//! [`DmaRegion`]s rather than the address of any kernel memory, which may
+//! be on a kernel stack that is not mapped at its physical address.
+
+use super::cma::frame_alloc_dma;
+use super::{FrameTracker, PhysAddr, VirtAddr};
+use crate::config::PAGE_SIZE;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+/// `len` bytes at `paddr`, as a device sees them
+#[derive(Copy, Clone)]
+pub struct DmaRegion {
+    pub paddr: PhysAddr,
+    pub len: usize,
+}
+
+/// Zeroed memory for a device to read or write
+pub struct DmaBuffer {
+    frames: Vec<FrameTracker>,
+    len: usize,
+}
+
+impl DmaBuffer {
+    /// Allocate `len` bytes, starting on a page boundary.
+    pub fn new(len: usize) -> Option<Self> {
+        let frames = frame_alloc_dma((len + PAGE_SIZE - 1) / PAGE_SIZE, 1)?;
+        Some(Self { frames, len })
+    }
+    /// The address the device uses.
+    pub fn paddr(&self) -> PhysAddr {
+        self.frames[0].ppn.into()
+    }
+    /// The address the kernel uses: kernel space maps all physical memory
+    /// at the same address.
+    pub fn vaddr(&self) -> VirtAddr {
+        self.paddr().0.into()
+    }
+    /// Number of frames, the last one maybe not used in full.
+    pub fn pages(&self) -> usize {
+        self.frames.len()
+    }
+    /// The bytes at `range` of the buffer, to hand to the device.
+    pub fn region(&self, range: Range<usize>) -> DmaRegion {
+        assert!(
+            range.start <= range.end && range.end <= self.len,
+            "DMA region out of its buffer"
+        );
+        DmaRegion {
+            paddr: (self.paddr().0 + range.start).into(),
+            len: range.end - range.start,
+        }
+    }
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.vaddr().0 as *const u8, self.len) }
+    }
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.vaddr().0 as *mut u8, self.len) }
+    }
+}
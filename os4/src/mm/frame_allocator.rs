@@ -1,8 +1,15 @@
-//! Implementation of [`FrameAllocator`] which 
+//! Implementation of [`FrameAllocator`] which
 //! controls all the frames in the operating system.
 
+use super::cma;
+use super::memory_map::usable_memory;
+#[cfg(feature = "page-poison")]
+use super::page_poison;
+use super::pressure::update_pressure;
 use super::{PhysAddr, PhysPageNum};
-use crate::config::MEMORY_END;
+use crate::board::board_info;
+use crate::cmdline::memory_limit;
+use crate::config::{PAGE_SIZE, ZERO_FRAMES_ON_FREE};
 use crate::sync::UPSafeCell;
 use alloc::vec::Vec;
 use core::fmt::{self, Debug, Formatter};
@@ -15,6 +22,8 @@ pub struct FrameTracker {
 
 impl FrameTracker {
     pub fn new(ppn: PhysPageNum) -> Self {
+        #[cfg(feature = "page-poison")]
+        page_poison::check(ppn);
         // page cleaning
         let bytes_array = ppn.get_bytes_array();
         for i in bytes_array {
@@ -32,6 +41,12 @@ impl Debug for FrameTracker {
 
 impl Drop for FrameTracker {
     fn drop(&mut self) {
+        // unmapping a page and a task exiting both free frames this way
+        #[cfg(feature = "page-poison")]
+        page_poison::poison(self.ppn);
+        if ZERO_FRAMES_ON_FREE && cfg!(not(feature = "page-poison")) {
+            self.ppn.get_bytes_array().fill(0);
+        }
         frame_dealloc(self.ppn);
     }
 }
@@ -39,44 +54,168 @@ impl Drop for FrameTracker {
 trait FrameAllocator {
     fn new() -> Self;
     fn alloc(&mut self) -> Option<PhysPageNum>;
+    fn alloc_contiguous(&mut self, pages: usize) -> Option<PhysPageNum>;
     fn dealloc(&mut self, ppn: PhysPageNum);
 }
 
-/// an implementation for frame allocator
-pub struct StackFrameAllocator {
+/// A range of usable frames, allocated from `start` up
+struct FrameRange {
+    start: usize,
     current: usize,
     end: usize,
+}
+
+/// an implementation for frame allocator
+pub struct StackFrameAllocator {
+    /// in address order
+    ranges: Vec<FrameRange>,
     recycled: Vec<usize>,
+    /// usable memory held back at boot, as [start, end) frames, until it
+    /// is added
+    offline: Vec<(usize, usize)>,
 }
 
 impl StackFrameAllocator {
-    pub fn init(&mut self, l: PhysPageNum, r: PhysPageNum) {
-        self.current = l.0;
-        self.end = r.0;
+    /// Manage the frames of `ranges`, in address order, up to `limit` of
+    /// them; the others, and those of `held`, are kept offline.
+    pub fn init(
+        &mut self,
+        ranges: &[(PhysPageNum, PhysPageNum)],
+        held: &[(PhysPageNum, PhysPageNum)],
+        limit: usize,
+    ) {
+        self.offline = held.iter().map(|&(l, r)| (l.0, r.0)).collect();
+        let mut left = limit;
+        for &(l, r) in ranges {
+            let online = (r.0 - l.0).min(left);
+            left -= online;
+            if online > 0 {
+                self.ranges.push(FrameRange {
+                    start: l.0,
+                    current: l.0,
+                    end: l.0 + online,
+                });
+            }
+            if l.0 + online < r.0 {
+                self.offline.push((l.0 + online, r.0));
+            }
+        }
+    }
+    /// Start managing the offline frames [start, end). Fails if any of them
+    /// is not offline.
+    fn add_range(&mut self, start: usize, end: usize) -> bool {
+        let i = match self
+            .offline
+            .iter()
+            .position(|&(l, r)| l <= start && end <= r && start < end)
+        {
+            Some(i) => i,
+            None => return false,
+        };
+        let (l, r) = self.offline.remove(i);
+        if end < r {
+            self.offline.insert(i, (end, r));
+        }
+        if l < start {
+            self.offline.insert(i, (l, start));
+        }
+        let at = self.ranges.partition_point(|range| range.start < start);
+        self.ranges.insert(
+            at,
+            FrameRange {
+                start,
+                current: start,
+                end,
+            },
+        );
+        true
+    }
+    /// Take the lowest recycled frame below `ppn`.
+    fn alloc_below(&mut self, ppn: PhysPageNum) -> Option<PhysPageNum> {
+        let (i, _) = self
+            .recycled
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| **v < ppn.0)
+            .min_by_key(|(_, v)| **v)?;
+        Some(self.recycled.swap_remove(i).into())
+    }
+    /// Give the recycled frames at the top of each range back to its
+    /// never-allocated part, where contiguous runs come from.
+    fn trim(&mut self) {
+        // highest first, so that the lowest frames are allocated next
+        self.recycled.sort_unstable_by(|a, b| b.cmp(a));
+        for range in self.ranges.iter_mut() {
+            while range.current > range.start {
+                let top = range.current - 1;
+                match self.recycled.binary_search_by(|v| top.cmp(v)) {
+                    Ok(i) => {
+                        self.recycled.remove(i);
+                        range.current -= 1;
+                    }
+                    Err(_) => break,
+                }
+            }
+        }
+    }
+    pub fn stats(&self) -> FrameStats {
+        let total = self
+            .ranges
+            .iter()
+            .map(|range| range.end - range.start)
+            .sum();
+        let never_allocated = self
+            .ranges
+            .iter()
+            .map(|range| range.end - range.current)
+            .sum();
+        FrameStats {
+            total,
+            free: never_allocated + self.recycled.len(),
+            never_allocated,
+        }
     }
 }
 impl FrameAllocator for StackFrameAllocator {
     fn new() -> Self {
         Self {
-            current: 0,
-            end: 0,
+            ranges: Vec::new(),
             recycled: Vec::new(),
+            offline: Vec::new(),
         }
     }
     fn alloc(&mut self) -> Option<PhysPageNum> {
         if let Some(ppn) = self.recycled.pop() {
-            Some(ppn.into())
-        } else if self.current == self.end {
-            None
-        } else {
-            self.current += 1;
-            Some((self.current - 1).into())
+            return Some(ppn.into());
+        }
+        let range = self
+            .ranges
+            .iter_mut()
+            .find(|range| range.current < range.end)?;
+        range.current += 1;
+        Some((range.current - 1).into())
+    }
+    /// Recycled frames are scattered, so contiguous runs only come from the
+    /// never-allocated part of a range.
+    fn alloc_contiguous(&mut self, pages: usize) -> Option<PhysPageNum> {
+        if pages == 0 {
+            return None;
         }
+        let range = self
+            .ranges
+            .iter_mut()
+            .find(|range| range.end - range.current >= pages)?;
+        range.current += pages;
+        Some((range.current - pages).into())
     }
     fn dealloc(&mut self, ppn: PhysPageNum) {
         let ppn = ppn.0;
         // validity check
-        if ppn >= self.current || self.recycled.iter().any(|v| *v == ppn) {
+        let allocated = self
+            .ranges
+            .iter()
+            .any(|range| range.start <= ppn && ppn < range.current);
+        if !allocated || self.recycled.iter().any(|v| *v == ppn) {
             panic!("Frame ppn={:#x} has not been allocated!", ppn);
         }
         // recycle
@@ -92,45 +231,144 @@ lazy_static! {
         unsafe { UPSafeCell::new(FrameAllocatorImpl::new()) };
 }
 
-/// initiate the frame allocator using `ekernel` and `MEMORY_END`
+/// initiate the frame allocator with the usable memory the board has, but
+/// the hotpluggable banks and what is past `mem=`
 pub fn init_frame_allocator() {
-    extern "C" {
-        fn ekernel();
+    let hotpluggable = board_info().hotpluggable_regions().to_vec();
+    let (held, mut ranges): (Vec<_>, Vec<_>) =
+        usable_memory().into_iter().partition(|&(start, _)| {
+            let start = PhysAddr::from(start).0;
+            hotpluggable
+                .iter()
+                .any(|&(base, size)| base <= start && start < base + size)
+        });
+    assert!(!ranges.is_empty(), "no memory is left past the kernel");
+    cma::init(&mut ranges);
+    let limit = memory_limit().map_or(usize::MAX, |limit| limit / PAGE_SIZE);
+    FRAME_ALLOCATOR
+        .exclusive_access()
+        .init(&ranges, &held, limit);
+}
+
+/// Add the `len` bytes of memory at `paddr`, held back at boot with `mem=`
+/// or as a hotpluggable bank, to the frame allocator, returning whether
+/// they were all offline.
+pub fn hot_add_memory(paddr: usize, len: usize) -> bool {
+    let end = match paddr.checked_add(len) {
+        Some(end) if paddr % PAGE_SIZE == 0 && len % PAGE_SIZE == 0 => end,
+        _ => return false,
+    };
+    let mut allocator = FRAME_ALLOCATOR.exclusive_access();
+    if !allocator.add_range(paddr / PAGE_SIZE, end / PAGE_SIZE) {
+        return false;
     }
-    FRAME_ALLOCATOR.exclusive_access().init(
-        PhysAddr::from(ekernel as usize).ceil(),
-        PhysAddr::from(MEMORY_END).floor(),
-    );
+    let free = allocator.stats().free;
+    drop(allocator);
+    update_pressure(free);
+    info!("[kernel] added memory [{:#x}, {:#x})", paddr, end);
+    true
+}
+
+/// Frame counts of the allocator
+pub struct FrameStats {
+    pub total: usize,
+    pub free: usize,
+    /// free frames past the highest one ever allocated in each range, the
+    /// only ones a contiguous run can come from
+    pub never_allocated: usize,
+}
+
+/// Counts of the frame allocator, or `None` if it is in use.
+pub fn frame_stats() -> Option<FrameStats> {
+    Some(FRAME_ALLOCATOR.try_exclusive_access()?.stats())
 }
 
 /// allocate a frame
 pub fn frame_alloc() -> Option<FrameTracker> {
+    #[cfg(feature = "fault-inject")]
+    if crate::fault::should_fail(crate::fault::FaultPoint::Frame) {
+        return None;
+    }
+    let mut allocator = FRAME_ALLOCATOR.exclusive_access();
+    let ppn = allocator.alloc();
+    let free = allocator.stats().free;
+    drop(allocator);
+    update_pressure(free);
+    ppn.map(FrameTracker::new)
+}
+
+/// allocate a frame at a lower address than `ppn`, for moving the page
+/// there
+pub(super) fn frame_alloc_below(ppn: PhysPageNum) -> Option<FrameTracker> {
     FRAME_ALLOCATOR
         .exclusive_access()
-        .alloc()
+        .alloc_below(ppn)
         .map(FrameTracker::new)
 }
 
-/// deallocate a frame
+/// Make the free frames freed at the top of each range allocatable as
+/// contiguous runs again, after pages were moved down.
+pub fn trim_free_frames() {
+    FRAME_ALLOCATOR.exclusive_access().trim();
+}
+
+/// allocate `pages` physically contiguous frames, in address order. If
+/// memory is too fragmented, the pages of the tasks are compacted first.
+pub fn frame_alloc_contiguous(pages: usize) -> Option<Vec<FrameTracker>> {
+    let mut allocator = FRAME_ALLOCATOR.exclusive_access();
+    let mut base = allocator.alloc_contiguous(pages);
+    if base.is_none() && pages > 0 && allocator.stats().free >= pages {
+        drop(allocator);
+        crate::task::compact_memory();
+        allocator = FRAME_ALLOCATOR.exclusive_access();
+        base = allocator.alloc_contiguous(pages);
+    }
+    let free = allocator.stats().free;
+    drop(allocator);
+    update_pressure(free);
+    let base = base?;
+    Some(
+        (0..pages)
+            .map(|i| FrameTracker::new(PhysPageNum(base.0 + i)))
+            .collect(),
+    )
+}
+
+/// deallocate a frame, which may be from the contiguous memory area
 fn frame_dealloc(ppn: PhysPageNum) {
-    FRAME_ALLOCATOR.exclusive_access().dealloc(ppn);
+    if cma::dealloc(ppn) {
+        return;
+    }
+    let mut allocator = FRAME_ALLOCATOR.exclusive_access();
+    allocator.dealloc(ppn);
+    let free = allocator.stats().free;
+    drop(allocator);
+    update_pressure(free);
 }
 
-#[allow(unused)]
 /// a simple test for frame allocator
 pub fn frame_allocator_test() {
+    let free = frame_stats().unwrap().free;
     let mut v: Vec<FrameTracker> = Vec::new();
-    for i in 0..5 {
+    for _ in 0..5 {
         let frame = frame_alloc().unwrap();
         info!("{:?}", frame);
         v.push(frame);
     }
     v.clear();
-    for i in 0..5 {
+    for _ in 0..5 {
         let frame = frame_alloc().unwrap();
         info!("{:?}", frame);
         v.push(frame);
     }
+    assert_eq!(frame_stats().unwrap().free, free - 5);
     drop(v);
+    assert_eq!(frame_stats().unwrap().free, free);
+    // frames freed at the top of a range can be allocated as a run again
+    trim_free_frames();
+    let never_allocated = frame_stats().unwrap().never_allocated;
+    drop(frame_alloc_contiguous(4).unwrap());
+    trim_free_frames();
+    assert_eq!(frame_stats().unwrap().never_allocated, never_allocated);
     info!("frame_allocator_test passed!");
 }
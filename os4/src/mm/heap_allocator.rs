@@ -3,10 +3,23 @@
 use crate::config::KERNEL_HEAP_SIZE;
 use buddy_system_allocator::LockedHeap;
 
-#[global_allocator]
+#[cfg(not(feature = "leak-detector"))]
+#[cfg_attr(not(feature = "fault-inject"), global_allocator)]
 /// heap allocator instance
 static HEAP_ALLOCATOR: LockedHeap = LockedHeap::empty();
 
+#[cfg(feature = "leak-detector")]
+#[cfg_attr(not(feature = "fault-inject"), global_allocator)]
+/// heap allocator instance, recording live allocations
+static HEAP_ALLOCATOR: super::heap_track::TrackingHeap = super::heap_track::TrackingHeap {
+    heap: LockedHeap::empty(),
+};
+
+#[cfg(feature = "fault-inject")]
+#[global_allocator]
+/// heap allocator instance, failing allocations on demand
+static FAULTY_ALLOCATOR: crate::fault::FaultyAlloc = crate::fault::FaultyAlloc(&HEAP_ALLOCATOR);
+
 #[alloc_error_handler]
 /// panic when heap allocation error occurs
 pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
@@ -18,14 +31,25 @@ static mut HEAP_SPACE: [u8; KERNEL_HEAP_SIZE] = [0; KERNEL_HEAP_SIZE];
 
 /// initiate heap allocator
 pub fn init_heap() {
+    #[cfg(feature = "leak-detector")]
+    let heap = &HEAP_ALLOCATOR.heap;
+    #[cfg(not(feature = "leak-detector"))]
+    let heap = &HEAP_ALLOCATOR;
     unsafe {
-        HEAP_ALLOCATOR
-            .lock()
+        heap.lock()
             .init(HEAP_SPACE.as_ptr() as usize, KERNEL_HEAP_SIZE);
     }
 }
 
-#[allow(unused)]
+/// Bytes of the heap in use, with what allocations were rounded up by.
+pub fn heap_used() -> usize {
+    #[cfg(feature = "leak-detector")]
+    let heap = &HEAP_ALLOCATOR.heap;
+    #[cfg(not(feature = "leak-detector"))]
+    let heap = &HEAP_ALLOCATOR;
+    heap.lock().stats_alloc_actual()
+}
+
 pub fn heap_test() {
     use alloc::boxed::Box;
     use alloc::vec::Vec;
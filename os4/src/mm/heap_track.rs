@@ -0,0 +1,343 @@
+//! Heap allocation tracking, built with the `leak-detector` feature
+//!
+//! Every live allocation of the kernel heap is recorded with its size and
+//! the return addresses of the code that made it. Reporting the live ones
+//! grouped by call site shows what keeps piling up, e.g. page tables or
+//! task control blocks that are not freed when a task exits.
+//!
+//! The record lives in a fixed table, as the allocator cannot allocate.
+//! Allocations made once it is full are counted but not tracked.
+//!
+//! With the `heap-redzone` feature, each allocation also gets
+//! `HEAP_REDZONE_SIZE` bytes of [`REDZONE_BYTE`] on both sides, checked
+//! when it is freed, so that a write past either end, as easily made by
+//! syscalls casting user buffers to structs, is reported with the call site
+//! of the allocation. The latest frees are remembered too: freeing one of
+//! those again is reported as a double free, with where it was allocated
+//! and first freed, and is otherwise ignored rather than corrupt the heap.
+
+#[cfg(feature = "heap-redzone")]
+use crate::config::{HEAP_FREED_RECORDS, HEAP_REDZONE_SIZE};
+use crate::config::{HEAP_SITE_DEPTH, MAX_TRACKED_ALLOCATIONS};
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use buddy_system_allocator::LockedHeap;
+use core::alloc::{GlobalAlloc, Layout};
+#[cfg(feature = "heap-redzone")]
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+
+/// Return addresses skipped when recording a call site: those of the
+/// allocator itself and of the `alloc` glue calling it
+const SKIPPED_FRAMES: usize = 2;
+/// what redzones are filled with
+#[cfg(feature = "heap-redzone")]
+const REDZONE_BYTE: u8 = 0xfb;
+
+/// overruns and double frees reported
+#[cfg(feature = "heap-redzone")]
+static HEAP_ERRORS: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Copy, Clone)]
+struct Allocation {
+    /// 0 for a free slot
+    ptr: usize,
+    size: usize,
+    site: [usize; HEAP_SITE_DEPTH],
+}
+
+/// An allocation freed lately
+#[cfg(feature = "heap-redzone")]
+#[derive(Copy, Clone)]
+struct Freed {
+    allocation: Allocation,
+    free_site: [usize; HEAP_SITE_DEPTH],
+}
+
+struct Table {
+    allocations: [Allocation; MAX_TRACKED_ALLOCATIONS],
+    /// allocations made while the table was full
+    untracked: usize,
+    /// a ring of the latest frees, a `ptr` of 0 once handed out again
+    #[cfg(feature = "heap-redzone")]
+    freed: [Freed; HEAP_FREED_RECORDS],
+    #[cfg(feature = "heap-redzone")]
+    next_freed: usize,
+}
+
+impl Table {
+    /// Index of `ptr` in the table, or of the free slot where it would go.
+    /// Slots are probed linearly from the hash of `ptr`.
+    fn find(&self, ptr: usize) -> Option<usize> {
+        let start = (ptr >> 3) % MAX_TRACKED_ALLOCATIONS;
+        (0..MAX_TRACKED_ALLOCATIONS)
+            .map(|i| (start + i) % MAX_TRACKED_ALLOCATIONS)
+            .find(|&i| self.allocations[i].ptr == ptr || self.allocations[i].ptr == 0)
+    }
+    fn insert(&mut self, allocation: Allocation) {
+        match self.find(allocation.ptr) {
+            Some(i) => self.allocations[i] = allocation,
+            None => self.untracked += 1,
+        }
+    }
+    /// Remove `ptr` from the table, returning its record if it was there.
+    fn remove(&mut self, ptr: usize) -> Option<Allocation> {
+        let mut hole = match self.find(ptr) {
+            Some(i) if self.allocations[i].ptr == ptr => i,
+            _ => return None,
+        };
+        let removed = self.allocations[hole];
+        self.allocations[hole].ptr = 0;
+        // move back the entries that probed past the hole
+        let mut i = hole;
+        loop {
+            i = (i + 1) % MAX_TRACKED_ALLOCATIONS;
+            let moved = self.allocations[i];
+            if moved.ptr == 0 {
+                break;
+            }
+            let home = (moved.ptr >> 3) % MAX_TRACKED_ALLOCATIONS;
+            let distance = |from: usize, to: usize| {
+                (to + MAX_TRACKED_ALLOCATIONS - from) % MAX_TRACKED_ALLOCATIONS
+            };
+            if distance(home, i) >= distance(hole, i) {
+                self.allocations[hole] = moved;
+                self.allocations[i].ptr = 0;
+                hole = i;
+            }
+        }
+        Some(removed)
+    }
+    #[cfg(feature = "heap-redzone")]
+    fn record_free(&mut self, allocation: Allocation, free_site: [usize; HEAP_SITE_DEPTH]) {
+        self.freed[self.next_freed] = Freed {
+            allocation,
+            free_site,
+        };
+        self.next_freed = (self.next_freed + 1) % HEAP_FREED_RECORDS;
+    }
+    /// The latest free of `ptr`, if it was not handed out again since.
+    #[cfg(feature = "heap-redzone")]
+    fn freed_slot(&self, ptr: usize) -> Option<usize> {
+        self.freed
+            .iter()
+            .position(|freed| freed.allocation.ptr == ptr)
+    }
+}
+
+const FREE_SLOT: Allocation = Allocation {
+    ptr: 0,
+    size: 0,
+    site: [0; HEAP_SITE_DEPTH],
+};
+
+#[cfg(feature = "heap-redzone")]
+const NOT_FREED: Freed = Freed {
+    allocation: FREE_SLOT,
+    free_site: [0; HEAP_SITE_DEPTH],
+};
+
+static TABLE: Mutex<Table> = Mutex::new(Table {
+    allocations: [FREE_SLOT; MAX_TRACKED_ALLOCATIONS],
+    untracked: 0,
+    #[cfg(feature = "heap-redzone")]
+    freed: [NOT_FREED; HEAP_FREED_RECORDS],
+    #[cfg(feature = "heap-redzone")]
+    next_freed: 0,
+});
+
+/// The return addresses of the code calling the allocator.
+#[inline(always)]
+fn call_site() -> [usize; HEAP_SITE_DEPTH] {
+    let mut site = [0; HEAP_SITE_DEPTH];
+    let mut depth = 0;
+    crate::crash::walk_frames(|ra| {
+        if depth >= SKIPPED_FRAMES {
+            site[depth - SKIPPED_FRAMES] = ra;
+        }
+        depth += 1;
+        depth < SKIPPED_FRAMES + HEAP_SITE_DEPTH
+    });
+    site
+}
+
+/// The layout of `layout` with its redzones, and where the allocation
+/// starts in it: the front redzone is widened to keep the alignment.
+#[cfg(feature = "heap-redzone")]
+fn padded(layout: Layout) -> (Layout, usize) {
+    let offset = (HEAP_REDZONE_SIZE + layout.align() - 1) / layout.align() * layout.align();
+    let size = offset + layout.size() + HEAP_REDZONE_SIZE;
+    (
+        Layout::from_size_align(size, layout.align()).unwrap(),
+        offset,
+    )
+}
+
+/// The first byte of a redzone of the allocation at `ptr` that was
+/// overwritten, as an offset from `ptr`, negative if before it.
+#[cfg(feature = "heap-redzone")]
+unsafe fn check_redzones(ptr: *mut u8, layout: Layout) -> Option<isize> {
+    let front = core::slice::from_raw_parts(ptr.sub(HEAP_REDZONE_SIZE), HEAP_REDZONE_SIZE);
+    let back = core::slice::from_raw_parts(ptr.add(layout.size()), HEAP_REDZONE_SIZE);
+    if let Some(i) = front.iter().rposition(|&byte| byte != REDZONE_BYTE) {
+        return Some(i as isize - HEAP_REDZONE_SIZE as isize);
+    }
+    back.iter()
+        .position(|&byte| byte != REDZONE_BYTE)
+        .map(|i| (layout.size() + i) as isize)
+}
+
+/// The heap, recording what it hands out
+pub struct TrackingHeap {
+    pub heap: LockedHeap,
+}
+
+#[cfg(not(feature = "heap-redzone"))]
+unsafe impl GlobalAlloc for TrackingHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.heap.alloc(layout);
+        if !ptr.is_null() {
+            TABLE.lock().insert(Allocation {
+                ptr: ptr as usize,
+                size: layout.size(),
+                site: call_site(),
+            });
+        }
+        ptr
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        TABLE.lock().remove(ptr as usize);
+        self.heap.dealloc(ptr, layout)
+    }
+}
+
+#[cfg(feature = "heap-redzone")]
+unsafe impl GlobalAlloc for TrackingHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let (padded, offset) = padded(layout);
+        let base = self.heap.alloc(padded);
+        if base.is_null() {
+            return base;
+        }
+        base.write_bytes(REDZONE_BYTE, padded.size());
+        let ptr = base.add(offset);
+        let mut table = TABLE.lock();
+        if let Some(i) = table.freed_slot(ptr as usize) {
+            table.freed[i].allocation.ptr = 0;
+        }
+        table.insert(Allocation {
+            ptr: ptr as usize,
+            size: layout.size(),
+            site: call_site(),
+        });
+        ptr
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let free_site = call_site();
+        let mut table = TABLE.lock();
+        let allocation = match table.remove(ptr as usize) {
+            Some(allocation) => allocation,
+            None => {
+                if let Some(i) = table.freed_slot(ptr as usize) {
+                    let freed = table.freed[i];
+                    // reported without the table, as logging may allocate
+                    drop(table);
+                    HEAP_ERRORS.fetch_add(1, Ordering::Relaxed);
+                    error!(
+                        "[kernel] double free of heap {:#x} ({} bytes) from {:x?}, allocated from {:x?} and freed from {:x?}",
+                        ptr as usize,
+                        layout.size(),
+                        free_site,
+                        freed.allocation.site,
+                        freed.free_site
+                    );
+                    return;
+                }
+                // made while the table was full
+                drop(table);
+                let (padded, offset) = padded(layout);
+                self.heap.dealloc(ptr.sub(offset), padded);
+                return;
+            }
+        };
+        table.record_free(allocation, free_site);
+        drop(table);
+        if let Some(offset) = check_redzones(ptr, layout) {
+            HEAP_ERRORS.fetch_add(1, Ordering::Relaxed);
+            error!(
+                "[kernel] heap {:#x} ({} bytes) overrun at offset {}, allocated from {:x?} and freed from {:x?}",
+                ptr as usize,
+                layout.size(),
+                offset,
+                allocation.site,
+                free_site
+            );
+        }
+        let (padded, offset) = padded(layout);
+        self.heap.dealloc(ptr.sub(offset), padded)
+    }
+}
+
+/// Live allocations made from the same call site
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct HeapSite {
+    /// return addresses, innermost first, 0 past the end of the stack
+    pub site: [usize; HEAP_SITE_DEPTH],
+    pub allocations: usize,
+    pub bytes: usize,
+}
+
+/// Live allocations grouped by call site, most bytes first, and how many
+/// allocations were not tracked.
+pub fn heap_sites() -> (Vec<HeapSite>, usize) {
+    // reserved before taking the table, as allocating needs it
+    let mut live = Vec::with_capacity(MAX_TRACKED_ALLOCATIONS);
+    let untracked = {
+        let table = TABLE.lock();
+        live.extend(table.allocations.iter().filter(|a| a.ptr != 0).copied());
+        table.untracked
+    };
+    let mut sites: BTreeMap<[usize; HEAP_SITE_DEPTH], (usize, usize)> = BTreeMap::new();
+    for allocation in live {
+        let (allocations, bytes) = sites.entry(allocation.site).or_insert((0, 0));
+        *allocations += 1;
+        *bytes += allocation.size;
+    }
+    let mut sites: Vec<HeapSite> = sites
+        .into_iter()
+        .map(|(site, (allocations, bytes))| HeapSite {
+            site,
+            allocations,
+            bytes,
+        })
+        .collect();
+    sites.sort_by(|a, b| b.bytes.cmp(&a.bytes));
+    (sites, untracked)
+}
+
+/// Overrun an allocation on each side, and free one twice, checking that
+/// each is reported and that the heap is left as it was.
+#[cfg(feature = "heap-redzone")]
+pub fn redzone_test() {
+    use super::heap_used;
+    use alloc::alloc::{alloc, dealloc};
+    let used = heap_used();
+    let errors = HEAP_ERRORS.load(Ordering::Relaxed);
+    let layout = Layout::from_size_align(24, 8).unwrap();
+    for &offset in &[-1, 24] {
+        unsafe {
+            let ptr = alloc(layout);
+            ptr.offset(offset).write(0);
+            dealloc(ptr, layout);
+        }
+    }
+    assert_eq!(HEAP_ERRORS.load(Ordering::Relaxed), errors + 2);
+    unsafe {
+        let ptr = alloc(layout);
+        dealloc(ptr, layout);
+        dealloc(ptr, layout);
+    }
+    assert_eq!(HEAP_ERRORS.load(Ordering::Relaxed), errors + 3);
+    assert_eq!(heap_used(), used, "the heap was not left as it was");
+}
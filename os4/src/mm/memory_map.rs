@@ -0,0 +1,75 @@
+//! Physical memory the kernel manages
+//!
+//! Every memory bank the device tree describes, or the board's layout
+//! without one, less what must not be handed out: in the bank holding the
+//! kernel, everything below the end of its image, where the firmware
+//! usually is as well, and the crash dump and the scheduling log at the end
+//! of the bank; and every reserved region.
+
+use super::{PhysAddr, PhysPageNum};
+use crate::board::board_info;
+use crate::config::{CRASH_DUMP_SIZE, SCHED_LOG_SIZE};
+use alloc::vec::Vec;
+
+extern "C" {
+    fn skernel();
+    fn ekernel();
+}
+
+/// The memory bank holding the kernel, as [start, end).
+fn kernel_bank() -> (usize, usize) {
+    let kernel = skernel as usize;
+    board_info()
+        .memory_regions()
+        .iter()
+        .map(|&(base, size)| (base, base + size))
+        .find(|&(start, end)| start <= kernel && kernel < end)
+        .expect("the kernel is loaded outside of the memory the board has")
+}
+
+/// Where the crash dump is kept, at the end of the bank holding the kernel.
+pub fn crash_dump_base() -> usize {
+    kernel_bank().1 - CRASH_DUMP_SIZE
+}
+
+/// Where the scheduling log is kept, below the crash dump.
+pub fn sched_log_base() -> usize {
+    crash_dump_base() - SCHED_LOG_SIZE
+}
+
+/// Page ranges [start, end) free for the frame allocator, in address order.
+pub fn usable_memory() -> Vec<(PhysPageNum, PhysPageNum)> {
+    let info = board_info();
+    let (bank_start, bank_end) = kernel_bank();
+    let mut holes: Vec<(usize, usize)> = info
+        .reserved_regions()
+        .iter()
+        .map(|&(base, size)| (base, base + size))
+        .collect();
+    holes.push((bank_start, ekernel as usize));
+    holes.push((sched_log_base(), bank_end));
+    holes.sort_unstable();
+    let mut ranges = Vec::new();
+    let mut add = |start: usize, end: usize| {
+        let (start, end) = (PhysAddr::from(start).ceil(), PhysAddr::from(end).floor());
+        if start < end {
+            ranges.push((start, end));
+        }
+    };
+    for &(base, size) in info.memory_regions() {
+        let (mut start, end) = (base, base + size);
+        for &(hole_start, hole_end) in &holes {
+            if hole_end <= start || hole_start >= end {
+                continue;
+            }
+            if hole_start > start {
+                add(start, hole_start);
+            }
+            start = hole_end;
+        }
+        if start < end {
+            add(start, end);
+        }
+    }
+    ranges
+}
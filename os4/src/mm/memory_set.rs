@@ -1,12 +1,20 @@
 //! Implementation of [`MapArea`] and [`MemorySet`].
 
-use super::{frame_alloc, FrameTracker};
+use super::frame_allocator::frame_alloc_below;
+use super::{frame_alloc, frame_stats, FrameTracker};
+use super::memory_map::{crash_dump_base, sched_log_base, usable_memory};
+use super::page_table::MemoryType;
 use super::{PTEFlags, PageTable, PageTableEntry};
 use super::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
-use super::{StepByOne, VPNRange};
-use crate::config::{MEMORY_END, PAGE_SIZE, TRAMPOLINE, TRAP_CONTEXT, USER_STACK_SIZE};
+use super::{SandboxProfile, StepByOne, VPNRange};
+use crate::board::{board_info, has_extensions, Extensions};
+use crate::config::{
+    CRASH_DUMP_SIZE, LAZY_MMAP, PAGE_SIZE, TRAMPOLINE, TRAP_CONTEXT, USER_SPACE_END,
+    USER_STACK_SIZE,
+};
 use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
+use alloc::vec;
 use alloc::vec::Vec;
 use lazy_static::*;
 use riscv::register::satp;
@@ -21,7 +29,6 @@ extern "C" {
     fn edata();
     fn sbss_with_stack();
     fn ebss();
-    fn ekernel();
     fn strampoline();
 }
 
@@ -35,6 +42,37 @@ lazy_static! {
 pub struct MemorySet {
     page_table: PageTable,
     areas: Vec<MapArea>,
+    /// whether `mmap` and `mprotect` may make pages both writable and
+    /// executable, as a JIT needs; no page is by default (W^X)
+    allow_wx: bool,
+    /// where and how much later mappings may map, if confined
+    sandbox: Option<SandboxProfile>,
+    /// pages `mmap` reserved that have no frame yet, with their permission,
+    /// when mappings are lazy
+    lazy_pages: BTreeMap<VirtPageNum, MapPermission>,
+}
+
+/// The user permission of the `port` bits of `mmap` and `mprotect`: read,
+/// write and execute from the lowest bit, as `PROT_*` in Linux.
+fn port_permission(port: usize) -> MapPermission {
+    let mut perm = MapPermission::U;
+    if port & 0x01 != 0 {
+        perm |= MapPermission::R;
+    }
+    if port & 0x02 != 0 {
+        perm |= MapPermission::W;
+    }
+    if port & 0x04 != 0 {
+        perm |= MapPermission::X;
+    }
+    perm
+}
+
+/// Whether `[start, start + len)` lies in the user half of the address
+/// space. The trampoline and the trap context are in the other half, and
+/// must not be reached from an aliasing user address.
+pub(super) fn in_user_space(start: usize, len: usize) -> bool {
+    start.checked_add(len).map_or(false, |end| end <= USER_SPACE_END)
 }
 
 impl MemorySet {
@@ -42,6 +80,9 @@ impl MemorySet {
         Self {
             page_table: PageTable::new(),
             areas: Vec::new(),
+            allow_wx: false,
+            sandbox: None,
+            lazy_pages: BTreeMap::new(),
         }
     }
     pub fn token(&self) -> usize {
@@ -60,74 +101,315 @@ impl MemorySet {
         );
     }
 
+    /// Unmap and drop the area starting at `start_vpn`, if there is one.
+    pub fn remove_area_with_start_vpn(&mut self, start_vpn: VirtPageNum) {
+        if let Some(idx) = self
+            .areas
+            .iter()
+            .position(|area| area.vpn_range.get_start() == start_vpn)
+        {
+            let mut area = self.areas.remove(idx);
+            area.unmap(&mut self.page_table);
+        }
+    }
+
+    /// Let `mmap` and `mprotect` make pages writable and executable.
+    pub fn set_allow_wx(&mut self, allow: bool) {
+        self.allow_wx = allow;
+    }
+
+    /// Confine later mappings to `profile`. Fails if there is a profile
+    /// already, which cannot be replaced.
+    pub fn set_sandbox(&mut self, profile: SandboxProfile) -> bool {
+        if self.sandbox.is_some() {
+            return false;
+        }
+        self.sandbox = Some(profile);
+        true
+    }
+
+    /// The profile later mappings are confined to, if any.
+    pub fn sandbox(&self) -> Option<SandboxProfile> {
+        self.sandbox
+    }
+
+    /// Whether the sandbox profile, if any, allows mapping `len` bytes at
+    /// `start`.
+    fn sandbox_allows(&self, start: usize, len: usize) -> bool {
+        self.sandbox.map_or(true, |profile| profile.allows(start, len))
+    }
+
+    fn check_wx(&self, perm: MapPermission) -> bool {
+        self.allow_wx || !perm.contains(MapPermission::W | MapPermission::X)
+    }
+
+    /// Whether any page of `[start, start + len)` is mapped, or reserved
+    /// for a lazy mapping.
+    fn any_mapped(&self, start: usize, len: usize) -> bool {
+        let rg = VPNRange::new(VirtAddr(start).floor(), VirtAddr(start + len).ceil());
+        rg.into_iter().any(|vpn| {
+            self.lazy_pages.contains_key(&vpn)
+                || matches!(self.page_table.find_pte(vpn), Some(pte) if pte.is_valid())
+        })
+    }
+
+    /// Map `[start, start + len)` for the user with permission `port`.
+    /// Fails, mapping nothing, if any page of it is mapped already.
     pub fn mmap(&mut self, start: usize, len: usize, port: usize) -> isize {
-        let rg = VPNRange::new(VirtAddr(start).floor(), VirtAddr(start+len).ceil() );
-        for vpn in rg {
-            if let Some(pte) = self.page_table.find_pte(vpn) {
-                let a = pte as *const PageTableEntry as usize;
-                if pte.is_valid() {
-                    info!("valid fault: {:?}", a);
-                    return -1;
-                }else {
-                    info!("invalid fault: {:?}", a);
-                }
+        let perm = port_permission(port);
+        if !self.check_wx(perm) || !in_user_space(start, len) || !self.sandbox_allows(start, len) {
+            return -1;
+        }
+        if self.any_mapped(start, len) {
+            return -1;
+        }
+        let rg = VPNRange::new(VirtAddr(start).floor(), VirtAddr(start + len).ceil());
+        let area = MapArea::new(
+            VirtAddr(start),
+            VirtAddr(start + len),
+            MapType::Framed,
+            perm,
+        );
+        if LAZY_MMAP {
+            // frames are given on first touch, see `handle_lazy_fault`
+            for vpn in rg {
+                self.lazy_pages.insert(vpn, perm);
             }
+            self.areas.push(area);
+        } else if !self.try_push(area, None) {
+            // out of frames
+            return -1;
         }
-        let mut perm = MapPermission::U;
-        if port & 0x01 != 0 {
-            perm |= MapPermission::R;
+        self.merge_areas();
+        0
+    }
+
+    /// The lowest page-aligned address from `from` up where `len` bytes are
+    /// free for a mapping, for an `mmap` that leaves the address to the
+    /// kernel. None if there is no room in user space.
+    pub fn find_free(&self, from: usize, len: usize) -> Option<usize> {
+        let len = len.checked_add(PAGE_SIZE - 1)? & !(PAGE_SIZE - 1);
+        let mut start = from.checked_add(PAGE_SIZE - 1)? & !(PAGE_SIZE - 1);
+        while in_user_space(start, len) {
+            let (first, last) = (VirtAddr(start).floor(), VirtAddr(start + len).ceil());
+            match self.areas.iter().find(|area| {
+                area.vpn_range.get_start() < last && first < area.vpn_range.get_end()
+            }) {
+                Some(area) => start = VirtAddr::from(area.vpn_range.get_end()).0,
+                None => return Some(start),
+            }
         }
-        if port & 0x02 != 0 {
-            perm |= MapPermission::W;
+        None
+    }
+
+    /// Whether every page of `[start, start + len)` is mapped for the user
+    /// or reserved, so that `munmap` or `mprotect` of it may succeed.
+    pub fn can_munmap(&self, start: usize, len: usize) -> bool {
+        if !in_user_space(start, len) {
+            return false;
         }
-        if port & 0x04 != 0 {
-            perm |= MapPermission::X;
+        let rg = VPNRange::new(VirtAddr(start).floor(), VirtAddr(start + len).ceil());
+        rg.into_iter().all(|vpn| {
+            self.lazy_pages.contains_key(&vpn)
+                || matches!(self.page_table.find_pte(vpn),
+                    Some(pte) if pte.is_valid() && pte.flags().contains(PTEFlags::U))
+        })
+    }
+
+    /// Whether each page of `[start, start + len)` has a frame, 1 if it
+    /// does and 0 if it is reserved for a lazy mapping still, as `mincore`
+    /// reports it. None if any page is not mapped for the user.
+    pub fn mincore(&self, start: usize, len: usize) -> Option<Vec<u8>> {
+        if !in_user_space(start, len) {
+            return None;
         }
+        let rg = VPNRange::new(VirtAddr(start).floor(), VirtAddr(start + len).ceil());
+        rg.into_iter()
+            .map(|vpn| match self.page_table.find_pte(vpn) {
+                Some(pte) if pte.is_valid() && pte.flags().contains(PTEFlags::U) => Some(1),
+                _ if self.lazy_pages.contains_key(&vpn) => Some(0),
+                _ => None,
+            })
+            .collect()
+    }
 
-        self.insert_framed_area(VirtAddr(start), VirtAddr(start+len), perm);
-        0
+    /// Whether every area in `[start, start + len)` is anonymous memory,
+    /// rather than frames owned elsewhere such as a framebuffer.
+    pub fn is_anonymous(&self, start: usize, len: usize) -> bool {
+        let (start, end) = (VirtAddr(start).floor(), VirtAddr(start + len).ceil());
+        self.areas
+            .iter()
+            .filter(|area| area.vpn_range.get_start() < end && start < area.vpn_range.get_end())
+            .all(|area| area.map_type == MapType::Framed)
+    }
 
+    /// Split the areas that straddle `start` or `end`, so that each area
+    /// lies either within `[start, end)` or outside of it.
+    fn split_areas(&mut self, start: VirtPageNum, end: VirtPageNum) {
+        // the pieces split off are pushed, and looked at in turn
+        let mut i = 0;
+        while i < self.areas.len() {
+            let area = &mut self.areas[i];
+            let (area_start, area_end) = (area.vpn_range.get_start(), area.vpn_range.get_end());
+            if area_start < start && start < area_end {
+                let rest = area.split_off(start);
+                self.areas.push(rest);
+            } else if area_start < end && end < area_end {
+                let rest = area.split_off(end);
+                self.areas.push(rest);
+            }
+            i += 1;
+        }
     }
 
-    pub fn munmap(&mut self, start: usize, len: usize) -> isize {
-        let rg = VPNRange::new(VirtAddr(start).floor(), VirtAddr(start+len).ceil() );
-        for vpn in rg {
-            if let Some(pte) = self.page_table.find_pte(vpn) {
-                if ! pte.is_valid(){
-                    return -1;
-                }
-            }else {
-                return -1;
+    /// Merge user areas that follow each other and map the same way.
+    fn merge_areas(&mut self) {
+        self.areas.sort_by_key(|area| area.vpn_range.get_start());
+        let mut merged: Vec<MapArea> = Vec::with_capacity(self.areas.len());
+        for area in self.areas.drain(..) {
+            match merged.last_mut() {
+                Some(last) if last.can_merge(&area) => last.merge(area),
+                _ => merged.push(area),
             }
         }
-        
-        for area in &mut self.areas {
-            for vpn in rg {
-                if vpn < area.vpn_range.get_end() && vpn >= area.vpn_range.get_start() {
+        self.areas = merged;
+    }
+
+    /// Unmap `[start, start + len)`, splitting the areas it cuts through.
+    /// Fails, unmapping nothing, if any page of it is not mapped.
+    pub fn munmap(&mut self, start: usize, len: usize) -> isize {
+        if !self.can_munmap(start, len) {
+            return -1;
+        }
+        let (start, end) = (VirtAddr(start).floor(), VirtAddr(start + len).ceil());
+        self.split_areas(start, end);
+        let (unmapped, kept): (Vec<MapArea>, Vec<MapArea>) = self.areas.drain(..).partition(|area| {
+            start <= area.vpn_range.get_start() && area.vpn_range.get_end() <= end
+        });
+        self.areas = kept;
+        for mut area in unmapped {
+            for vpn in area.vpn_range {
+                // untouched lazy pages only have to be forgotten
+                if self.lazy_pages.remove(&vpn).is_none() {
                     area.unmap_one(&mut self.page_table, vpn);
                 }
             }
         }
+        0
+    }
 
-        
+    /// Change the permission of the user pages in `[start, start + len)`
+    /// to `port`, splitting the areas it cuts through. Fails, changing
+    /// nothing, if a page is not mapped for the user or the permission
+    /// breaks W^X.
+    pub fn mprotect(&mut self, start: usize, len: usize, port: usize) -> isize {
+        let perm = port_permission(port);
+        if !self.check_wx(perm) || !in_user_space(start, len) {
+            return -1;
+        }
+        if !self.can_munmap(start, len) {
+            return -1;
+        }
+        let rg = VPNRange::new(VirtAddr(start).floor(), VirtAddr(start + len).ceil());
+        self.split_areas(rg.get_start(), rg.get_end());
+        for area in self.areas.iter_mut() {
+            if rg.get_start() <= area.vpn_range.get_start()
+                && area.vpn_range.get_end() <= rg.get_end()
+            {
+                area.map_perm = perm;
+            }
+        }
+        let flags = perm.pte_flags();
+        for vpn in rg {
+            match self.lazy_pages.get_mut(&vpn) {
+                Some(lazy_perm) => *lazy_perm = perm,
+                None => self.page_table.set_flags(vpn, flags),
+            }
+        }
+        self.merge_areas();
         0
+    }
 
+    /// Give a frame to the lazily mapped page holding `va`, if it allows
+    /// `access`. Returns whether it did, so that the access can be retried.
+    pub fn handle_lazy_fault(&mut self, va: usize, access: MapPermission) -> bool {
+        if !in_user_space(va, 1) {
+            return false;
+        }
+        let vpn = VirtAddr::from(va).floor();
+        let perm = match self.lazy_pages.get(&vpn) {
+            Some(&perm) if perm.contains(access) => perm,
+            _ => return false,
+        };
+        let area = self
+            .areas
+            .iter_mut()
+            .find(|area| area.vpn_range.get_start() <= vpn && vpn < area.vpn_range.get_end())
+            .unwrap();
+        if !area.map_one(&mut self.page_table, vpn) {
+            // out of frames, the page stays lazy
+            return false;
+        }
+        self.lazy_pages.remove(&vpn);
+        // `mprotect` may have changed the permission since the mapping
+        self.page_table
+            .set_flags(vpn, perm.pte_flags());
+        true
+    }
+
+    /// Map `pages` frames starting at `ppn`, owned by someone else (e.g. a
+    /// device framebuffer), at `start`. Fails if any page is already mapped.
+    pub fn map_linear(
+        &mut self,
+        start: usize,
+        ppn: PhysPageNum,
+        pages: usize,
+        permission: MapPermission,
+    ) -> isize {
+        if !in_user_space(start, pages * PAGE_SIZE)
+            || !self.sandbox_allows(start, pages * PAGE_SIZE)
+        {
+            return -1;
+        }
+        let end = start + pages * PAGE_SIZE;
+        if self.any_mapped(start, end - start) {
+            return -1;
+        }
+        let area = MapArea::new(
+            VirtAddr(start),
+            VirtAddr(end),
+            MapType::Linear(ppn),
+            permission,
+        );
+        if !self.try_push(area, None) {
+            return -1;
+        }
+        0
     }
 
-    fn push(&mut self, mut map_area: MapArea, data: Option<&[u8]>) {
-        map_area.map(&mut self.page_table);
+    fn push(&mut self, map_area: MapArea, data: Option<&[u8]>) {
+        assert!(self.try_push(map_area, data), "out of frames mapping an area");
+    }
+    /// Map `map_area` and add it, or map nothing and return false if frames
+    /// run out.
+    fn try_push(&mut self, mut map_area: MapArea, data: Option<&[u8]>) -> bool {
+        if !map_area.map(&mut self.page_table) {
+            return false;
+        }
         if let Some(data) = data {
             map_area.copy_data(&mut self.page_table, data);
         }
         self.areas.push(map_area);
+        true
     }
     /// Mention that trampoline is not collected by areas.
     fn map_trampoline(&mut self) {
-        self.page_table.map(
+        let mapped = self.page_table.map(
             VirtAddr::from(TRAMPOLINE).into(),
             PhysAddr::from(strampoline as usize).into(),
             PTEFlags::R | PTEFlags::X,
         );
+        assert!(mapped, "out of frames mapping the trampoline");
     }
     /// Without kernel stacks.
     pub fn new_kernel() -> Self {
@@ -183,23 +465,82 @@ impl MemorySet {
             None,
         );
         info!("mapping physical memory");
-        memory_set.push(
+        // the scheduling log and the crash dump, which follows it
+        let kept = (
+            PhysAddr::from(sched_log_base()).floor(),
+            PhysAddr::from(crash_dump_base() + CRASH_DUMP_SIZE).ceil(),
+        );
+        for (start, end) in usable_memory().into_iter().chain(Some(kept)) {
+            memory_set.push(
+                MapArea::new(
+                    PhysAddr::from(start).0.into(),
+                    PhysAddr::from(end).0.into(),
+                    MapType::Identical,
+                    MapPermission::R | MapPermission::W,
+                ),
+                None,
+            );
+        }
+        info!("mapping memory-mapped registers");
+        for (start, size) in board_info().mmio_regions() {
+            memory_set.push(
+                MapArea::new(
+                    start.into(),
+                    (start + size).into(),
+                    MapType::Identical,
+                    MapPermission::R | MapPermission::W | MapPermission::IO,
+                ),
+                None,
+            );
+        }
+        memory_set
+    }
+    /// Map what a user address space needs of the kernel: the trampoline,
+    /// through which traps switch to the kernel page table, and the
+    /// TrapContext it saves registers to. Neither has the U flag.
+    fn map_trap_entry(&mut self) {
+        self.map_trampoline();
+        self.push(
             MapArea::new(
-                (ekernel as usize).into(),
-                MEMORY_END.into(),
-                MapType::Identical,
+                TRAP_CONTEXT.into(),
+                TRAMPOLINE.into(),
+                MapType::Framed,
                 MapPermission::R | MapPermission::W,
             ),
             None,
         );
+    }
+    /// Check that the trap entry is all a user address space maps of the
+    /// kernel: every other area lies in the user half and has the U flag.
+    fn assert_user_isolated(&self) {
+        let trap_context: VirtPageNum = VirtAddr::from(TRAP_CONTEXT).into();
+        for area in &self.areas {
+            if area.vpn_range.get_start() == trap_context {
+                continue;
+            }
+            let end: VirtAddr = area.vpn_range.get_end().into();
+            assert!(
+                area.map_perm.contains(MapPermission::U) && end.0 <= USER_SPACE_END,
+                "user space maps kernel-only page {:?}",
+                area.vpn_range.get_start()
+            );
+        }
+    }
+    /// Only the trampoline and TrapContext, for a task whose code does not
+    /// run in user space.
+    #[allow(unused)]
+    pub fn new_trap_only() -> Self {
+        let mut memory_set = Self::new_bare();
+        memory_set.map_trap_entry();
         memory_set
     }
     /// Include sections in elf and trampoline and TrapContext and user stack,
-    /// also returns user_sp and entry point.
+    /// also returns user_sp and entry point. Nothing else of the kernel is
+    /// mapped, so that user code cannot probe it.
     pub fn from_elf(elf_data: &[u8]) -> (Self, usize, usize) {
         let mut memory_set = Self::new_bare();
-        // map trampoline
-        memory_set.map_trampoline();
+        // map trampoline and TrapContext
+        memory_set.map_trap_entry();
         // map program headers of elf, with U flag
         let elf = xmas_elf::ElfFile::new(elf_data).unwrap();
         let elf_header = elf.header;
@@ -212,6 +553,10 @@ impl MemorySet {
             if ph.get_type().unwrap() == xmas_elf::program::Type::Load {
                 let start_va: VirtAddr = (ph.virtual_addr() as usize).into();
                 let end_va: VirtAddr = ((ph.virtual_addr() + ph.mem_size()) as usize).into();
+                assert!(
+                    in_user_space(start_va.0, end_va.0 - start_va.0),
+                    "elf segment outside of user space"
+                );
                 let mut map_perm = MapPermission::U;
                 let ph_flags = ph.flags();
                 if ph_flags.is_read() {
@@ -223,6 +568,13 @@ impl MemorySet {
                 if ph_flags.is_execute() {
                     map_perm |= MapPermission::X;
                 }
+                if map_perm.contains(MapPermission::W | MapPermission::X) {
+                    warn!(
+                        "[kernel] writable and executable segment at {:#x} mapped not executable",
+                        ph.virtual_addr()
+                    );
+                    map_perm.remove(MapPermission::X);
+                }
                 let map_area = MapArea::new(start_va, end_va, MapType::Framed, map_perm);
                 max_end_vpn = map_area.vpn_range.get_end();
                 memory_set.push(
@@ -246,16 +598,7 @@ impl MemorySet {
             ),
             None,
         );
-        // map TrapContext
-        memory_set.push(
-            MapArea::new(
-                TRAP_CONTEXT.into(),
-                TRAMPOLINE.into(),
-                MapType::Framed,
-                MapPermission::R | MapPermission::W,
-            ),
-            None,
-        );
+        memory_set.assert_user_isolated();
         (
             memory_set,
             user_stack_top,
@@ -272,6 +615,140 @@ impl MemorySet {
     pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
         self.page_table.translate(vpn)
     }
+    /// Number of frames owned by the areas, leaving out linear mappings of
+    /// memory that belongs to someone else.
+    pub fn resident_pages(&self) -> usize {
+        self.areas.iter().map(|area| area.data_frames.len()).sum()
+    }
+    /// Move the pages of the framed user areas to free frames at lower
+    /// addresses where there are any, returning how many moved. Kernel-only
+    /// pages such as the trap context stay, as the kernel keeps their frame.
+    pub fn migrate_frames(&mut self) -> usize {
+        let mut moved = 0;
+        for area in self.areas.iter_mut() {
+            moved += area.migrate_frames(&mut self.page_table);
+        }
+        if moved > 0 {
+            unsafe {
+                core::arch::asm!("sfence.vma");
+            }
+        }
+        moved
+    }
+    /// Unmap and free up to `max` frames of the user areas but the trap
+    /// context, returning whether any are left, so that a large address
+    /// space can be torn down a batch at a time.
+    pub fn release_frames(&mut self, max: usize) -> bool {
+        let trap_context: VirtPageNum = VirtAddr::from(TRAP_CONTEXT).into();
+        self.lazy_pages.clear();
+        let mut released = 0;
+        for area in self.areas.iter_mut() {
+            if area.vpn_range.get_start() == trap_context {
+                continue;
+            }
+            while let Some(&vpn) = area.data_frames.keys().next_back() {
+                if released == max {
+                    return true;
+                }
+                area.unmap_one(&mut self.page_table, vpn);
+                released += 1;
+            }
+        }
+        false
+    }
+    /// Whether `mmap` and `mprotect` may make pages writable and executable.
+    pub fn allow_wx(&self) -> bool {
+        self.allow_wx
+    }
+    /// The user areas as (start, end, permission), for a checkpoint. None if
+    /// one maps memory owned elsewhere, such as a framebuffer, which a
+    /// checkpoint cannot take.
+    pub fn user_areas(&self) -> Option<Vec<(VirtPageNum, VirtPageNum, MapPermission)>> {
+        let mut areas = Vec::new();
+        for area in self.areas.iter().filter(|area| area.map_perm.contains(MapPermission::U)) {
+            if area.map_type != MapType::Framed {
+                return None;
+            }
+            areas.push((area.vpn_range.get_start(), area.vpn_range.get_end(), area.map_perm));
+        }
+        Some(areas)
+    }
+    /// The pages of the user areas that have a frame, with their frame.
+    pub fn user_pages(&self) -> Vec<(VirtPageNum, PhysPageNum)> {
+        self.areas
+            .iter()
+            .filter(|area| area.map_perm.contains(MapPermission::U))
+            .flat_map(|area| area.data_frames.iter().map(|(&vpn, frame)| (vpn, frame.ppn)))
+            .collect()
+    }
+    /// The pages reserved for a lazy mapping, with their permission.
+    pub fn lazy_pages(&self) -> Vec<(VirtPageNum, MapPermission)> {
+        self.lazy_pages.iter().map(|(&vpn, &perm)| (vpn, perm)).collect()
+    }
+    /// Add a user area of a restored checkpoint, with no page of it mapped
+    /// yet. Fails if it is empty or out of user space, if it overlaps
+    /// another area or the sandbox profile does not allow it, or if its
+    /// permission is not a user one or breaks W^X.
+    pub fn restore_area(
+        &mut self,
+        start: VirtPageNum,
+        end: VirtPageNum,
+        perm: MapPermission,
+    ) -> bool {
+        let user = MapPermission::R | MapPermission::W | MapPermission::X | MapPermission::U;
+        if start.0 >= end.0
+            || end.0 > USER_SPACE_END / PAGE_SIZE
+            || !perm.contains(MapPermission::U)
+            || !user.contains(perm)
+            || !self.check_wx(perm)
+            || !self.sandbox_allows(VirtAddr::from(start).0, (end.0 - start.0) * PAGE_SIZE)
+            || self.areas.iter().any(|area| {
+                area.vpn_range.get_start().0 < end.0 && start.0 < area.vpn_range.get_end().0
+            })
+        {
+            return false;
+        }
+        self.areas.push(MapArea::new(start.into(), end.into(), MapType::Framed, perm));
+        true
+    }
+    /// The index of the restored user area holding `vpn`, if the page is
+    /// not mapped or reserved yet.
+    fn restored_area(&self, vpn: VirtPageNum) -> Option<usize> {
+        if self.lazy_pages.contains_key(&vpn) {
+            return None;
+        }
+        self.areas.iter().position(|area| {
+            area.map_perm.contains(MapPermission::U)
+                && area.vpn_range.get_start() <= vpn
+                && vpn < area.vpn_range.get_end()
+                && !area.data_frames.contains_key(&vpn)
+        })
+    }
+    /// Give page `vpn` of a restored area a frame holding `data`. Fails if
+    /// the page is not in a user area or is mapped already, or if frames
+    /// run out.
+    pub fn restore_page(&mut self, vpn: VirtPageNum, data: &[u8; PAGE_SIZE]) -> bool {
+        let area = match self.restored_area(vpn) {
+            Some(i) => &mut self.areas[i],
+            None => return false,
+        };
+        if !area.map_one(&mut self.page_table, vpn) {
+            return false;
+        }
+        area.data_frames[&vpn].ppn.get_bytes_array().copy_from_slice(data);
+        true
+    }
+    /// Reserve page `vpn` of a restored area for a lazy mapping, as its
+    /// area allows. Fails if the page is not in a user area or is mapped
+    /// already.
+    pub fn restore_lazy_page(&mut self, vpn: VirtPageNum) -> bool {
+        let perm = match self.restored_area(vpn) {
+            Some(i) => self.areas[i].map_perm,
+            None => return false,
+        };
+        self.lazy_pages.insert(vpn, perm);
+        true
+    }
 }
 
 /// map area structure, controls a contiguous piece of virtual memory
@@ -298,22 +775,89 @@ impl MapArea {
             map_perm,
         }
     }
-    pub fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
-        let ppn: PhysPageNum;
-        match self.map_type {
-            MapType::Identical => {
-                ppn = PhysPageNum(vpn.0);
-            }
-            MapType::Framed => {
-                let frame = frame_alloc().unwrap();
-                ppn = frame.ppn;
-                self.data_frames.insert(vpn, frame);
-            }
+    /// Map `vpn`, returning false with nothing mapped if frames run out.
+    pub fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) -> bool {
+        let mut frame = None;
+        let ppn = match self.map_type {
+            MapType::Identical => PhysPageNum(vpn.0),
+            MapType::Framed => match frame_alloc() {
+                Some(tracker) => frame.insert(tracker).ppn,
+                None => return false,
+            },
+            MapType::Linear(base) => PhysPageNum(base.0 + vpn.0 - self.vpn_range.get_start().0),
+        };
+        if !page_table.map(vpn, ppn, self.map_perm.pte_flags()) {
+            return false;
+        }
+        if let Some(frame) = frame {
+            self.data_frames.insert(vpn, frame);
+        }
+        let memory_type = self.map_perm.memory_type();
+        if memory_type != MemoryType::Pma {
+            page_table.set_memory_type(vpn, memory_type);
         }
-        let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
-        page_table.map(vpn, ppn, pte_flags);
+        true
     }
     #[allow(unused)]
+    /// Keep `[start, at)` of the area, `at` being inside it, and return
+    /// the rest as an area of its own, with its frames.
+    fn split_off(&mut self, at: VirtPageNum) -> MapArea {
+        let (start, end) = (self.vpn_range.get_start(), self.vpn_range.get_end());
+        self.vpn_range = VPNRange::new(start, at);
+        let map_type = match self.map_type {
+            MapType::Linear(ppn) => MapType::Linear(PhysPageNum(ppn.0 + at.0 - start.0)),
+            map_type => map_type,
+        };
+        MapArea {
+            vpn_range: VPNRange::new(at, end),
+            data_frames: self.data_frames.split_off(&at),
+            map_type,
+            map_perm: self.map_perm,
+        }
+    }
+    /// Whether `next` starts where this user area ends and maps the same
+    /// way, so that the two can be one area.
+    fn can_merge(&self, next: &MapArea) -> bool {
+        let (start, end) = (self.vpn_range.get_start(), self.vpn_range.get_end());
+        end == next.vpn_range.get_start()
+            && self.map_perm == next.map_perm
+            && self.map_perm.contains(MapPermission::U)
+            && match (self.map_type, next.map_type) {
+                (MapType::Framed, MapType::Framed) => true,
+                (MapType::Linear(ppn), MapType::Linear(next_ppn)) => {
+                    next_ppn.0 == ppn.0 + end.0 - start.0
+                }
+                _ => false,
+            }
+    }
+    /// Append `next`, which [`MapArea::can_merge`] allows.
+    fn merge(&mut self, mut next: MapArea) {
+        self.vpn_range = VPNRange::new(self.vpn_range.get_start(), next.vpn_range.get_end());
+        self.data_frames.append(&mut next.data_frames);
+    }
+    /// Copy each page of a framed user area to a free frame below its own,
+    /// if there is one, and remap it there. Returns how many moved.
+    fn migrate_frames(&mut self, page_table: &mut PageTable) -> usize {
+        if self.map_type != MapType::Framed || !self.map_perm.contains(MapPermission::U) {
+            return 0;
+        }
+        let mut moved = 0;
+        for (&vpn, frame) in self.data_frames.iter_mut() {
+            let new_frame = match frame_alloc_below(frame.ppn) {
+                Some(new_frame) => new_frame,
+                None => continue,
+            };
+            new_frame
+                .ppn
+                .get_bytes_array()
+                .copy_from_slice(frame.ppn.get_bytes_array());
+            page_table.set_ppn(vpn, new_frame.ppn);
+            // frees the old frame
+            *frame = new_frame;
+            moved += 1;
+        }
+        moved
+    }
     pub fn unmap_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
         #[allow(clippy::single_match)]
         match self.map_type {
@@ -324,10 +868,17 @@ impl MapArea {
         }
         page_table.unmap(vpn);
     }
-    pub fn map(&mut self, page_table: &mut PageTable) {
+    /// Map every page, or none and return false if frames run out.
+    pub fn map(&mut self, page_table: &mut PageTable) -> bool {
         for vpn in self.vpn_range {
-            self.map_one(page_table, vpn);
+            if !self.map_one(page_table, vpn) {
+                for mapped in VPNRange::new(self.vpn_range.get_start(), vpn) {
+                    self.unmap_one(page_table, mapped);
+                }
+                return false;
+            }
         }
+        true
     }
     #[allow(unused)]
     pub fn unmap(&mut self, page_table: &mut PageTable) {
@@ -360,20 +911,135 @@ impl MapArea {
 }
 
 #[derive(Copy, Clone, PartialEq, Debug)]
-/// map type for memory set: identical or framed
+/// map type for memory set: identical, framed, or linear onto frames
+/// owned elsewhere starting at the given page
 pub enum MapType {
     Identical,
     Framed,
+    Linear(PhysPageNum),
 }
 
 bitflags! {
-    /// map permission corresponding to that in pte: `R W X U`
+    /// map permission corresponding to that in pte: `R W X U`, and the
+    /// Svpbmt memory type: `NC` for memory written like a framebuffer, `IO`
+    /// for device registers
     pub struct MapPermission: u8 {
         const R = 1 << 1;
         const W = 1 << 2;
         const X = 1 << 3;
         const U = 1 << 4;
+        const NC = 1 << 5;
+        const IO = 1 << 6;
+    }
+}
+
+impl MapPermission {
+    /// The pte flags giving this permission.
+    pub fn pte_flags(self) -> PTEFlags {
+        PTEFlags::from_bits_truncate(
+            (self & (MapPermission::R | MapPermission::W | MapPermission::X | MapPermission::U))
+                .bits,
+        )
+    }
+    /// The memory type asked for, which is only honoured with Svpbmt.
+    pub fn memory_type(self) -> MemoryType {
+        if !self.intersects(MapPermission::NC | MapPermission::IO)
+            || !has_extensions(Extensions::SVPBMT)
+        {
+            MemoryType::Pma
+        } else if self.contains(MapPermission::IO) {
+            MemoryType::Io
+        } else {
+            MemoryType::Nc
+        }
+    }
+}
+
+/// Check that `mmap` fails on any mapped page and `munmap` on any unmapped
+/// one, changing nothing, that areas are split and merged again, and that
+/// no frame is left behind.
+pub fn mmap_test() {
+    let free = frame_stats().unwrap().free;
+    let mut memory_set = MemorySet::new_bare();
+    let base = 0x1000_0000;
+    let page = |i: usize| base + i * PAGE_SIZE;
+    let mapped = |memory_set: &MemorySet, i: usize| memory_set.can_munmap(page(i), PAGE_SIZE);
+    assert_eq!(memory_set.mmap(page(0), 4 * PAGE_SIZE, 0x3), 0);
+    // overlapping the end of the mapping
+    assert_eq!(memory_set.mmap(page(3), 2 * PAGE_SIZE, 0x3), -1);
+    assert!(!mapped(&memory_set, 4));
+    assert_eq!(memory_set.munmap(page(1), 2 * PAGE_SIZE), 0);
+    assert_eq!(memory_set.areas.len(), 2);
+    // across the hole just made
+    assert_eq!(memory_set.munmap(page(0), 2 * PAGE_SIZE), -1);
+    assert!(mapped(&memory_set, 0));
+    assert_eq!(memory_set.mmap(page(0), 2 * PAGE_SIZE, 0x3), -1);
+    assert!(!mapped(&memory_set, 1));
+    // filling the hole makes one area again
+    assert_eq!(memory_set.mmap(page(1), 2 * PAGE_SIZE, 0x3), 0);
+    assert_eq!(memory_set.areas.len(), 1);
+    assert_eq!(memory_set.mprotect(page(1), PAGE_SIZE, 0x1), 0);
+    assert_eq!(memory_set.areas.len(), 3);
+    assert_eq!(memory_set.mprotect(page(1), PAGE_SIZE, 0x3), 0);
+    assert_eq!(memory_set.areas.len(), 1);
+    assert_eq!(memory_set.munmap(page(0), 4 * PAGE_SIZE), 0);
+    assert!(memory_set.areas.is_empty());
+    assert_eq!(memory_set.munmap(page(0), PAGE_SIZE), -1);
+    drop(memory_set);
+    assert_eq!(frame_stats().unwrap().free, free, "munmap left frames behind");
+    info!("mmap_test passed!");
+}
+
+/// Check which pages `mincore` reports resident, lazily mapped ones only
+/// once touched.
+pub fn mincore_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let base = 0x1000_0000;
+    assert_eq!(memory_set.mmap(base, 3 * PAGE_SIZE, 0x3), 0);
+    if LAZY_MMAP {
+        assert_eq!(memory_set.mincore(base, 3 * PAGE_SIZE), Some(vec![0, 0, 0]));
+        assert!(memory_set.handle_lazy_fault(base + PAGE_SIZE, MapPermission::W));
+        assert_eq!(memory_set.mincore(base, 3 * PAGE_SIZE), Some(vec![0, 1, 0]));
+    } else {
+        assert_eq!(memory_set.mincore(base, 3 * PAGE_SIZE), Some(vec![1, 1, 1]));
+    }
+    // a length that is not a multiple of the page size covers its last page
+    assert_eq!(memory_set.mincore(base + PAGE_SIZE, 1).map(|vec| vec.len()), Some(1));
+    // past the end of the mapping
+    assert_eq!(memory_set.mincore(base, 4 * PAGE_SIZE), None);
+    info!("mincore_test passed!");
+}
+
+/// Fail the frame allocation for a page table under a new mapping, and
+/// check that the mapping, or the lazy fault, fails cleanly and leaves no
+/// frame behind.
+#[cfg(feature = "fault-inject")]
+pub fn mmap_fault_test() {
+    use crate::fault::{configure, FaultConfig, FaultPoint};
+    let free = frame_stats().unwrap().free;
+    let mut memory_set = MemorySet::new_bare();
+    let start = 0x1000_0000;
+    let unmapped = |memory_set: &MemorySet| {
+        memory_set.translate(VirtAddr(start).floor()).map_or(true, |pte| !pte.is_valid())
+    };
+    // the data frame of the first page is taken, then its page table fails
+    let fail_second = FaultConfig { probability: 1000, skip: 1, times: 1, pid: -1 };
+    configure(FaultPoint::Frame, fail_second);
+    if LAZY_MMAP {
+        assert_eq!(memory_set.mmap(start, 4 * PAGE_SIZE, 0x3), 0);
+        assert!(!memory_set.handle_lazy_fault(start, MapPermission::R));
+        assert!(unmapped(&memory_set));
+        assert!(memory_set.handle_lazy_fault(start, MapPermission::R));
+    } else {
+        assert_eq!(memory_set.mmap(start, 4 * PAGE_SIZE, 0x3), -1);
+        assert!(memory_set.areas.is_empty());
+        assert!(unmapped(&memory_set));
+        assert_eq!(memory_set.mmap(start, 4 * PAGE_SIZE, 0x3), 0);
     }
+    let off = FaultConfig { probability: 0, skip: 0, times: 0, pid: -1 };
+    assert_eq!(configure(FaultPoint::Frame, off), 1);
+    drop(memory_set);
+    assert_eq!(frame_stats().unwrap().free, free, "the failed mapping left frames behind");
 }
 
 #[allow(unused)]
@@ -8,18 +8,46 @@
 
 
 mod address;
+mod cma;
+mod dma;
 mod frame_allocator;
 mod heap_allocator;
+#[cfg(feature = "leak-detector")]
+mod heap_track;
+mod memory_map;
 mod memory_set;
+#[cfg(feature = "page-poison")]
+mod page_poison;
 mod page_table;
+mod paging;
+mod pressure;
+mod sandbox;
+mod user_ptr;
+
+use crate::ktest::KernelTest;
 
 pub use address::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
 pub use address::{StepByOne, VPNRange};
-pub use frame_allocator::{frame_alloc, FrameTracker};
+pub use dma::{DmaBuffer, DmaRegion};
+pub use frame_allocator::{frame_alloc, frame_stats, FrameTracker};
+#[cfg(any(feature = "hypervisor", feature = "kcov"))]
+pub use frame_allocator::frame_alloc_contiguous;
+pub use frame_allocator::{hot_add_memory, trim_free_frames};
+pub use heap_allocator::heap_used;
+#[cfg(feature = "leak-detector")]
+pub use heap_track::{heap_sites, HeapSite};
+pub use memory_map::{crash_dump_base, sched_log_base};
+#[cfg(feature = "page-poison")]
+pub use page_poison::on_switch as page_poison_on_switch;
 pub use memory_set::remap_test;
 pub use memory_set::{MapPermission, MemorySet, KERNEL_SPACE};
-pub use page_table::{translated_byte_buffer, PageTableEntry};
-pub use page_table::{PTEFlags, PageTable};
+use page_table::translated_byte_buffer_checked;
+pub use page_table::PageTableEntry;
+pub use page_table::{PTEFlags, PageTable, UserBuffer};
+pub use pressure::{pressure_events, set_watermarks};
+pub use pressure::{PressureLevel, Watermarks};
+pub use sandbox::SandboxProfile;
+pub use user_ptr::{read_user_str, UserPtr, UserSlice};
 
 /// initiate heap allocator, frame allocator and kernel space
 pub fn init() {
@@ -27,3 +55,17 @@ pub fn init() {
     frame_allocator::init_frame_allocator();
     KERNEL_SPACE.lock().activate();
 }
+
+/// Tests of the heap, frame and DMA allocators, page tables and `mmap`
+pub const TESTS: &[KernelTest] = &[
+    test_case!(heap_allocator::heap_test),
+    #[cfg(feature = "heap-redzone")]
+    test_case!(heap_track::redzone_test),
+    test_case!(frame_allocator::frame_allocator_test),
+    test_case!(cma::cma_test),
+    test_case!(page_table::page_table_test),
+    test_case!(memory_set::mmap_test),
+    test_case!(memory_set::mincore_test),
+    #[cfg(feature = "fault-inject")]
+    test_case!(memory_set::mmap_fault_test),
+];
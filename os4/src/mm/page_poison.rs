@@ -0,0 +1,85 @@
+//! Page poisoning, built with the `page-poison` feature
+//!
+//! A freed frame is filled with [`POISON`] instead of zeros, except for a
+//! record at its start of who freed it: the task that was running and the
+//! return addresses of the code. When the frame is allocated again, the
+//! poison is checked before the frame is cleared. A byte that changed means
+//! something wrote to the frame while it was free, typically through a
+//! page that stayed mapped after its frame was dropped, and is reported
+//! with the record.
+//!
+//! Frames that were never freed have no record and are not checked, nor
+//! are those whose record itself was overwritten.
+
+use super::PhysPageNum;
+use core::mem::size_of;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// the byte freed frames are filled with, as in Linux
+const POISON: u8 = 0xaa;
+/// marks a frame holding a [`FreeRecord`]
+const MAGIC: usize = 0x706f_6973;
+/// return addresses kept of the code that freed a frame
+const SITE_DEPTH: usize = 4;
+/// those of the poisoning itself and of the drop of the frame tracker
+const SKIPPED_FRAMES: usize = 2;
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct FreeRecord {
+    magic: usize,
+    /// the task running when the frame was freed
+    task: usize,
+    /// innermost first, 0 past the end of the stack
+    site: [usize; SITE_DEPTH],
+}
+
+/// the task running now, `usize::MAX` before the first one
+static RUNNING_TASK: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// Task switch path: `next` is about to run.
+pub fn on_switch(next: usize) {
+    RUNNING_TASK.store(next, Ordering::Relaxed);
+}
+
+/// Fill the frame `ppn`, being freed, with poison and the record of who
+/// freed it.
+pub(super) fn poison(ppn: PhysPageNum) {
+    let mut site = [0; SITE_DEPTH];
+    let mut depth = 0;
+    crate::crash::walk_frames(|ra| {
+        if depth >= SKIPPED_FRAMES {
+            site[depth - SKIPPED_FRAMES] = ra;
+        }
+        depth += 1;
+        depth < SKIPPED_FRAMES + SITE_DEPTH
+    });
+    let bytes = ppn.get_bytes_array();
+    bytes.fill(POISON);
+    let record = FreeRecord {
+        magic: MAGIC,
+        task: RUNNING_TASK.load(Ordering::Relaxed),
+        site,
+    };
+    unsafe { (bytes.as_mut_ptr() as *mut FreeRecord).write_unaligned(record) };
+}
+
+/// Check that the frame `ppn`, being allocated, still holds the poison it
+/// was freed with, and report it if not.
+pub(super) fn check(ppn: PhysPageNum) {
+    let bytes = ppn.get_bytes_array();
+    let record = unsafe { (bytes.as_ptr() as *const FreeRecord).read_unaligned() };
+    if record.magic != MAGIC {
+        return;
+    }
+    let poisoned = &bytes[size_of::<FreeRecord>()..];
+    if let Some(offset) = poisoned.iter().position(|&byte| byte != POISON) {
+        error!(
+            "[kernel] frame {:#x} written at offset {:#x} after task {} freed it from {:x?}",
+            ppn.0,
+            size_of::<FreeRecord>() + offset,
+            record.task as isize,
+            record.site
+        );
+    }
+}
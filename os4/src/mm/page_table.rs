@@ -1,6 +1,8 @@
 //! Implementation of [`PageTableEntry`] and [`PageTable`].
 
-use super::{frame_alloc, FrameTracker, PhysPageNum, StepByOne, VirtAddr, VirtPageNum};
+use super::paging::{PAGE_TABLE_LEVELS, PBMT_MASK, PBMT_SHIFT, PPN_MASK, SATP_MODE};
+use super::{frame_alloc, FrameTracker, PhysAddr, PhysPageNum, StepByOne, VirtAddr, VirtPageNum};
+use crate::config::LAZY_MMAP;
 use alloc::vec;
 use alloc::vec::Vec;
 use bitflags::*;
@@ -19,6 +21,18 @@ bitflags! {
     }
 }
 
+/// Svpbmt memory type of a page, overriding the attributes the platform
+/// gives its physical address
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum MemoryType {
+    /// the attributes of the platform
+    Pma = 0,
+    /// non-cacheable and idempotent, so that writes may be combined
+    Nc = 1,
+    /// non-cacheable, non-idempotent and strongly ordered, for devices
+    Io = 2,
+}
+
 #[derive(Copy, Clone)]
 #[repr(C)]
 /// page table entry structure
@@ -36,7 +50,7 @@ impl PageTableEntry {
         PageTableEntry { bits: 0 }
     }
     pub fn ppn(&self) -> PhysPageNum {
-        (self.bits >> 10 & ((1usize << 44) - 1)).into()
+        (self.bits >> 10 & PPN_MASK).into()
     }
     pub fn flags(&self) -> PTEFlags {
         PTEFlags::from_bits(self.bits as u8).unwrap()
@@ -73,7 +87,7 @@ impl PageTable {
     /// Temporarily used to get arguments from user space.
     pub fn from_token(satp: usize) -> Self {
         Self {
-            root_ppn: PhysPageNum::from(satp & ((1usize << 44) - 1)),
+            root_ppn: PhysPageNum::from(satp & PPN_MASK),
             frames: Vec::new(),
         }
     }
@@ -83,12 +97,12 @@ impl PageTable {
         let mut result: Option<&mut PageTableEntry> = None;
         for (i, idx) in idxs.iter_mut().enumerate() {
             let pte = &mut ppn.get_pte_array()[*idx];
-            if i == 2 {
+            if i == PAGE_TABLE_LEVELS - 1 {
                 result = Some(pte);
                 break;
             }
             if !pte.is_valid() {
-                let frame = frame_alloc().unwrap();
+                let frame = frame_alloc()?;
                 *pte = PageTableEntry::new(frame.ppn, PTEFlags::V);
                 self.frames.push(frame);
             }
@@ -102,7 +116,7 @@ impl PageTable {
         let mut result: Option<&PageTableEntry> = None;
         for (i, idx) in idxs.iter().enumerate() {
             let pte = &ppn.get_pte_array()[*idx];
-            if i == 2 {
+            if i == PAGE_TABLE_LEVELS - 1 {
                 result = Some(pte);
                 break;
             }
@@ -114,11 +128,17 @@ impl PageTable {
         }
         result
     }
+    /// Map `vpn` to `ppn`, returning false if there was no frame for a
+    /// page table it needed.
     #[allow(unused)]
-    pub fn map(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) {
-        let pte = self.find_pte_create(vpn).unwrap();
+    pub fn map(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) -> bool {
+        let pte = match self.find_pte_create(vpn) {
+            Some(pte) => pte,
+            None => return false,
+        };
         assert!(!pte.is_valid(), "vpn {:?} is mapped before mapping", vpn);
         *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
+        true
     }
     #[allow(unused)]
     pub fn unmap(&mut self, vpn: VirtPageNum) {
@@ -126,36 +146,90 @@ impl PageTable {
         assert!(pte.is_valid(), "vpn {:?} is invalid before unmapping", vpn);
         *pte = PageTableEntry::empty();
     }
+    /// Change the flags of the mapped page `vpn`, keeping its frame and
+    /// memory type.
+    pub fn set_flags(&mut self, vpn: VirtPageNum, flags: PTEFlags) {
+        let pte = self.find_pte_create(vpn).unwrap();
+        assert!(pte.is_valid(), "vpn {:?} is invalid before protecting", vpn);
+        let memory_type = pte.bits & PBMT_MASK;
+        *pte = PageTableEntry::new(pte.ppn(), flags | PTEFlags::V);
+        pte.bits |= memory_type;
+    }
+    /// Point the mapped page `vpn` at the frame `ppn`, keeping its flags and
+    /// memory type.
+    pub fn set_ppn(&mut self, vpn: VirtPageNum, ppn: PhysPageNum) {
+        let pte = self.find_pte_create(vpn).unwrap();
+        assert!(pte.is_valid(), "vpn {:?} is invalid before moving", vpn);
+        let memory_type = pte.bits & PBMT_MASK;
+        *pte = PageTableEntry::new(ppn, pte.flags());
+        pte.bits |= memory_type;
+    }
+    /// Set the memory type of the mapped page `vpn`. Only call this when the
+    /// harts have Svpbmt, as the field is reserved otherwise.
+    pub fn set_memory_type(&mut self, vpn: VirtPageNum, memory_type: MemoryType) {
+        let pte = self.find_pte_create(vpn).unwrap();
+        assert!(pte.is_valid(), "vpn {:?} is invalid before typing", vpn);
+        pte.bits = pte.bits & !PBMT_MASK | (memory_type as usize) << PBMT_SHIFT & PBMT_MASK;
+    }
     pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
         self.find_pte(vpn).copied()
     }
+    #[allow(unused)]
+    pub fn translate_va(&self, va: VirtAddr) -> Option<PhysAddr> {
+        self.find_pte(va.floor()).map(|pte| {
+            let aligned_pa: PhysAddr = pte.ppn().into();
+            let offset = va.page_offset();
+            let aligned_pa_usize: usize = aligned_pa.into();
+            (aligned_pa_usize + offset).into()
+        })
+    }
+    /// Like [`PageTable::translate`], but for a user address space, faulting
+    /// in `vpn` first if it is a lazily mapped page without a frame yet.
+    fn translate_user(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
+        match self.translate(vpn) {
+            Some(pte) if pte.is_valid() => Some(pte),
+            other if !LAZY_MMAP => other,
+            other => {
+                if crate::task::fault_in(self.token(), VirtAddr::from(vpn).0) {
+                    self.translate(vpn)
+                } else {
+                    other
+                }
+            }
+        }
+    }
     pub fn token(&self) -> usize {
-        8usize << 60 | self.root_ppn.0
+        SATP_MODE | self.root_ppn.0
     }
 }
 
-/// translate a pointer to a mutable u8 Vec through page table
-pub fn translated_byte_buffer(token: usize, ptr: *const u8, len: usize) -> Vec<&'static mut [u8]> {
+/// translate the `len` bytes at `ptr` to the slices of the frames they are
+/// in, through page table. Only the prefix of the range whose pages are
+/// mapped with all of `flags` is covered; translation stops at the first
+/// page that is unmapped or lacks a permission.
+pub fn translated_byte_buffer_checked(
+    token: usize,
+    ptr: *const u8,
+    len: usize,
+    flags: PTEFlags,
+) -> Vec<&'static mut [u8]> {
     let page_table = PageTable::from_token(token);
     let mut start = ptr as usize;
-    let end = start + len;
+    let end = start.saturating_add(len);
     let mut v = Vec::new();
     while start < end {
         let start_va = VirtAddr::from(start);
-        //info!("start_va: {:?}", start_va);
         let mut vpn = start_va.floor();
-        let ppn = page_table.translate(vpn).unwrap().ppn();
+        let ppn = match page_table.translate_user(vpn) {
+            Some(pte) if pte.is_valid() && pte.flags().contains(flags) => pte.ppn(),
+            _ => break,
+        };
         vpn.step();
         let mut end_va: VirtAddr = vpn.into();
-        //info!("end_va1: {:?}", end_va);
-        //info!("virt addr of end: {:?}", VirtAddr::from(end));
         end_va = end_va.min(VirtAddr::from(end));
-        //info!("end_va2: {:?}", end_va);
         if end_va.page_offset() == 0 {
-            //info!("page offset is zero");
             v.push(&mut ppn.get_bytes_array()[start_va.page_offset()..]);
         } else {
-            //info!("page offset not zero");
             v.push(&mut ppn.get_bytes_array()[start_va.page_offset()..end_va.page_offset()]);
         }
         start = end_va.into();
@@ -163,4 +237,79 @@ pub fn translated_byte_buffer(token: usize, ptr: *const u8, len: usize) -> Vec<&
     v
 }
 
+/// An abstraction over a buffer passed from user space to kernel space
+pub struct UserBuffer {
+    pub buffers: Vec<&'static mut [u8]>,
+}
 
+impl UserBuffer {
+    /// Constuct a UserBuffer
+    pub fn new(buffers: Vec<&'static mut [u8]>) -> Self {
+        Self { buffers }
+    }
+    /// Get the length of a UserBuffer
+    pub fn len(&self) -> usize {
+        let mut total: usize = 0;
+        for b in self.buffers.iter() {
+            total += b.len();
+        }
+        total
+    }
+}
+
+impl IntoIterator for UserBuffer {
+    type Item = *mut u8;
+    type IntoIter = UserBufferIterator;
+    fn into_iter(self) -> Self::IntoIter {
+        UserBufferIterator {
+            buffers: self.buffers,
+            current_buffer: 0,
+            current_idx: 0,
+        }
+    }
+}
+
+/// An iterator over a UserBuffer
+pub struct UserBufferIterator {
+    buffers: Vec<&'static mut [u8]>,
+    current_buffer: usize,
+    current_idx: usize,
+}
+
+impl Iterator for UserBufferIterator {
+    type Item = *mut u8;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.current_buffer >= self.buffers.len() {
+            None
+        } else {
+            let r = &mut self.buffers[self.current_buffer][self.current_idx] as *mut _;
+            if self.current_idx + 1 == self.buffers[self.current_buffer].len() {
+                self.current_idx = 0;
+                self.current_buffer += 1;
+            } else {
+                self.current_idx += 1;
+            }
+            Some(r)
+        }
+    }
+}
+
+/// Map, translate and unmap a page in a page table of its own.
+pub fn page_table_test() {
+    let mut page_table = PageTable::new();
+    let frame = frame_alloc().unwrap();
+    let vpn = VirtPageNum(0x12345);
+    let unmapped = |page_table: &PageTable| page_table.translate(vpn).map_or(true, |pte| !pte.is_valid());
+    assert!(unmapped(&page_table));
+    assert!(page_table.map(vpn, frame.ppn, PTEFlags::R | PTEFlags::W));
+    let pte = page_table.translate(vpn).unwrap();
+    assert!(pte.is_valid() && pte.readable() && pte.writable() && !pte.executable());
+    assert_eq!(pte.ppn(), frame.ppn);
+    let va = VirtAddr::from(vpn).0 + 0x123;
+    assert_eq!(page_table.translate_va(va.into()).unwrap().0, PhysAddr::from(frame.ppn).0 + 0x123);
+    page_table.set_flags(vpn, PTEFlags::R);
+    assert!(!page_table.translate(vpn).unwrap().writable());
+    page_table.unmap(vpn);
+    assert!(unmapped(&page_table));
+    info!("page_table_test passed!");
+}
@@ -0,0 +1,37 @@
+//! Paging mode of the target
+//!
+//! riscv64 kernels use Sv39: three levels of 512 entries of 8 bytes, and
+//! 44-bit physical page numbers. riscv32 kernels use Sv32: two levels of
+//! 1024 entries of 4 bytes, and 22-bit physical page numbers. Everything
+//! else in `mm` is written in terms of these constants. Only Sv39 entries
+//! have room for an Svpbmt memory type.
+
+#[cfg(target_pointer_width = "64")]
+mod mode {
+    pub const PAGE_TABLE_LEVELS: usize = 3;
+    pub const VPN_INDEX_BITS: usize = 9;
+    pub const PPN_BITS: usize = 44;
+    /// `MODE` field of `satp` selecting Sv39
+    pub const SATP_MODE: usize = 8 << 60;
+    /// Svpbmt memory type field of a leaf entry, bits 62:61
+    pub const PBMT_SHIFT: usize = 61;
+    pub const PBMT_MASK: usize = 3 << PBMT_SHIFT;
+}
+
+#[cfg(target_pointer_width = "32")]
+mod mode {
+    pub const PAGE_TABLE_LEVELS: usize = 2;
+    pub const VPN_INDEX_BITS: usize = 10;
+    pub const PPN_BITS: usize = 22;
+    /// `MODE` field of `satp` selecting Sv32
+    pub const SATP_MODE: usize = 1 << 31;
+    /// Sv32 entries have no memory type field, so types are never applied
+    pub const PBMT_SHIFT: usize = 0;
+    pub const PBMT_MASK: usize = 0;
+}
+
+pub use mode::*;
+
+/// Entries in one page table
+pub const PTES_PER_TABLE: usize = 1 << VPN_INDEX_BITS;
+pub const PPN_MASK: usize = (1 << PPN_BITS) - 1;
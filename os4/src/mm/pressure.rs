@@ -0,0 +1,95 @@
+//! Memory pressure levels
+//!
+//! The free frames are compared with two watermarks whenever a frame is
+//! allocated or freed. Falling below `low` raises the level to
+//! [`PressureLevel::Low`], below `min` to [`PressureLevel::Critical`]. Each
+//! rise is an event reported by the pressure descriptors of
+//! [`crate::fs::PressureFd`], so that programs keeping caches can give
+//! memory back before allocations start to fail.
+
+use super::frame_stats;
+use crate::config::{MEMORY_LOW_WATERMARK_PAGES, MEMORY_MIN_WATERMARK_PAGES};
+use crate::sync::UPSafeCell;
+use lazy_static::*;
+
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PressureLevel {
+    None = 0,
+    Low = 1,
+    Critical = 2,
+}
+
+/// Free frames below which memory is under pressure
+#[derive(Copy, Clone)]
+pub struct Watermarks {
+    pub low: usize,
+    /// at most `low`
+    pub min: usize,
+}
+
+impl Watermarks {
+    fn level(&self, free: usize) -> PressureLevel {
+        if free < self.min {
+            PressureLevel::Critical
+        } else if free < self.low {
+            PressureLevel::Low
+        } else {
+            PressureLevel::None
+        }
+    }
+}
+
+struct Pressure {
+    watermarks: Watermarks,
+    level: PressureLevel,
+    /// how many times the level rose, and to which level the last time
+    events: usize,
+    event_level: PressureLevel,
+}
+
+lazy_static! {
+    static ref PRESSURE: UPSafeCell<Pressure> = unsafe {
+        UPSafeCell::new(Pressure {
+            watermarks: Watermarks {
+                low: MEMORY_LOW_WATERMARK_PAGES,
+                min: MEMORY_MIN_WATERMARK_PAGES,
+            },
+            level: PressureLevel::None,
+            events: 0,
+            event_level: PressureLevel::None,
+        })
+    };
+}
+
+/// Update the level with `free` frames left, counting an event if it rose.
+pub(super) fn update_pressure(free: usize) {
+    let mut pressure = PRESSURE.exclusive_access();
+    let level = pressure.watermarks.level(free);
+    if level > pressure.level {
+        pressure.events += 1;
+        pressure.event_level = level;
+    }
+    pressure.level = level;
+}
+
+/// The events so far, and the level the last one rose to.
+pub fn pressure_events() -> (usize, PressureLevel) {
+    let pressure = PRESSURE.exclusive_access();
+    (pressure.events, pressure.event_level)
+}
+
+/// Replace the watermarks, which takes effect at once. Returns false, with
+/// nothing changed, if `min` is above `low` or `low` above all of memory.
+pub fn set_watermarks(watermarks: Watermarks) -> bool {
+    let stats = match frame_stats() {
+        Some(stats) => stats,
+        None => return false,
+    };
+    if watermarks.min > watermarks.low || watermarks.low > stats.total {
+        return false;
+    }
+    PRESSURE.exclusive_access().watermarks = watermarks;
+    update_pressure(stats.free);
+    true
+}
@@ -0,0 +1,56 @@
+//! Address-space sandbox profiles
+//!
+//! A profile limits where a task may map memory and how much at a time. A
+//! supervisor sets it on a task that has not run yet, so that an untrusted
+//! binary starts confined; a task may also confine itself. Either way a
+//! profile, once set, cannot be replaced.
+//!
+//! The user stack is mapped once at load and never grows, so the profile
+//! only applies to mappings made later.
+
+use super::VirtAddr;
+use crate::config::{MAX_SANDBOX_RANGES, USER_SPACE_END};
+
+/// A range of user addresses, `[start, end)`
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct VaRange {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// A sandbox profile, as passed by user space
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct SandboxProfile {
+    /// largest mapping, in bytes
+    pub max_mapping: usize,
+    /// ranges in use in `ranges`
+    pub count: usize,
+    /// where mappings may be made; each must fit in one of them
+    pub ranges: [VaRange; MAX_SANDBOX_RANGES],
+}
+
+impl SandboxProfile {
+    /// Whether the profile is well formed: page-aligned, non-empty ranges
+    /// in user space.
+    pub fn is_valid(&self) -> bool {
+        self.count <= MAX_SANDBOX_RANGES
+            && self.ranges[..self.count].iter().all(|range| {
+                VirtAddr(range.start).aligned()
+                    && VirtAddr(range.end).aligned()
+                    && range.start < range.end
+                    && range.end <= USER_SPACE_END
+            })
+    }
+    /// Whether the profile allows mapping `[start, start + len)`.
+    pub fn allows(&self, start: usize, len: usize) -> bool {
+        let end = match start.checked_add(len) {
+            Some(end) if len <= self.max_mapping => end,
+            _ => return false,
+        };
+        self.ranges[..self.count]
+            .iter()
+            .any(|range| range.start <= start && end <= range.end)
+    }
+}
@@ -0,0 +1,187 @@
+//! Typed pointers into user memory
+//!
+//! A syscall argument pointing into user memory is wrapped, with the token
+//! of the task it belongs to, in a [`UserPtr`] or a [`UserSlice`]. Reading
+//! or writing one checks that every page it covers is a user page with the
+//! needed permission, and copies byte by byte, so that the value may span
+//! pages and need not be aligned. A write changes nothing unless all of it
+//! fits.
+//!
+//! What is read must be plain data: any bit pattern from user space must be
+//! a valid `T`, which rules out enums and references.
+
+use super::memory_set::in_user_space;
+use super::{translated_byte_buffer_checked, PTEFlags, UserBuffer};
+use crate::config::USER_SPACE_END;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use core::mem::{size_of, MaybeUninit};
+
+/// The pages of the `len` bytes at `addr`, if all are user pages mapped
+/// with `flags`. A range that wraps around or goes past the user half of
+/// the address space is never translated.
+fn user_buffers(
+    token: usize,
+    addr: usize,
+    len: usize,
+    flags: PTEFlags,
+) -> Option<Vec<&'static mut [u8]>> {
+    if !in_user_space(addr, len) {
+        return None;
+    }
+    let buffers =
+        translated_byte_buffer_checked(token, addr as *const u8, len, flags | PTEFlags::U);
+    if buffers.iter().map(|buffer| buffer.len()).sum::<usize>() < len {
+        return None;
+    }
+    Some(buffers)
+}
+
+/// Copy `bytes` to the `bytes.len()` bytes at `addr`, if all are writable.
+fn copy_out(token: usize, addr: usize, bytes: &[u8]) -> bool {
+    let buffers = match user_buffers(token, addr, bytes.len(), PTEFlags::W) {
+        Some(buffers) => buffers,
+        None => return false,
+    };
+    let mut copied = 0;
+    for buffer in buffers {
+        buffer.copy_from_slice(&bytes[copied..copied + buffer.len()]);
+        copied += buffer.len();
+    }
+    true
+}
+
+/// Fill `bytes` from `buffers`, which hold exactly as many.
+fn copy_in(buffers: Vec<&'static mut [u8]>, bytes: &mut [u8]) {
+    let mut copied = 0;
+    for buffer in buffers {
+        bytes[copied..copied + buffer.len()].copy_from_slice(buffer);
+        copied += buffer.len();
+    }
+}
+
+/// One `T` in the memory of a task
+pub struct UserPtr<T> {
+    token: usize,
+    addr: usize,
+    _marker: PhantomData<*mut T>,
+}
+
+impl<T: Copy> UserPtr<T> {
+    pub fn new(token: usize, ptr: *const T) -> Self {
+        Self {
+            token,
+            addr: ptr as usize,
+            _marker: PhantomData,
+        }
+    }
+    pub fn is_null(&self) -> bool {
+        self.addr == 0
+    }
+    /// Read the value, or None if it is not all readable.
+    pub fn read(&self) -> Option<T> {
+        let buffers = user_buffers(self.token, self.addr, size_of::<T>(), PTEFlags::R)?;
+        let mut value = MaybeUninit::<T>::uninit();
+        let bytes = unsafe {
+            core::slice::from_raw_parts_mut(value.as_mut_ptr() as *mut u8, size_of::<T>())
+        };
+        copy_in(buffers, bytes);
+        Some(unsafe { value.assume_init() })
+    }
+    /// Write `value`, returning whether it is all writable.
+    pub fn write(&self, value: T) -> bool {
+        let bytes =
+            unsafe { core::slice::from_raw_parts(&value as *const T as *const u8, size_of::<T>()) };
+        copy_out(self.token, self.addr, bytes)
+    }
+}
+
+/// `len` consecutive `T`s in the memory of a task
+pub struct UserSlice<T> {
+    token: usize,
+    addr: usize,
+    len: usize,
+    _marker: PhantomData<*mut T>,
+}
+
+impl<T: Copy> UserSlice<T> {
+    pub fn new(token: usize, ptr: *const T, len: usize) -> Self {
+        Self {
+            token,
+            addr: ptr as usize,
+            len,
+            _marker: PhantomData,
+        }
+    }
+    /// Read every element, or None if they are not all readable.
+    pub fn read(&self) -> Option<Vec<T>> {
+        // translate first, so that a bogus length fails before allocating
+        let size = self.len.checked_mul(size_of::<T>())?;
+        let buffers = user_buffers(self.token, self.addr, size, PTEFlags::R)?;
+        let mut values: Vec<T> = Vec::with_capacity(self.len);
+        let bytes =
+            unsafe { core::slice::from_raw_parts_mut(values.as_mut_ptr() as *mut u8, size) };
+        copy_in(buffers, bytes);
+        unsafe { values.set_len(self.len) };
+        Some(values)
+    }
+    /// Read every element into `values`, which holds as many, returning
+    /// whether they are all readable. For slices too big to take the heap
+    /// for granted, which the caller reserves `values` for.
+    pub fn read_into(&self, values: &mut [T]) -> bool {
+        debug_assert_kernel!(
+            values.len() == self.len,
+            "reading a user slice into another size"
+        );
+        let size = core::mem::size_of_val(values);
+        let buffers = match user_buffers(self.token, self.addr, size, PTEFlags::R) {
+            Some(buffers) => buffers,
+            None => return false,
+        };
+        let bytes =
+            unsafe { core::slice::from_raw_parts_mut(values.as_mut_ptr() as *mut u8, size) };
+        copy_in(buffers, bytes);
+        true
+    }
+    /// Write `values` to the first elements, returning whether they are all
+    /// writable. There must be no more of them than the slice holds.
+    pub fn write(&self, values: &[T]) -> bool {
+        debug_assert_kernel!(values.len() <= self.len, "writing past a user slice");
+        let bytes = unsafe {
+            core::slice::from_raw_parts(values.as_ptr() as *const u8, values.len() * size_of::<T>())
+        };
+        copy_out(self.token, self.addr, bytes)
+    }
+}
+
+impl UserSlice<u8> {
+    /// The bytes as a [`UserBuffer`] for a file to write out, or to read
+    /// into if `writable`, or None if they are not all accessible so.
+    pub fn buffer(&self, writable: bool) -> Option<UserBuffer> {
+        let flags = if writable { PTEFlags::W } else { PTEFlags::R };
+        user_buffers(self.token, self.addr, self.len, flags).map(UserBuffer::new)
+    }
+    /// The longest prefix of the bytes accessible as [`Self::buffer`] asks,
+    /// for a copy that stops at the first page it cannot access.
+    pub fn prefix_buffer(&self, writable: bool) -> UserBuffer {
+        let flags = if writable { PTEFlags::W } else { PTEFlags::R };
+        let len = self.len.min(USER_SPACE_END.saturating_sub(self.addr));
+        let ptr = self.addr as *const u8;
+        let buffers = translated_byte_buffer_checked(self.token, ptr, len, flags | PTEFlags::U);
+        UserBuffer::new(buffers)
+    }
+}
+
+/// The NUL-terminated string at `ptr` in the memory of a task, or None if
+/// it is not all readable, is longer than `max_len` bytes or is not UTF-8.
+pub fn read_user_str(token: usize, ptr: *const u8, max_len: usize) -> Option<String> {
+    let mut bytes = Vec::new();
+    for i in 0..=max_len {
+        match UserPtr::new(token, (ptr as usize).wrapping_add(i) as *const u8).read()? {
+            0 => return String::from_utf8(bytes).ok(),
+            byte => bytes.push(byte),
+        }
+    }
+    None
+}
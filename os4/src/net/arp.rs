@@ -0,0 +1,99 @@
+//! Neighbor cache, IPv4 address -> MAC address
+
+use super::Ipv4Addr;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+/// how long a learned address is trusted
+const ENTRY_LIFETIME_MS: usize = 60_000;
+/// least time between two requests for the same address
+const REQUEST_INTERVAL_MS: usize = 1000;
+/// entries kept before the one expiring first is dropped
+const MAX_ENTRIES: usize = 64;
+
+/// A cache entry as shown to user space
+pub struct Neighbor {
+    pub ip: Ipv4Addr,
+    /// `None` while a request is outstanding
+    pub mac: Option<[u8; 6]>,
+}
+
+struct Entry {
+    mac: [u8; 6],
+    expires: usize,
+}
+
+pub struct ArpCache {
+    entries: BTreeMap<Ipv4Addr, Entry>,
+    /// address -> time of the last request sent for it
+    requested: BTreeMap<Ipv4Addr, usize>,
+}
+
+impl ArpCache {
+    pub fn new() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+            requested: BTreeMap::new(),
+        }
+    }
+    pub fn lookup(&self, ip: Ipv4Addr, now: usize) -> Option<[u8; 6]> {
+        self.entries
+            .get(&ip)
+            .filter(|entry| entry.expires > now)
+            .map(|entry| entry.mac)
+    }
+    pub fn insert(&mut self, ip: Ipv4Addr, mac: [u8; 6], now: usize) {
+        if !self.entries.contains_key(&ip) && self.entries.len() == MAX_ENTRIES {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.expires)
+                .map(|(&ip, _)| ip);
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.entries.insert(
+            ip,
+            Entry {
+                mac,
+                expires: now + ENTRY_LIFETIME_MS,
+            },
+        );
+        self.requested.remove(&ip);
+    }
+    /// Whether a request for `ip` should go out now; if so, it is recorded
+    /// as sent.
+    pub fn should_request(&mut self, ip: Ipv4Addr, now: usize) -> bool {
+        match self.requested.get(&ip) {
+            Some(&last) if now < last + REQUEST_INTERVAL_MS => false,
+            _ => {
+                self.requested.insert(ip, now);
+                true
+            }
+        }
+    }
+    /// Drop the expired entries.
+    pub fn expire(&mut self, now: usize) {
+        self.entries.retain(|_, entry| entry.expires > now);
+        self.requested
+            .retain(|_, &mut last| now < last + REQUEST_INTERVAL_MS);
+    }
+    /// The live entries, then the addresses being requested.
+    pub fn neighbors(&self, now: usize) -> Vec<Neighbor> {
+        let resolved = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.expires > now)
+            .map(|(&ip, entry)| Neighbor {
+                ip,
+                mac: Some(entry.mac),
+            });
+        let incomplete = self
+            .requested
+            .iter()
+            .filter(|(ip, _)| !self.entries.contains_key(ip))
+            .map(|(&ip, _)| Neighbor { ip, mac: None });
+        resolved.chain(incomplete).collect()
+    }
+}
@@ -0,0 +1,207 @@
+//! DHCP client
+//!
+//! Runs the DISCOVER / OFFER / REQUEST / ACK exchange once to configure the
+//! interface. Leases are not renewed: the exchange can simply be run again.
+
+use super::Ipv4Addr;
+use alloc::vec::Vec;
+
+pub const CLIENT_PORT: u16 = 68;
+pub const SERVER_PORT: u16 = 67;
+const MAGIC_COOKIE: [u8; 4] = [0x63, 0x82, 0x53, 0x63];
+/// fixed part of a message, up to and including the magic cookie
+const FIXED_LEN: usize = 240;
+/// time between retransmissions, and tries before giving up
+const RETRY_MS: usize = 1000;
+const MAX_TRIES: usize = 4;
+
+const OP_REQUEST: u8 = 1;
+const OP_REPLY: u8 = 2;
+
+const OPTION_SUBNET_MASK: u8 = 1;
+const OPTION_ROUTER: u8 = 3;
+const OPTION_DNS: u8 = 6;
+const OPTION_REQUESTED_IP: u8 = 50;
+const OPTION_MESSAGE_TYPE: u8 = 53;
+const OPTION_SERVER_ID: u8 = 54;
+const OPTION_PARAMETERS: u8 = 55;
+const OPTION_END: u8 = 255;
+
+const DHCPDISCOVER: u8 = 1;
+const DHCPOFFER: u8 = 2;
+const DHCPREQUEST: u8 = 3;
+const DHCPACK: u8 = 5;
+const DHCPNAK: u8 = 6;
+
+/// What the server handed out
+#[derive(Copy, Clone, Debug)]
+pub struct Lease {
+    pub ip: Ipv4Addr,
+    pub prefix_len: u8,
+    pub gateway: Ipv4Addr,
+    pub dns: Ipv4Addr,
+}
+
+#[derive(Copy, Clone)]
+enum Phase {
+    Selecting,
+    Requesting { offered: Ipv4Addr, server: Ipv4Addr },
+}
+
+/// The outcome of handling a reply
+pub enum Event {
+    None,
+    Bound(Lease),
+    Failed,
+}
+
+pub struct DhcpClient {
+    mac: [u8; 6],
+    xid: u32,
+    phase: Phase,
+    /// time of the last message sent, and messages sent in this phase
+    sent_at: Option<usize>,
+    tries: usize,
+}
+
+struct Reply {
+    message_type: u8,
+    yiaddr: Ipv4Addr,
+    server: Option<Ipv4Addr>,
+    mask: Option<Ipv4Addr>,
+    router: Option<Ipv4Addr>,
+    dns: Option<Ipv4Addr>,
+}
+
+impl DhcpClient {
+    pub fn new(mac: [u8; 6], xid: u32) -> Self {
+        Self {
+            mac,
+            xid,
+            phase: Phase::Selecting,
+            sent_at: None,
+            tries: 0,
+        }
+    }
+    fn message(&self, message_type: u8) -> Vec<u8> {
+        let mut message = Vec::with_capacity(FIXED_LEN + 32);
+        message.extend_from_slice(&[OP_REQUEST, 1, 6, 0]);
+        message.extend_from_slice(&self.xid.to_be_bytes());
+        // secs, then flags asking for broadcast replies as we have no address
+        message.extend_from_slice(&[0, 0, 0x80, 0]);
+        // ciaddr, yiaddr, siaddr, giaddr
+        message.extend_from_slice(&[0; 16]);
+        message.extend_from_slice(&self.mac);
+        // rest of chaddr, sname and file
+        message.resize(FIXED_LEN - MAGIC_COOKIE.len(), 0);
+        message.extend_from_slice(&MAGIC_COOKIE);
+        message.extend_from_slice(&[OPTION_MESSAGE_TYPE, 1, message_type]);
+        if let Phase::Requesting { offered, server } = self.phase {
+            message.extend_from_slice(&[OPTION_REQUESTED_IP, 4]);
+            message.extend_from_slice(&offered.0);
+            message.extend_from_slice(&[OPTION_SERVER_ID, 4]);
+            message.extend_from_slice(&server.0);
+        }
+        message.extend_from_slice(&[
+            OPTION_PARAMETERS,
+            3,
+            OPTION_SUBNET_MASK,
+            OPTION_ROUTER,
+            OPTION_DNS,
+        ]);
+        message.push(OPTION_END);
+        message
+    }
+    /// The message to broadcast now, if one is due. An error once too many
+    /// tries went unanswered.
+    pub fn poll(&mut self, now: usize) -> Result<Option<Vec<u8>>, ()> {
+        if let Some(sent_at) = self.sent_at {
+            if now < sent_at + RETRY_MS {
+                return Ok(None);
+            }
+        }
+        if self.tries == MAX_TRIES {
+            return Err(());
+        }
+        self.tries += 1;
+        self.sent_at = Some(now);
+        let message_type = match self.phase {
+            Phase::Selecting => DHCPDISCOVER,
+            Phase::Requesting { .. } => DHCPREQUEST,
+        };
+        Ok(Some(self.message(message_type)))
+    }
+    fn parse(&self, data: &[u8]) -> Option<Reply> {
+        if data.len() < FIXED_LEN
+            || data[0] != OP_REPLY
+            || data[4..8] != self.xid.to_be_bytes()
+            || data[28..34] != self.mac
+            || data[236..240] != MAGIC_COOKIE
+        {
+            return None;
+        }
+        let mut reply = Reply {
+            message_type: 0,
+            yiaddr: Ipv4Addr::from_bytes(&data[16..20]),
+            server: None,
+            mask: None,
+            router: None,
+            dns: None,
+        };
+        let mut options = &data[FIXED_LEN..];
+        while let Some(&code) = options.first() {
+            match code {
+                0 => {
+                    options = &options[1..];
+                    continue;
+                }
+                OPTION_END => break,
+                _ => {}
+            }
+            let len = *options.get(1)? as usize;
+            let value = options.get(2..2 + len)?;
+            let addr = (len >= 4).then(|| Ipv4Addr::from_bytes(value));
+            match code {
+                OPTION_MESSAGE_TYPE if len == 1 => reply.message_type = value[0],
+                OPTION_SUBNET_MASK => reply.mask = addr,
+                OPTION_ROUTER => reply.router = addr,
+                OPTION_DNS => reply.dns = addr,
+                OPTION_SERVER_ID => reply.server = addr,
+                _ => {}
+            }
+            options = &options[2 + len..];
+        }
+        Some(reply)
+    }
+    /// Handle a message from the server port.
+    pub fn handle(&mut self, data: &[u8]) -> Event {
+        let reply = match self.parse(data) {
+            Some(reply) => reply,
+            None => return Event::None,
+        };
+        match (self.phase, reply.message_type) {
+            (Phase::Selecting, DHCPOFFER) => {
+                if let Some(server) = reply.server {
+                    self.phase = Phase::Requesting {
+                        offered: reply.yiaddr,
+                        server,
+                    };
+                    self.sent_at = None;
+                    self.tries = 0;
+                }
+                Event::None
+            }
+            (Phase::Requesting { offered, .. }, DHCPACK) if reply.yiaddr == offered => {
+                let mask = reply.mask.unwrap_or(Ipv4Addr([255, 255, 255, 0]));
+                Event::Bound(Lease {
+                    ip: offered,
+                    prefix_len: mask.to_u32().count_ones() as u8,
+                    gateway: reply.router.unwrap_or(Ipv4Addr::UNSPECIFIED),
+                    dns: reply.dns.unwrap_or(Ipv4Addr::UNSPECIFIED),
+                })
+            }
+            (Phase::Requesting { .. }, DHCPNAK) => Event::Failed,
+            _ => Event::None,
+        }
+    }
+}
@@ -0,0 +1,146 @@
+//! DNS: A record queries and a cache of their answers
+
+use super::Ipv4Addr;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+pub const SERVER_PORT: u16 = 53;
+const HEADER_LEN: usize = 12;
+/// recursion desired
+const FLAG_RD: u16 = 0x0100;
+const FLAG_QR: u16 = 0x8000;
+const RCODE_MASK: u16 = 0x000f;
+const TYPE_A: u16 = 1;
+const CLASS_IN: u16 = 1;
+const MAX_NAME_LEN: usize = 253;
+const MAX_LABEL_LEN: usize = 63;
+/// names cached before the one expiring first is dropped
+const MAX_ENTRIES: usize = 64;
+/// bounds on how long an answer is kept, whatever its TTL
+const MIN_TTL_MS: usize = 1000;
+const MAX_TTL_MS: usize = 3600 * 1000;
+
+fn read_u16(data: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([*data.get(pos)?, *data.get(pos + 1)?]))
+}
+
+fn read_u32(data: &[u8], pos: usize) -> Option<u32> {
+    Some(((read_u16(data, pos)? as u32) << 16) | read_u16(data, pos + 2)? as u32)
+}
+
+/// Position after the possibly compressed name at `pos`.
+fn skip_name(data: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = *data.get(pos)? as usize;
+        match len {
+            0 => return Some(pos + 1),
+            // a pointer ends the name
+            _ if len & 0xc0 == 0xc0 => return Some(pos + 2),
+            _ if len > MAX_LABEL_LEN => return None,
+            _ => pos += 1 + len,
+        }
+    }
+}
+
+/// Query for the A record of `name`, or `None` if it is not a valid host
+/// name.
+pub fn build_query(id: u16, name: &str) -> Option<Vec<u8>> {
+    let name = name.strip_suffix('.').unwrap_or(name);
+    if name.is_empty() || name.len() > MAX_NAME_LEN {
+        return None;
+    }
+    let mut query = Vec::with_capacity(HEADER_LEN + name.len() + 6);
+    query.extend_from_slice(&id.to_be_bytes());
+    query.extend_from_slice(&FLAG_RD.to_be_bytes());
+    // one question, no answer, authority or additional records
+    query.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.split('.') {
+        if label.is_empty() || label.len() > MAX_LABEL_LEN {
+            return None;
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&TYPE_A.to_be_bytes());
+    query.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Some(query)
+}
+
+/// The answer to query `id`: `Some(None)` if the name has no A record,
+/// `None` if `response` is not an answer to it.
+pub fn parse_response(id: u16, response: &[u8]) -> Option<Option<(Ipv4Addr, u32)>> {
+    if response.len() < HEADER_LEN || read_u16(response, 0)? != id {
+        return None;
+    }
+    let flags = read_u16(response, 2)?;
+    if flags & FLAG_QR == 0 {
+        return None;
+    }
+    if flags & RCODE_MASK != 0 {
+        return Some(None);
+    }
+    let questions = read_u16(response, 4)?;
+    let answers = read_u16(response, 6)?;
+    let mut pos = HEADER_LEN;
+    for _ in 0..questions {
+        pos = skip_name(response, pos)? + 4;
+    }
+    // aliases come before the address they lead to, so take the first A
+    for _ in 0..answers {
+        pos = skip_name(response, pos)?;
+        let record_type = read_u16(response, pos)?;
+        let class = read_u16(response, pos + 2)?;
+        let ttl = read_u32(response, pos + 4)?;
+        let len = read_u16(response, pos + 8)? as usize;
+        pos += 10;
+        let data = response.get(pos..pos + len)?;
+        if record_type == TYPE_A && class == CLASS_IN && len == 4 {
+            return Some(Some((Ipv4Addr::from_bytes(data), ttl)));
+        }
+        pos += len;
+    }
+    Some(None)
+}
+
+pub struct DnsCache {
+    /// lower-case name -> address and when it expires
+    entries: BTreeMap<String, (Ipv4Addr, usize)>,
+}
+
+impl DnsCache {
+    pub fn new() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+        }
+    }
+    pub fn lookup(&mut self, name: &str, now: usize) -> Option<Ipv4Addr> {
+        let name = name.to_ascii_lowercase();
+        match self.entries.get(&name) {
+            Some(&(ip, expires)) if now < expires => Some(ip),
+            Some(_) => {
+                self.entries.remove(&name);
+                None
+            }
+            None => None,
+        }
+    }
+    pub fn insert(&mut self, name: &str, ip: Ipv4Addr, ttl: u32, now: usize) {
+        let ttl_ms = (ttl as usize)
+            .saturating_mul(1000)
+            .clamp(MIN_TTL_MS, MAX_TTL_MS);
+        let name = name.to_ascii_lowercase();
+        if !self.entries.contains_key(&name) && self.entries.len() == MAX_ENTRIES {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, &(_, expires))| expires)
+                .map(|(name, _)| name.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.entries.insert(name, (ip, now + ttl_ms));
+    }
+}
@@ -0,0 +1,120 @@
+//! ICMP echo: requests are answered, replies kept for whoever pings
+
+use super::wire::checksum;
+use super::Ipv4Addr;
+use alloc::collections::{BTreeSet, VecDeque};
+use alloc::vec::Vec;
+
+pub const PROTOCOL_ICMP: u8 = 1;
+const HEADER_LEN: usize = 8;
+const ECHO_REPLY: u8 = 0;
+const ECHO_REQUEST: u8 = 8;
+/// replies kept before the oldest is dropped
+const MAX_REPLIES: usize = 16;
+
+/// Protocol counters, as in the `Icmp:` lines of `/proc/net/snmp`
+#[derive(Copy, Clone, Default)]
+pub struct IcmpStats {
+    pub in_msgs: usize,
+    /// messages that were malformed or failed the checksum
+    pub in_errors: usize,
+    pub in_echos: usize,
+    pub in_echo_reps: usize,
+    pub out_msgs: usize,
+    pub out_echos: usize,
+    pub out_echo_reps: usize,
+}
+
+/// An echo reply that arrived
+struct EchoReply {
+    from: Ipv4Addr,
+    id: u16,
+    seq: u16,
+}
+
+fn build_echo(echo_type: u8, id: u16, seq: u16, data: &[u8]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(HEADER_LEN + data.len());
+    message.extend_from_slice(&[echo_type, 0, 0, 0]);
+    message.extend_from_slice(&id.to_be_bytes());
+    message.extend_from_slice(&seq.to_be_bytes());
+    message.extend_from_slice(data);
+    let sum = checksum(&message);
+    message[2..4].copy_from_slice(&sum.to_be_bytes());
+    message
+}
+
+pub struct Icmp {
+    replies: VecDeque<EchoReply>,
+    /// answers to send, with their destination
+    outgoing: Vec<(Ipv4Addr, Vec<u8>)>,
+    stats: IcmpStats,
+    /// ids that received a reply since [`Icmp::take_woken`]
+    woken: BTreeSet<u16>,
+}
+
+impl Icmp {
+    pub fn new() -> Self {
+        Self {
+            replies: VecDeque::new(),
+            outgoing: Vec::new(),
+            stats: IcmpStats::default(),
+            woken: BTreeSet::new(),
+        }
+    }
+    /// Build echo request `id`/`seq` carrying `data`, counting it as sent.
+    pub fn echo_request(&mut self, id: u16, seq: u16, data: &[u8]) -> Vec<u8> {
+        self.stats.out_msgs += 1;
+        self.stats.out_echos += 1;
+        build_echo(ECHO_REQUEST, id, seq, data)
+    }
+    /// Handle a message from `src`: answer echo requests and keep echo
+    /// replies.
+    pub fn deliver(&mut self, src: Ipv4Addr, message: &[u8]) {
+        self.stats.in_msgs += 1;
+        if message.len() < HEADER_LEN || checksum(message) != 0 {
+            self.stats.in_errors += 1;
+            return;
+        }
+        if message[1] != 0 {
+            return;
+        }
+        let id = u16::from_be_bytes([message[4], message[5]]);
+        let seq = u16::from_be_bytes([message[6], message[7]]);
+        match message[0] {
+            ECHO_REQUEST => {
+                self.stats.in_echos += 1;
+                self.stats.out_msgs += 1;
+                self.stats.out_echo_reps += 1;
+                let reply = build_echo(ECHO_REPLY, id, seq, &message[HEADER_LEN..]);
+                self.outgoing.push((src, reply));
+            }
+            ECHO_REPLY => {
+                self.stats.in_echo_reps += 1;
+                if self.replies.len() == MAX_REPLIES {
+                    self.replies.pop_front();
+                }
+                self.replies.push_back(EchoReply { from: src, id, seq });
+                self.woken.insert(id);
+            }
+            _ => {}
+        }
+    }
+    /// Take the reply to echo `id`/`seq`, returning who sent it.
+    pub fn take_reply(&mut self, id: u16, seq: u16) -> Option<Ipv4Addr> {
+        let index = self
+            .replies
+            .iter()
+            .position(|reply| reply.id == id && reply.seq == seq)?;
+        self.replies.remove(index).map(|reply| reply.from)
+    }
+    /// The ids that received a reply since the last call.
+    pub fn take_woken(&mut self) -> BTreeSet<u16> {
+        core::mem::take(&mut self.woken)
+    }
+    pub fn stats(&self) -> IcmpStats {
+        self.stats
+    }
+    pub fn take_outgoing(&mut self) -> Vec<(Ipv4Addr, Vec<u8>)> {
+        core::mem::take(&mut self.outgoing)
+    }
+}
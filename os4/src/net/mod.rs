@@ -0,0 +1,883 @@
+//! In-kernel network stack
+//!
+//! A minimal IPv4 stack on top of [`NET_DEVICE`]: one [`Interface`],
+//! configured by DHCP at boot or from [`crate::config`] if no server
+//! answers, answering and resolving ARP and delivering IPv4 packets
+//! addressed to it to the UDP and TCP sockets; ICMP echo requests are
+//! answered directly. The kernel has no threads of its own, so the stack is
+//! polled from the timer tick and after external interrupts, and by
+//! whoever uses it; the tasks waiting on it are woken through [`watch`].
+//!
+//! Packets for 127.0.0.0/8 or for our own address never reach the device:
+//! they are queued on the loopback path and received on the next poll.
+//! Without a network device the interface still exists, as 127.0.0.1 with
+//! loopback only.
+
+mod arp;
+mod dhcp;
+mod dns;
+mod icmp;
+mod tcp;
+mod udp;
+mod wire;
+
+use crate::config::{NET_DNS, NET_GATEWAY, NET_IP, NET_PREFIX_LEN};
+use crate::drivers::net::{NetDevice, NET_DEVICE};
+use crate::random::fill_random;
+use crate::sync::UPSafeCell;
+use crate::task::WaitQueue;
+use crate::timer::get_time_ms;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::{Arc, Weak};
+use alloc::vec;
+use alloc::vec::Vec;
+use arp::ArpCache;
+pub use arp::Neighbor;
+use dhcp::{DhcpClient, Event as DhcpEvent, Lease};
+use dns::DnsCache;
+pub use icmp::IcmpStats;
+use icmp::{Icmp, PROTOCOL_ICMP};
+use lazy_static::*;
+use tcp::PROTOCOL_TCP;
+pub use tcp::{Handle as TcpHandle, State as TcpState, TcpError, TcpTable};
+pub use tcp::{TcpSocketInfo, TcpStats};
+pub use udp::Datagram;
+pub use udp::{UdpSocketInfo, UdpStats};
+use udp::{UdpTable, PROTOCOL_UDP};
+pub use wire::Ipv4Addr;
+pub use wire::MacDisplay;
+use wire::{ArpPacket, EthernetFrame, Ipv4Packet};
+use wire::{ARP_REPLY, ARP_REQUEST, BROADCAST_MAC, ETHERTYPE_ARP, ETHERTYPE_IPV4};
+use wire::{ETHERNET_HEADER_LEN, IPV4_HEADER_LEN};
+
+/// packets kept while their next hop is being resolved
+const MAX_PENDING: usize = 16;
+/// how long a packet may wait for its next hop
+const PENDING_TIMEOUT_MS: usize = 3000;
+/// packets queued on the loopback path before new ones are dropped
+const MAX_LOOPBACK: usize = 64;
+/// times the loopback queue is drained in one poll, so that a request and
+/// its answer both go through
+const LOOPBACK_ROUNDS: usize = 4;
+/// frame size of the loopback-only interface, as for Ethernet
+const NO_LINK_FRAME_SIZE: usize = 1514;
+/// how long boot waits for DHCP before falling back to the static address
+const DHCP_BOOT_TIMEOUT_MS: usize = 5000;
+
+/// Packet counters of an interface
+#[derive(Copy, Clone, Default)]
+pub struct NetStats {
+    pub rx_packets: usize,
+    pub rx_bytes: usize,
+    /// frames that were malformed or not for us
+    pub rx_dropped: usize,
+    pub tx_packets: usize,
+    pub tx_bytes: usize,
+    /// packets the device refused, or that found no next hop
+    pub tx_dropped: usize,
+}
+
+impl NetStats {
+    fn received(&mut self, len: usize) {
+        self.rx_packets += 1;
+        self.rx_bytes += len;
+    }
+    fn sent(&mut self, len: usize) {
+        self.tx_packets += 1;
+        self.tx_bytes += len;
+    }
+}
+
+/// Protocol counters, as in the `Ip:` lines of `/proc/net/snmp`
+#[derive(Copy, Clone, Default)]
+pub struct IpStats {
+    pub in_receives: usize,
+    /// packets that were malformed, failed the checksum or were fragments
+    pub in_hdr_errors: usize,
+    /// packets for another address
+    pub in_addr_errors: usize,
+    pub in_unknown_protos: usize,
+    pub in_delivers: usize,
+    pub out_requests: usize,
+    /// packets dropped for lack of a link, a next hop or queue space
+    pub out_discards: usize,
+}
+
+/// The counters of all protocols, for `/proc/net/snmp`
+pub struct ProtocolStats {
+    pub ip: IpStats,
+    pub icmp: IcmpStats,
+    pub tcp: TcpStats,
+    pub udp: UdpStats,
+}
+
+/// Where tasks wait on the stack: a TCP or UDP port, the replies to an echo
+/// request id, or the end of a DHCP exchange
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Endpoint {
+    Tcp(u16),
+    Udp(u16),
+    Echo(u16),
+    Dhcp,
+}
+
+struct PendingPacket {
+    next_hop: Ipv4Addr,
+    packet: Vec<u8>,
+    queued: usize,
+}
+
+/// The device of an interface without a network device: nothing arrives
+/// and nothing can be sent
+struct NoLink;
+
+impl NetDevice for NoLink {
+    fn mac(&self) -> [u8; 6] {
+        [0; 6]
+    }
+    fn max_frame_size(&self) -> usize {
+        NO_LINK_FRAME_SIZE
+    }
+    fn can_send(&mut self) -> bool {
+        false
+    }
+    fn can_recv(&mut self) -> bool {
+        false
+    }
+    fn send_frame(&mut self, _frame: &[u8]) -> bool {
+        false
+    }
+    fn recv_frame(&mut self, _buf: &mut [u8]) -> Option<usize> {
+        None
+    }
+    fn handle_irq(&mut self) {}
+}
+
+pub struct Interface {
+    /// whether there is a network device behind the interface
+    link: bool,
+    mac: [u8; 6],
+    ip: Ipv4Addr,
+    prefix_len: u8,
+    gateway: Ipv4Addr,
+    /// name server, 0.0.0.0 if none is known
+    dns: Ipv4Addr,
+    /// the DHCP exchange in progress, with the configuration to go back to
+    /// if it fails
+    dhcp: Option<(DhcpClient, Lease)>,
+    /// whether the current configuration came from DHCP
+    leased: bool,
+    /// whether a DHCP exchange ended since [`Interface::take_woken`]
+    dhcp_ended: bool,
+    dns_cache: DnsCache,
+    arp: ArpCache,
+    pending: VecDeque<PendingPacket>,
+    /// IPv4 packets sent to ourselves
+    loopback: VecDeque<Vec<u8>>,
+    stats: NetStats,
+    loopback_stats: NetStats,
+    ip_stats: IpStats,
+    icmp: Icmp,
+    udp: UdpTable,
+    tcp: TcpTable,
+    /// identification field of the next packet sent
+    next_id: u16,
+}
+
+lazy_static! {
+    /// the interface, once initialized
+    static ref INTERFACE: UPSafeCell<Option<Interface>> = unsafe { UPSafeCell::new(None) };
+    /// the wait queues of the sockets and callers waiting on each endpoint
+    static ref WATCHERS: UPSafeCell<BTreeMap<Endpoint, Vec<Weak<WaitQueue>>>> =
+        unsafe { UPSafeCell::new(BTreeMap::new()) };
+}
+
+impl Interface {
+    fn new(link: bool, mac: [u8; 6], ip: Ipv4Addr) -> Self {
+        Self {
+            link,
+            mac,
+            ip,
+            prefix_len: NET_PREFIX_LEN,
+            gateway: Ipv4Addr(NET_GATEWAY),
+            dns: Ipv4Addr(NET_DNS),
+            dhcp: None,
+            leased: false,
+            dhcp_ended: false,
+            dns_cache: DnsCache::new(),
+            arp: ArpCache::new(),
+            pending: VecDeque::new(),
+            loopback: VecDeque::new(),
+            stats: NetStats::default(),
+            loopback_stats: NetStats::default(),
+            ip_stats: IpStats::default(),
+            icmp: Icmp::new(),
+            udp: UdpTable::new(),
+            tcp: TcpTable::new(),
+            next_id: 0,
+        }
+    }
+    /// Receive everything the device has, retry or drop the packets waiting
+    /// for a next hop, then send what TCP has due and take in what was sent
+    /// to ourselves.
+    fn poll(&mut self, device: &mut dyn NetDevice, now: usize) {
+        let mut buf = vec![0u8; device.max_frame_size()];
+        while let Some(len) = device.recv_frame(&mut buf) {
+            let len = len.min(buf.len());
+            self.stats.received(len);
+            if !self.handle_frame(device, &buf[..len], now) {
+                self.stats.rx_dropped += 1;
+            }
+        }
+        self.poll_dhcp(device, now);
+        self.arp.expire(now);
+        for _ in 0..self.pending.len() {
+            let pending = self.pending.pop_front().unwrap();
+            if now >= pending.queued + PENDING_TIMEOUT_MS {
+                debug!("[kernel] net: no ARP reply from {}", pending.next_hop);
+                self.stats.tx_dropped += 1;
+                self.ip_stats.out_discards += 1;
+                continue;
+            }
+            match self.arp.lookup(pending.next_hop, now) {
+                Some(mac) => {
+                    self.transmit(device, mac, ETHERTYPE_IPV4, &pending.packet);
+                }
+                None => {
+                    self.request(device, pending.next_hop, now);
+                    self.pending.push_back(pending);
+                }
+            }
+        }
+        self.flush(device, now);
+        for _ in 0..LOOPBACK_ROUNDS {
+            if self.loopback.is_empty() {
+                break;
+            }
+            while let Some(packet) = self.loopback.pop_front() {
+                self.loopback_stats.received(packet.len());
+                if let Some(packet) = self.parse_ipv4(&packet) {
+                    self.handle_ipv4(packet, now);
+                }
+            }
+            self.flush(device, now);
+        }
+    }
+    fn lease(&self) -> Lease {
+        Lease {
+            ip: self.ip,
+            prefix_len: self.prefix_len,
+            gateway: self.gateway,
+            dns: self.dns,
+        }
+    }
+    fn configure(&mut self, lease: Lease) {
+        self.ip = lease.ip;
+        self.prefix_len = lease.prefix_len;
+        self.gateway = lease.gateway;
+        self.dns = lease.dns;
+        info!(
+            "[kernel] net: {} at {}/{} via {}, dns {}",
+            MacDisplay(self.mac),
+            self.ip,
+            self.prefix_len,
+            self.gateway,
+            self.dns
+        );
+    }
+    /// Start a DHCP exchange. The interface has no address until it ends.
+    /// Returns false if there is no link or an exchange is already running.
+    fn start_dhcp(&mut self) -> bool {
+        if !self.link || self.dhcp.is_some() || self.udp.bind(dhcp::CLIENT_PORT).is_none() {
+            return false;
+        }
+        let mut xid = [0u8; 4];
+        fill_random(&mut xid);
+        let client = DhcpClient::new(self.mac, u32::from_ne_bytes(xid));
+        self.dhcp = Some((client, self.lease()));
+        self.leased = false;
+        self.ip = Ipv4Addr::UNSPECIFIED;
+        true
+    }
+    /// Feed the replies to the DHCP client and send what it has due, ending
+    /// the exchange when it is bound or gives up.
+    fn poll_dhcp(&mut self, device: &mut dyn NetDevice, now: usize) {
+        let (client, fallback) = match self.dhcp.as_mut() {
+            Some((client, fallback)) => (client, *fallback),
+            None => return,
+        };
+        let mut outcome = None;
+        while let Some(datagram) = self.udp.recv(dhcp::CLIENT_PORT) {
+            if datagram.src_port != dhcp::SERVER_PORT {
+                continue;
+            }
+            match client.handle(&datagram.data) {
+                DhcpEvent::None => {}
+                DhcpEvent::Bound(lease) => outcome = Some(Some(lease)),
+                DhcpEvent::Failed => outcome = Some(None),
+            }
+            if outcome.is_some() {
+                break;
+            }
+        }
+        let message = match outcome {
+            Some(_) => None,
+            None => match client.poll(now) {
+                Ok(message) => message,
+                Err(()) => {
+                    outcome = Some(None);
+                    None
+                }
+            },
+        };
+        if let Some(message) = message {
+            let dst = Ipv4Addr::BROADCAST;
+            self.send_udp(
+                device,
+                dhcp::CLIENT_PORT,
+                dst,
+                dhcp::SERVER_PORT,
+                &message,
+                now,
+            );
+        }
+        if let Some(lease) = outcome {
+            self.dhcp = None;
+            self.dhcp_ended = true;
+            self.udp.unbind(dhcp::CLIENT_PORT);
+            match lease {
+                Some(lease) => {
+                    self.leased = true;
+                    self.configure(lease);
+                }
+                None => {
+                    warn!("[kernel] net: DHCP failed, keeping the previous address");
+                    self.configure(fallback);
+                }
+            }
+        }
+    }
+    /// The endpoints where something changed since the last call.
+    fn take_woken(&mut self) -> Vec<Endpoint> {
+        let tcp = self.tcp.take_woken().into_iter().map(Endpoint::Tcp);
+        let udp = self.udp.take_woken().into_iter().map(Endpoint::Udp);
+        let echo = self.icmp.take_woken().into_iter().map(Endpoint::Echo);
+        let mut woken: Vec<Endpoint> = tcp.chain(udp).chain(echo).collect();
+        if core::mem::take(&mut self.dhcp_ended) {
+            woken.push(Endpoint::Dhcp);
+        }
+        woken
+    }
+    /// Send the ICMP answers and what TCP has due.
+    fn flush(&mut self, device: &mut dyn NetDevice, now: usize) {
+        for (dst, message) in self.icmp.take_outgoing() {
+            self.send_ipv4(device, dst, PROTOCOL_ICMP, &message, now);
+        }
+        for (dst, segment) in self.tcp.poll(now) {
+            self.send_ipv4(device, dst, PROTOCOL_TCP, &segment, now);
+        }
+    }
+    /// Handle a received frame. Returns false if it was dropped.
+    fn handle_frame(&mut self, device: &mut dyn NetDevice, frame: &[u8], now: usize) -> bool {
+        let frame = match EthernetFrame::parse(frame) {
+            Some(frame) => frame,
+            None => return false,
+        };
+        match frame.ethertype {
+            ETHERTYPE_ARP => match ArpPacket::parse(frame.payload) {
+                Some(packet) => {
+                    self.handle_arp(device, packet, now);
+                    true
+                }
+                None => false,
+            },
+            ETHERTYPE_IPV4 => {
+                let packet = match self.parse_ipv4(frame.payload) {
+                    Some(packet) => packet,
+                    None => return false,
+                };
+                // while configuring, offers may come to the address on offer
+                let for_us = packet.dst == self.ip
+                    || packet.dst == Ipv4Addr::BROADCAST
+                    || self.dhcp.is_some();
+                // loopback addresses never come from the wire
+                if !for_us || packet.src.is_loopback() {
+                    self.ip_stats.in_addr_errors += 1;
+                    return false;
+                }
+                // the sender is a neighbor or the router we reply to
+                if packet.src.same_subnet(self.ip, self.prefix_len) {
+                    self.arp.insert(packet.src, frame.src, now);
+                }
+                self.handle_ipv4(packet, now);
+                true
+            }
+            _ => false,
+        }
+    }
+    fn handle_arp(&mut self, device: &mut dyn NetDevice, packet: ArpPacket, now: usize) {
+        if packet.sender_ip != Ipv4Addr::UNSPECIFIED {
+            self.arp.insert(packet.sender_ip, packet.sender_mac, now);
+        }
+        if packet.operation == ARP_REQUEST && packet.target_ip == self.ip {
+            let reply = ArpPacket {
+                operation: ARP_REPLY,
+                sender_mac: self.mac,
+                sender_ip: self.ip,
+                target_mac: packet.sender_mac,
+                target_ip: packet.sender_ip,
+            };
+            self.transmit(device, packet.sender_mac, ETHERTYPE_ARP, &reply.build());
+        }
+    }
+    fn parse_ipv4<'a>(&mut self, data: &'a [u8]) -> Option<Ipv4Packet<'a>> {
+        self.ip_stats.in_receives += 1;
+        let packet = Ipv4Packet::parse(data);
+        if packet.is_none() {
+            self.ip_stats.in_hdr_errors += 1;
+        }
+        packet
+    }
+    fn handle_ipv4(&mut self, packet: Ipv4Packet, now: usize) {
+        if matches!(packet.protocol, PROTOCOL_ICMP | PROTOCOL_UDP | PROTOCOL_TCP) {
+            self.ip_stats.in_delivers += 1;
+        } else {
+            self.ip_stats.in_unknown_protos += 1;
+        }
+        match packet.protocol {
+            PROTOCOL_ICMP => self.icmp.deliver(packet.src, packet.payload),
+            PROTOCOL_UDP => self.udp.deliver(packet.src, packet.dst, packet.payload),
+            PROTOCOL_TCP => self
+                .tcp
+                .deliver(packet.src, packet.dst, packet.payload, now),
+            protocol => trace!(
+                "[kernel] net: dropping protocol {} packet from {}",
+                protocol,
+                packet.src
+            ),
+        }
+    }
+    /// Ask who has `ip`, unless that was asked recently.
+    fn request(&mut self, device: &mut dyn NetDevice, ip: Ipv4Addr, now: usize) {
+        if !self.arp.should_request(ip, now) {
+            return;
+        }
+        let request = ArpPacket {
+            operation: ARP_REQUEST,
+            sender_mac: self.mac,
+            sender_ip: self.ip,
+            target_mac: [0; 6],
+            target_ip: ip,
+        };
+        self.transmit(device, BROADCAST_MAC, ETHERTYPE_ARP, &request.build());
+    }
+    /// Send `data` from UDP `src_port` to `dst`:`dst_port`. Returns false if
+    /// it was dropped.
+    fn send_udp(
+        &mut self,
+        device: &mut dyn NetDevice,
+        src_port: u16,
+        dst: Ipv4Addr,
+        dst_port: u16,
+        data: &[u8],
+        now: usize,
+    ) -> bool {
+        let segment = udp::build(self.source_for(dst), src_port, dst, dst_port, data);
+        self.udp.sent();
+        self.send_ipv4(device, dst, PROTOCOL_UDP, &segment, now)
+    }
+    /// Send a frame of `ethertype` carrying `payload` to `dst`. Returns
+    /// false if the device refused it.
+    fn transmit(
+        &mut self,
+        device: &mut dyn NetDevice,
+        dst: [u8; 6],
+        ethertype: u16,
+        payload: &[u8],
+    ) -> bool {
+        let frame = wire::build_ethernet(dst, self.mac, ethertype, payload);
+        if device.send_frame(&frame) {
+            self.stats.sent(frame.len());
+            true
+        } else {
+            self.stats.tx_dropped += 1;
+            false
+        }
+    }
+    /// The address packets to `dst` are sent from.
+    fn source_for(&self, dst: Ipv4Addr) -> Ipv4Addr {
+        if dst.is_loopback() {
+            Ipv4Addr::LOOPBACK
+        } else {
+            self.ip
+        }
+    }
+    /// Send `payload` to `dst` in an IPv4 packet of `protocol`. If the next
+    /// hop is not resolved yet, the packet waits for it. Returns false if
+    /// the packet was dropped.
+    fn send_ipv4(
+        &mut self,
+        device: &mut dyn NetDevice,
+        dst: Ipv4Addr,
+        protocol: u8,
+        payload: &[u8],
+        now: usize,
+    ) -> bool {
+        let src = self.source_for(dst);
+        let packet = wire::build_ipv4(src, dst, protocol, self.next_id, payload);
+        self.next_id = self.next_id.wrapping_add(1);
+        self.ip_stats.out_requests += 1;
+        if dst.is_loopback() || dst == self.ip {
+            if self.loopback.len() == MAX_LOOPBACK {
+                self.loopback_stats.tx_dropped += 1;
+                self.ip_stats.out_discards += 1;
+                return false;
+            }
+            self.loopback_stats.sent(packet.len());
+            self.loopback.push_back(packet);
+            return true;
+        }
+        if !self.link {
+            self.ip_stats.out_discards += 1;
+            return false;
+        }
+        let mac = if dst == Ipv4Addr::BROADCAST {
+            BROADCAST_MAC
+        } else {
+            let next_hop = if dst.same_subnet(self.ip, self.prefix_len) {
+                dst
+            } else {
+                self.gateway
+            };
+            match self.arp.lookup(next_hop, now) {
+                Some(mac) => mac,
+                None => {
+                    if self.pending.len() == MAX_PENDING {
+                        self.stats.tx_dropped += 1;
+                        self.ip_stats.out_discards += 1;
+                        return false;
+                    }
+                    self.pending.push_back(PendingPacket {
+                        next_hop,
+                        packet,
+                        queued: now,
+                    });
+                    self.request(device, next_hop, now);
+                    return true;
+                }
+            }
+        };
+        self.transmit(device, mac, ETHERTYPE_IPV4, &packet)
+    }
+}
+
+/// Bring up the interface, on the network device if there is one, and
+/// configure it by DHCP. Without an answer within [`DHCP_BOOT_TIMEOUT_MS`]
+/// the static address from [`crate::config`] is used.
+pub fn init() {
+    let mac = NET_DEVICE
+        .exclusive_access()
+        .as_ref()
+        .map(|device| device.mac());
+    let iface = match mac {
+        Some(mac) => Interface::new(true, mac, Ipv4Addr(NET_IP)),
+        None => {
+            info!("[kernel] net: no network device, loopback only");
+            Interface::new(false, [0; 6], Ipv4Addr::LOOPBACK)
+        }
+    };
+    *INTERFACE.exclusive_access() = Some(iface);
+    if !dhcp_start() {
+        return;
+    }
+    let deadline = get_time_ms() + DHCP_BOOT_TIMEOUT_MS;
+    while dhcp_running() {
+        if get_time_ms() >= deadline {
+            with_interface(|iface, _, _| {
+                if let Some((_, fallback)) = iface.dhcp.take() {
+                    iface.udp.unbind(dhcp::CLIENT_PORT);
+                    warn!("[kernel] net: no DHCP answer, using the static address");
+                    iface.configure(fallback);
+                }
+            });
+            break;
+        }
+        poll();
+    }
+}
+
+/// Run `f` on the interface and its device, or return `None` before the
+/// interface is up. The tasks waiting where `f` changed something are
+/// woken afterwards.
+fn with_interface<T>(f: impl FnOnce(&mut Interface, &mut dyn NetDevice, usize) -> T) -> Option<T> {
+    let (result, woken) = {
+        let mut device = NET_DEVICE.exclusive_access();
+        let mut iface = INTERFACE.exclusive_access();
+        let iface = iface.as_mut()?;
+        let now = get_time_ms();
+        let result = match device.as_mut() {
+            Some(device) => f(iface, device.as_mut(), now),
+            None => f(iface, &mut NoLink, now),
+        };
+        (result, iface.take_woken())
+    };
+    wake_watchers(&woken);
+    Some(result)
+}
+
+/// Have `queue` woken whenever something changes at `endpoint`, for as
+/// long as the queue lives: a datagram or segment arrives there, a
+/// connection on its port changes state, or an exchange ends.
+pub fn watch(endpoint: Endpoint, queue: &Arc<WaitQueue>) {
+    let mut watchers = WATCHERS.exclusive_access();
+    let queues = watchers.entry(endpoint).or_default();
+    queues.retain(|queue| queue.strong_count() > 0);
+    queues.push(Arc::downgrade(queue));
+}
+
+fn wake_watchers(endpoints: &[Endpoint]) {
+    let mut queues = Vec::new();
+    {
+        let mut watchers = WATCHERS.exclusive_access();
+        for endpoint in endpoints {
+            if let Some(watching) = watchers.get_mut(endpoint) {
+                watching.retain(|queue| queue.strong_count() > 0);
+                queues.extend(watching.iter().filter_map(Weak::upgrade));
+                if watching.is_empty() {
+                    watchers.remove(endpoint);
+                }
+            }
+        }
+    }
+    for queue in queues {
+        queue.wake_all();
+    }
+}
+
+/// Whether a task waits on the stack, for a device interrupt or a timer
+/// of the stack maybe.
+pub fn has_watchers() -> bool {
+    WATCHERS
+        .exclusive_access()
+        .values()
+        .flatten()
+        .filter_map(Weak::upgrade)
+        .any(|queue| !queue.is_empty())
+}
+
+/// Process received frames and pending work of the interface.
+pub fn poll() {
+    with_interface(|iface, device, now| iface.poll(device, now));
+}
+
+/// Address of the interface, if it is up.
+pub fn local_ip() -> Option<Ipv4Addr> {
+    INTERFACE.exclusive_access().as_ref().map(|iface| iface.ip)
+}
+
+/// Start configuring the interface by DHCP. Returns false if there is no
+/// network device or an exchange is already running.
+pub fn dhcp_start() -> bool {
+    with_interface(|iface, device, now| {
+        let started = iface.start_dhcp();
+        if started {
+            iface.poll_dhcp(device, now);
+        }
+        started
+    })
+    .unwrap_or(false)
+}
+
+/// Whether a DHCP exchange is running.
+pub fn dhcp_running() -> bool {
+    INTERFACE
+        .exclusive_access()
+        .as_ref()
+        .map_or(false, |iface| iface.dhcp.is_some())
+}
+
+/// Whether the interface is configured by DHCP.
+pub fn dhcp_leased() -> bool {
+    INTERFACE
+        .exclusive_access()
+        .as_ref()
+        .map_or(false, |iface| iface.leased)
+}
+
+/// The address of `name` if it is `localhost`, a dotted quad or a cached
+/// answer.
+pub fn dns_cached(name: &str) -> Option<Ipv4Addr> {
+    if name.eq_ignore_ascii_case("localhost") {
+        return Some(Ipv4Addr::LOOPBACK);
+    }
+    if let Some(ip) = Ipv4Addr::parse(name) {
+        return Some(ip);
+    }
+    with_interface(|iface, _, now| iface.dns_cache.lookup(name, now)).flatten()
+}
+
+/// Ask the name server for the address of `name`, as query `id` from UDP
+/// `port`. Returns false if the name is invalid, there is no name server or
+/// the query was dropped.
+pub fn dns_query(port: u16, id: u16, name: &str) -> bool {
+    let query = match dns::build_query(id, name) {
+        Some(query) => query,
+        None => return false,
+    };
+    let server = match INTERFACE.exclusive_access().as_ref() {
+        Some(iface) if iface.dns != Ipv4Addr::UNSPECIFIED => iface.dns,
+        _ => return false,
+    };
+    udp_send(port, server, dns::SERVER_PORT, &query)
+}
+
+/// Take the answer to query `id` for `name` from UDP `port`, polling the
+/// device first, and cache it. `Some(None)` if the name has no address.
+pub fn dns_answer(port: u16, id: u16, name: &str) -> Option<Option<Ipv4Addr>> {
+    with_interface(|iface, device, now| {
+        iface.poll(device, now);
+        while let Some(datagram) = iface.udp.recv(port) {
+            if datagram.src != iface.dns || datagram.src_port != dns::SERVER_PORT {
+                continue;
+            }
+            if let Some(answer) = dns::parse_response(id, &datagram.data) {
+                if let Some((ip, ttl)) = answer {
+                    iface.dns_cache.insert(name, ip, ttl, now);
+                }
+                return Some(answer.map(|(ip, _)| ip));
+            }
+        }
+        None
+    })
+    .flatten()
+}
+
+/// The neighbor cache, for `/proc/net/arp`.
+pub fn neighbors() -> Vec<Neighbor> {
+    with_interface(|iface, _, now| iface.arp.neighbors(now)).unwrap_or_default()
+}
+
+/// Counters of the loopback path, then of the network device if there is
+/// one, for `/proc/net/dev`.
+pub fn interface_stats() -> (NetStats, Option<NetStats>) {
+    match INTERFACE.exclusive_access().as_ref() {
+        Some(iface) => (iface.loopback_stats, iface.link.then(|| iface.stats)),
+        None => (NetStats::default(), None),
+    }
+}
+
+/// The counters of all protocols.
+pub fn protocol_stats() -> Option<ProtocolStats> {
+    INTERFACE
+        .exclusive_access()
+        .as_ref()
+        .map(|iface| ProtocolStats {
+            ip: iface.ip_stats,
+            icmp: iface.icmp.stats(),
+            tcp: iface.tcp.stats(),
+            udp: iface.udp.stats(),
+        })
+}
+
+/// The TCP listeners and connections, for `/proc/net/tcp`.
+pub fn tcp_sockets() -> Vec<TcpSocketInfo> {
+    INTERFACE
+        .exclusive_access()
+        .as_ref()
+        .map_or_else(Vec::new, |iface| iface.tcp.sockets())
+}
+
+/// The bound UDP ports, for `/proc/net/udp`.
+pub fn udp_sockets() -> Vec<UdpSocketInfo> {
+    INTERFACE
+        .exclusive_access()
+        .as_ref()
+        .map_or_else(Vec::new, |iface| iface.udp.sockets())
+}
+
+/// Whether `ip` is one of our addresses.
+pub fn is_local_ip(ip: Ipv4Addr) -> bool {
+    ip.is_loopback() || Some(ip) == local_ip()
+}
+
+/// The address packets to `dst` are sent from, if the interface is up.
+pub fn source_address(dst: Ipv4Addr) -> Option<Ipv4Addr> {
+    INTERFACE
+        .exclusive_access()
+        .as_ref()
+        .map(|iface| iface.source_for(dst))
+}
+
+/// Bind UDP `port`, or an ephemeral port if it is 0. Returns the bound port,
+/// or `None` if it is taken or there is no interface.
+pub fn udp_bind(port: u16) -> Option<u16> {
+    with_interface(|iface, _, _| iface.udp.bind(port)).flatten()
+}
+
+pub fn udp_unbind(port: u16) {
+    with_interface(|iface, _, _| iface.udp.unbind(port));
+}
+
+/// Send `data` from UDP `src_port` to `dst`:`dst_port`. Returns false if it
+/// does not fit in one frame or was dropped.
+pub fn udp_send(src_port: u16, dst: Ipv4Addr, dst_port: u16, data: &[u8]) -> bool {
+    with_interface(|iface, device, now| {
+        if ETHERNET_HEADER_LEN + IPV4_HEADER_LEN + udp::HEADER_LEN + data.len()
+            > device.max_frame_size()
+        {
+            return false;
+        }
+        iface.send_udp(device, src_port, dst, dst_port, data, now)
+    })
+    .unwrap_or(false)
+}
+
+/// Take the oldest datagram received on UDP `port`, polling the device
+/// first.
+pub fn udp_recv(port: u16) -> Option<Datagram> {
+    with_interface(|iface, device, now| {
+        iface.poll(device, now);
+        iface.udp.recv(port)
+    })
+    .flatten()
+}
+
+/// Whether a datagram is waiting on UDP `port`, polling the device first.
+pub fn udp_can_recv(port: u16) -> bool {
+    with_interface(|iface, device, now| {
+        iface.poll(device, now);
+        iface.udp.can_recv(port)
+    })
+    .unwrap_or(false)
+}
+
+/// Run `f` on the TCP connections, with the device polled before and the
+/// segments it causes sent after. `None` if there is no interface.
+pub fn with_tcp<T>(f: impl FnOnce(&mut TcpTable) -> T) -> Option<T> {
+    with_interface(|iface, device, now| {
+        iface.poll(device, now);
+        let result = f(&mut iface.tcp);
+        iface.flush(device, now);
+        result
+    })
+}
+
+/// Send echo request `id`/`seq` to `dst`, carrying `data`. Returns false if
+/// it was dropped.
+pub fn ping_send(dst: Ipv4Addr, id: u16, seq: u16, data: &[u8]) -> bool {
+    with_interface(|iface, device, now| {
+        let message = iface.icmp.echo_request(id, seq, data);
+        iface.send_ipv4(device, dst, PROTOCOL_ICMP, &message, now)
+    })
+    .unwrap_or(false)
+}
+
+/// Take the reply to echo request `id`/`seq`, polling the device first.
+/// Returns who answered.
+pub fn ping_reply(id: u16, seq: u16) -> Option<Ipv4Addr> {
+    with_interface(|iface, device, now| {
+        iface.poll(device, now);
+        iface.icmp.take_reply(id, seq)
+    })
+    .flatten()
+}
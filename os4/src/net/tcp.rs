@@ -0,0 +1,951 @@
+//! TCP
+//!
+//! Connections live in a table indexed by handle, so that they can outlive
+//! the socket that opened them while the closing handshake finishes.
+//! Received segments are only taken in order; anything else is answered
+//! with a duplicate ACK. Lost segments are resent go-back-N style after an
+//! exponentially backed off timeout.
+
+use super::wire::{checksum_add, checksum_finish};
+use super::Ipv4Addr;
+use crate::random::fill_random;
+use alloc::collections::{BTreeMap, BTreeSet, VecDeque};
+use alloc::vec::Vec;
+
+pub const PROTOCOL_TCP: u8 = 6;
+const HEADER_LEN: usize = 20;
+/// largest segment we send and accept, for a 1500 byte MTU
+const MSS: usize = 1460;
+/// MSS to assume when the peer does not announce one
+const DEFAULT_MSS: usize = 536;
+const SEND_BUFFER_SIZE: usize = 16384;
+const RECV_BUFFER_SIZE: usize = 16384;
+const INITIAL_RTO_MS: usize = 1000;
+const MAX_RTO_MS: usize = 16000;
+/// timeouts in a row after which the connection is given up
+const MAX_RETRIES: usize = 6;
+/// how long a closed connection lingers in TIME-WAIT
+const TIME_WAIT_MS: usize = 2000;
+const MAX_BACKLOG: usize = 16;
+const EPHEMERAL_PORTS: core::ops::RangeInclusive<u16> = 49152..=65535;
+
+bitflags! {
+    struct TcpFlags: u8 {
+        const FIN = 0x01;
+        const SYN = 0x02;
+        const RST = 0x04;
+        const PSH = 0x08;
+        const ACK = 0x10;
+    }
+}
+
+pub type Handle = usize;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum State {
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+    Closed,
+}
+
+/// Protocol counters, as in the `Tcp:` lines of `/proc/net/snmp`
+#[derive(Copy, Clone, Default)]
+pub struct TcpStats {
+    pub active_opens: usize,
+    pub passive_opens: usize,
+    /// connections reset or timed out before they were established
+    pub attempt_fails: usize,
+    /// established connections reset or timed out
+    pub estab_resets: usize,
+    pub curr_estab: usize,
+    pub in_segs: usize,
+    pub out_segs: usize,
+    pub retrans_segs: usize,
+    /// segments that were malformed or failed the checksum
+    pub in_errs: usize,
+    pub out_rsts: usize,
+}
+
+/// A connection or listener, as in `/proc/net/tcp`
+pub struct TcpSocketInfo {
+    pub local: Ipv4Addr,
+    pub local_port: u16,
+    pub remote: Ipv4Addr,
+    pub remote_port: u16,
+    /// `None` for a listener
+    pub state: Option<State>,
+    /// bytes sent but unacknowledged or not sent yet
+    pub send_queue: usize,
+    /// bytes received but not read, or connections waiting to be accepted
+    pub recv_queue: usize,
+    /// retransmissions of the oldest unacknowledged segment
+    pub retries: usize,
+}
+
+pub enum TcpError {
+    /// nothing can be done without waiting
+    WouldBlock,
+    /// the connection is closed for this direction, or was reset
+    Closed,
+}
+
+fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+fn seq_le(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) <= 0
+}
+
+struct Segment<'a> {
+    src_port: u16,
+    dst_port: u16,
+    seq: u32,
+    ack: u32,
+    flags: TcpFlags,
+    window: u16,
+    mss: Option<usize>,
+    payload: &'a [u8],
+}
+
+impl<'a> Segment<'a> {
+    fn parse(src: Ipv4Addr, dst: Ipv4Addr, data: &'a [u8]) -> Option<Self> {
+        if data.len() < HEADER_LEN || pseudo_checksum(src, dst, data) != 0 {
+            return None;
+        }
+        let header_len = (data[12] >> 4) as usize * 4;
+        if header_len < HEADER_LEN || header_len > data.len() {
+            return None;
+        }
+        let u16_at = |offset: usize| u16::from_be_bytes([data[offset], data[offset + 1]]);
+        let u32_at = |offset: usize| {
+            u32::from_be_bytes([
+                data[offset],
+                data[offset + 1],
+                data[offset + 2],
+                data[offset + 3],
+            ])
+        };
+        Some(Self {
+            src_port: u16_at(0),
+            dst_port: u16_at(2),
+            seq: u32_at(4),
+            ack: u32_at(8),
+            flags: TcpFlags::from_bits_truncate(data[13]),
+            window: u16_at(14),
+            mss: parse_mss(&data[HEADER_LEN..header_len]),
+            payload: &data[header_len..],
+        })
+    }
+    /// Sequence space taken by the segment.
+    fn len(&self) -> u32 {
+        self.payload.len() as u32
+            + self.flags.contains(TcpFlags::SYN) as u32
+            + self.flags.contains(TcpFlags::FIN) as u32
+    }
+}
+
+/// The MSS option among `options`, if present.
+fn parse_mss(mut options: &[u8]) -> Option<usize> {
+    while let Some(&kind) = options.first() {
+        match kind {
+            0 => break,
+            1 => options = &options[1..],
+            _ => {
+                let len = *options.get(1)? as usize;
+                if len < 2 || len > options.len() {
+                    return None;
+                }
+                if kind == 2 && len == 4 {
+                    return Some(u16::from_be_bytes([options[2], options[3]]) as usize);
+                }
+                options = &options[len..];
+            }
+        }
+    }
+    None
+}
+
+fn pseudo_checksum(src: Ipv4Addr, dst: Ipv4Addr, segment: &[u8]) -> u16 {
+    let mut sum = checksum_add(0, &src.0);
+    sum = checksum_add(sum, &dst.0);
+    sum += PROTOCOL_TCP as u32 + segment.len() as u32;
+    checksum_finish(checksum_add(sum, segment))
+}
+
+#[allow(clippy::too_many_arguments)]
+fn build(
+    src: Ipv4Addr,
+    src_port: u16,
+    dst: Ipv4Addr,
+    dst_port: u16,
+    seq: u32,
+    ack: u32,
+    flags: TcpFlags,
+    window: u16,
+    payload: &[u8],
+) -> Vec<u8> {
+    // SYNs announce our MSS
+    let options_len = if flags.contains(TcpFlags::SYN) { 4 } else { 0 };
+    let header_len = HEADER_LEN + options_len;
+    let mut segment = Vec::with_capacity(header_len + payload.len());
+    segment.extend_from_slice(&src_port.to_be_bytes());
+    segment.extend_from_slice(&dst_port.to_be_bytes());
+    segment.extend_from_slice(&seq.to_be_bytes());
+    segment.extend_from_slice(&ack.to_be_bytes());
+    segment.extend_from_slice(&[(header_len as u8 / 4) << 4, flags.bits()]);
+    segment.extend_from_slice(&window.to_be_bytes());
+    segment.extend_from_slice(&[0, 0, 0, 0]);
+    if options_len != 0 {
+        segment.extend_from_slice(&[2, 4]);
+        segment.extend_from_slice(&(MSS as u16).to_be_bytes());
+    }
+    segment.extend_from_slice(payload);
+    let sum = pseudo_checksum(src, dst, &segment);
+    segment[16..18].copy_from_slice(&sum.to_be_bytes());
+    segment
+}
+
+fn random_u32() -> u32 {
+    let mut bytes = [0u8; 4];
+    fill_random(&mut bytes);
+    u32::from_ne_bytes(bytes)
+}
+
+/// Segments waiting to be sent, with their destination
+type Outgoing = Vec<(Ipv4Addr, Vec<u8>)>;
+
+/// Transmission control block
+struct Tcb {
+    state: State,
+    local: Ipv4Addr,
+    local_port: u16,
+    remote: Ipv4Addr,
+    remote_port: u16,
+    /// the listening port this connection arrived on, until accepted
+    listener: Option<u16>,
+    iss: u32,
+    snd_una: u32,
+    snd_nxt: u32,
+    snd_wnd: usize,
+    mss: usize,
+    /// data from `snd_una` on: first sent but unacknowledged, then unsent
+    send_buf: VecDeque<u8>,
+    /// writing was shut down; a FIN follows the data
+    fin_queued: bool,
+    fin_sent: bool,
+    rcv_nxt: u32,
+    recv_buf: VecDeque<u8>,
+    fin_received: bool,
+    /// reading was shut down; data is acknowledged and dropped
+    read_shutdown: bool,
+    ack_pending: bool,
+    rto: usize,
+    retransmit_at: Option<usize>,
+    retries: usize,
+    time_wait_until: usize,
+    reset: bool,
+    /// the socket is gone; the entry is dropped once closed
+    orphaned: bool,
+}
+
+impl Tcb {
+    fn new(
+        state: State,
+        local: Ipv4Addr,
+        local_port: u16,
+        remote: Ipv4Addr,
+        remote_port: u16,
+    ) -> Self {
+        let iss = random_u32();
+        Self {
+            state,
+            local,
+            local_port,
+            remote,
+            remote_port,
+            listener: None,
+            iss,
+            snd_una: iss,
+            snd_nxt: iss,
+            snd_wnd: 0,
+            mss: DEFAULT_MSS,
+            send_buf: VecDeque::new(),
+            fin_queued: false,
+            fin_sent: false,
+            rcv_nxt: 0,
+            recv_buf: VecDeque::new(),
+            fin_received: false,
+            read_shutdown: false,
+            ack_pending: false,
+            rto: INITIAL_RTO_MS,
+            retransmit_at: None,
+            retries: 0,
+            time_wait_until: 0,
+            reset: false,
+            orphaned: false,
+        }
+    }
+    fn window(&self) -> u16 {
+        (RECV_BUFFER_SIZE - self.recv_buf.len()) as u16
+    }
+    fn emit(&mut self, flags: TcpFlags, seq: u32, payload: &[u8], out: &mut Outgoing) {
+        let ack = if flags.contains(TcpFlags::ACK) {
+            self.ack_pending = false;
+            self.rcv_nxt
+        } else {
+            0
+        };
+        out.push((
+            self.remote,
+            build(
+                self.local,
+                self.local_port,
+                self.remote,
+                self.remote_port,
+                seq,
+                ack,
+                flags,
+                self.window(),
+                payload,
+            ),
+        ));
+    }
+    fn abort(&mut self) {
+        self.state = State::Closed;
+        self.reset = true;
+        self.send_buf.clear();
+        self.retransmit_at = None;
+    }
+    fn enter_time_wait(&mut self, now: usize) {
+        self.state = State::TimeWait;
+        self.time_wait_until = now + TIME_WAIT_MS;
+        self.retransmit_at = None;
+    }
+    fn on_timer(&mut self, now: usize) {
+        if self.state == State::TimeWait && now >= self.time_wait_until {
+            self.state = State::Closed;
+        }
+        match self.retransmit_at {
+            Some(at) if now >= at => {}
+            _ => return,
+        }
+        self.retries += 1;
+        if self.retries > MAX_RETRIES {
+            debug!(
+                "[kernel] tcp: {}:{} timed out",
+                self.remote, self.remote_port
+            );
+            self.abort();
+            return;
+        }
+        self.rto = (self.rto * 2).min(MAX_RTO_MS);
+        self.retransmit_at = None;
+        self.snd_nxt = self.snd_una;
+        self.fin_sent = false;
+    }
+    /// Send whatever the state, the buffers and the peer's window allow.
+    fn transmit(&mut self, now: usize, out: &mut Outgoing) {
+        match self.state {
+            State::SynSent if self.snd_nxt == self.iss => {
+                self.emit(TcpFlags::SYN, self.iss, &[], out);
+                self.snd_nxt = self.iss.wrapping_add(1);
+            }
+            State::SynReceived if self.snd_nxt == self.iss => {
+                self.emit(TcpFlags::SYN | TcpFlags::ACK, self.iss, &[], out);
+                self.snd_nxt = self.iss.wrapping_add(1);
+            }
+            State::Established
+            | State::CloseWait
+            | State::FinWait1
+            | State::Closing
+            | State::LastAck => self.transmit_data(out),
+            _ => {}
+        }
+        if self.ack_pending {
+            self.emit(TcpFlags::ACK, self.snd_nxt, &[], out);
+        }
+        if self.snd_nxt != self.snd_una && self.retransmit_at.is_none() {
+            self.retransmit_at = Some(now + self.rto);
+        }
+    }
+    fn transmit_data(&mut self, out: &mut Outgoing) {
+        while !self.fin_sent {
+            let in_flight = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+            let unsent = self.send_buf.len() - in_flight;
+            if unsent == 0 {
+                break;
+            }
+            // with nothing in flight, probe a closed window with one byte
+            let window = if in_flight == 0 {
+                self.snd_wnd.max(1)
+            } else {
+                self.snd_wnd
+            };
+            let len = unsent.min(self.mss).min(window.saturating_sub(in_flight));
+            if len == 0 {
+                break;
+            }
+            let payload: Vec<u8> = self
+                .send_buf
+                .range(in_flight..in_flight + len)
+                .copied()
+                .collect();
+            self.emit(TcpFlags::ACK | TcpFlags::PSH, self.snd_nxt, &payload, out);
+            self.snd_nxt = self.snd_nxt.wrapping_add(len as u32);
+        }
+        let all_sent = self.snd_nxt.wrapping_sub(self.snd_una) as usize == self.send_buf.len();
+        if self.fin_queued && !self.fin_sent && all_sent {
+            self.emit(TcpFlags::FIN | TcpFlags::ACK, self.snd_nxt, &[], out);
+            self.snd_nxt = self.snd_nxt.wrapping_add(1);
+            self.fin_sent = true;
+            self.state = match self.state {
+                State::Established => State::FinWait1,
+                State::CloseWait => State::LastAck,
+                state => state,
+            };
+        }
+    }
+    fn on_ack(&mut self, segment: &Segment, now: usize) {
+        let ack = segment.ack;
+        if seq_lt(self.snd_una, ack) && seq_le(ack, self.snd_nxt) {
+            let fin_acked = self.fin_sent && ack == self.snd_nxt;
+            let acked = ack.wrapping_sub(self.snd_una) as usize - fin_acked as usize;
+            self.send_buf.drain(..acked.min(self.send_buf.len()));
+            self.snd_una = ack;
+            self.retries = 0;
+            self.rto = INITIAL_RTO_MS;
+            self.retransmit_at = if self.snd_una == self.snd_nxt {
+                None
+            } else {
+                Some(now + self.rto)
+            };
+            if fin_acked {
+                match self.state {
+                    State::FinWait1 => self.state = State::FinWait2,
+                    State::Closing => self.enter_time_wait(now),
+                    State::LastAck => self.state = State::Closed,
+                    _ => {}
+                }
+            }
+        }
+        if seq_le(ack, self.snd_nxt) {
+            self.snd_wnd = segment.window as usize;
+        }
+    }
+    fn on_segment(&mut self, segment: &Segment, now: usize, out: &mut Outgoing) {
+        let flags = segment.flags;
+        if flags.contains(TcpFlags::RST) {
+            let acceptable = match self.state {
+                State::SynSent => flags.contains(TcpFlags::ACK) && segment.ack == self.snd_nxt,
+                _ => segment.seq == self.rcv_nxt,
+            };
+            if acceptable {
+                self.abort();
+            }
+            return;
+        }
+        match self.state {
+            State::SynSent => {
+                if flags.contains(TcpFlags::ACK) && segment.ack != self.snd_nxt {
+                    self.emit(TcpFlags::RST, segment.ack, &[], out);
+                    return;
+                }
+                if flags.contains(TcpFlags::SYN | TcpFlags::ACK) {
+                    self.rcv_nxt = segment.seq.wrapping_add(1);
+                    self.snd_una = segment.ack;
+                    self.snd_wnd = segment.window as usize;
+                    self.mss = segment.mss.unwrap_or(DEFAULT_MSS).min(MSS);
+                    self.state = State::Established;
+                    self.retransmit_at = None;
+                    self.retries = 0;
+                    self.ack_pending = true;
+                }
+                return;
+            }
+            State::SynReceived => {
+                // our SYN-ACK was lost
+                if flags.contains(TcpFlags::SYN) && segment.seq.wrapping_add(1) == self.rcv_nxt {
+                    self.snd_nxt = self.iss;
+                    return;
+                }
+                if !flags.contains(TcpFlags::ACK) || segment.ack != self.snd_nxt {
+                    return;
+                }
+                self.snd_una = segment.ack;
+                self.snd_wnd = segment.window as usize;
+                self.state = State::Established;
+                self.retransmit_at = None;
+                self.retries = 0;
+            }
+            State::Closed => return,
+            _ => {}
+        }
+        if flags.contains(TcpFlags::SYN) {
+            // a retransmitted SYN: our answer was lost
+            self.ack_pending = true;
+            return;
+        }
+        if flags.contains(TcpFlags::ACK) {
+            self.on_ack(segment, now);
+        }
+        if segment.seq != self.rcv_nxt {
+            if segment.len() != 0 {
+                self.ack_pending = true;
+            }
+            return;
+        }
+        let receiving = matches!(
+            self.state,
+            State::Established | State::FinWait1 | State::FinWait2
+        );
+        let payload = segment.payload;
+        if !payload.is_empty() && receiving {
+            let accepted = if self.read_shutdown {
+                payload.len()
+            } else {
+                let accepted = payload.len().min(RECV_BUFFER_SIZE - self.recv_buf.len());
+                self.recv_buf.extend(&payload[..accepted]);
+                accepted
+            };
+            self.rcv_nxt = self.rcv_nxt.wrapping_add(accepted as u32);
+            self.ack_pending = true;
+            if accepted < payload.len() {
+                return;
+            }
+        }
+        if flags.contains(TcpFlags::FIN) && receiving {
+            self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+            self.fin_received = true;
+            self.ack_pending = true;
+            match self.state {
+                State::Established => self.state = State::CloseWait,
+                State::FinWait1 => self.state = State::Closing,
+                State::FinWait2 => self.enter_time_wait(now),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Answer a segment that matches no connection with a reset.
+fn reset_reply(local: Ipv4Addr, remote: Ipv4Addr, segment: &Segment, out: &mut Outgoing) {
+    if segment.flags.contains(TcpFlags::RST) {
+        return;
+    }
+    let (seq, ack, flags) = if segment.flags.contains(TcpFlags::ACK) {
+        (segment.ack, 0, TcpFlags::RST)
+    } else {
+        (
+            0,
+            segment.seq.wrapping_add(segment.len()),
+            TcpFlags::RST | TcpFlags::ACK,
+        )
+    };
+    out.push((
+        remote,
+        build(
+            local,
+            segment.dst_port,
+            remote,
+            segment.src_port,
+            seq,
+            ack,
+            flags,
+            0,
+            &[],
+        ),
+    ));
+}
+
+struct Listener {
+    backlog: usize,
+    /// established connections waiting for `accept`
+    ready: VecDeque<Handle>,
+}
+
+pub struct TcpTable {
+    conns: BTreeMap<Handle, Tcb>,
+    listeners: BTreeMap<u16, Listener>,
+    /// ports bound by sockets
+    reserved: BTreeSet<u16>,
+    next_handle: Handle,
+    next_ephemeral: u16,
+    outgoing: Outgoing,
+    stats: TcpStats,
+    /// local ports of the connections and listeners that changed since
+    /// [`TcpTable::take_woken`]
+    woken: BTreeSet<u16>,
+}
+
+impl TcpTable {
+    pub fn new() -> Self {
+        Self {
+            conns: BTreeMap::new(),
+            listeners: BTreeMap::new(),
+            reserved: BTreeSet::new(),
+            next_handle: 0,
+            next_ephemeral: *EPHEMERAL_PORTS.start(),
+            outgoing: Vec::new(),
+            stats: TcpStats::default(),
+            woken: BTreeSet::new(),
+        }
+    }
+    fn port_in_use(&self, port: u16) -> bool {
+        self.reserved.contains(&port)
+            || self.listeners.contains_key(&port)
+            || self.conns.values().any(|tcb| tcb.local_port == port)
+    }
+    /// Reserve `port` for a socket, or a free ephemeral port if it is 0.
+    /// Unless `reuse` is set, a port still used by connections fails too.
+    pub fn reserve(&mut self, port: u16, reuse: bool) -> Option<u16> {
+        if port != 0 {
+            if self.listeners.contains_key(&port)
+                || (!reuse && self.conns.values().any(|tcb| tcb.local_port == port))
+            {
+                return None;
+            }
+            return self.reserved.insert(port).then(|| port);
+        }
+        let count = EPHEMERAL_PORTS.len();
+        let start = *EPHEMERAL_PORTS.start();
+        let offset = (self.next_ephemeral - start) as usize;
+        let port = (0..count)
+            .map(|i| start + ((offset + i) % count) as u16)
+            .find(|&port| !self.port_in_use(port))?;
+        self.next_ephemeral = if port == *EPHEMERAL_PORTS.end() {
+            start
+        } else {
+            port + 1
+        };
+        self.reserved.insert(port);
+        Some(port)
+    }
+    pub fn release(&mut self, port: u16) {
+        self.reserved.remove(&port);
+    }
+    fn insert(&mut self, tcb: Tcb) -> Handle {
+        let handle = self.next_handle;
+        self.next_handle += 1;
+        self.conns.insert(handle, tcb);
+        handle
+    }
+    pub fn listen(&mut self, port: u16, backlog: usize) -> bool {
+        if self.listeners.contains_key(&port) {
+            return false;
+        }
+        self.listeners.insert(
+            port,
+            Listener {
+                backlog: backlog.clamp(1, MAX_BACKLOG),
+                ready: VecDeque::new(),
+            },
+        );
+        true
+    }
+    /// Stop listening on `port`, resetting the connections not accepted yet.
+    pub fn unlisten(&mut self, port: u16) {
+        self.listeners.remove(&port);
+        let outgoing = &mut self.outgoing;
+        self.conns.retain(|_, tcb| {
+            if tcb.listener != Some(port) {
+                return true;
+            }
+            if tcb.state != State::Closed {
+                let seq = tcb.snd_nxt;
+                out_reset(tcb, seq, outgoing);
+            }
+            false
+        });
+    }
+    pub fn accept(&mut self, port: u16) -> Option<Handle> {
+        let handle = self.listeners.get_mut(&port)?.ready.pop_front()?;
+        if let Some(tcb) = self.conns.get_mut(&handle) {
+            tcb.listener = None;
+        }
+        Some(handle)
+    }
+    pub fn can_accept(&self, port: u16) -> bool {
+        self.listeners
+            .get(&port)
+            .map_or(false, |listener| !listener.ready.is_empty())
+    }
+    /// Open a connection from `local`:`port` to `remote`:`remote_port`.
+    pub fn connect(
+        &mut self,
+        local: Ipv4Addr,
+        port: u16,
+        remote: Ipv4Addr,
+        remote_port: u16,
+    ) -> Handle {
+        let tcb = Tcb::new(State::SynSent, local, port, remote, remote_port);
+        self.stats.active_opens += 1;
+        self.insert(tcb)
+    }
+    pub fn state(&self, handle: Handle) -> State {
+        self.conns
+            .get(&handle)
+            .map_or(State::Closed, |tcb| tcb.state)
+    }
+    pub fn remote(&self, handle: Handle) -> Option<(Ipv4Addr, u16)> {
+        self.conns
+            .get(&handle)
+            .map(|tcb| (tcb.remote, tcb.remote_port))
+    }
+    /// Whether the connection was refused, reset or timed out.
+    pub fn was_reset(&self, handle: Handle) -> bool {
+        self.conns.get(&handle).map_or(true, |tcb| tcb.reset)
+    }
+    /// Queue as much of `data` as fits in the send buffer.
+    pub fn send(&mut self, handle: Handle, data: &[u8]) -> Result<usize, TcpError> {
+        let tcb = self.conns.get_mut(&handle).ok_or(TcpError::Closed)?;
+        match tcb.state {
+            State::SynSent | State::SynReceived => return Err(TcpError::WouldBlock),
+            State::Established | State::CloseWait if !tcb.fin_queued => {}
+            _ => return Err(TcpError::Closed),
+        }
+        let len = data.len().min(SEND_BUFFER_SIZE - tcb.send_buf.len());
+        if len == 0 && !data.is_empty() {
+            return Err(TcpError::WouldBlock);
+        }
+        tcb.send_buf.extend(&data[..len]);
+        Ok(len)
+    }
+    pub fn can_send(&self, handle: Handle) -> bool {
+        self.conns.get(&handle).map_or(false, |tcb| {
+            matches!(tcb.state, State::Established | State::CloseWait)
+                && !tcb.fin_queued
+                && tcb.send_buf.len() < SEND_BUFFER_SIZE
+        })
+    }
+    /// Take received data into `buf`. `Ok(0)` is the end of the stream.
+    pub fn recv(&mut self, handle: Handle, buf: &mut [u8]) -> Result<usize, TcpError> {
+        let tcb = self.conns.get_mut(&handle).ok_or(TcpError::Closed)?;
+        if tcb.recv_buf.is_empty() {
+            return if tcb.reset {
+                Err(TcpError::Closed)
+            } else if tcb.fin_received || tcb.read_shutdown || tcb.state == State::Closed {
+                Ok(0)
+            } else {
+                Err(TcpError::WouldBlock)
+            };
+        }
+        let window_was_small = (tcb.window() as usize) < tcb.mss;
+        let len = buf.len().min(tcb.recv_buf.len());
+        for (dst, src) in buf.iter_mut().zip(tcb.recv_buf.drain(..len)) {
+            *dst = src;
+        }
+        // tell the peer the window opened again
+        if window_was_small && tcb.window() as usize >= tcb.mss {
+            tcb.ack_pending = true;
+        }
+        Ok(len)
+    }
+    pub fn can_recv(&self, handle: Handle) -> bool {
+        self.conns.get(&handle).map_or(true, |tcb| {
+            !tcb.recv_buf.is_empty()
+                || tcb.fin_received
+                || tcb.read_shutdown
+                || tcb.state == State::Closed
+        })
+    }
+    /// Shut down reading and/or writing; shutting down writing sends a FIN
+    /// once the queued data is out.
+    pub fn shutdown(&mut self, handle: Handle, read: bool, write: bool) {
+        if let Some(tcb) = self.conns.get_mut(&handle) {
+            if read {
+                tcb.read_shutdown = true;
+                tcb.recv_buf.clear();
+            }
+            if write {
+                tcb.fin_queued = true;
+            }
+        }
+    }
+    /// The socket of `handle` is closed: finish the connection gracefully
+    /// and forget it afterwards.
+    pub fn close(&mut self, handle: Handle) {
+        let remove = match self.conns.get_mut(&handle) {
+            Some(tcb) => match tcb.state {
+                State::SynSent | State::Closed => true,
+                _ => {
+                    tcb.fin_queued = true;
+                    tcb.orphaned = true;
+                    false
+                }
+            },
+            None => false,
+        };
+        if remove {
+            self.conns.remove(&handle);
+        }
+    }
+    /// Handle a segment from `src` to `dst`.
+    pub fn deliver(&mut self, src: Ipv4Addr, dst: Ipv4Addr, data: &[u8], now: usize) {
+        let segment = match Segment::parse(src, dst, data) {
+            Some(segment) => segment,
+            None => {
+                self.stats.in_errs += 1;
+                return;
+            }
+        };
+        self.stats.in_segs += 1;
+        let handle = self
+            .conns
+            .iter()
+            .find(|(_, tcb)| {
+                tcb.local_port == segment.dst_port
+                    && tcb.remote == src
+                    && tcb.remote_port == segment.src_port
+                    && tcb.state != State::Closed
+            })
+            .map(|(&handle, _)| handle);
+        if let Some(handle) = handle {
+            let tcb = self.conns.get_mut(&handle).unwrap();
+            self.woken.insert(tcb.local_port);
+            let before = tcb.state;
+            let was_syn_received = before == State::SynReceived;
+            tcb.on_segment(&segment, now, &mut self.outgoing);
+            count_failure(&mut self.stats, before, tcb);
+            if was_syn_received && tcb.state != State::SynReceived {
+                if let Some(port) = tcb.listener {
+                    match self.listeners.get_mut(&port) {
+                        Some(listener) if tcb.state != State::Closed => {
+                            listener.ready.push_back(handle)
+                        }
+                        _ => tcb.abort(),
+                    }
+                }
+            }
+            return;
+        }
+        let flags = segment.flags;
+        if flags.contains(TcpFlags::SYN) && !flags.intersects(TcpFlags::ACK | TcpFlags::RST) {
+            if let Some(listener) = self.listeners.get(&segment.dst_port) {
+                let port = segment.dst_port;
+                let half_open = self
+                    .conns
+                    .values()
+                    .filter(|tcb| tcb.listener == Some(port) && tcb.state == State::SynReceived)
+                    .count();
+                if listener.ready.len() + half_open >= listener.backlog {
+                    // let the peer retry its SYN later
+                    return;
+                }
+                let mut tcb = Tcb::new(State::SynReceived, dst, port, src, segment.src_port);
+                tcb.listener = Some(port);
+                tcb.rcv_nxt = segment.seq.wrapping_add(1);
+                tcb.snd_wnd = segment.window as usize;
+                tcb.mss = segment.mss.unwrap_or(DEFAULT_MSS).min(MSS);
+                self.stats.passive_opens += 1;
+                self.insert(tcb);
+                return;
+            }
+        }
+        reset_reply(dst, src, &segment, &mut self.outgoing);
+    }
+    /// Run the timers and send what is due. Returns the segments to send.
+    pub fn poll(&mut self, now: usize) -> Outgoing {
+        for tcb in self.conns.values_mut() {
+            let (before, retries) = (tcb.state, tcb.retries);
+            tcb.on_timer(now);
+            if tcb.retries > retries && tcb.state != State::Closed {
+                self.stats.retrans_segs += 1;
+            }
+            count_failure(&mut self.stats, before, tcb);
+            if tcb.state != before {
+                self.woken.insert(tcb.local_port);
+            }
+            tcb.transmit(now, &mut self.outgoing);
+        }
+        self.conns.retain(|_, tcb| {
+            tcb.state != State::Closed || !(tcb.orphaned || tcb.listener.is_some())
+        });
+        let outgoing = core::mem::take(&mut self.outgoing);
+        self.stats.out_segs += outgoing.len();
+        self.stats.out_rsts += outgoing
+            .iter()
+            .filter(|(_, segment)| segment[13] & TcpFlags::RST.bits() != 0)
+            .count();
+        outgoing
+    }
+    /// The local ports where a segment arrived or a timer changed the state
+    /// of a connection since the last call, for the sockets to look again.
+    pub fn take_woken(&mut self) -> BTreeSet<u16> {
+        core::mem::take(&mut self.woken)
+    }
+    pub fn stats(&self) -> TcpStats {
+        let curr_estab = self
+            .conns
+            .values()
+            .filter(|tcb| matches!(tcb.state, State::Established | State::CloseWait))
+            .count();
+        TcpStats {
+            curr_estab,
+            ..self.stats
+        }
+    }
+    /// The listeners, then the connections.
+    pub fn sockets(&self) -> Vec<TcpSocketInfo> {
+        let listeners = self
+            .listeners
+            .iter()
+            .map(|(&port, listener)| TcpSocketInfo {
+                local: Ipv4Addr::UNSPECIFIED,
+                local_port: port,
+                remote: Ipv4Addr::UNSPECIFIED,
+                remote_port: 0,
+                state: None,
+                send_queue: 0,
+                recv_queue: listener.ready.len(),
+                retries: 0,
+            });
+        let conns = self.conns.values().map(|tcb| TcpSocketInfo {
+            local: tcb.local,
+            local_port: tcb.local_port,
+            remote: tcb.remote,
+            remote_port: tcb.remote_port,
+            state: Some(tcb.state),
+            send_queue: tcb.send_buf.len(),
+            recv_queue: tcb.recv_buf.len(),
+            retries: tcb.retries,
+        });
+        listeners.chain(conns).collect()
+    }
+}
+
+/// Count a connection that the last event reset or timed out.
+fn count_failure(stats: &mut TcpStats, before: State, tcb: &Tcb) {
+    if tcb.state != State::Closed || before == State::Closed || !tcb.reset {
+        return;
+    }
+    match before {
+        State::SynSent | State::SynReceived => stats.attempt_fails += 1,
+        State::Established | State::CloseWait => stats.estab_resets += 1,
+        _ => {}
+    }
+}
+
+fn out_reset(tcb: &Tcb, seq: u32, out: &mut Outgoing) {
+    out.push((
+        tcb.remote,
+        build(
+            tcb.local,
+            tcb.local_port,
+            tcb.remote,
+            tcb.remote_port,
+            seq,
+            0,
+            TcpFlags::RST,
+            0,
+            &[],
+        ),
+    ));
+}
@@ -0,0 +1,199 @@
+//! UDP: datagrams queued per bound port
+
+use super::wire::{checksum_add, checksum_finish};
+use super::Ipv4Addr;
+use alloc::collections::{BTreeMap, BTreeSet, VecDeque};
+use alloc::vec::Vec;
+
+pub const PROTOCOL_UDP: u8 = 17;
+pub const HEADER_LEN: usize = 8;
+/// datagrams kept per port before new ones are dropped
+const QUEUE_LIMIT: usize = 64;
+/// ports handed out to sockets bound to port 0
+const EPHEMERAL_PORTS: core::ops::RangeInclusive<u16> = 49152..=65535;
+
+/// A received datagram
+pub struct Datagram {
+    pub src: Ipv4Addr,
+    pub src_port: u16,
+    pub data: Vec<u8>,
+}
+
+/// Protocol counters, as in the `Udp:` lines of `/proc/net/snmp`
+#[derive(Copy, Clone, Default)]
+pub struct UdpStats {
+    pub in_datagrams: usize,
+    /// datagrams for a port nobody bound
+    pub no_ports: usize,
+    /// datagrams that were malformed, failed the checksum or found their
+    /// queue full
+    pub in_errors: usize,
+    pub out_datagrams: usize,
+    /// datagrams that found their queue full
+    pub rcvbuf_errors: usize,
+}
+
+/// A bound port, as in `/proc/net/udp`
+pub struct UdpSocketInfo {
+    pub port: u16,
+    /// bytes in the queued datagrams
+    pub recv_queue: usize,
+    /// datagrams dropped because the queue was full
+    pub drops: usize,
+}
+
+struct Binding {
+    queue: VecDeque<Datagram>,
+    drops: usize,
+}
+
+pub struct UdpTable {
+    /// bound port -> datagrams received on it
+    bindings: BTreeMap<u16, Binding>,
+    next_ephemeral: u16,
+    stats: UdpStats,
+    /// ports that received a datagram since [`UdpTable::take_woken`]
+    woken: BTreeSet<u16>,
+}
+
+/// Checksum over the IPv4 pseudo header and `segment`.
+fn pseudo_checksum(src: Ipv4Addr, dst: Ipv4Addr, segment: &[u8]) -> u16 {
+    let mut sum = checksum_add(0, &src.0);
+    sum = checksum_add(sum, &dst.0);
+    sum += PROTOCOL_UDP as u32 + segment.len() as u32;
+    checksum_finish(checksum_add(sum, segment))
+}
+
+pub fn build(src: Ipv4Addr, src_port: u16, dst: Ipv4Addr, dst_port: u16, data: &[u8]) -> Vec<u8> {
+    let len = (HEADER_LEN + data.len()) as u16;
+    let mut segment = Vec::with_capacity(len as usize);
+    segment.extend_from_slice(&src_port.to_be_bytes());
+    segment.extend_from_slice(&dst_port.to_be_bytes());
+    segment.extend_from_slice(&len.to_be_bytes());
+    segment.extend_from_slice(&[0, 0]);
+    segment.extend_from_slice(data);
+    // zero means no checksum, so a computed zero is sent as all ones
+    let sum = match pseudo_checksum(src, dst, &segment) {
+        0 => 0xffff,
+        sum => sum,
+    };
+    segment[6..8].copy_from_slice(&sum.to_be_bytes());
+    segment
+}
+
+impl UdpTable {
+    pub fn new() -> Self {
+        Self {
+            bindings: BTreeMap::new(),
+            next_ephemeral: *EPHEMERAL_PORTS.start(),
+            stats: UdpStats::default(),
+            woken: BTreeSet::new(),
+        }
+    }
+    /// Bind `port`, or a free ephemeral port if it is 0. Returns the bound
+    /// port, or `None` if it is taken.
+    pub fn bind(&mut self, port: u16) -> Option<u16> {
+        let port = if port == 0 {
+            let count = EPHEMERAL_PORTS.len();
+            let port = (0..count)
+                .map(|i| {
+                    let offset = (self.next_ephemeral - EPHEMERAL_PORTS.start()) as usize;
+                    EPHEMERAL_PORTS.start() + ((offset + i) % count) as u16
+                })
+                .find(|port| !self.bindings.contains_key(port))?;
+            self.next_ephemeral = if port == *EPHEMERAL_PORTS.end() {
+                *EPHEMERAL_PORTS.start()
+            } else {
+                port + 1
+            };
+            port
+        } else if self.bindings.contains_key(&port) {
+            return None;
+        } else {
+            port
+        };
+        self.bindings.insert(
+            port,
+            Binding {
+                queue: VecDeque::new(),
+                drops: 0,
+            },
+        );
+        Some(port)
+    }
+    pub fn unbind(&mut self, port: u16) {
+        self.bindings.remove(&port);
+    }
+    /// Queue a received segment on its port. Segments that are malformed,
+    /// fail the checksum or are for an unbound port are dropped.
+    pub fn deliver(&mut self, src: Ipv4Addr, dst: Ipv4Addr, segment: &[u8]) {
+        let len = match segment.get(4..6) {
+            Some(len) => u16::from_be_bytes([len[0], len[1]]) as usize,
+            None => 0,
+        };
+        if len < HEADER_LEN || len > segment.len() {
+            self.stats.in_errors += 1;
+            return;
+        }
+        let segment = &segment[..len];
+        if segment[6..8] != [0, 0] && pseudo_checksum(src, dst, segment) != 0 {
+            self.stats.in_errors += 1;
+            return;
+        }
+        let src_port = u16::from_be_bytes([segment[0], segment[1]]);
+        let dst_port = u16::from_be_bytes([segment[2], segment[3]]);
+        let binding = match self.bindings.get_mut(&dst_port) {
+            Some(binding) => binding,
+            None => {
+                self.stats.no_ports += 1;
+                return;
+            }
+        };
+        if binding.queue.len() == QUEUE_LIMIT {
+            binding.drops += 1;
+            self.stats.rcvbuf_errors += 1;
+            self.stats.in_errors += 1;
+            return;
+        }
+        self.stats.in_datagrams += 1;
+        binding.queue.push_back(Datagram {
+            src,
+            src_port,
+            data: segment[HEADER_LEN..].to_vec(),
+        });
+        self.woken.insert(dst_port);
+    }
+    pub fn recv(&mut self, port: u16) -> Option<Datagram> {
+        self.bindings.get_mut(&port)?.queue.pop_front()
+    }
+    pub fn can_recv(&self, port: u16) -> bool {
+        self.bindings
+            .get(&port)
+            .map_or(false, |binding| !binding.queue.is_empty())
+    }
+    /// The ports that received a datagram since the last call.
+    pub fn take_woken(&mut self) -> BTreeSet<u16> {
+        core::mem::take(&mut self.woken)
+    }
+    /// Count a datagram sent.
+    pub fn sent(&mut self) {
+        self.stats.out_datagrams += 1;
+    }
+    pub fn stats(&self) -> UdpStats {
+        self.stats
+    }
+    pub fn sockets(&self) -> Vec<UdpSocketInfo> {
+        self.bindings
+            .iter()
+            .map(|(&port, binding)| UdpSocketInfo {
+                port,
+                recv_queue: binding
+                    .queue
+                    .iter()
+                    .map(|datagram| datagram.data.len())
+                    .sum(),
+                drops: binding.drops,
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,245 @@
+//! Packet formats
+//!
+//! Parsing borrows from the received frame; building returns a new buffer.
+//! All multi-byte fields are big-endian on the wire.
+
+use alloc::vec::Vec;
+use core::fmt;
+
+pub const ETHERNET_HEADER_LEN: usize = 14;
+pub const ETHERTYPE_IPV4: u16 = 0x0800;
+pub const ETHERTYPE_ARP: u16 = 0x0806;
+pub const BROADCAST_MAC: [u8; 6] = [0xff; 6];
+
+pub const ARP_PACKET_LEN: usize = 28;
+pub const ARP_REQUEST: u16 = 1;
+pub const ARP_REPLY: u16 = 2;
+
+pub const IPV4_HEADER_LEN: usize = 20;
+/// time to live of the packets we send
+const IPV4_TTL: u8 = 64;
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct Ipv4Addr(pub [u8; 4]);
+
+impl Ipv4Addr {
+    pub const UNSPECIFIED: Self = Self([0; 4]);
+    pub const BROADCAST: Self = Self([0xff; 4]);
+    pub const LOOPBACK: Self = Self([127, 0, 0, 1]);
+
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        Self([bytes[0], bytes[1], bytes[2], bytes[3]])
+    }
+    /// Parse dotted-quad notation such as `10.0.2.2`.
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut bytes = [0u8; 4];
+        let mut parts = text.split('.');
+        for byte in bytes.iter_mut() {
+            let part = parts.next()?;
+            if part.is_empty() || part.len() > 3 || !part.bytes().all(|c| c.is_ascii_digit()) {
+                return None;
+            }
+            *byte = part.parse().ok()?;
+        }
+        match parts.next() {
+            Some(_) => None,
+            None => Some(Self(bytes)),
+        }
+    }
+    pub fn to_u32(self) -> u32 {
+        u32::from_be_bytes(self.0)
+    }
+    /// Whether this is in 127.0.0.0/8.
+    pub fn is_loopback(self) -> bool {
+        self.0[0] == 127
+    }
+    /// Whether `self` and `other` share the first `prefix_len` bits.
+    pub fn same_subnet(self, other: Self, prefix_len: u8) -> bool {
+        let mask = match prefix_len {
+            0 => 0,
+            len => u32::MAX << (32 - len.min(32) as u32),
+        };
+        self.to_u32() & mask == other.to_u32() & mask
+    }
+}
+
+impl fmt::Display for Ipv4Addr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [a, b, c, d] = self.0;
+        write!(f, "{}.{}.{}.{}", a, b, c, d)
+    }
+}
+
+impl fmt::Debug for Ipv4Addr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+pub struct MacDisplay(pub [u8; 6]);
+
+impl fmt::Display for MacDisplay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let m = self.0;
+        write!(
+            f,
+            "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            m[0], m[1], m[2], m[3], m[4], m[5]
+        )
+    }
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([data[offset], data[offset + 1]])
+}
+
+/// Internet checksum (RFC 1071) of `data`, continuing from `sum`.
+pub fn checksum_add(mut sum: u32, data: &[u8]) -> u32 {
+    let mut chunks = data.chunks_exact(2);
+    for pair in &mut chunks {
+        sum += u16::from_be_bytes([pair[0], pair[1]]) as u32;
+    }
+    if let [last] = chunks.remainder() {
+        sum += (*last as u32) << 8;
+    }
+    sum
+}
+
+/// Fold a sum from [`checksum_add`] into the final checksum.
+pub fn checksum_finish(mut sum: u32) -> u16 {
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+pub fn checksum(data: &[u8]) -> u16 {
+    checksum_finish(checksum_add(0, data))
+}
+
+pub struct EthernetFrame<'a> {
+    pub src: [u8; 6],
+    pub ethertype: u16,
+    pub payload: &'a [u8],
+}
+
+impl<'a> EthernetFrame<'a> {
+    pub fn parse(frame: &'a [u8]) -> Option<Self> {
+        if frame.len() < ETHERNET_HEADER_LEN {
+            return None;
+        }
+        let mut src = [0; 6];
+        src.copy_from_slice(&frame[6..12]);
+        Some(Self {
+            src,
+            ethertype: u16_at(frame, 12),
+            payload: &frame[ETHERNET_HEADER_LEN..],
+        })
+    }
+}
+
+pub fn build_ethernet(dst: [u8; 6], src: [u8; 6], ethertype: u16, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(ETHERNET_HEADER_LEN + payload.len());
+    frame.extend_from_slice(&dst);
+    frame.extend_from_slice(&src);
+    frame.extend_from_slice(&ethertype.to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// An ARP packet for IPv4 over Ethernet
+pub struct ArpPacket {
+    pub operation: u16,
+    pub sender_mac: [u8; 6],
+    pub sender_ip: Ipv4Addr,
+    pub target_mac: [u8; 6],
+    pub target_ip: Ipv4Addr,
+}
+
+impl ArpPacket {
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < ARP_PACKET_LEN
+            || u16_at(data, 0) != 1
+            || u16_at(data, 2) != ETHERTYPE_IPV4
+            || data[4] != 6
+            || data[5] != 4
+        {
+            return None;
+        }
+        let mut sender_mac = [0; 6];
+        let mut target_mac = [0; 6];
+        sender_mac.copy_from_slice(&data[8..14]);
+        target_mac.copy_from_slice(&data[18..24]);
+        Some(Self {
+            operation: u16_at(data, 6),
+            sender_mac,
+            sender_ip: Ipv4Addr::from_bytes(&data[14..18]),
+            target_mac,
+            target_ip: Ipv4Addr::from_bytes(&data[24..28]),
+        })
+    }
+    pub fn build(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(ARP_PACKET_LEN);
+        data.extend_from_slice(&1u16.to_be_bytes());
+        data.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+        data.extend_from_slice(&[6, 4]);
+        data.extend_from_slice(&self.operation.to_be_bytes());
+        data.extend_from_slice(&self.sender_mac);
+        data.extend_from_slice(&self.sender_ip.0);
+        data.extend_from_slice(&self.target_mac);
+        data.extend_from_slice(&self.target_ip.0);
+        data
+    }
+}
+
+pub struct Ipv4Packet<'a> {
+    pub protocol: u8,
+    pub src: Ipv4Addr,
+    pub dst: Ipv4Addr,
+    pub payload: &'a [u8],
+}
+
+impl<'a> Ipv4Packet<'a> {
+    /// Parse an unfragmented packet with a valid header checksum.
+    pub fn parse(data: &'a [u8]) -> Option<Self> {
+        if data.len() < IPV4_HEADER_LEN || data[0] >> 4 != 4 {
+            return None;
+        }
+        let header_len = (data[0] & 0xf) as usize * 4;
+        let total_len = u16_at(data, 2) as usize;
+        if header_len < IPV4_HEADER_LEN
+            || total_len < header_len
+            || total_len > data.len()
+            || checksum(&data[..header_len]) != 0
+        {
+            return None;
+        }
+        // more fragments set, or a non-zero fragment offset
+        if u16_at(data, 6) & 0x3fff != 0 {
+            return None;
+        }
+        Some(Self {
+            protocol: data[9],
+            src: Ipv4Addr::from_bytes(&data[12..16]),
+            dst: Ipv4Addr::from_bytes(&data[16..20]),
+            payload: &data[header_len..total_len],
+        })
+    }
+}
+
+pub fn build_ipv4(src: Ipv4Addr, dst: Ipv4Addr, protocol: u8, id: u16, payload: &[u8]) -> Vec<u8> {
+    let total_len = (IPV4_HEADER_LEN + payload.len()) as u16;
+    let mut packet = Vec::with_capacity(total_len as usize);
+    packet.extend_from_slice(&[0x45, 0]);
+    packet.extend_from_slice(&total_len.to_be_bytes());
+    packet.extend_from_slice(&id.to_be_bytes());
+    // don't fragment
+    packet.extend_from_slice(&0x4000u16.to_be_bytes());
+    packet.extend_from_slice(&[IPV4_TTL, protocol, 0, 0]);
+    packet.extend_from_slice(&src.0);
+    packet.extend_from_slice(&dst.0);
+    let sum = checksum(&packet);
+    packet[10..12].copy_from_slice(&sum.to_be_bytes());
+    packet.extend_from_slice(payload);
+    packet
+}
@@ -0,0 +1,167 @@
+//! Hardware performance counters
+//!
+//! `cycle` and `instret` are read directly. The events of [`EVENTS`] are
+//! programmed onto `hpmcounter`s through the SBI PMU extension when the
+//! firmware has it; those it cannot count read as 0.
+//!
+//! The counters run all the time. At each task switch the counts since the
+//! previous one are charged to the task that ran, so every task sees its
+//! own counts, including the kernel work done on its behalf.
+
+use crate::sbi::{pmu_num_counters, pmu_start_event};
+use crate::sync::UPSafeCell;
+use core::arch::asm;
+use lazy_static::*;
+
+pub const PERF_EVENTS: usize = 4;
+/// SBI hardware events: cache references, cache misses, branches and
+/// branch misses
+const EVENTS: [usize; PERF_EVENTS] = [3, 4, 5, 6];
+
+/// Counts of one task, as copied out to user space
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+pub struct PerfCounters {
+    pub cycles: u64,
+    pub instret: u64,
+    /// counts of the events of [`EVENTS`], in order
+    pub events: [u64; PERF_EVENTS],
+}
+
+impl PerfCounters {
+    /// Counts from `earlier` to `self`.
+    pub fn since(&self, earlier: &Self) -> Self {
+        let mut events = [0; PERF_EVENTS];
+        for (i, event) in events.iter_mut().enumerate() {
+            *event = self.events[i].wrapping_sub(earlier.events[i]);
+        }
+        Self {
+            cycles: self.cycles.wrapping_sub(earlier.cycles),
+            instret: self.instret.wrapping_sub(earlier.instret),
+            events,
+        }
+    }
+    pub fn add(&mut self, other: &Self) {
+        self.cycles += other.cycles;
+        self.instret += other.instret;
+        for (event, other) in self.events.iter_mut().zip(other.events.iter()) {
+            *event += other;
+        }
+    }
+}
+
+struct Perf {
+    /// the `hpmcounter` counting each event, if any
+    counters: [Option<usize>; PERF_EVENTS],
+    /// counts at the last task switch
+    last: PerfCounters,
+}
+
+lazy_static! {
+    static ref PERF: UPSafeCell<Perf> = unsafe {
+        UPSafeCell::new(Perf {
+            counters: [None; PERF_EVENTS],
+            last: PerfCounters::default(),
+        })
+    };
+}
+
+/// Read the 64-bit counter CSR `$lo`, whose high half is `$hi` on riscv32.
+#[cfg(target_pointer_width = "64")]
+macro_rules! read_counter {
+    ($lo: literal, $hi: literal) => {{
+        let value: u64;
+        unsafe {
+            asm!(concat!("csrr {}, ", $lo), out(reg) value);
+        }
+        value
+    }};
+}
+
+#[cfg(target_pointer_width = "32")]
+macro_rules! read_counter {
+    ($lo: literal, $hi: literal) => {{
+        // read the high half again in case the low one wrapped in between
+        let (mut high, mut low, mut again): (u32, u32, u32);
+        loop {
+            unsafe {
+                asm!(concat!("csrr {}, ", $hi), out(reg) high);
+                asm!(concat!("csrr {}, ", $lo), out(reg) low);
+                asm!(concat!("csrr {}, ", $hi), out(reg) again);
+            }
+            if high == again {
+                break;
+            }
+        }
+        (high as u64) << 32 | low as u64
+    }};
+}
+
+/// Read `hpmcounter<index>`, 0 if there is no such counter.
+fn read_hpm(index: usize) -> u64 {
+    macro_rules! hpm {
+        ($($index: literal => $reg: ident),*) => {
+            match index {
+                $($index => riscv::register::$reg::read64(),)*
+                _ => 0,
+            }
+        };
+    }
+    hpm!(
+        3 => hpmcounter3, 4 => hpmcounter4, 5 => hpmcounter5, 6 => hpmcounter6,
+        7 => hpmcounter7, 8 => hpmcounter8, 9 => hpmcounter9, 10 => hpmcounter10,
+        11 => hpmcounter11, 12 => hpmcounter12, 13 => hpmcounter13, 14 => hpmcounter14,
+        15 => hpmcounter15, 16 => hpmcounter16, 17 => hpmcounter17, 18 => hpmcounter18,
+        19 => hpmcounter19, 20 => hpmcounter20, 21 => hpmcounter21, 22 => hpmcounter22,
+        23 => hpmcounter23, 24 => hpmcounter24, 25 => hpmcounter25, 26 => hpmcounter26,
+        27 => hpmcounter27, 28 => hpmcounter28, 29 => hpmcounter29, 30 => hpmcounter30,
+        31 => hpmcounter31
+    )
+}
+
+impl Perf {
+    fn read(&self) -> PerfCounters {
+        let cycles = read_counter!("cycle", "cycleh");
+        let instret = read_counter!("instret", "instreth");
+        let mut events = [0; PERF_EVENTS];
+        for (event, counter) in events.iter_mut().zip(self.counters.iter()) {
+            *event = counter.map_or(0, |csr| read_hpm(csr - 0xc00));
+        }
+        PerfCounters {
+            cycles,
+            instret,
+            events,
+        }
+    }
+}
+
+/// Start counting the events the firmware can count.
+pub fn init() {
+    let counters = pmu_num_counters();
+    if counters == 0 {
+        info!("[kernel] no SBI PMU, counting cycles and instructions only");
+        return;
+    }
+    let mut perf = PERF.exclusive_access();
+    for (i, &event) in EVENTS.iter().enumerate() {
+        perf.counters[i] =
+            pmu_start_event(counters, event).filter(|csr| (0xc03..=0xc1f).contains(csr));
+    }
+    perf.last = perf.read();
+}
+
+/// Task switch path: the counts since the previous switch, to be charged to
+/// the task that ran.
+pub fn on_switch() -> PerfCounters {
+    let mut perf = PERF.exclusive_access();
+    let now = perf.read();
+    let delta = now.since(&perf.last);
+    perf.last = now;
+    delta
+}
+
+/// The counts since the last task switch, not charged to anyone yet.
+pub fn since_switch() -> PerfCounters {
+    let perf = PERF.exclusive_access();
+    perf.read().since(&perf.last)
+}
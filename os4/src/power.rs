@@ -0,0 +1,311 @@
+//! Power management: idling, suspending, powering off and rebooting
+//!
+//! With no task to run, the CPU waits for an interrupt in the idle state
+//! the governor picks, the deepest allowed that keeps what is needed:
+//! polling only checks for one, `wfi` stalls the hart until one comes, and
+//! tickless idle does so with the tick stopped, so that only the first
+//! deadline of a sleeping task wakes it. The tick is kept while it has work
+//! to do, as long as tasks poll file descriptors or wait for console input
+//! that raises no interrupt, the network is up or the watchdog daemon has a
+//! deadline. `idle=` on the command line sets the deepest state allowed.
+//!
+//! A privileged task may suspend the machine to RAM, on firmware with the
+//! SBI System Suspend extension. The other tasks are frozen as they are,
+//! since none runs until the one asking comes back from the syscall: the
+//! timer is disarmed and the interrupts masked, the devices keeping their
+//! state along with the memory. The firmware resumes the kernel where it
+//! suspended, and the timer is armed again for the ticks and the deadlines,
+//! those passed in between waking their tasks at once.
+//!
+//! Besides the reboot through the firmware, a warm reboot starts the
+//! kernel over without leaving it: the devices are reset, the initialized
+//! data is put back as it was at boot, and the kernel jumps to its entry
+//! point with the hart and device tree it booted with. Everything else is
+//! built again from there, the frame allocator, the heap and the tasks
+//! loaded from the app images included. What is kept out of the frame
+//! allocator, the crash dump and the scheduling log, survives it as it
+//! survives a reboot through the firmware.
+
+use crate::board::board_info;
+use crate::config::{CLOCK_FREQ, WARM_REBOOT_DATA_SIZE};
+use crate::drivers::test_finisher;
+use crate::sbi::{has_system_suspend, system_reset, ResetReason, ResetType};
+use crate::timer::{disarm, get_time, restart_tick, set_next_trigger, stop_tick};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use riscv::register::{sie, sstatus};
+
+global_asm_xlen!(include_str!("suspend.S"));
+
+/// How deep the CPU idles, shallowest first
+#[repr(usize)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum IdleState {
+    /// check for an interrupt over and over
+    Poll = 0,
+    /// stall the hart with `wfi`, the tick going on
+    Wfi = 1,
+    /// stall the hart with `wfi` and the tick stopped
+    Tickless = 2,
+}
+
+impl IdleState {
+    const ALL: [Self; 3] = [Self::Poll, Self::Wfi, Self::Tickless];
+    /// The name of a state, as `idle=` takes it.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Poll => "poll",
+            Self::Wfi => "wfi",
+            Self::Tickless => "tickless",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|state| state.name() == name)
+    }
+}
+
+/// How often a state was entered, and for how long in all
+struct Residency {
+    entries: AtomicUsize,
+    /// in timer cycles
+    time: AtomicUsize,
+}
+
+impl Residency {
+    const fn new() -> Self {
+        Self {
+            entries: AtomicUsize::new(0),
+            time: AtomicUsize::new(0),
+        }
+    }
+    fn add(&self, since: usize) {
+        self.entries.fetch_add(1, Ordering::Relaxed);
+        self.time.fetch_add(get_time() - since, Ordering::Relaxed);
+    }
+}
+
+/// the deepest idle state the governor may pick
+static IDLE_LIMIT: AtomicUsize = AtomicUsize::new(IdleState::Tickless as usize);
+/// indexed by [`IdleState`]
+static IDLE_RESIDENCY: [Residency; 3] = [Residency::new(), Residency::new(), Residency::new()];
+static SUSPEND_RESIDENCY: Residency = Residency::new();
+
+/// Let the governor pick `state` at most.
+pub fn set_idle_limit(state: IdleState) {
+    IDLE_LIMIT.store(state as usize, Ordering::Relaxed);
+}
+
+/// The deepest idle state allowed, short of stopping the tick while it has
+/// work to do: waking the pollers, which look again once a tick, running
+/// the network timers, checking the watchdog daemon deadline, looking for
+/// console input that raises no interrupt, and collecting entropy for a
+/// task waiting for the random generator to be seeded.
+fn select_idle_state() -> IdleState {
+    let limit = IdleState::ALL[IDLE_LIMIT.load(Ordering::Relaxed)];
+    if limit == IdleState::Tickless
+        && (crate::fs::has_pollers()
+            || crate::net::local_ip().is_some()
+            || crate::watchdog::daemon_armed()
+            || crate::fs::tty_needs_tick()
+            || crate::random::has_seed_waiters())
+    {
+        IdleState::Wfi
+    } else {
+        limit
+    }
+}
+
+/// Wait until an interrupt is pending, in the state the governor picks.
+/// Interrupts stay disabled: the caller takes the one pending.
+pub fn idle() {
+    let state = select_idle_state();
+    let start = get_time();
+    match state {
+        IdleState::Poll => core::hint::spin_loop(),
+        IdleState::Wfi => unsafe { riscv::asm::wfi() },
+        IdleState::Tickless => {
+            stop_tick();
+            unsafe { riscv::asm::wfi() };
+            restart_tick();
+            // no tick petted the watchdog while the kernel idled, which is
+            // no lack of progress
+            crate::watchdog::pet_kernel();
+        }
+    }
+    IDLE_RESIDENCY[state as usize].add(start);
+}
+
+/// Each idle state, then suspend, with the times it was entered and the
+/// microseconds spent in it.
+pub fn residency() -> impl Iterator<Item = (&'static str, usize, usize)> {
+    let states = IdleState::ALL
+        .iter()
+        .map(|&state| (state.name(), &IDLE_RESIDENCY[state as usize]));
+    states
+        .chain(core::iter::once(("suspend", &SUSPEND_RESIDENCY)))
+        .map(|(name, residency)| {
+            (
+                name,
+                residency.entries.load(Ordering::Relaxed),
+                residency.time.load(Ordering::Relaxed) / (CLOCK_FREQ / 1_000_000),
+            )
+        })
+}
+
+/// what `__suspend` saves for `__resume`
+static mut SUSPEND_CONTEXT: [usize; 18] = [0; 18];
+
+/// Suspend the machine to RAM until the firmware wakes it. Returns false,
+/// without suspending, if the firmware cannot.
+pub fn suspend() -> bool {
+    extern "C" {
+        fn __suspend(context: *mut usize) -> isize;
+    }
+    if !has_system_suspend() {
+        return false;
+    }
+    println!("[kernel] Suspending.");
+    let start = get_time();
+    let enabled = sie::read();
+    disarm();
+    unsafe {
+        sie::clear_stimer();
+        sie::clear_sext();
+        sie::clear_ssoft();
+    }
+    // the context is static, as the firmware resumes with paging off and
+    // only the kernel image is mapped at its physical address
+    let error = unsafe { __suspend(core::ptr::addr_of_mut!(SUSPEND_CONTEXT) as *mut usize) };
+    unsafe {
+        if enabled.stimer() {
+            sie::set_stimer();
+        }
+        if enabled.sext() {
+            sie::set_sext();
+        }
+        if enabled.ssoft() {
+            sie::set_ssoft();
+        }
+    }
+    // for the ticks and the first deadline again, firing at once if it
+    // passed
+    set_next_trigger();
+    crate::watchdog::pet_kernel();
+    if error != 0 {
+        warn!("[kernel] the firmware did not suspend: error {}", error);
+        return false;
+    }
+    SUSPEND_RESIDENCY.add(start);
+    println!("[kernel] Resumed.");
+    true
+}
+
+/// How a run ended, as the status QEMU exits with
+#[repr(u16)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ExitCode {
+    /// all tasks are done and every application and test passed
+    Success = 0,
+    /// a test or application failed, or the tasks could not finish
+    Failure = 1,
+    /// the kernel panicked
+    Panic = 2,
+}
+
+/// Power off after all tasks are done, or on an unrecoverable error if
+/// `failure` is set.
+pub fn shutdown(failure: bool) -> ! {
+    exit_qemu(if failure {
+        ExitCode::Failure
+    } else {
+        ExitCode::Success
+    })
+}
+
+/// Power off, QEMU exiting with status `code` through the test finisher.
+/// Other machines only tell a failure from a success, as the reason for
+/// the SBI shutdown.
+pub fn exit_qemu(code: ExitCode) -> ! {
+    println!("[kernel] Powering off.");
+    test_finisher::finish(code as u16);
+    let reason = if code == ExitCode::Success {
+        ResetReason::NoReason
+    } else {
+        ResetReason::SystemFailure
+    };
+    system_reset(ResetType::Shutdown, reason)
+}
+
+/// Restart the machine.
+pub fn reboot() -> ! {
+    // nothing is cached in memory: console output is unbuffered and there
+    // is no block device yet, so there is nothing to flush first
+    println!("[kernel] Rebooting.");
+    system_reset(ResetType::ColdReboot, ResetReason::NoReason)
+}
+
+/// the initialized data as it was at boot
+static mut BOOT_DATA: [u8; WARM_REBOOT_DATA_SIZE] = [0; WARM_REBOOT_DATA_SIZE];
+/// bytes of `BOOT_DATA` in use, 0 if the data did not fit
+static BOOT_DATA_LEN: AtomicUsize = AtomicUsize::new(0);
+
+fn data_section() -> (usize, usize) {
+    extern "C" {
+        fn sdata();
+        fn edata();
+    }
+    (sdata as usize, edata as usize - sdata as usize)
+}
+
+/// Keep a copy of the initialized data for a warm reboot. Must run first
+/// thing at boot, before anything changes it.
+pub fn save_boot_data() {
+    let (start, len) = data_section();
+    if len > WARM_REBOOT_DATA_SIZE {
+        return;
+    }
+    unsafe {
+        let saved = core::ptr::addr_of_mut!(BOOT_DATA) as *mut u8;
+        core::ptr::copy_nonoverlapping(start as *const u8, saved, len);
+    }
+    BOOT_DATA_LEN.store(len, Ordering::Relaxed);
+}
+
+/// Restart the kernel without going through the firmware, falling back to
+/// [`reboot`] if the initialized data did not fit the copy kept of it.
+pub fn warm_reboot() -> ! {
+    let len = BOOT_DATA_LEN.load(Ordering::Relaxed);
+    if len == 0 {
+        warn!("[kernel] no copy of the kernel data for a warm reboot");
+        reboot();
+    }
+    println!("[kernel] Warm rebooting.");
+    crate::drivers::shutdown();
+    unsafe {
+        sstatus::clear_sie();
+        sie::clear_stimer();
+        sie::clear_sext();
+        sie::clear_ssoft();
+    }
+    let info = board_info();
+    let (start, _) = data_section();
+    extern "C" {
+        fn _start();
+    }
+    // Nothing read from here on is in the data put back, which goes from
+    // the values it had since boot to those it had at boot. Kernel space
+    // maps the kernel at its physical address, so turning paging off does
+    // not move the code.
+    unsafe {
+        let saved = core::ptr::addr_of!(BOOT_DATA) as *const u8;
+        core::ptr::copy_nonoverlapping(saved, start as *mut u8, len);
+        core::arch::asm!(
+            "csrw satp, zero",
+            "sfence.vma",
+            "jr {entry}",
+            entry = in(reg) _start as usize,
+            in("a0") info.boot_hart,
+            in("a1") info.dtb,
+            options(noreturn)
+        )
+    }
+}
@@ -0,0 +1,115 @@
+//! Sampling profiler
+//!
+//! While the profiler runs, every `interval`-th timer interrupt records the
+//! interrupted pc, and whether it was in the kernel or in user space, in a
+//! histogram of the task that was running. Resolving the pcs against the
+//! symbols of the kernel or the app gives a flat profile of both, without
+//! any tooling in the kernel.
+//!
+//! The kernel runs with interrupts disabled, so a tick that comes during a
+//! long kernel operation is taken at its next safe point instead. That
+//! sample is a kernel one, recorded at the pc the syscall was made from.
+
+use crate::config::MAX_PROFILE_PCS;
+use crate::sync::UPSafeCell;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use lazy_static::*;
+
+/// One histogram entry, as copied out to user space
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct ProfileSample {
+    pub pc: usize,
+    /// 1 if `pc` is in the kernel, 0 if in user space
+    pub kernel: usize,
+    pub count: usize,
+}
+
+#[derive(Default)]
+struct Histogram {
+    /// (in kernel, pc) -> samples
+    samples: BTreeMap<(bool, usize), usize>,
+    /// samples at a new pc once the histogram is full
+    dropped: usize,
+}
+
+struct Profiler {
+    /// sample every `interval` ticks, 0 when stopped
+    interval: usize,
+    /// ticks since the last sample
+    ticks: usize,
+    /// pid -> its samples
+    histograms: BTreeMap<usize, Histogram>,
+}
+
+lazy_static! {
+    static ref PROFILER: UPSafeCell<Profiler> = unsafe {
+        UPSafeCell::new(Profiler {
+            interval: 0,
+            ticks: 0,
+            histograms: BTreeMap::new(),
+        })
+    };
+}
+
+/// Sample every `interval` timer interrupts from now on, discarding the
+/// previous samples, or stop sampling if `interval` is 0.
+pub fn start(interval: usize) {
+    let mut profiler = PROFILER.exclusive_access();
+    if interval != 0 {
+        profiler.histograms.clear();
+    }
+    profiler.interval = interval;
+    profiler.ticks = 0;
+}
+
+/// Timer interrupt path: sample `pc` for task `pid` if it is time to.
+pub fn on_timer(pid: usize, pc: usize, kernel: bool) {
+    let mut profiler = PROFILER.exclusive_access();
+    if profiler.interval == 0 {
+        return;
+    }
+    profiler.ticks += 1;
+    if profiler.ticks < profiler.interval {
+        return;
+    }
+    profiler.ticks = 0;
+    let histogram = profiler.histograms.entry(pid).or_default();
+    let full = histogram.samples.len() >= MAX_PROFILE_PCS;
+    match histogram.samples.get_mut(&(kernel, pc)) {
+        Some(count) => *count += 1,
+        None if full => {
+            if histogram.dropped == 0 {
+                warn!("[kernel] profile of task {} is full, dropping new pcs", pid);
+            }
+            histogram.dropped += 1;
+        }
+        None => {
+            histogram.samples.insert((kernel, pc), 1);
+        }
+    }
+}
+
+/// Reaping path: task `pid` is gone, and so is its histogram.
+pub fn on_reap(pid: usize) {
+    PROFILER.exclusive_access().histograms.remove(&pid);
+}
+
+/// The histogram of task `pid` in pc order, user entries first.
+pub fn samples(pid: usize) -> Vec<ProfileSample> {
+    let profiler = PROFILER.exclusive_access();
+    let histogram = match profiler.histograms.get(&pid) {
+        Some(histogram) => histogram,
+        None => return Vec::new(),
+    };
+    histogram
+        .samples
+        .iter()
+        .map(|(&(kernel, pc), &count)| ProfileSample {
+            pc,
+            kernel: kernel as usize,
+            count,
+        })
+        .collect()
+}
@@ -0,0 +1,204 @@
+//! Kernel random number generator
+//!
+//! Every trap mixes the current time into an entropy pool: interrupt
+//! arrival and syscall timing jitter are the only randomness this machine
+//! offers. Output comes from ChaCha20 keyed from the pool, rekeyed after
+//! each request (fast key erasure) and reseeded from the pool once enough
+//! new samples have arrived.
+//!
+//! Samples go through a repetition count health check: a source stuck at a
+//! constant interval contributes nothing, so such runs are not counted as
+//! entropy.
+
+use crate::sync::UPSafeCell;
+use crate::task::WaitQueue;
+use lazy_static::*;
+
+/// healthy samples needed before the generator counts as seeded
+const SEED_SAMPLES: usize = 256;
+/// new samples after which the next request reseeds
+const RESEED_SAMPLES: usize = 64;
+/// identical intervals in a row after which the source counts as stuck
+const REPETITION_CUTOFF: usize = 32;
+/// bytes produced after which a reseed is forced, fresh samples or not
+const RESEED_BYTES: usize = 1 << 20;
+
+struct EntropyPool {
+    words: [u32; 16],
+    position: usize,
+    last_sample: usize,
+    last_interval: usize,
+    repetitions: usize,
+    /// healthy samples since boot and since the last reseed
+    total_samples: usize,
+    fresh_samples: usize,
+    healthy: bool,
+}
+
+struct Generator {
+    key: [u32; 8],
+    counter: u64,
+    bytes_since_reseed: usize,
+}
+
+lazy_static! {
+    static ref POOL: UPSafeCell<EntropyPool> = unsafe {
+        UPSafeCell::new(EntropyPool {
+            words: [0; 16],
+            position: 0,
+            last_sample: 0,
+            last_interval: 0,
+            repetitions: 0,
+            total_samples: 0,
+            fresh_samples: 0,
+            healthy: true,
+        })
+    };
+    static ref GENERATOR: UPSafeCell<Generator> = unsafe {
+        UPSafeCell::new(Generator {
+            key: [0; 8],
+            counter: 0,
+            bytes_since_reseed: 0,
+        })
+    };
+    /// tasks waiting for the generator to be seeded
+    static ref SEED_WAITERS: WaitQueue = WaitQueue::new();
+}
+
+impl EntropyPool {
+    fn mix(&mut self, sample: usize) {
+        let word = &mut self.words[self.position];
+        *word = word.rotate_left(7) ^ (sample as u32) ^ ((sample as u64 >> 32) as u32);
+        *word = word.wrapping_mul(0x9e37_79b9);
+        self.position = (self.position + 1) % self.words.len();
+    }
+    fn add_sample(&mut self, sample: usize) {
+        let interval = sample.wrapping_sub(self.last_sample);
+        self.last_sample = sample;
+        if interval == self.last_interval {
+            self.repetitions += 1;
+        } else {
+            self.repetitions = 0;
+            self.last_interval = interval;
+        }
+        self.mix(sample);
+        let healthy = self.repetitions < REPETITION_CUTOFF;
+        if healthy != self.healthy {
+            self.healthy = healthy;
+            if !healthy {
+                warn!("[kernel] random: entropy source stuck, samples not counted");
+            }
+        }
+        if healthy {
+            self.total_samples += 1;
+            self.fresh_samples += 1;
+        }
+    }
+}
+
+/// Mix a timing sample into the entropy pool, waking the tasks waiting for
+/// the seed if this sample completes it.
+pub fn add_sample(sample: usize) {
+    let mut pool = POOL.exclusive_access();
+    let seeded = pool.total_samples >= SEED_SAMPLES;
+    pool.add_sample(sample);
+    let seeded_now = !seeded && pool.total_samples >= SEED_SAMPLES;
+    drop(pool);
+    if seeded_now {
+        SEED_WAITERS.wake_all();
+    }
+}
+
+/// Block the current task until the generator is seeded, or a signal
+/// wakes it.
+pub fn wait_for_seed() {
+    SEED_WAITERS.block_current_and_run_next();
+}
+
+/// Whether a task waits for the seed, which only interrupts bring once
+/// every task is blocked.
+pub fn has_seed_waiters() -> bool {
+    !SEED_WAITERS.is_empty()
+}
+
+/// Whether enough healthy samples have been collected since boot.
+pub fn is_seeded() -> bool {
+    POOL.exclusive_access().total_samples >= SEED_SAMPLES
+}
+
+fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(12);
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
+
+/// One ChaCha20 block with a zero nonce.
+fn chacha20_block(key: &[u32; 8], counter: u64) -> [u32; 16] {
+    let mut input = [0u32; 16];
+    input[..4].copy_from_slice(&[0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574]);
+    input[4..12].copy_from_slice(key);
+    input[12] = counter as u32;
+    input[13] = (counter >> 32) as u32;
+    let mut state = input;
+    for _ in 0..10 {
+        quarter_round(&mut state, 0, 4, 8, 12);
+        quarter_round(&mut state, 1, 5, 9, 13);
+        quarter_round(&mut state, 2, 6, 10, 14);
+        quarter_round(&mut state, 3, 7, 11, 15);
+        quarter_round(&mut state, 0, 5, 10, 15);
+        quarter_round(&mut state, 1, 6, 11, 12);
+        quarter_round(&mut state, 2, 7, 8, 13);
+        quarter_round(&mut state, 3, 4, 9, 14);
+    }
+    for (word, initial) in state.iter_mut().zip(input.iter()) {
+        *word = word.wrapping_add(*initial);
+    }
+    state
+}
+
+impl Generator {
+    fn reseed(&mut self, pool: &mut EntropyPool) {
+        for (i, word) in self.key.iter_mut().enumerate() {
+            *word ^= pool.words[i] ^ pool.words[i + 8];
+        }
+        self.rekey();
+        pool.fresh_samples = 0;
+        self.bytes_since_reseed = 0;
+    }
+    /// Replace the key with output of the old one, so that past output
+    /// cannot be recovered from the current state.
+    fn rekey(&mut self) {
+        let block = chacha20_block(&self.key, self.counter);
+        self.counter = self.counter.wrapping_add(1);
+        self.key.copy_from_slice(&block[..8]);
+    }
+    fn fill(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(64) {
+            let block = chacha20_block(&self.key, self.counter);
+            self.counter = self.counter.wrapping_add(1);
+            for (i, byte) in chunk.iter_mut().enumerate() {
+                *byte = (block[i / 4] >> (8 * (i % 4))) as u8;
+            }
+        }
+        self.bytes_since_reseed += buf.len();
+        self.rekey();
+    }
+}
+
+/// Fill `buf` with random bytes. Never blocks, even before the pool is
+/// seeded; see [`is_seeded`].
+pub fn fill_random(buf: &mut [u8]) {
+    let mut generator = GENERATOR.exclusive_access();
+    {
+        let mut pool = POOL.exclusive_access();
+        if pool.fresh_samples >= RESEED_SAMPLES || generator.bytes_since_reseed >= RESEED_BYTES {
+            generator.reseed(&mut pool);
+        }
+    }
+    generator.fill(buf);
+}
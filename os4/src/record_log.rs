@@ -0,0 +1,58 @@
+//! Append-only record logs
+//!
+//! A [`RecordLog`] keeps records in the order they were appended, up to a
+//! fixed capacity. Unlike the trace ring, it is never overwritten nor
+//! drained: once it is full, new records are dropped and counted, so what
+//! happened first stays on record. Readers copy records out from any
+//! position. The audit and accounting logs are such logs.
+
+use crate::sync::UPSafeCell;
+use alloc::vec::Vec;
+
+struct Records<T> {
+    records: Vec<T>,
+    /// records dropped because the log was full
+    dropped: usize,
+}
+
+pub struct RecordLog<T> {
+    /// what the log is of, for the warning when it fills up
+    name: &'static str,
+    capacity: usize,
+    inner: UPSafeCell<Records<T>>,
+}
+
+impl<T: Copy> RecordLog<T> {
+    pub fn new(name: &'static str, capacity: usize) -> Self {
+        Self {
+            name,
+            capacity,
+            inner: unsafe {
+                UPSafeCell::new(Records {
+                    records: Vec::with_capacity(capacity),
+                    dropped: 0,
+                })
+            },
+        }
+    }
+    /// Append `record`, or drop it if the log is full, warning the first
+    /// time.
+    pub fn append(&self, record: T) {
+        let mut inner = self.inner.exclusive_access();
+        if inner.records.len() == self.capacity {
+            if inner.dropped == 0 {
+                warn!("[kernel] {} log full, dropping new records", self.name);
+            }
+            inner.dropped += 1;
+            return;
+        }
+        inner.records.push(record);
+    }
+    /// Copy up to `count` records from the `start`th on.
+    pub fn read(&self, start: usize, count: usize) -> Vec<T> {
+        let inner = self.inner.exclusive_access();
+        let start = start.min(inner.records.len());
+        let end = start + count.min(inner.records.len() - start);
+        inner.records[start..end].to_vec()
+    }
+}
@@ -7,6 +7,40 @@ const SBI_CONSOLE_PUTCHAR: usize = 1;
 const SBI_CONSOLE_GETCHAR: usize = 2;
 const SBI_SHUTDOWN: usize = 8;
 
+/// the System Reset extension, "SRST"
+const SBI_EXT_SRST: usize = 0x5352_5354;
+const SBI_SRST_SYSTEM_RESET: usize = 0;
+
+/// the System Suspend extension, "SUSP", which `suspend.S` calls
+const SBI_EXT_SUSP: usize = 0x5355_5350;
+
+/// the Base extension
+const SBI_EXT_BASE: usize = 0x10;
+const SBI_BASE_PROBE_EXTENSION: usize = 3;
+
+/// the Performance Monitoring Unit extension, "PMU"
+const SBI_EXT_PMU: usize = 0x50_4d55;
+const SBI_PMU_NUM_COUNTERS: usize = 0;
+const SBI_PMU_COUNTER_GET_INFO: usize = 1;
+const SBI_PMU_COUNTER_CONFIG_MATCHING: usize = 2;
+/// start the counter once it is configured
+const SBI_PMU_CFG_FLAG_AUTO_START: usize = 1 << 1;
+
+/// SRST reset types
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ResetType {
+    Shutdown = 0,
+    ColdReboot = 1,
+    WarmReboot = 2,
+}
+
+/// SRST reset reasons
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ResetReason {
+    NoReason = 0,
+    SystemFailure = 1,
+}
+
 #[inline(always)]
 /// general sbi call
 fn sbi_call(which: usize, arg0: usize, arg1: usize, arg2: usize) -> usize {
@@ -23,6 +57,41 @@ fn sbi_call(which: usize, arg0: usize, arg1: usize, arg2: usize) -> usize {
     ret
 }
 
+#[inline(always)]
+/// sbi call to function `fid` of extension `eid`, returning (error, value)
+fn sbi_call_ext(eid: usize, fid: usize, arg0: usize, arg1: usize) -> (isize, usize) {
+    let (error, value);
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("x10") arg0 => error,
+            inlateout("x11") arg1 => value,
+            in("x16") fid,
+            in("x17") eid,
+        );
+    }
+    (error, value)
+}
+
+#[inline(always)]
+/// sbi call to function `fid` of extension `eid` with up to five arguments
+fn sbi_call_ext5(eid: usize, fid: usize, args: [usize; 5]) -> (isize, usize) {
+    let (error, value);
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("x10") args[0] => error,
+            inlateout("x11") args[1] => value,
+            in("x12") args[2],
+            in("x13") args[3],
+            in("x14") args[4],
+            in("x16") fid,
+            in("x17") eid,
+        );
+    }
+    (error, value)
+}
+
 /// use sbi call to set timer
 pub fn set_timer(timer: usize) {
     sbi_call(SBI_SET_TIMER, timer, 0, 0);
@@ -38,8 +107,63 @@ pub fn console_getchar() -> usize {
     sbi_call(SBI_CONSOLE_GETCHAR, 0, 0, 0)
 }
 
-/// use sbi call to shutdown the kernel
-pub fn shutdown() -> ! {
+/// use the SRST extension to power off or reboot, falling back to the
+/// legacy shutdown call on firmware without it
+pub fn system_reset(reset_type: ResetType, reason: ResetReason) -> ! {
+    sbi_call_ext(
+        SBI_EXT_SRST,
+        SBI_SRST_SYSTEM_RESET,
+        reset_type as usize,
+        reason as usize,
+    );
     sbi_call(SBI_SHUTDOWN, 0, 0, 0);
     panic!("It should shutdown!");
 }
+
+/// Whether the firmware implements extension `eid`.
+pub fn probe_extension(eid: usize) -> bool {
+    let (error, value) = sbi_call_ext(SBI_EXT_BASE, SBI_BASE_PROBE_EXTENSION, eid, 0);
+    error == 0 && value != 0
+}
+
+/// Whether the firmware can suspend the system to RAM.
+pub fn has_system_suspend() -> bool {
+    probe_extension(SBI_EXT_SUSP)
+}
+
+/// Number of counters of the PMU extension, 0 without it.
+pub fn pmu_num_counters() -> usize {
+    if !probe_extension(SBI_EXT_PMU) {
+        return 0;
+    }
+    let (error, value) = sbi_call_ext(SBI_EXT_PMU, SBI_PMU_NUM_COUNTERS, 0, 0);
+    if error == 0 {
+        value
+    } else {
+        0
+    }
+}
+
+/// Program one of the first `counters` PMU counters to count hardware event
+/// `event` and start it, returning the CSR it is read from, if it is one.
+pub fn pmu_start_event(counters: usize, event: usize) -> Option<usize> {
+    let mask = if counters >= usize::BITS as usize {
+        usize::MAX
+    } else {
+        (1 << counters) - 1
+    };
+    let (error, counter) = sbi_call_ext5(
+        SBI_EXT_PMU,
+        SBI_PMU_COUNTER_CONFIG_MATCHING,
+        [0, mask, SBI_PMU_CFG_FLAG_AUTO_START, event, 0],
+    );
+    if error != 0 {
+        return None;
+    }
+    let (error, info) = sbi_call_ext(SBI_EXT_PMU, SBI_PMU_COUNTER_GET_INFO, counter, 0);
+    // firmware counters, with the top bit set, have no CSR
+    if error != 0 || (info as isize) < 0 {
+        return None;
+    }
+    Some(info & 0xfff)
+}
@@ -0,0 +1,26 @@
+//! Process accounting syscalls
+
+use super::errno::EFAULT;
+use crate::acct::{self, AcctRecord};
+use crate::mm::UserSlice;
+use crate::task::{current_is_privileged, current_user_token};
+use core::mem::size_of;
+
+/// Copy as many whole [`AcctRecord`]s as fit in the `len` bytes at `buf`,
+/// from the `start`th record of the accounting log on, and return the bytes
+/// copied. Privileged; fails with `-EFAULT` if `buf` is not writable.
+pub fn sys_acct_read(start: usize, buf: *mut u8, len: usize) -> isize {
+    if !current_is_privileged() {
+        return -1;
+    }
+    let records = acct::read(start, len / size_of::<AcctRecord>());
+    let buf = UserSlice::new(
+        current_user_token(),
+        buf as *const AcctRecord,
+        records.len(),
+    );
+    if !buf.write(&records) {
+        return -EFAULT;
+    }
+    (records.len() * size_of::<AcctRecord>()) as isize
+}
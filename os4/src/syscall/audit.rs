@@ -0,0 +1,27 @@
+//! Audit log syscalls
+
+use super::errno::EFAULT;
+use crate::audit::{self, AuditRecord};
+use crate::mm::UserSlice;
+use crate::task::{current_is_privileged, current_user_token};
+use core::mem::size_of;
+
+/// Copy as many whole [`AuditRecord`]s as fit in the `len` bytes at `buf`,
+/// from the `start`th record of the audit log on, and return the bytes
+/// copied. The log is left as it is. Privileged; fails with `-EFAULT` if
+/// `buf` is not writable.
+pub fn sys_audit_read(start: usize, buf: *mut u8, len: usize) -> isize {
+    if !current_is_privileged() {
+        return -1;
+    }
+    let records = audit::read(start, len / size_of::<AuditRecord>());
+    let buf = UserSlice::new(
+        current_user_token(),
+        buf as *const AuditRecord,
+        records.len(),
+    );
+    if !buf.write(&records) {
+        return -EFAULT;
+    }
+    (records.len() * size_of::<AuditRecord>()) as isize
+}
@@ -0,0 +1,38 @@
+//! Error numbers, same values as Linux
+//!
+//! Most syscalls still fail with -1. These are returned negated where the
+//! caller has to tell the reason apart, such as a non-blocking call that
+//! would have waited.
+
+use crate::fs::WaitError;
+
+pub const EPERM: isize = 1;
+pub const ENOENT: isize = 2;
+pub const ESRCH: isize = 3;
+pub const EINTR: isize = 4;
+pub const EAGAIN: isize = 11;
+pub const ENOMEM: isize = 12;
+pub const EFAULT: isize = 14;
+pub const ENODEV: isize = 19;
+pub const ENOTDIR: isize = 20;
+pub const EINVAL: isize = 22;
+pub const EMFILE: isize = 24;
+pub const ENOTTY: isize = 25;
+pub const EPIPE: isize = 32;
+pub const ERANGE: isize = 34;
+pub const ENOSYS: isize = 38;
+pub const EINPROGRESS: isize = 115;
+
+/// The return value of a syscall that opened a file descriptor, if the
+/// task had room for it.
+pub fn new_fd(fd: Option<usize>) -> isize {
+    fd.map_or(-EMFILE, |fd| fd as isize)
+}
+
+/// The return value of a syscall that gave up waiting.
+pub fn wait_error(err: WaitError) -> isize {
+    match err {
+        WaitError::WouldBlock => -EAGAIN,
+        WaitError::Interrupted => -EINTR,
+    }
+}
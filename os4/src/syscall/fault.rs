@@ -0,0 +1,31 @@
+//! Fault injection syscall
+
+/// Set up how fault point `point` fails from `config`, see
+/// [`crate::fault`], and return how many failures it injected since it was
+/// last set up. A null `config` only returns the count. Privileged; fails
+/// with -1 if the kernel was built without the `fault-inject` feature.
+#[cfg(feature = "fault-inject")]
+pub fn sys_fault_inject(point: usize, config: *const crate::fault::FaultConfig) -> isize {
+    use crate::fault::{configure, injected, FaultPoint};
+    use crate::mm::UserPtr;
+    use crate::task::{current_is_privileged, current_user_token};
+    if !current_is_privileged() {
+        return -1;
+    }
+    let point = match FaultPoint::from_usize(point) {
+        Some(point) => point,
+        None => return -1,
+    };
+    if config.is_null() {
+        return injected(point) as isize;
+    }
+    match UserPtr::new(current_user_token(), config).read() {
+        Some(config) => configure(point, config) as isize,
+        None => -1,
+    }
+}
+
+#[cfg(not(feature = "fault-inject"))]
+pub fn sys_fault_inject(_point: usize, _config: *const u8) -> isize {
+    -1
+}
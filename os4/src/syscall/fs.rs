@@ -1,21 +1,407 @@
 //! File and filesystem-related syscalls
 
-use crate::mm::translated_byte_buffer;
-use crate::task::current_user_token;
+use super::errno::{
+    new_fd, wait_error, EAGAIN, EFAULT, EINTR, EINVAL, EMFILE, ENOENT, ENOTDIR, ENOTTY, EPIPE,
+    ERANGE, ESRCH,
+};
+use crate::audit::{self, AuditEvent};
+use crate::config::CLOCK_FREQ;
+use crate::fs::{
+    file_mode, is_dir, open_device, open_proc, resolve_path, tty_foreground, tty_set_foreground,
+    tty_set_termios, tty_termios, wait_for_poll, wait_ready, Access, Pipe, PollEvents, PollFd,
+    PressureFd, SignalFd, Termios,
+};
+use crate::mm::{read_user_str, UserPtr, UserSlice};
+use crate::random::{fill_random, is_seeded, wait_for_seed};
+use crate::task::{
+    current_add_file, current_close_file, current_credentials, current_cwd, current_file,
+    current_signal_interrupted, current_task_id, current_user_token, group_exists, set_current_cwd,
+    task_limit, Resource, SignalFlags,
+};
+use crate::timer::get_time;
+use alloc::string::String;
+use alloc::sync::Arc;
 
-const FD_STDOUT: usize = 1;
+const F_GETFL: u32 = 3;
+const F_SETFL: u32 = 4;
+const TCGETS: usize = 0x5401;
+const TCSETS: usize = 0x5402;
+const TCSETSW: usize = 0x5403;
+const TCSETSF: usize = 0x5404;
+const TIOCGPGRP: usize = 0x540f;
+const TIOCSPGRP: usize = 0x5410;
+const O_RDONLY: usize = 0;
+const O_WRONLY: usize = 1;
+const O_RDWR: usize = 2;
+const O_ACCMODE: usize = 3;
+const O_NONBLOCK: usize = 0o4000;
+const O_CLOEXEC: usize = 0o2000000;
+/// `dirfd` of the `*at` syscalls standing for the working directory
+const AT_FDCWD: isize = -100;
+/// longest path a syscall takes, without the NUL, as Linux `PATH_MAX`
+const MAX_PATH_LEN: usize = 4095;
 
+/// Write to `fd`. A non-blocking file that cannot take anything fails
+/// with `-EAGAIN`, and one whose other end is gone with `-EPIPE`.
 pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
-    match fd {
-        FD_STDOUT => {
-            let buffers = translated_byte_buffer(current_user_token(), buf, len);
-            for buffer in buffers {
-                print!("{}", core::str::from_utf8(buffer).unwrap());
+    let token = current_user_token();
+    match current_file(fd) {
+        Some(file) if file.writable() => {
+            let buffer = match UserSlice::new(token, buf, len).buffer(false) {
+                Some(buffer) => buffer,
+                None => return -EFAULT,
+            };
+            if let Err(err) = wait_ready(file.as_ref(), PollEvents::POLLOUT) {
+                return wait_error(err);
             }
-            len as isize
+            match file.write(buffer) {
+                // nothing written as the other end hung up
+                0 if len > 0 && file.poll(PollEvents::POLLOUT).contains(PollEvents::POLLERR) => {
+                    -EPIPE
+                }
+                written => written as isize,
+            }
+        }
+        _ => -1,
+    }
+}
+
+/// Read from `fd`. A non-blocking file with nothing to read, or a socket
+/// whose receive timeout passed, fails with `-EAGAIN`.
+pub fn sys_read(fd: usize, buf: *const u8, len: usize) -> isize {
+    let token = current_user_token();
+    match current_file(fd) {
+        Some(file) if file.readable() => {
+            let buffer = match UserSlice::new(token, buf, len).buffer(true) {
+                Some(buffer) => buffer,
+                None => return -EFAULT,
+            };
+            if let Err(err) = wait_ready(file.as_ref(), PollEvents::POLLIN) {
+                return wait_error(err);
+            }
+            #[cfg(feature = "fault-inject")]
+            if crate::fault::should_fail(crate::fault::FaultPoint::Read) {
+                return -1;
+            }
+            file.read(buffer) as isize
+        }
+        _ => -1,
+    }
+}
+
+/// The absolute path `path` names for a `*at` syscall: relative to the
+/// working directory if `dirfd` is `AT_FDCWD`. There being no directory
+/// files to open, any other `dirfd` only goes with absolute paths.
+fn at_path(dirfd: usize, path: *const u8) -> Option<String> {
+    let path = read_user_str(current_user_token(), path, MAX_PATH_LEN)?;
+    if dirfd as isize != AT_FDCWD && !path.starts_with('/') {
+        return None;
+    }
+    Some(resolve_path(&current_cwd(), &path))
+}
+
+/// Open the file at `path`, if its mode allows the access asked for in
+/// `flags`. Only device and `/proc` files exist, so `mode` is ignored, as
+/// are the other flags.
+pub fn sys_openat(dirfd: usize, path: *const u8, flags: u32, _mode: u32) -> isize {
+    let path = match at_path(dirfd, path) {
+        Some(path) => path,
+        None => return -1,
+    };
+    let access = match flags as usize & O_ACCMODE {
+        O_RDONLY => Access::READ,
+        O_WRONLY => Access::WRITE,
+        O_RDWR => Access::READ | Access::WRITE,
+        _ => return -1,
+    };
+    let (uid, gid) = current_credentials();
+    match file_mode(&path) {
+        Some(mode) if !mode.allows(uid, gid, access) => {
+            audit::record(
+                AuditEvent::Denied,
+                [mode.mode as usize, access.bits() as usize],
+            );
+            return -1;
+        }
+        _ => {}
+    }
+    match open_device(&path).or_else(|| open_proc(&path)) {
+        Some(file) => new_fd(current_add_file(file)),
+        None => -1,
+    }
+}
+
+/// Write the working directory of the task to `buf`, NUL terminated,
+/// returning its length with the NUL. Fails with `-ERANGE` if `size` is too
+/// small for it.
+pub fn sys_getcwd(buf: *mut u8, size: usize) -> isize {
+    let mut cwd = current_cwd().into_bytes();
+    cwd.push(0);
+    if cwd.len() > size {
+        return -ERANGE;
+    }
+    match UserSlice::new(current_user_token(), buf, cwd.len()).write(&cwd) {
+        true => cwd.len() as isize,
+        false => -EFAULT,
+    }
+}
+
+/// Change the working directory of the task to `path`, failing with
+/// `-ENOENT` if there is no such file and with `-ENOTDIR` if it is not a
+/// directory.
+pub fn sys_chdir(path: *const u8) -> isize {
+    let path = match read_user_str(current_user_token(), path, MAX_PATH_LEN) {
+        Some(path) => resolve_path(&current_cwd(), &path),
+        None => return -EFAULT,
+    };
+    if is_dir(&path) {
+        set_current_cwd(path);
+        0
+    } else if file_mode(&path).is_some() {
+        -ENOTDIR
+    } else {
+        -ENOENT
+    }
+}
+
+/// Make a pipe and write the descriptors of its read and write ends to
+/// `fds`. `O_NONBLOCK` in `flags` makes both ends non-blocking. `O_CLOEXEC`
+/// is accepted, and always holds: spawned tasks inherit no descriptors.
+pub fn sys_pipe2(fds: *mut i32, flags: usize) -> isize {
+    if flags & !(O_NONBLOCK | O_CLOEXEC) != 0 {
+        return -EINVAL;
+    }
+    let (read_end, write_end) = Pipe::new(flags & O_NONBLOCK != 0);
+    let read_fd = match current_add_file(Arc::new(read_end)) {
+        Some(fd) => fd,
+        None => return -EMFILE,
+    };
+    let write_fd = match current_add_file(Arc::new(write_end)) {
+        Some(fd) => fd,
+        None => {
+            current_close_file(read_fd);
+            return -EMFILE;
+        }
+    };
+    let pair = [read_fd as i32, write_fd as i32];
+    if !UserSlice::new(current_user_token(), fds, 2).write(&pair) {
+        current_close_file(read_fd);
+        current_close_file(write_fd);
+        return -EFAULT;
+    }
+    0
+}
+
+pub fn sys_close(fd: usize) -> isize {
+    if current_close_file(fd) {
+        0
+    } else {
+        -1
+    }
+}
+
+/// Get (`F_GETFL`) or set (`F_SETFL`) the status flags of `fd`. Only
+/// `O_NONBLOCK` can be changed. Every file kind supports it, the console,
+/// pipes and sockets included: reads and writes that would wait fail with
+/// `-EAGAIN` instead.
+pub fn sys_fcntl(fd: usize, cmd: u32, arg: usize) -> isize {
+    let file = match current_file(fd) {
+        Some(file) => file,
+        None => return -1,
+    };
+    match cmd {
+        F_GETFL => {
+            let access = match (file.readable(), file.writable()) {
+                (true, true) => O_RDWR,
+                (false, true) => O_WRONLY,
+                _ => O_RDONLY,
+            };
+            let nonblock = if file.nonblocking() { O_NONBLOCK } else { 0 };
+            (access | nonblock) as isize
         }
-        _ => {
-            panic!("Unsupported fd in sys_write!");
+        F_SETFL => {
+            let nonblocking = arg & O_NONBLOCK != 0;
+            if file.set_nonblocking(nonblocking) || !nonblocking {
+                0
+            } else {
+                -1
+            }
         }
+        _ => -1,
+    }
+}
+
+/// Control the console, which `fd` must be:
+///
+/// - `TCGETS` and `TCSETS` get and change its settings, as a `struct
+///   termios` at `arg`. `TCSETSW` is the same change, as output is never
+///   held back; `TCSETSF` also discards the input not read yet.
+/// - `TIOCGPGRP` and `TIOCSPGRP` get and change its foreground process
+///   group, as an `int` at `arg`. Getting it fails with `-ENOTTY` while
+///   none was set, and setting it with `-ESRCH` if there is no such group.
+///
+/// Fails with `-ENOTTY` for another file or request.
+pub fn sys_ioctl(fd: usize, request: usize, arg: usize) -> isize {
+    let file = match current_file(fd) {
+        Some(file) => file,
+        None => return -1,
+    };
+    if !file.is_tty() {
+        return -ENOTTY;
+    }
+    let termios = UserPtr::new(current_user_token(), arg as *const Termios);
+    let pgid = UserPtr::new(current_user_token(), arg as *const i32);
+    match request {
+        TCGETS => match termios.write(tty_termios()) {
+            true => 0,
+            false => -EFAULT,
+        },
+        TCSETS | TCSETSW | TCSETSF => match termios.read() {
+            Some(value) => {
+                tty_set_termios(value, request == TCSETSF);
+                0
+            }
+            None => -EFAULT,
+        },
+        TIOCGPGRP => match tty_foreground() {
+            Some(foreground) => match pgid.write(foreground as i32) {
+                true => 0,
+                false => -EFAULT,
+            },
+            None => -ENOTTY,
+        },
+        TIOCSPGRP => match pgid.read() {
+            Some(value) if value < 0 => -EINVAL,
+            Some(value) if !group_exists(value as usize) => -ESRCH,
+            Some(value) => {
+                tty_set_foreground(value as usize);
+                0
+            }
+            None => -EFAULT,
+        },
+        _ => -ENOTTY,
+    }
+}
+
+/// Create a signalfd reporting the signals in `mask`, non-blocking if
+/// `flags` has `O_NONBLOCK`, or change the mask of the existing signalfd
+/// `fd` when it is not -1.
+pub fn sys_signalfd(fd: isize, mask: u32, flags: usize) -> isize {
+    let mask = SignalFlags::from_bits_truncate(mask);
+    if fd == -1 {
+        let signalfd = SignalFd::new(mask, flags & O_NONBLOCK != 0);
+        return new_fd(current_add_file(Arc::new(signalfd)));
+    }
+    match current_file(fd as usize) {
+        Some(file) => match file.as_signalfd() {
+            Some(signalfd) => {
+                signalfd.set_mask(mask);
+                fd
+            }
+            None => -1,
+        },
+        None => -1,
+    }
+}
+
+/// Open a descriptor that becomes readable when memory pressure rises, see
+/// [`PressureFd`], non-blocking if `flags` has `O_NONBLOCK`.
+pub fn sys_memory_pressure_fd(flags: usize) -> isize {
+    let pressure_fd = PressureFd::new(flags & O_NONBLOCK != 0);
+    new_fd(current_add_file(Arc::new(pressure_fd)))
+}
+
+/// Wait until one of the `nfds` descriptors in `fds` is ready, or until
+/// `timeout_ms` milliseconds elapsed (a negative timeout waits forever).
+/// The task blocks in between, with the timer armed for the timeout. The
+/// descriptors are looked at once more when the timeout passes, so that an
+/// event coming as it does is reported rather than lost.
+///
+/// Returns the number of descriptors with non-zero `revents`, or `-EINTR`
+/// when interrupted by a signal. More descriptors than the task may open
+/// fail with `-EINVAL`, and `fds` not all readable and writable with
+/// `-EFAULT`.
+pub fn sys_ppoll(fds: *mut PollFd, nfds: usize, timeout_ms: isize) -> isize {
+    let max_fds = task_limit(current_task_id(), Resource::NoFile).map_or(0, |limit| limit.cur);
+    if nfds > max_fds {
+        return -EINVAL;
+    }
+    let fds = UserSlice::new(current_user_token(), fds as *const PollFd, nfds);
+    let mut pollfds = match fds.read() {
+        Some(pollfds) => pollfds,
+        None => return -EFAULT,
+    };
+    let deadline = if timeout_ms >= 0 {
+        let cycles = (timeout_ms as usize).saturating_mul(CLOCK_FREQ / 1000);
+        Some(get_time().saturating_add(cycles))
+    } else {
+        None
+    };
+    loop {
+        let mut ready = 0;
+        for pollfd in pollfds.iter_mut() {
+            let revents = if pollfd.fd < 0 {
+                PollEvents::empty()
+            } else {
+                match current_file(pollfd.fd as usize) {
+                    Some(file) => file.poll(PollEvents::from_bits_truncate(pollfd.events)),
+                    None => PollEvents::POLLNVAL,
+                }
+            };
+            pollfd.revents = revents.bits();
+            if !revents.is_empty() {
+                ready += 1;
+            }
+        }
+        let timed_out = deadline.map_or(false, |deadline| get_time() >= deadline);
+        if ready > 0 || timed_out {
+            if !fds.write(&pollfds) {
+                return -EFAULT;
+            }
+            return ready;
+        }
+        if current_signal_interrupted() {
+            return -EINTR;
+        }
+        wait_for_poll(deadline);
+    }
+}
+
+bitflags! {
+    /// `getrandom` flags, same values as Linux
+    struct GetRandomFlags: u32 {
+        const GRND_NONBLOCK = 1;
+        const GRND_RANDOM = 2;
+        const GRND_INSECURE = 4;
+    }
+}
+
+/// Fill `buf` with random bytes, waiting until the entropy pool is seeded
+/// unless `GRND_NONBLOCK` (fail with `-EAGAIN` instead) or `GRND_INSECURE`
+/// (do not wait) is given. `GRND_RANDOM` makes no difference. Fails with
+/// `-EINTR` if a signal interrupts the wait, and with `-EFAULT` if `buf` is
+/// not all writable.
+pub fn sys_getrandom(buf: *mut u8, len: usize, flags: u32) -> isize {
+    let flags = match GetRandomFlags::from_bits(flags) {
+        Some(flags) => flags,
+        None => return -1,
+    };
+    while !flags.contains(GetRandomFlags::GRND_INSECURE) && !is_seeded() {
+        if flags.contains(GetRandomFlags::GRND_NONBLOCK) {
+            return -EAGAIN;
+        }
+        if current_signal_interrupted() {
+            return -EINTR;
+        }
+        wait_for_seed();
+    }
+    let mut buffer = match UserSlice::new(current_user_token(), buf as *const u8, len).buffer(true)
+    {
+        Some(buffer) => buffer,
+        None => return -EFAULT,
+    };
+    for bytes in buffer.buffers.iter_mut() {
+        fill_random(bytes);
     }
+    len as isize
 }
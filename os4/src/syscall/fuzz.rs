@@ -0,0 +1,308 @@
+//! Syscall fuzzing, built with the `syscall-fuzz` feature
+//!
+//! A sacrificial task, running the first application without any capability
+//! but `CAP_MMAP_FIXED` and not as root, is added at boot. When it makes its
+//! first syscall, the kernel makes `SYSCALL_FUZZ_ITERATIONS` random ones in
+//! its place instead, from its kernel stack and with its address space, and
+//! then exits it. Arguments favour what handlers get wrong: null, kernel
+//! and unmapped addresses, user addresses off by a few bytes, unaligned
+//! lengths and sizes just past a page. The kernel passes if it does not
+//! panic; each call is logged at debug level first, so that the panic log
+//! ends with the call that caused it. The generator is seeded from the
+//! entropy pool, and the seed is logged too.
+//!
+//! Calls that end the task or may wait with nothing to wake them are not
+//! made. Those that could wait are tamed instead: `read` is made on a
+//! descriptor that never blocks, `ppoll` with a short timeout and maybe on
+//! that descriptor, `semop` with `IPC_NOWAIT` on every operation it can
+//! read, and `getrandom` with `GRND_NONBLOCK`.
+
+use super::*;
+use crate::config::{MAX_SYSCALL_NUM, PAGE_SIZE, SYSCALL_FUZZ_ITERATIONS};
+use crate::config::{TRAMPOLINE, TRAP_CONTEXT};
+use crate::fs::{open_device, File, PollEvents};
+use crate::ipc::{IpcFlags, SEMOPM};
+use crate::loader::{get_app_data, get_num_app};
+use crate::mm::{UserPtr, UserSlice};
+use crate::random::fill_random;
+use crate::task::{
+    add_task, current_add_file, current_file, current_trap_cx, current_user_token,
+    exit_current_and_run_next, TaskControlBlock,
+};
+use crate::trap::cond_resched;
+use alloc::sync::Arc;
+use core::mem::size_of;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// `getrandom` flag: fail instead of waiting for the entropy pool
+const GRND_NONBLOCK: usize = 1;
+
+/// the syscalls made, but those that would end the task or wait for good
+const FUZZED: &[usize] = &[
+    SYSCALL_READ,
+    SYSCALL_GETCWD,
+    SYSCALL_FCNTL,
+    SYSCALL_IOCTL,
+    SYSCALL_CAPGET,
+    SYSCALL_CAPSET,
+    SYSCALL_CHDIR,
+    SYSCALL_OPENAT,
+    SYSCALL_CLOSE,
+    SYSCALL_PIPE2,
+    SYSCALL_WRITE,
+    SYSCALL_PPOLL,
+    SYSCALL_SIGNALFD,
+    SYSCALL_SYSLOG,
+    SYSCALL_YIELD,
+    SYSCALL_KILL,
+    SYSCALL_SIGPROCMASK,
+    SYSCALL_SETPGID,
+    SYSCALL_GETPGID,
+    SYSCALL_GET_TIME,
+    SYSCALL_GETPID,
+    SYSCALL_SPAWN,
+    SYSCALL_WAITPID,
+    SYSCALL_GETUID,
+    SYSCALL_GETGID,
+    SYSCALL_SEMGET,
+    SYSCALL_SEMOP,
+    SYSCALL_SEMCTL,
+    SYSCALL_SOCKET,
+    SYSCALL_BIND,
+    SYSCALL_LISTEN,
+    SYSCALL_SENDTO,
+    SYSCALL_SETSOCKOPT,
+    SYSCALL_GETSOCKOPT,
+    SYSCALL_SHUTDOWN_SOCKET,
+    SYSCALL_MUNMAP,
+    SYSCALL_MMAP,
+    SYSCALL_MPROTECT,
+    SYSCALL_MSYNC,
+    SYSCALL_MINCORE,
+    SYSCALL_PROCESS_VM_READV,
+    SYSCALL_PROCESS_VM_WRITEV,
+    SYSCALL_PRLIMIT,
+    SYSCALL_SCHED_GETSCHEDULER,
+    SYSCALL_SCHED_GETPARAM,
+    SYSCALL_SCHED_RR_GET_INTERVAL,
+    SYSCALL_SET_PRIORITY,
+    SYSCALL_PRCTL,
+    SYSCALL_SETGID,
+    SYSCALL_SETUID,
+    SYSCALL_TASK_INFO,
+    SYSCALL_TASK_INFO_V2,
+    SYSCALL_TASK_USAGE,
+    SYSCALL_RENICE,
+    SYSCALL_LOG_LEVEL,
+    SYSCALL_FRAMEBUFFER,
+    SYSCALL_FRAMEBUFFER_FLUSH,
+    SYSCALL_FRAMEBUFFER_INFO,
+    SYSCALL_SHUTDOWN,
+    SYSCALL_REBOOT,
+    SYSCALL_WATCHDOG,
+    SYSCALL_SUSPEND,
+    SYSCALL_PROFILE,
+    SYSCALL_PROFILE_READ,
+    SYSCALL_TRACE_CTL,
+    SYSCALL_TRACE_READ,
+    SYSCALL_KCOV,
+    SYSCALL_HEAP_SITES,
+    SYSCALL_PERF_READ,
+    SYSCALL_ALLOW_WX,
+    SYSCALL_AUDIT_READ,
+    SYSCALL_SANDBOX,
+    SYSCALL_ACCT_READ,
+    SYSCALL_MEMORY_PRESSURE_FD,
+    SYSCALL_MEMORY_WATERMARKS,
+    SYSCALL_COMPACT_MEMORY,
+    SYSCALL_MEMORY_HOT_ADD,
+    SYSCALL_CGROUP_CREATE,
+    SYSCALL_CGROUP_ATTACH,
+    SYSCALL_CGROUP_SET_WEIGHT,
+    SYSCALL_CGROUP_REMOVE,
+    SYSCALL_CHECKPOINT,
+    SYSCALL_RESTORE,
+    SYSCALL_PERSONALITY,
+    SYSCALL_GETRANDOM,
+];
+
+/// the sacrificial task, `usize::MAX` before it is added
+static TARGET: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// xorshift64*, which is enough to cover argument space and cheap to
+/// reproduce from a seed
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> usize {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d) as usize
+    }
+    fn below(&mut self, n: usize) -> usize {
+        self.next() % n
+    }
+    /// An argument likely to find a missing check, for a task whose stack
+    /// pointer is `sp`.
+    fn arg(&mut self, sp: usize) -> usize {
+        match self.below(10) {
+            0 => 0,
+            // descriptors, flags, counts
+            1 => self.below(64),
+            // negative numbers
+            2 => 0usize.wrapping_sub(1 + self.below(PAGE_SIZE)),
+            // kernel memory, mapped or not
+            3 => 0x8000_0000 + self.below(0x100_0000),
+            // on the user stack, maybe unaligned or past its top
+            4 => sp
+                .wrapping_add(self.below(2 * PAGE_SIZE))
+                .wrapping_sub(PAGE_SIZE),
+            // the trap context and the trampoline, mapped but not for users
+            5 => [TRAP_CONTEXT, TRAMPOLINE][self.below(2)] + self.below(PAGE_SIZE),
+            // page-sized lengths and addresses, off by one either way
+            6 => (self.below(0x1000) * PAGE_SIZE)
+                .wrapping_add(self.below(3))
+                .wrapping_sub(1),
+            7 => usize::MAX,
+            _ => self.next(),
+        }
+    }
+}
+
+/// A descriptor of the sacrificial task whose reads never block:
+/// `/dev/urandom`, installed again if a fuzzed call closed it
+struct QuietFd {
+    file: Arc<dyn File + Send + Sync>,
+    fd: Option<usize>,
+}
+
+impl QuietFd {
+    fn new() -> Self {
+        Self {
+            file: open_device("/dev/urandom").expect("no /dev/urandom to fuzz reads on"),
+            fd: None,
+        }
+    }
+    /// The descriptor, or None if the descriptor table is full.
+    fn get(&mut self) -> Option<usize> {
+        let installed = self.fd.and_then(current_file).map_or(false, |file| {
+            Arc::as_ptr(&file) as *const u8 == Arc::as_ptr(&self.file) as *const u8
+        });
+        if !installed {
+            self.fd = current_add_file(self.file.clone());
+        }
+        self.fd
+    }
+}
+
+/// Keep syscall `id` from waiting for good, changing its arguments `args`
+/// as the module documentation says. Returns false if it cannot be, for
+/// the call to be skipped.
+fn tame(id: usize, args: &mut [usize; 6], rng: &mut Rng, sp: usize, quiet: &mut QuietFd) -> bool {
+    let token = current_user_token();
+    match id {
+        SYSCALL_READ => match quiet.get() {
+            Some(fd) => args[0] = fd,
+            None => return false,
+        },
+        SYSCALL_PPOLL => {
+            // a valid request half of the time, right below the stack top
+            if rng.below(2) == 0 {
+                let fd = match quiet.get() {
+                    Some(fd) => fd,
+                    None => return false,
+                };
+                let pollfd = PollFd {
+                    fd: fd as i32,
+                    events: PollEvents::POLLIN.bits(),
+                    revents: 0,
+                };
+                let addr = (sp - size_of::<PollFd>()) & !(size_of::<usize>() - 1);
+                if !UserPtr::new(token, addr as *const PollFd).write(pollfd) {
+                    return false;
+                }
+                args[0] = addr;
+                args[1] = 1;
+            }
+            args[2] = rng.below(2);
+        }
+        SYSCALL_SEMOP if (1..=SEMOPM).contains(&args[2]) => {
+            let sops = UserSlice::new(token, args[1] as *const SemBuf, args[2]);
+            if let Some(mut ops) = sops.read() {
+                for op in ops.iter_mut() {
+                    op.sem_flg |= IpcFlags::IPC_NOWAIT.bits() as i16;
+                }
+                if !sops.write(&ops) {
+                    return false;
+                }
+            }
+        }
+        SYSCALL_GETRANDOM => args[2] |= GRND_NONBLOCK,
+        _ => {}
+    }
+    true
+}
+
+/// Add the sacrificial task. Must run before the first task does.
+pub fn add_fuzz_target() {
+    if get_num_app() == 0 {
+        warn!("[kernel] no application to fuzz syscalls from");
+        return;
+    }
+    let mut task = TaskControlBlock::new(get_app_data(0), false);
+    task.set_name("syscall-fuzz");
+    task.capabilities = Capabilities::CAP_MMAP_FIXED;
+    match add_task(task) {
+        Some(id) => TARGET.store(id, Ordering::Relaxed),
+        None => warn!("[kernel] no room for the syscall fuzzing task"),
+    }
+}
+
+/// Whether task `id` is the sacrificial task.
+pub fn is_fuzz_target(id: usize) -> bool {
+    TARGET.load(Ordering::Relaxed) == id
+}
+
+/// Make random syscalls as the sacrificial task, the current one, then
+/// exit it.
+pub fn run_fuzzer() -> ! {
+    let mut seed = [0u8; 8];
+    fill_random(&mut seed);
+    // xorshift never leaves 0
+    let seed = u64::from_ne_bytes(seed) | 1;
+    info!(
+        "[kernel] fuzzing {} syscalls, seed {:#x}",
+        SYSCALL_FUZZ_ITERATIONS, seed
+    );
+    let mut rng = Rng(seed);
+    let sp = current_trap_cx().x[2];
+    let mut quiet = QuietFd::new();
+    let mut failed = 0;
+    for i in 0..SYSCALL_FUZZ_ITERATIONS {
+        // now and then a number that is no syscall at all
+        let id = if rng.below(8) == 0 {
+            rng.below(2 * MAX_SYSCALL_NUM)
+        } else {
+            FUZZED[rng.below(FUZZED.len())]
+        };
+        let mut args = [0; 6];
+        for arg in args.iter_mut() {
+            *arg = rng.arg(sp);
+        }
+        if !tame(id, &mut args, &mut rng, sp, &mut quiet) {
+            continue;
+        }
+        debug!("[fuzz] #{} syscall {} {:#x?}", i, id, args);
+        if syscall(id, args) < 0 {
+            failed += 1;
+        }
+        cond_resched();
+    }
+    info!(
+        "[kernel] syscall fuzzing done: {} calls, {} failed",
+        SYSCALL_FUZZ_ITERATIONS, failed
+    );
+    exit_current_and_run_next(0);
+    panic!("Unreachable in run_fuzzer!");
+}
@@ -0,0 +1,49 @@
+//! Framebuffer syscalls for graphical programs
+
+use super::errno::EFAULT;
+use crate::config::FB_VADDR;
+use crate::drivers::virtio::FbInfo;
+use crate::drivers::GPU_DEVICE;
+use crate::mm::{MapPermission, UserPtr};
+use crate::task::{current_map_linear, current_user_token};
+
+/// Map the framebuffer at `FB_VADDR` in the current task and return that
+/// address. Fails if there is no GPU or the range is already in use.
+///
+/// With Svpbmt the mapping is non-cacheable, so that writes may be combined
+/// but reach the device; without it, the platform's attributes apply.
+pub fn sys_framebuffer() -> isize {
+    let (ppn, pages) = match GPU_DEVICE.exclusive_access().as_ref() {
+        Some(gpu) => gpu.framebuffer(),
+        None => return -1,
+    };
+    let perm = MapPermission::R | MapPermission::W | MapPermission::U | MapPermission::NC;
+    if current_map_linear(FB_VADDR, ppn, pages, perm) != 0 {
+        return -1;
+    }
+    FB_VADDR as isize
+}
+
+/// Write the geometry of the framebuffer to `info`, failing with `-EFAULT`
+/// if it is not writable.
+pub fn sys_framebuffer_info(info: *mut FbInfo) -> isize {
+    let fb_info = match GPU_DEVICE.exclusive_access().as_ref() {
+        Some(gpu) => gpu.info(),
+        None => return -1,
+    };
+    if !UserPtr::new(current_user_token(), info).write(fb_info) {
+        return -EFAULT;
+    }
+    0
+}
+
+/// Show the rectangle at (`x`, `y`) of the framebuffer on the screen.
+pub fn sys_framebuffer_flush(x: u32, y: u32, width: u32, height: u32) -> isize {
+    match GPU_DEVICE.exclusive_access().as_mut() {
+        Some(gpu) => match gpu.flush(x, y, width, height) {
+            Some(()) => 0,
+            None => -1,
+        },
+        None => -1,
+    }
+}
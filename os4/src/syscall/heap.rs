@@ -0,0 +1,29 @@
+//! Kernel heap syscall
+
+/// Copy up to `count` groups of live kernel heap allocations, by call site
+/// and most bytes first, to `buf` and return how many groups there are.
+/// Privileged; fails with -1 if the kernel was built without the
+/// `leak-detector` feature, and with `-EFAULT` if `buf` is not writable.
+#[cfg(feature = "leak-detector")]
+pub fn sys_heap_sites(buf: *mut crate::mm::HeapSite, count: usize) -> isize {
+    use super::errno::EFAULT;
+    use crate::mm::{heap_sites, UserSlice};
+    use crate::task::{current_is_privileged, current_user_token};
+    if !current_is_privileged() {
+        return -1;
+    }
+    let (sites, untracked) = heap_sites();
+    if untracked != 0 {
+        warn!("[kernel] {} heap allocations were not tracked", untracked);
+    }
+    let copied = &sites[..count.min(sites.len())];
+    if !UserSlice::new(current_user_token(), buf, copied.len()).write(copied) {
+        return -EFAULT;
+    }
+    sites.len() as isize
+}
+
+#[cfg(not(feature = "leak-detector"))]
+pub fn sys_heap_sites(_buf: *mut u8, _count: usize) -> isize {
+    -1
+}
@@ -0,0 +1,30 @@
+//! System V IPC syscalls
+
+use super::errno::{EAGAIN, EFAULT, EINTR, EINVAL};
+use crate::ipc::{semctl, semget, semop, IpcFlags, SemBuf, SemOpError, SEMOPM};
+use crate::mm::UserSlice;
+use crate::task::{current_task_id, current_user_token};
+
+pub fn sys_semget(key: usize, nsems: usize, semflg: u32) -> isize {
+    semget(key, nsems, IpcFlags::from_bits_truncate(semflg))
+}
+
+pub fn sys_semop(semid: usize, sops: *const SemBuf, nsops: usize) -> isize {
+    if nsops == 0 || nsops > SEMOPM {
+        return -EINVAL;
+    }
+    let sops = match UserSlice::new(current_user_token(), sops, nsops).read() {
+        Some(sops) => sops,
+        None => return -EFAULT,
+    };
+    match semop(current_task_id(), semid, &sops) {
+        Ok(()) => 0,
+        Err(SemOpError::Invalid) => -EINVAL,
+        Err(SemOpError::WouldBlock) => -EAGAIN,
+        Err(SemOpError::Interrupted) => -EINTR,
+    }
+}
+
+pub fn sys_semctl(semid: usize, semnum: usize, cmd: usize, arg: usize) -> isize {
+    semctl(semid, semnum, cmd, arg)
+}
@@ -0,0 +1,29 @@
+//! Kernel coverage syscall
+
+/// `KCOV_INIT` maps a coverage buffer of `arg` pages in the current task
+/// and returns its address. `KCOV_ENABLE` and `KCOV_DISABLE` then start and
+/// stop collecting the kernel pcs reached by the syscalls of that task.
+/// Fails with -1 if the kernel was built without the `kcov` feature.
+#[cfg(feature = "kcov")]
+pub fn sys_kcov(cmd: usize, arg: usize) -> isize {
+    use crate::kcov;
+    const KCOV_INIT: usize = 0;
+    const KCOV_ENABLE: usize = 1;
+    const KCOV_DISABLE: usize = 2;
+    let ok = match cmd {
+        KCOV_INIT => return kcov::init(arg).map_or(-1, |addr| addr as isize),
+        KCOV_ENABLE => kcov::enable(true),
+        KCOV_DISABLE => kcov::enable(false),
+        _ => false,
+    };
+    if ok {
+        0
+    } else {
+        -1
+    }
+}
+
+#[cfg(not(feature = "kcov"))]
+pub fn sys_kcov(_cmd: usize, _arg: usize) -> isize {
+    -1
+}
@@ -0,0 +1,167 @@
+//! The Linux syscall ABI, for the tasks with the Linux personality
+//!
+//! Linux numbers its riscv64 syscalls as this kernel numbers most of its
+//! own, and the syscalls that take the same arguments and layouts in both
+//! go to the native ones: `openat`, `close`, `read`, `write`, `ioctl`,
+//! `pipe2`, `exit`, `nanosleep`, `sched_yield`, `kill`, `gettimeofday`,
+//! `getpid`, `getuid`, `getgid`, `munmap`, `mprotect`, `getrandom` and
+//! `personality`.
+//!
+//! Those below are Linux's own. `exit_group` exits, and `set_tid_address`
+//! only returns the task id, as there are no threads. `writev` writes each
+//! segment in turn. `clock_gettime` reads the time since boot, which every
+//! clock counts as there is no real-time clock. `brk` grows a heap from the
+//! top of the stack. `mmap` maps anonymous memory only, where the kernel
+//! finds room from `LINUX_MMAP_BASE` up unless `MAP_FIXED` asks for a range,
+//! which must then be free; `PROT_NONE` reservations are not supported.
+//!
+//! Anything else fails with `ENOSYS`, the native syscalls Linux numbers
+//! otherwise included. Native syscalls report most errors as -1, which a
+//! Linux task reads as `EPERM`.
+
+use super::errno::{EFAULT, EINVAL, ENODEV, ENOMEM, ENOSYS};
+use super::{required_capability as native_capability, IoVec, TimeSpec};
+use super::{sys_exit, sys_getgid, sys_getpid, sys_getuid, sys_mmap as native_mmap, sys_write};
+use super::{
+    SYSCALL_CLOSE, SYSCALL_EXIT, SYSCALL_GETGID, SYSCALL_GETPID, SYSCALL_GETRANDOM, SYSCALL_GETUID,
+    SYSCALL_GET_TIME, SYSCALL_IOCTL, SYSCALL_KILL, SYSCALL_MPROTECT, SYSCALL_MUNMAP,
+    SYSCALL_NANOSLEEP, SYSCALL_OPENAT, SYSCALL_PERSONALITY, SYSCALL_PIPE2, SYSCALL_READ,
+    SYSCALL_WRITE, SYSCALL_YIELD,
+};
+use crate::audit::{self, AuditEvent};
+use crate::config::PAGE_SIZE;
+use crate::mm::{UserPtr, UserSlice};
+use crate::task::{current_mmap_anywhere, current_user_token, set_current_brk, Capabilities};
+use crate::timer::get_time_us;
+
+const LINUX_WRITEV: usize = 66;
+const LINUX_EXIT_GROUP: usize = 94;
+const LINUX_SET_TID_ADDRESS: usize = 96;
+const LINUX_CLOCK_GETTIME: usize = 113;
+const LINUX_GETEUID: usize = 175;
+const LINUX_GETEGID: usize = 177;
+const LINUX_BRK: usize = 214;
+const LINUX_MMAP: usize = 222;
+
+const CLOCK_REALTIME: usize = 0;
+const CLOCK_MONOTONIC: usize = 1;
+const CLOCK_MONOTONIC_RAW: usize = 4;
+const CLOCK_BOOTTIME: usize = 7;
+
+const MAP_SHARED: usize = 0x01;
+const MAP_PRIVATE: usize = 0x02;
+const MAP_FIXED: usize = 0x10;
+const MAP_ANONYMOUS: usize = 0x20;
+
+/// The capability a Linux task needs to make syscall `syscall_id`: that of
+/// the native syscall of the number, but `mmap` only needs
+/// `CAP_MMAP_FIXED` for `MAP_FIXED`.
+pub fn required_capability(syscall_id: usize, args: [usize; 6]) -> Capabilities {
+    match syscall_id {
+        LINUX_MMAP if args[3] & MAP_FIXED == 0 => Capabilities::empty(),
+        _ => native_capability(syscall_id),
+    }
+}
+
+/// Handle Linux syscall `syscall_id`, or None if the native syscall of the
+/// number is the same.
+pub fn syscall(syscall_id: usize, args: [usize; 6]) -> Option<isize> {
+    let ret = match syscall_id {
+        SYSCALL_OPENAT | SYSCALL_CLOSE | SYSCALL_READ | SYSCALL_WRITE | SYSCALL_IOCTL
+        | SYSCALL_PIPE2 | SYSCALL_EXIT | SYSCALL_NANOSLEEP | SYSCALL_YIELD | SYSCALL_KILL
+        | SYSCALL_GET_TIME | SYSCALL_GETPID | SYSCALL_GETUID | SYSCALL_GETGID | SYSCALL_MUNMAP
+        | SYSCALL_MPROTECT | SYSCALL_GETRANDOM | SYSCALL_PERSONALITY => return None,
+        LINUX_WRITEV => sys_writev(args[0], args[1] as *const IoVec, args[2]),
+        LINUX_EXIT_GROUP => sys_exit(args[0] as i32),
+        LINUX_SET_TID_ADDRESS => sys_getpid(),
+        LINUX_CLOCK_GETTIME => sys_clock_gettime(args[0], args[1] as *mut TimeSpec),
+        LINUX_GETEUID => sys_getuid(),
+        LINUX_GETEGID => sys_getgid(),
+        LINUX_BRK => set_current_brk(args[0]) as isize,
+        LINUX_MMAP => sys_mmap(args[0], args[1], args[2], args[3], args[5]),
+        _ => {
+            warn!("[kernel] Unsupported Linux syscall_id: {}", syscall_id);
+            -ENOSYS
+        }
+    };
+    Some(ret)
+}
+
+/// Write the `iovcnt` segments at `iov` to `fd` in turn, stopping at the
+/// first one written short. Returns how much was written, or the error of
+/// the first segment.
+fn sys_writev(fd: usize, iov: *const IoVec, iovcnt: usize) -> isize {
+    let iovecs = match UserSlice::new(current_user_token(), iov, iovcnt).read() {
+        Some(iovecs) => iovecs,
+        None => return -EFAULT,
+    };
+    let mut written = 0;
+    for iovec in iovecs.iter().filter(|iovec| iovec.len > 0) {
+        let ret = sys_write(fd, iovec.base, iovec.len);
+        if ret < 0 {
+            return if written > 0 { written } else { ret };
+        }
+        written += ret;
+        if (ret as usize) < iovec.len {
+            break;
+        }
+    }
+    written
+}
+
+fn sys_clock_gettime(clock: usize, tp: *mut TimeSpec) -> isize {
+    if ![
+        CLOCK_REALTIME,
+        CLOCK_MONOTONIC,
+        CLOCK_MONOTONIC_RAW,
+        CLOCK_BOOTTIME,
+    ]
+    .contains(&clock)
+    {
+        return -EINVAL;
+    }
+    let us = get_time_us();
+    let time = TimeSpec {
+        sec: us / 1_000_000,
+        nsec: us % 1_000_000 * 1000,
+    };
+    match UserPtr::new(current_user_token(), tp).write(time) {
+        true => 0,
+        false => -EFAULT,
+    }
+}
+
+/// Map `len` bytes of anonymous memory with protection `prot`, at `addr`
+/// with `MAP_FIXED` or wherever there is room, returning where.
+fn sys_mmap(addr: usize, len: usize, prot: usize, flags: usize, offset: usize) -> isize {
+    if len == 0 || offset % PAGE_SIZE != 0 || prot & !0x7 != 0 || prot == 0 {
+        return -EINVAL;
+    }
+    if flags & MAP_ANONYMOUS == 0 {
+        // there is no file to map
+        return -ENODEV;
+    }
+    // shared or private alike, as no other task can see the pages
+    let sharing = flags & (MAP_SHARED | MAP_PRIVATE);
+    if sharing != MAP_SHARED && sharing != MAP_PRIVATE {
+        return -EINVAL;
+    }
+    if flags & MAP_FIXED != 0 {
+        if addr % PAGE_SIZE != 0 {
+            return -EINVAL;
+        }
+        return match native_mmap(addr, len, prot) {
+            0 => addr as isize,
+            _ => -ENOMEM,
+        };
+    }
+    match current_mmap_anywhere(len, prot) {
+        Some(start) => {
+            if prot & 0x4 != 0 {
+                audit::record(AuditEvent::MmapExec, [start, len]);
+            }
+            start as isize
+        }
+        None => -ENOMEM,
+    }
+}
@@ -10,34 +10,347 @@
 //! `sys_` then the name of the syscall. You can find functions like this in
 //! submodules, and you should also implement syscalls this way.
 
+const SYSCALL_GETCWD: usize = 17;
+const SYSCALL_FCNTL: usize = 25;
+const SYSCALL_IOCTL: usize = 29;
+const SYSCALL_CAPGET: usize = 90;
+const SYSCALL_CAPSET: usize = 91;
+const SYSCALL_PERSONALITY: usize = 92;
+const SYSCALL_CHDIR: usize = 49;
+const SYSCALL_OPENAT: usize = 56;
+const SYSCALL_CLOSE: usize = 57;
+const SYSCALL_PIPE2: usize = 59;
+const SYSCALL_READ: usize = 63;
 const SYSCALL_WRITE: usize = 64;
+const SYSCALL_PPOLL: usize = 73;
+const SYSCALL_SIGNALFD: usize = 74;
 const SYSCALL_EXIT: usize = 93;
+const SYSCALL_NANOSLEEP: usize = 101;
+const SYSCALL_CLOCK_NANOSLEEP: usize = 115;
+const SYSCALL_SYSLOG: usize = 116;
 const SYSCALL_YIELD: usize = 124;
+const SYSCALL_KILL: usize = 129;
+const SYSCALL_SIGPROCMASK: usize = 135;
+const SYSCALL_SETPGID: usize = 154;
+const SYSCALL_GETPGID: usize = 155;
 const SYSCALL_GET_TIME: usize = 169;
+const SYSCALL_GETPID: usize = 172;
+const SYSCALL_GETUID: usize = 174;
+const SYSCALL_GETGID: usize = 176;
+const SYSCALL_SEMGET: usize = 190;
+const SYSCALL_SEMCTL: usize = 191;
+const SYSCALL_SEMOP: usize = 193;
+const SYSCALL_SOCKET: usize = 198;
+const SYSCALL_BIND: usize = 200;
+const SYSCALL_LISTEN: usize = 201;
+const SYSCALL_ACCEPT: usize = 202;
+const SYSCALL_CONNECT: usize = 203;
+const SYSCALL_SENDTO: usize = 206;
+const SYSCALL_RECVFROM: usize = 207;
+const SYSCALL_SETSOCKOPT: usize = 208;
+const SYSCALL_GETSOCKOPT: usize = 209;
+const SYSCALL_SHUTDOWN_SOCKET: usize = 210;
 const SYSCALL_MUNMAP: usize = 215;
 const SYSCALL_MMAP: usize = 222;
+const SYSCALL_MPROTECT: usize = 226;
+const SYSCALL_MSYNC: usize = 227;
+const SYSCALL_MINCORE: usize = 232;
+const SYSCALL_PROCESS_VM_READV: usize = 270;
+const SYSCALL_PROCESS_VM_WRITEV: usize = 271;
+const SYSCALL_WAITPID: usize = 260;
+const SYSCALL_PRLIMIT: usize = 261;
+const SYSCALL_GETRANDOM: usize = 278;
+const SYSCALL_SCHED_GETSCHEDULER: usize = 120;
+const SYSCALL_SCHED_GETPARAM: usize = 121;
+const SYSCALL_SCHED_RR_GET_INTERVAL: usize = 127;
 const SYSCALL_SET_PRIORITY: usize = 140;
+const SYSCALL_PRCTL: usize = 167;
+const SYSCALL_SETGID: usize = 144;
+const SYSCALL_SETUID: usize = 146;
+const SYSCALL_SPAWN: usize = 400;
 const SYSCALL_TASK_INFO: usize = 410;
+const SYSCALL_TASK_INFO_V2: usize = 411;
+const SYSCALL_TASK_USAGE: usize = 412;
+const SYSCALL_RENICE: usize = 413;
+const SYSCALL_LOG_LEVEL: usize = 420;
+const SYSCALL_FRAMEBUFFER: usize = 430;
+const SYSCALL_FRAMEBUFFER_FLUSH: usize = 431;
+const SYSCALL_FRAMEBUFFER_INFO: usize = 432;
+const SYSCALL_SHUTDOWN: usize = 440;
+const SYSCALL_REBOOT: usize = 441;
+const SYSCALL_WATCHDOG: usize = 442;
+const SYSCALL_SUSPEND: usize = 443;
+const SYSCALL_PING: usize = 450;
+const SYSCALL_DHCP: usize = 451;
+const SYSCALL_GETHOSTBYNAME: usize = 452;
+const SYSCALL_PROFILE: usize = 460;
+const SYSCALL_PROFILE_READ: usize = 461;
+const SYSCALL_TRACE_CTL: usize = 462;
+const SYSCALL_TRACE_READ: usize = 463;
+const SYSCALL_KCOV: usize = 464;
+const SYSCALL_HEAP_SITES: usize = 465;
+const SYSCALL_PERF_READ: usize = 466;
+const SYSCALL_ALLOW_WX: usize = 470;
+const SYSCALL_AUDIT_READ: usize = 471;
+const SYSCALL_SANDBOX: usize = 472;
+const SYSCALL_ACCT_READ: usize = 473;
+const SYSCALL_MEMORY_PRESSURE_FD: usize = 480;
+const SYSCALL_MEMORY_WATERMARKS: usize = 481;
+const SYSCALL_COMPACT_MEMORY: usize = 482;
+const SYSCALL_MEMORY_HOT_ADD: usize = 483;
+const SYSCALL_TEST_REPORT: usize = 490;
+const SYSCALL_FAULT_INJECT: usize = 491;
+const SYSCALL_CGROUP_CREATE: usize = 500;
+const SYSCALL_CGROUP_ATTACH: usize = 501;
+const SYSCALL_CGROUP_SET_WEIGHT: usize = 502;
+const SYSCALL_CGROUP_REMOVE: usize = 503;
+const SYSCALL_CHECKPOINT: usize = 510;
+const SYSCALL_RESTORE: usize = 511;
 
+mod acct;
+mod audit;
+mod errno;
+mod fault;
 mod fs;
+#[cfg(feature = "syscall-fuzz")]
+mod fuzz;
+mod gui;
+mod heap;
+mod ipc;
+mod kcov;
+mod linux;
+mod syslog;
+mod net;
 mod process;
+mod profile;
+mod trace;
 
+use crate::drivers::virtio::FbInfo;
+use crate::fs::PollFd;
+use crate::ipc::SemBuf;
+use crate::mm::SandboxProfile;
+use crate::perf::PerfCounters;
+use crate::audit::AuditEvent;
+use crate::profile::ProfileSample;
+use crate::task::{current_capabilities, current_personality, Capabilities, Personality, RLimit};
+use acct::*;
+use audit::*;
+use fault::*;
 use fs::*;
+use gui::*;
+use heap::*;
+use ipc::*;
+use kcov::*;
+use syslog::*;
+use net::*;
+use profile::*;
+use trace::*;
 pub use process::*;
+#[cfg(feature = "syscall-fuzz")]
+pub use fuzz::{add_fuzz_target, is_fuzz_target, run_fuzzer};
 
+/// The capability a task needs to make syscall `syscall_id`.
+fn required_capability(syscall_id: usize) -> Capabilities {
+    match syscall_id {
+        SYSCALL_KILL => Capabilities::CAP_KILL,
+        SYSCALL_SHUTDOWN | SYSCALL_REBOOT | SYSCALL_WATCHDOG | SYSCALL_SUSPEND => {
+            Capabilities::CAP_SHUTDOWN
+        }
+        SYSCALL_PROFILE
+        | SYSCALL_PROFILE_READ
+        | SYSCALL_TRACE_CTL
+        | SYSCALL_TRACE_READ
+        | SYSCALL_KCOV
+        | SYSCALL_HEAP_SITES
+        | SYSCALL_PROCESS_VM_READV
+        | SYSCALL_PROCESS_VM_WRITEV
+        | SYSCALL_CHECKPOINT => Capabilities::CAP_TRACE,
+        SYSCALL_MMAP => Capabilities::CAP_MMAP_FIXED,
+        _ => Capabilities::empty(),
+    }
+}
 
 /// handle syscall exception with `syscall_id` and other arguments
-pub fn syscall(syscall_id: usize, args: [usize; 3]) -> isize {
+pub fn syscall(syscall_id: usize, args: [usize; 6]) -> isize {
+    let linux = current_personality() == Personality::Linux;
+    let required = match linux {
+        true => linux::required_capability(syscall_id, args),
+        false => required_capability(syscall_id),
+    };
+    if !current_capabilities().contains(required) {
+        crate::audit::record(AuditEvent::Denied, [0, required.bits() as usize]);
+        return -1;
+    }
+    if linux {
+        if let Some(ret) = linux::syscall(syscall_id, args) {
+            return ret;
+        }
+    }
     // LAB1: You may need to update syscall info here.
     match syscall_id {
+        SYSCALL_GETCWD => sys_getcwd(args[0] as *mut u8, args[1]),
+        SYSCALL_FCNTL => sys_fcntl(args[0], args[1] as u32, args[2]),
+        SYSCALL_IOCTL => sys_ioctl(args[0], args[1], args[2]),
+        SYSCALL_CHDIR => sys_chdir(args[0] as *const u8),
+        SYSCALL_OPENAT => sys_openat(
+            args[0],
+            args[1] as *const u8,
+            args[2] as u32,
+            args[3] as u32,
+        ),
+        SYSCALL_CLOSE => sys_close(args[0]),
+        SYSCALL_PIPE2 => sys_pipe2(args[0] as *mut i32, args[1]),
+        SYSCALL_READ => sys_read(args[0], args[1] as *const u8, args[2]),
         SYSCALL_WRITE => sys_write(args[0], args[1] as *const u8, args[2]),
+        SYSCALL_PPOLL => sys_ppoll(args[0] as *mut PollFd, args[1], args[2] as isize),
+        SYSCALL_SIGNALFD => sys_signalfd(args[0] as isize, args[1] as u32, args[2]),
         SYSCALL_EXIT => sys_exit(args[0] as i32),
+        SYSCALL_NANOSLEEP => sys_nanosleep(args[0] as *const TimeSpec, args[1] as *mut TimeSpec),
+        SYSCALL_CLOCK_NANOSLEEP => sys_clock_nanosleep(
+            args[0],
+            args[1],
+            args[2] as *const TimeSpec,
+            args[3] as *mut TimeSpec,
+        ),
+        SYSCALL_SYSLOG => sys_dmesg(args[0], args[1] as *mut u8, args[2]),
         SYSCALL_YIELD => sys_yield(),
-        SYSCALL_GET_TIME => sys_get_time(args[0] as *mut TimeVal, args[1]),
+        SYSCALL_KILL => sys_kill(args[0], args[1]),
+        SYSCALL_SIGPROCMASK => sys_sigprocmask(args[0] as u32),
+        SYSCALL_SETPGID => sys_setpgid(args[0], args[1]),
+        SYSCALL_GETPGID => sys_getpgid(args[0]),
+        SYSCALL_GET_TIME => sys_get_time(args[0] as *mut TimeVal, args[1] as *mut TimeZone),
+        SYSCALL_GETPID => sys_getpid(),
+        SYSCALL_SPAWN => sys_spawn(args[0] as *const u8),
+        SYSCALL_WAITPID => sys_waitpid(args[0] as isize, args[1] as *mut i32),
+        SYSCALL_TEST_REPORT => sys_test_report(args[0] as *const u8, args[1]),
+        SYSCALL_PRCTL => sys_prctl(args[0], args[1]),
+        SYSCALL_CAPGET => sys_capget(),
+        SYSCALL_CAPSET => sys_capset(args[0] as u32),
+        SYSCALL_PERSONALITY => sys_personality(args[0]),
+        SYSCALL_GETUID => sys_getuid(),
+        SYSCALL_GETGID => sys_getgid(),
+        SYSCALL_SETUID => sys_setuid(args[0] as u32),
+        SYSCALL_SETGID => sys_setgid(args[0] as u32),
+        SYSCALL_SEMGET => sys_semget(args[0], args[1], args[2] as u32),
+        SYSCALL_SEMCTL => sys_semctl(args[0], args[1], args[2], args[3]),
+        SYSCALL_SEMOP => sys_semop(args[0], args[1] as *const SemBuf, args[2]),
+        SYSCALL_SOCKET => sys_socket(args[0] as u32, args[1] as u32, args[2] as u32),
+        SYSCALL_BIND => sys_bind(args[0], args[1] as *const SockAddrIn, args[2]),
+        SYSCALL_LISTEN => sys_listen(args[0], args[1]),
+        SYSCALL_ACCEPT => sys_accept(args[0], args[1] as *mut SockAddrIn, args[2] as *mut u32),
+        SYSCALL_CONNECT => sys_connect(args[0], args[1] as *const SockAddrIn, args[2]),
+        SYSCALL_SENDTO => sys_sendto(
+            args[0],
+            args[1] as *const u8,
+            args[2],
+            args[3] as u32,
+            args[4] as *const SockAddrIn,
+            args[5],
+        ),
+        SYSCALL_RECVFROM => sys_recvfrom(
+            args[0],
+            args[1] as *mut u8,
+            args[2],
+            args[3] as u32,
+            args[4] as *mut SockAddrIn,
+            args[5] as *mut u32,
+        ),
+        SYSCALL_SETSOCKOPT => sys_setsockopt(
+            args[0],
+            args[1] as u32,
+            args[2] as u32,
+            args[3] as *const u8,
+            args[4],
+        ),
+        SYSCALL_GETSOCKOPT => sys_getsockopt(
+            args[0],
+            args[1] as u32,
+            args[2] as u32,
+            args[3] as *mut u8,
+            args[4] as *mut u32,
+        ),
+        SYSCALL_SHUTDOWN_SOCKET => sys_shutdown_socket(args[0], args[1] as u32),
         SYSCALL_MMAP => sys_mmap(args[0], args[1], args[2]),
         SYSCALL_MUNMAP => sys_munmap(args[0], args[1]),
+        SYSCALL_MPROTECT => sys_mprotect(args[0], args[1], args[2]),
+        SYSCALL_MSYNC => sys_msync(args[0], args[1], args[2]),
+        SYSCALL_MINCORE => sys_mincore(args[0], args[1], args[2] as *mut u8),
+        SYSCALL_MEMORY_PRESSURE_FD => sys_memory_pressure_fd(args[0]),
+        SYSCALL_MEMORY_WATERMARKS => sys_memory_watermarks(args[0], args[1]),
+        SYSCALL_COMPACT_MEMORY => sys_compact_memory(),
+        SYSCALL_MEMORY_HOT_ADD => sys_memory_hot_add(args[0], args[1]),
+        SYSCALL_ALLOW_WX => sys_allow_wx(args[0] != 0),
+        SYSCALL_SANDBOX => sys_sandbox(args[0], args[1] as *const SandboxProfile),
+        SYSCALL_AUDIT_READ => sys_audit_read(args[0], args[1] as *mut u8, args[2]),
+        SYSCALL_ACCT_READ => sys_acct_read(args[0], args[1] as *mut u8, args[2]),
+        SYSCALL_PROCESS_VM_READV => sys_process_vm_readv(
+            args[0],
+            args[1] as *const IoVec,
+            args[2],
+            args[3] as *const IoVec,
+            args[4],
+            args[5],
+        ),
+        SYSCALL_PROCESS_VM_WRITEV => sys_process_vm_writev(
+            args[0],
+            args[1] as *const IoVec,
+            args[2],
+            args[3] as *const IoVec,
+            args[4],
+            args[5],
+        ),
+        SYSCALL_PRLIMIT => sys_prlimit(
+            args[0],
+            args[1],
+            args[2] as *const RLimit,
+            args[3] as *mut RLimit,
+        ),
+        SYSCALL_GETRANDOM => sys_getrandom(args[0] as *mut u8, args[1], args[2] as u32),
+        SYSCALL_SCHED_GETSCHEDULER => sys_sched_getscheduler(args[0]),
+        SYSCALL_SCHED_GETPARAM => sys_sched_getparam(args[0], args[1] as *mut SchedParam),
+        SYSCALL_SCHED_RR_GET_INTERVAL => {
+            sys_sched_rr_get_interval(args[0], args[1] as *mut TimeSpec)
+        }
         SYSCALL_SET_PRIORITY => sys_set_priority(args[0] as isize),
         SYSCALL_TASK_INFO => sys_task_info(args[0] as *mut TaskInfo),
-        _ => panic!("Unsupported syscall_id: {}", syscall_id),
+        SYSCALL_TASK_INFO_V2 => sys_task_info_v2(args[0] as *mut TaskInfoV2, args[1]),
+        SYSCALL_TASK_USAGE => sys_task_usage(args[0] as *mut TaskUsageInfo, args[1]),
+        SYSCALL_RENICE => sys_renice(args[0], args[1] as isize),
+        SYSCALL_LOG_LEVEL => sys_log_level(args[0] as *const u8, args[1] as isize),
+        SYSCALL_FRAMEBUFFER => sys_framebuffer(),
+        SYSCALL_FRAMEBUFFER_FLUSH => sys_framebuffer_flush(
+            args[0] as u32,
+            args[1] as u32,
+            args[2] as u32,
+            args[3] as u32,
+        ),
+        SYSCALL_FRAMEBUFFER_INFO => sys_framebuffer_info(args[0] as *mut FbInfo),
+        SYSCALL_SHUTDOWN => sys_shutdown(),
+        SYSCALL_REBOOT => sys_reboot(args[0]),
+        SYSCALL_WATCHDOG => sys_watchdog(args[0]),
+        SYSCALL_SUSPEND => sys_suspend(),
+        SYSCALL_PING => sys_ping(args[0] as u32, args[1] as u16, args[2]),
+        SYSCALL_DHCP => sys_dhcp(),
+        SYSCALL_GETHOSTBYNAME => {
+            sys_gethostbyname(args[0] as *const u8, args[1] as *mut [u8; 4])
+        }
+        SYSCALL_PROFILE => sys_profile(args[0]),
+        SYSCALL_PROFILE_READ => sys_profile_read(args[0], args[1] as *mut ProfileSample, args[2]),
+        SYSCALL_TRACE_CTL => sys_trace_ctl(args[0] as u32),
+        SYSCALL_TRACE_READ => sys_trace_read(args[0] as *mut u8, args[1]),
+        SYSCALL_KCOV => sys_kcov(args[0], args[1]),
+        SYSCALL_FAULT_INJECT => sys_fault_inject(args[0], args[1] as *const _),
+        SYSCALL_HEAP_SITES => sys_heap_sites(args[0] as *mut _, args[1]),
+        SYSCALL_PERF_READ => sys_perf_read(args[0] as *mut PerfCounters),
+        SYSCALL_CGROUP_CREATE => sys_cgroup_create(args[0] as *const u8, args[1]),
+        SYSCALL_CGROUP_ATTACH => sys_cgroup_attach(args[0], args[1]),
+        SYSCALL_CGROUP_SET_WEIGHT => sys_cgroup_set_weight(args[0], args[1]),
+        SYSCALL_CGROUP_REMOVE => sys_cgroup_remove(args[0]),
+        SYSCALL_CHECKPOINT => sys_checkpoint(args[0], args[1] as *mut u8, args[2]),
+        SYSCALL_RESTORE => sys_restore(args[0] as *const u8, args[1]),
+        _ => {
+            warn!("[kernel] Unsupported syscall_id: {}", syscall_id);
+            -1
+        }
     }
 }
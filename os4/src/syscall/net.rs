@@ -0,0 +1,477 @@
+//! Socket syscalls
+//!
+//! IPv4 UDP and TCP sockets, plus a ping, DHCP configuration and name
+//! lookup. Addresses use the Linux
+//! `sockaddr_in` layout.
+
+use super::errno::{new_fd, wait_error, EAGAIN, EFAULT, EINPROGRESS};
+use super::TimeVal;
+use crate::fs::{wait_ready, File, PollEvents, SocketOptions, TcpSocket, UdpSocket};
+use crate::mm::{read_user_str, UserPtr, UserSlice};
+use crate::net::{
+    dhcp_leased, dhcp_running, dhcp_start, dns_answer, dns_cached, dns_query, is_local_ip,
+    ping_reply, ping_send, udp_bind, udp_unbind, watch, Endpoint, Ipv4Addr,
+};
+use crate::random::fill_random;
+use crate::task::{
+    block_until, current_add_file, current_file, current_is_privileged, current_signal_interrupted,
+    current_task_id, current_user_token, WaitQueue,
+};
+use crate::timer::{deadline_after_ms, get_time, get_time_us};
+use alloc::sync::Arc;
+use core::mem::size_of;
+
+const AF_INET: u16 = 2;
+const SOCK_STREAM: u32 = 1;
+const SOCK_DGRAM: u32 = 2;
+const SOCK_NONBLOCK: u32 = 0o4000;
+const SOCK_CLOEXEC: u32 = 0o2000000;
+/// `recvfrom` flag: do not wait for a datagram
+const MSG_DONTWAIT: u32 = 0x40;
+const SHUT_RD: u32 = 0;
+const SHUT_WR: u32 = 1;
+const SHUT_RDWR: u32 = 2;
+const SOL_SOCKET: u32 = 1;
+const SO_REUSEADDR: u32 = 2;
+const SO_RCVTIMEO: u32 = 20;
+/// payload carried by `ping` requests
+const PING_DATA: &[u8] = b"rCore ping";
+/// time between DNS queries, and queries sent before giving up
+const DNS_RETRY_MS: usize = 1000;
+const DNS_TRIES: usize = 3;
+/// longest host name `sys_gethostbyname` reads, as DNS allows
+const MAX_HOST_NAME_LEN: usize = 253;
+
+/// An IPv4 socket address, layout of `struct sockaddr_in`
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct SockAddrIn {
+    pub family: u16,
+    /// big-endian
+    pub port: u16,
+    pub addr: [u8; 4],
+    pub zero: [u8; 8],
+}
+
+/// Read the address at `addr`, which must be `addrlen` bytes of AF_INET.
+/// Fails with -1, or `-EFAULT` if it is not readable.
+fn read_sockaddr(addr: *const SockAddrIn, addrlen: usize) -> Result<(Ipv4Addr, u16), isize> {
+    let addr = UserPtr::new(current_user_token(), addr);
+    if addr.is_null() || addrlen < size_of::<SockAddrIn>() {
+        return Err(-1);
+    }
+    let addr = addr.read().ok_or(-EFAULT)?;
+    if addr.family != AF_INET {
+        return Err(-1);
+    }
+    Ok((Ipv4Addr(addr.addr), u16::from_be(addr.port)))
+}
+
+/// Store `ip`:`port` at `addr` and its size at `addrlen`, unless `addr` is
+/// null. Returns false if they are not writable.
+fn write_sockaddr(addr: *mut SockAddrIn, addrlen: *mut u32, ip: Ipv4Addr, port: u16) -> bool {
+    let token = current_user_token();
+    let addr = UserPtr::new(token, addr);
+    if addr.is_null() {
+        return true;
+    }
+    let sockaddr = SockAddrIn {
+        family: AF_INET,
+        port: port.to_be(),
+        addr: ip.0,
+        zero: [0; 8],
+    };
+    let addrlen = UserPtr::new(token, addrlen);
+    addr.write(sockaddr) && (addrlen.is_null() || addrlen.write(size_of::<SockAddrIn>() as u32))
+}
+
+fn with_udp_socket(fd: usize, f: impl FnOnce(&UdpSocket) -> isize) -> isize {
+    match current_file(fd) {
+        Some(file) => match file.as_udp_socket() {
+            Some(socket) => f(socket),
+            None => -1,
+        },
+        None => -1,
+    }
+}
+
+fn with_tcp_socket(fd: usize, f: impl FnOnce(&TcpSocket) -> isize) -> isize {
+    match current_file(fd) {
+        Some(file) => match file.as_tcp_socket() {
+            Some(socket) => f(socket),
+            None => -1,
+        },
+        None => -1,
+    }
+}
+
+fn with_socket_options(fd: usize, f: impl FnOnce(&mut SocketOptions) -> isize) -> isize {
+    let file = match current_file(fd) {
+        Some(file) => file,
+        None => return -1,
+    };
+    if let Some(socket) = file.as_udp_socket() {
+        f(&mut socket.options())
+    } else if let Some(socket) = file.as_tcp_socket() {
+        f(&mut socket.options())
+    } else {
+        -1
+    }
+}
+
+/// Create a socket. `SOCK_NONBLOCK` may be or-ed into `socket_type`.
+pub fn sys_socket(domain: u32, socket_type: u32, _protocol: u32) -> isize {
+    let flags = socket_type & (SOCK_NONBLOCK | SOCK_CLOEXEC);
+    let nonblocking = flags & SOCK_NONBLOCK != 0;
+    if domain != AF_INET as u32 {
+        return -1;
+    }
+    match socket_type & !flags {
+        SOCK_DGRAM => new_fd(current_add_file(Arc::new(UdpSocket::new(nonblocking)))),
+        SOCK_STREAM => new_fd(current_add_file(Arc::new(TcpSocket::new(nonblocking)))),
+        _ => -1,
+    }
+}
+
+/// Bind socket `fd` to the port of `addr`, an ephemeral one if it is 0. The
+/// address part must be one of ours or 0.0.0.0.
+pub fn sys_bind(fd: usize, addr: *const SockAddrIn, addrlen: usize) -> isize {
+    let (ip, port) = match read_sockaddr(addr, addrlen) {
+        Ok(addr) => addr,
+        Err(err) => return err,
+    };
+    if ip != Ipv4Addr::UNSPECIFIED && !is_local_ip(ip) {
+        return -1;
+    }
+    let file = match current_file(fd) {
+        Some(file) => file,
+        None => return -1,
+    };
+    let bound = if let Some(socket) = file.as_udp_socket() {
+        socket.bind(port)
+    } else if let Some(socket) = file.as_tcp_socket() {
+        socket.bind(port)
+    } else {
+        false
+    };
+    if bound {
+        0
+    } else {
+        -1
+    }
+}
+
+/// Accept connections on TCP socket `fd`, at most `backlog` of them waiting.
+pub fn sys_listen(fd: usize, backlog: usize) -> isize {
+    with_tcp_socket(fd, |socket| if socket.listen(backlog) { 0 } else { -1 })
+}
+
+/// Take a connection from listening socket `fd` as a new descriptor, storing
+/// the peer's address in `addr` unless that is null. Fails with `-EAGAIN`
+/// if none is waiting and the socket is non-blocking or its receive timeout
+/// passed.
+pub fn sys_accept(fd: usize, addr: *mut SockAddrIn, addrlen: *mut u32) -> isize {
+    with_tcp_socket(fd, |socket| {
+        if let Err(err) = wait_ready(socket, PollEvents::POLLIN) {
+            return wait_error(err);
+        }
+        accept(socket, addr, addrlen)
+    })
+}
+
+fn accept(socket: &TcpSocket, addr: *mut SockAddrIn, addrlen: *mut u32) -> isize {
+    match socket.accept() {
+        Some((connection, ip, port)) => {
+            if !write_sockaddr(addr, addrlen, ip, port) {
+                return -EFAULT;
+            }
+            new_fd(current_add_file(Arc::new(connection)))
+        }
+        None => -1,
+    }
+}
+
+/// Connect TCP socket `fd` to `addr`, waiting for the handshake unless the
+/// socket is non-blocking, in which case it fails with `-EINPROGRESS` while
+/// the handshake goes on.
+pub fn sys_connect(fd: usize, addr: *const SockAddrIn, addrlen: usize) -> isize {
+    let (ip, port) = match read_sockaddr(addr, addrlen) {
+        Ok(addr) => addr,
+        Err(err) => return err,
+    };
+    with_tcp_socket(fd, |socket| {
+        if socket.connect(ip, port) {
+            0
+        } else if socket.nonblocking() && socket.connecting() {
+            -EINPROGRESS
+        } else {
+            -1
+        }
+    })
+}
+
+/// Shut down the reading (`SHUT_RD`), writing (`SHUT_WR`) or both sides
+/// (`SHUT_RDWR`) of TCP socket `fd`.
+pub fn sys_shutdown_socket(fd: usize, how: u32) -> isize {
+    let (read, write) = match how {
+        SHUT_RD => (true, false),
+        SHUT_WR => (false, true),
+        SHUT_RDWR => (true, true),
+        _ => return -1,
+    };
+    with_tcp_socket(fd, |socket| match socket.shutdown(read, write) {
+        true => 0,
+        false => -1,
+    })
+}
+
+/// Send `len` bytes at `buf` as one datagram to `addr`.
+pub fn sys_sendto(
+    fd: usize,
+    buf: *const u8,
+    len: usize,
+    _flags: u32,
+    addr: *const SockAddrIn,
+    addrlen: usize,
+) -> isize {
+    let (ip, port) = match read_sockaddr(addr, addrlen) {
+        Ok(addr) => addr,
+        Err(err) => return err,
+    };
+    let data = match UserSlice::new(current_user_token(), buf, len).read() {
+        Some(data) => data,
+        None => return -EFAULT,
+    };
+    with_udp_socket(fd, |socket| {
+        if socket.send_to(&data, ip, port) {
+            len as isize
+        } else {
+            -1
+        }
+    })
+}
+
+/// Receive one datagram into `buf`, truncated to `len` bytes, and store its
+/// source in `addr` unless that is null. Waits unless the socket is
+/// non-blocking or `MSG_DONTWAIT` is given; `-EAGAIN` if there was nothing
+/// to receive then or before the receive timeout passed.
+pub fn sys_recvfrom(
+    fd: usize,
+    buf: *mut u8,
+    len: usize,
+    flags: u32,
+    addr: *mut SockAddrIn,
+    addrlen: *mut u32,
+) -> isize {
+    with_udp_socket(fd, |socket| {
+        let dont_wait = flags & MSG_DONTWAIT != 0;
+        if dont_wait && socket.poll(PollEvents::POLLIN).is_empty() {
+            return -EAGAIN;
+        }
+        if let Err(err) = wait_ready(socket, PollEvents::POLLIN) {
+            return wait_error(err);
+        }
+        let datagram = match socket.recv_from(dont_wait) {
+            Some(datagram) => datagram,
+            None => return -1,
+        };
+        let data = &datagram.data[..len.min(datagram.data.len())];
+        if !UserSlice::new(current_user_token(), buf, data.len()).write(data)
+            || !write_sockaddr(addr, addrlen, datagram.src, datagram.src_port)
+        {
+            return -EFAULT;
+        }
+        data.len() as isize
+    })
+}
+
+/// Set socket option `optname` of `level` from the `optlen` bytes at
+/// `optval`. Supported are `SO_REUSEADDR`, an `int`, and `SO_RCVTIMEO`, a
+/// `struct timeval` where zero means no timeout, both at `SOL_SOCKET`.
+pub fn sys_setsockopt(
+    fd: usize,
+    level: u32,
+    optname: u32,
+    optval: *const u8,
+    optlen: usize,
+) -> isize {
+    if level != SOL_SOCKET || optval.is_null() {
+        return -1;
+    }
+    let token = current_user_token();
+    with_socket_options(fd, |options| match optname {
+        SO_REUSEADDR if optlen >= size_of::<i32>() => {
+            match UserPtr::new(token, optval as *const i32).read() {
+                Some(reuse_addr) => options.reuse_addr = reuse_addr != 0,
+                None => return -EFAULT,
+            }
+            0
+        }
+        SO_RCVTIMEO if optlen >= size_of::<TimeVal>() => {
+            let timeout = match UserPtr::new(token, optval as *const TimeVal).read() {
+                Some(timeout) => timeout,
+                None => return -EFAULT,
+            };
+            let timeout_ms = timeout.sec * 1000 + timeout.usec / 1000;
+            options.recv_timeout = match timeout_ms {
+                0 if timeout.usec == 0 => None,
+                // shorter than a millisecond still times out
+                0 => Some(1),
+                ms => Some(ms),
+            };
+            0
+        }
+        _ => -1,
+    })
+}
+
+/// Store socket option `optname` of `level` at `optval`, and its size at
+/// `optlen`, which must give at least that much room.
+pub fn sys_getsockopt(
+    fd: usize,
+    level: u32,
+    optname: u32,
+    optval: *mut u8,
+    optlen: *mut u32,
+) -> isize {
+    if level != SOL_SOCKET || optval.is_null() || optlen.is_null() {
+        return -1;
+    }
+    let token = current_user_token();
+    let optlen = UserPtr::new(token, optlen as *const u32);
+    let room = match optlen.read() {
+        Some(room) => room as usize,
+        None => return -EFAULT,
+    };
+    with_socket_options(fd, |options| {
+        let (written, len) = match optname {
+            SO_REUSEADDR if room >= size_of::<i32>() => {
+                let reuse_addr = options.reuse_addr as i32;
+                let optval = UserPtr::new(token, optval as *const i32);
+                (optval.write(reuse_addr), size_of::<i32>())
+            }
+            SO_RCVTIMEO if room >= size_of::<TimeVal>() => {
+                let timeout_ms = options.recv_timeout.unwrap_or(0);
+                let timeout = TimeVal {
+                    sec: timeout_ms / 1000,
+                    usec: timeout_ms % 1000 * 1000,
+                };
+                let optval = UserPtr::new(token, optval as *const TimeVal);
+                (optval.write(timeout), size_of::<TimeVal>())
+            }
+            _ => return -1,
+        };
+        if !written || !optlen.write(len as u32) {
+            return -EFAULT;
+        }
+        0
+    })
+}
+
+/// Send ICMP echo request `seq` to `ip`, given as a big-endian number (so
+/// 10.0.2.2 is `0x0a00_0202`), and wait up to `timeout_ms` for the reply.
+/// Returns the round trip time in microseconds, or -1 if the request could
+/// not be sent, timed out or was interrupted by a signal.
+pub fn sys_ping(ip: u32, seq: u16, timeout_ms: usize) -> isize {
+    let dst = Ipv4Addr(ip.to_be_bytes());
+    let id = current_task_id() as u16;
+    let start = get_time_us();
+    let deadline = deadline_after_ms(timeout_ms);
+    let replies = Arc::new(WaitQueue::new());
+    watch(Endpoint::Echo(id), &replies);
+    if !ping_send(dst, id, seq, PING_DATA) {
+        return -1;
+    }
+    loop {
+        if ping_reply(id, seq).is_some() {
+            return (get_time_us() - start) as isize;
+        }
+        if get_time() >= deadline || current_signal_interrupted() {
+            return -1;
+        }
+        block_until(&replies, Some(deadline));
+    }
+}
+
+/// Reconfigure the interface by DHCP and wait for the exchange to end.
+/// Returns 0 once a lease is bound, -1 if the caller is not privileged or
+/// there is no network device, or if no server answered, in which case the
+/// previous address is kept. The wait itself cannot be interrupted: the
+/// interface has no address until it ends.
+pub fn sys_dhcp() -> isize {
+    if !current_is_privileged() {
+        return -1;
+    }
+    let done = Arc::new(WaitQueue::new());
+    watch(Endpoint::Dhcp, &done);
+    if !dhcp_start() {
+        return -1;
+    }
+    while dhcp_running() {
+        done.block_current_and_run_next();
+    }
+    if dhcp_leased() {
+        0
+    } else {
+        -1
+    }
+}
+
+/// Look up the IPv4 address of the NUL-terminated host name at `name` and
+/// store it at `addr`, most significant byte first. `localhost` and dotted
+/// quads are answered directly, anything else by the name server, with the
+/// answer cached. Returns 0, or -1 if the name has no address, the server
+/// did not answer or the wait was interrupted by a signal, and `-EFAULT` if
+/// `addr` is not writable.
+pub fn sys_gethostbyname(name: *const u8, addr: *mut [u8; 4]) -> isize {
+    let token = current_user_token();
+    let name = match read_user_str(token, name, MAX_HOST_NAME_LEN) {
+        Some(name) => name,
+        None => return -1,
+    };
+    let ip = match dns_cached(&name) {
+        Some(ip) => Some(ip),
+        None => resolve(&name),
+    };
+    match ip {
+        Some(ip) => {
+            if !UserPtr::new(token, addr as *const [u8; 4]).write(ip.0) {
+                return -EFAULT;
+            }
+            0
+        }
+        None => -1,
+    }
+}
+
+/// Ask the name server for `name`, retrying until it answers.
+fn resolve(name: &str) -> Option<Ipv4Addr> {
+    let port = udp_bind(0)?;
+    let mut id = [0u8; 2];
+    fill_random(&mut id);
+    let id = u16::from_ne_bytes(id);
+    let answers = Arc::new(WaitQueue::new());
+    watch(Endpoint::Udp(port), &answers);
+    let mut answer = None;
+    'tries: for _ in 0..DNS_TRIES {
+        if !dns_query(port, id, name) {
+            break;
+        }
+        let deadline = deadline_after_ms(DNS_RETRY_MS);
+        loop {
+            if let Some(ip) = dns_answer(port, id, name) {
+                answer = ip;
+                break 'tries;
+            }
+            if current_signal_interrupted() {
+                break 'tries;
+            }
+            if get_time() >= deadline {
+                break;
+            }
+            block_until(&answers, Some(deadline));
+        }
+    }
+    udp_unbind(port);
+    answer
+}
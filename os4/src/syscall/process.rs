@@ -3,19 +3,96 @@
 use crate::config::MAX_SYSCALL_NUM;
 use crate::task::{exit_current_and_run_next, suspend_current_and_run_next, TaskStatus, TASK_MANAGER, 
         get_task_info_inner, sys_mmap_inner, sys_munmap_inner};
-use crate::timer::get_time_us;
-use crate::mm::translated_byte_buffer;
+use crate::timer::{get_time, get_time_us};
+use crate::task::sleep_until;
 use crate::task::current_user_token;
+use crate::task::{
+    current_is_privileged, current_task_id, send_signal, set_current_signal_mask, SignalFlags,
+};
+use crate::task::{current_task_counters, task_user_token};
+use crate::task::{current_credentials, current_may_access, set_current_credentials};
+use crate::task::{group_exists, set_task_pgid, task_parent, task_pgid};
+use crate::task::{cgroup_create, cgroup_remove, cgroup_set_weight, set_task_cgroup};
+use crate::task::{checkpoint_task, restore_task};
+use crate::task::{current_personality, set_current_personality, Personality};
+use crate::task::{current_is_anonymous, current_mincore, current_mprotect, set_current_allow_wx};
+use crate::task::{current_capabilities, restrict_current_capabilities, Capabilities};
+use crate::task::{current_may_grow, set_task_limit, task_limit, RLimit, Resource};
+use crate::task::set_task_sandbox;
+use crate::task::set_current_priority;
+use crate::task::{set_task_nice, task_nice, task_priority};
+use crate::cmdline::{scheduler, Scheduler};
+use crate::timer::TICKS_PER_SEC;
+use crate::config::{MAX_NICE, MIN_NICE};
+use crate::task::{current_task_name, set_current_task_name};
+use crate::config::TASK_NAME_LEN;
+use crate::config::CLOCK_FREQ;
+use crate::task::task_usage;
+use crate::mm::SandboxProfile;
+use super::errno::{EFAULT, EINTR, EINVAL, ENOMEM, ENOSYS, EPERM, ESRCH};
+use crate::audit::{self, AuditEvent};
+use crate::mm::{read_user_str, UserPtr, UserSlice};
+use crate::loader::find_app;
+use crate::task::{collect_child, spawn, wait_child};
+use crate::mm::{hot_add_memory, set_watermarks, Watermarks};
+use crate::task::compact_memory;
+use alloc::vec::Vec;
 use crate::mm::PageTable;
 use crate::mm::VirtAddr;
 
+/// longest application name `sys_spawn` reads
+const MAX_APP_NAME_LEN: usize = 64;
+/// longest test name `sys_test_report` reads
+const MAX_TEST_NAME_LEN: usize = 128;
+/// longest control group path taken, without the NUL
+const MAX_CGROUP_PATH_LEN: usize = 128;
+
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct TimeVal {
     pub sec: usize,
     pub usec: usize,
 }
 
+/// A time as the sleep syscalls take it, as Linux `struct timespec`
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct TimeSpec {
+    pub sec: usize,
+    pub nsec: usize,
+}
+
+const NSEC_PER_SEC: usize = 1_000_000_000;
+
+impl TimeSpec {
+    /// In timer cycles, or None if `nsec` is not below a second.
+    fn to_cycles(self) -> Option<usize> {
+        if self.nsec >= NSEC_PER_SEC {
+            return None;
+        }
+        let cycles = (self.sec as u64)
+            .saturating_mul(CLOCK_FREQ as u64)
+            .saturating_add(self.nsec as u64 * CLOCK_FREQ as u64 / NSEC_PER_SEC as u64);
+        Some(cycles.min(usize::MAX as u64) as usize)
+    }
+    fn from_cycles(cycles: usize) -> Self {
+        Self {
+            sec: cycles / CLOCK_FREQ,
+            nsec: ((cycles % CLOCK_FREQ) as u64 * NSEC_PER_SEC as u64 / CLOCK_FREQ as u64) as usize,
+        }
+    }
+}
+
+/// The time zone `sys_get_time` reports, as Linux `struct timezone`
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct TimeZone {
+    /// minutes west of Greenwich
+    pub minuteswest: i32,
+    /// type of daylight saving time correction, obsolete
+    pub dsttime: i32,
+}
+
 #[derive(Clone, Copy)]
 pub struct TaskInfo {
     pub status: TaskStatus,
@@ -24,8 +101,13 @@ pub struct TaskInfo {
 }
 
 pub fn sys_exit(exit_code: i32) -> ! {
-    info!("[kernel] Application exited with code {}", exit_code);
-    exit_current_and_run_next();
+    info!(
+        "[kernel] Application {} (pid {}) exited with code {}",
+        current_task_name(),
+        current_task_id(),
+        exit_code
+    );
+    exit_current_and_run_next(exit_code);
     panic!("Unreachable in sys_exit!");
 }
 
@@ -37,98 +119,905 @@ pub fn sys_yield() -> isize {
 
 
 
-// YOUR JOB: 引入虚地址后重写 sys_get_time
-pub fn sys_get_time(_ts: *mut TimeVal, _tz: usize) -> isize {
-    // let page_table = PageTable::from_token(current_user_token());
-    // let mut start = _tz as usize;
-    // let start_va = VirtAddr::from(start);
-    // let   end_va = VirtAddr::from(start + core::mem::size_of::<TimeVal>()) ;
-    // let mut vpn = start_va.floor();
-    // let ppn = page_table.translate(vpn).unwrap().ppn();
-    // let ts = &mut ppn.get_bytes_array()[start_va.page_offset()..end_va.page_offset()];
-    // let ts = ts.as_mut_ptr() as *mut TimeVal;
-    // info!("tv virt ptr: {:?}", ts);
-    // let us = get_time_us();
-    // unsafe {
-    //     *ts = TimeVal {
-    //         sec: us / 1_000_000,
-    //         usec: us % 1_000_000,
-    //     };
-    // }
-
-    
-    let mut v = translated_byte_buffer( current_user_token(), _ts as *const u8, core::mem::size_of::<TimeVal>());
+/// Write the time since boot to `ts` and the time zone, always UTC as
+/// there is no clock to set, to `tz`, either of which may be null, as with
+/// Linux `gettimeofday`. Fails with `-EFAULT` if either is not writable.
+pub fn sys_get_time(ts: *mut TimeVal, tz: *mut TimeZone) -> isize {
+    let token = current_user_token();
     let us = get_time_us();
-    let ts;
-    if v.len() == 1 {
-        let a = v[0].as_mut_ptr();
-        //info!("a as ptr: {:?}", a);
-        let a: *mut TimeVal  = unsafe { core::mem::transmute(a) };
-        ts = a as *mut TimeVal;
-        
-        unsafe {
-            *ts = TimeVal {
-                sec: us / 1_000_000,
-                usec: us % 1_000_000,
-            };
-        }
-    }else {
-        error!("cross two page !!!!!");
+    let time = TimeVal {
+        sec: us / 1_000_000,
+        usec: us % 1_000_000,
+    };
+    let ts = UserPtr::new(token, ts);
+    if !ts.is_null() && !ts.write(time) {
+        return -EFAULT;
+    }
+    let utc = TimeZone {
+        minuteswest: 0,
+        dsttime: 0,
+    };
+    let tz = UserPtr::new(token, tz);
+    if !tz.is_null() && !tz.write(utc) {
+        return -EFAULT;
     }
-
-    
     0
 }
 
+/// clocks `sys_clock_nanosleep` takes; both count from boot, as there is
+/// no real-time clock to tell the date
+const CLOCK_REALTIME: usize = 0;
+const CLOCK_MONOTONIC: usize = 1;
+/// `sys_clock_nanosleep` flag: `req` is a time to sleep until
+const TIMER_ABSTIME: usize = 1;
+
+/// Sleep for `req`, as `sys_clock_nanosleep` does on the monotonic clock.
+pub fn sys_nanosleep(req: *const TimeSpec, rem: *mut TimeSpec) -> isize {
+    sys_clock_nanosleep(CLOCK_MONOTONIC, 0, req, rem)
+}
+
+/// Sleep for `req` on `clock`, or until the clock reads `req` if `flags`
+/// has `TIMER_ABSTIME`, so that periodic tasks do not drift. A signal ends
+/// the sleep early with `-EINTR`, and, for a relative sleep, the time left
+/// is written to `rem` unless it is null. Fails with `-EINVAL` for another
+/// clock or more than a second of nanoseconds, `-EFAULT` for bad pointers.
+pub fn sys_clock_nanosleep(clock: usize, flags: usize, req: *const TimeSpec, rem: *mut TimeSpec) -> isize {
+    if clock != CLOCK_REALTIME && clock != CLOCK_MONOTONIC {
+        return -EINVAL;
+    }
+    let token = current_user_token();
+    let req = match UserPtr::new(token, req).read() {
+        Some(req) => req,
+        None => return -EFAULT,
+    };
+    let cycles = match req.to_cycles() {
+        Some(cycles) => cycles,
+        None => return -EINVAL,
+    };
+    let absolute = flags & TIMER_ABSTIME != 0;
+    let deadline = if absolute {
+        cycles
+    } else {
+        get_time().saturating_add(cycles)
+    };
+    if sleep_until(deadline) {
+        return 0;
+    }
+    let rem = UserPtr::new(token, rem);
+    if !absolute && !rem.is_null() && !rem.write(TimeSpec::from_cycles(deadline.saturating_sub(get_time()))) {
+        return -EFAULT;
+    }
+    -EINTR
+}
+
 // CLUE: 从 ch4 开始不再对调度算法进行测试~
-pub fn sys_set_priority(_prio: isize) -> isize {
-    -1
+/// Set the stride scheduling priority of the current task, which only
+/// matters with `sched=stride`. Returns `prio`, or -1 if it is below 2.
+pub fn sys_set_priority(prio: isize) -> isize {
+    if prio < 2 {
+        return -1;
+    }
+    set_current_priority(prio as usize);
+    prio
 }
 
 // YOUR JOB: 扩展内核以实现 sys_mmap 和 sys_munmap
-pub fn sys_mmap(_start: usize, _len: usize, _port: usize) -> isize {
-    sys_mmap_inner(_start, _len, _port)
-    
+pub fn sys_mmap(start: usize, len: usize, port: usize) -> isize {
+    if !current_may_grow(len) {
+        return -ENOMEM;
+    }
+    let ret = sys_mmap_inner(start, len, port);
+    if ret == 0 && port & 0x4 != 0 {
+        audit::record(AuditEvent::MmapExec, [start, len]);
+    }
+    ret
 }
 
 pub fn sys_munmap(_start: usize, _len: usize) -> isize {
     sys_munmap_inner(_start, _len)
 }
 
+/// Change the permission of mapped pages, `port` being as in `sys_mmap`.
+/// Pages cannot be made writable and executable at once, see
+/// `sys_allow_wx`.
+pub fn sys_mprotect(start: usize, len: usize, port: usize) -> isize {
+    let ret = current_mprotect(start, len, port);
+    if ret == 0 && port & 0x4 != 0 {
+        audit::record(AuditEvent::MmapExec, [start, len]);
+    }
+    ret
+}
+
+/// Write to `vec` a byte for each page of `[start, start + len)`, whose
+/// lowest bit tells whether the page is resident: lazily mapped pages are
+/// not until first touched. `start` must be page aligned, and every page
+/// mapped, or this fails with `-EINVAL` and `-ENOMEM`.
+pub fn sys_mincore(start: usize, len: usize, vec: *mut u8) -> isize {
+    if !VirtAddr(start).aligned() {
+        return -EINVAL;
+    }
+    let residency = match current_mincore(start, len) {
+        Some(residency) => residency,
+        None => return -ENOMEM,
+    };
+    let token = current_user_token();
+    match UserSlice::new(token, vec, residency.len()).write(&residency) {
+        true => 0,
+        false => -EFAULT,
+    }
+}
+
+bitflags! {
+    /// `msync` flags, same values as Linux
+    struct MsyncFlags: usize {
+        const MS_ASYNC = 1;
+        const MS_INVALIDATE = 2;
+        const MS_SYNC = 4;
+    }
+}
+
+/// Write the modified pages of `[start, start + len)` back to the file
+/// they map, at once with `MS_SYNC` or later with `MS_ASYNC`. There being
+/// no files to map, no writeback is implemented: anonymous memory has
+/// nothing to write back, nor `MS_INVALIDATE` other mappings of the same
+/// file to update, so only the arguments are checked, as Linux does.
+/// `start` must be page aligned, `MS_SYNC` and `MS_ASYNC` not both given
+/// and every page mapped, or this fails with `-EINVAL` and `-ENOMEM`. A
+/// range with frames owned elsewhere, such as the framebuffer, which
+/// could only be synced with their owner, fails with `-ENOSYS`.
+pub fn sys_msync(start: usize, len: usize, flags: usize) -> isize {
+    match MsyncFlags::from_bits(flags) {
+        Some(flags) if !flags.contains(MsyncFlags::MS_ASYNC | MsyncFlags::MS_SYNC) => {}
+        _ => return -EINVAL,
+    }
+    if !VirtAddr(start).aligned() {
+        return -EINVAL;
+    }
+    if current_mincore(start, len).is_none() {
+        return -ENOMEM;
+    }
+    if !current_is_anonymous(start, len) {
+        return -ENOSYS;
+    }
+    0
+}
+
+/// Set the free frames below which memory is under low and critical
+/// pressure, see `sys_memory_pressure_fd`. `min` may not be above `low`.
+/// Privileged.
+pub fn sys_memory_watermarks(low: usize, min: usize) -> isize {
+    if !current_is_privileged() || !set_watermarks(Watermarks { low, min }) {
+        return -1;
+    }
+    0
+}
+
+/// Give the frame allocator the `len` bytes of physical memory at `paddr`,
+/// which was held back at boot as a hotpluggable bank or by `mem=`. Both must be multiples of the page size.
+/// Privileged.
+pub fn sys_memory_hot_add(paddr: usize, len: usize) -> isize {
+    if !current_is_privileged() || !hot_add_memory(paddr, len) {
+        return -1;
+    }
+    0
+}
+
+/// Compact physical memory, as writing `/proc/sys/vm/compact_memory` does
+/// in Linux, and return how many pages moved. Privileged.
+pub fn sys_compact_memory() -> isize {
+    if !current_is_privileged() {
+        return -1;
+    }
+    compact_memory() as isize
+}
+
+/// Let the current task (`allow`) or not map pages that are both writable
+/// and executable, which W^X forbids by default. It is the task's own
+/// choice, meant for JIT compilers.
+pub fn sys_allow_wx(allow: bool) -> isize {
+    set_current_allow_wx(allow);
+    0
+}
+
 // YOUR JOB: 引入虚地址后重写 sys_task_info
 
 
 pub fn sys_task_info(ti: *mut TaskInfo) -> isize {
-    //info!("call task info api");
-    //info!("ti virt ptr: {:?}", ti as usize);
-
-    // let page_table = PageTable::from_token(current_user_token());
-    // let mut start = ti as usize;
-    // let start_va = VirtAddr::from(start);
-    // let mut vpn = start_va.floor();
-    // let ppn = page_table.translate(vpn).unwrap().ppn();
-    // let ti = ppn.get_mut::<TaskInfo>() as *mut TaskInfo;
-    // println!("ti ptr: {:?}", ti as usize);
-    
-     
-    let ll = core::mem::size_of::<TaskInfo>();
-    info!("ll: {:?}", ll);
-    let mut v = translated_byte_buffer( current_user_token(), ti as *const u8, ll);
-    if v.len() == 1 {
-        //info!("len of task vec is 1 !");
-        let a = v[0].as_mut_ptr();
-        info!("taskinfo a ptr {:?}", a);
-        let ti: *mut TaskInfo  = unsafe { core::mem::transmute(a) };
-        //info!("taskinfo ti ptr {:?}", a);
-        //info!("before inner");
-        get_task_info_inner(ti);
-    }else {
-        error!("cross two page !!!!!");
-        panic!("!!!!");
-    }
-    
-    
-    //get_task_info_inner(ti) ;
-    
+    if !UserPtr::new(current_user_token(), ti).write(get_task_info_inner()) {
+        return -1;
+    }
+    0
+}
+
+/// [`TaskInfo`] with the counters of [`crate::task::TaskCounters`].
+///
+/// Fields are only ever appended: a caller passes the size of the struct it
+/// was built with and gets that prefix back.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct TaskInfoV2 {
+    /// [`TaskStatus`] as a number
+    pub status: usize,
+    pub syscall_times: [u32; MAX_SYSCALL_NUM],
+    pub time: usize,
+    pub minor_faults: usize,
+    pub major_faults: usize,
+    pub voluntary_switches: usize,
+    pub involuntary_switches: usize,
+    pub resident_pages: usize,
+    pub peak_resident_pages: usize,
+    pub nice: isize,
+    pub lazy_faults: usize,
+    pub cow_faults: usize,
+}
+
+/// Copy the first `size` bytes of the current task's [`TaskInfoV2`] to `ti`,
+/// returning how many were copied, or -1 if `ti` is not writable.
+pub fn sys_task_info_v2(ti: *mut TaskInfoV2, size: usize) -> isize {
+    let base = get_task_info_inner();
+    let (counters, resident_pages) = current_task_counters();
+    let info = TaskInfoV2 {
+        status: base.status as usize,
+        syscall_times: base.syscall_times,
+        time: base.time,
+        minor_faults: counters.minor_faults,
+        major_faults: counters.major_faults,
+        voluntary_switches: counters.voluntary_switches,
+        involuntary_switches: counters.involuntary_switches,
+        resident_pages,
+        peak_resident_pages: counters.peak_resident_pages,
+        nice: task_nice(current_task_id()).unwrap_or(0),
+        lazy_faults: counters.lazy_faults,
+        cow_faults: counters.cow_faults,
+    };
+    let len = size.min(core::mem::size_of::<TaskInfoV2>());
+    let bytes = unsafe { core::slice::from_raw_parts(&info as *const _ as *const u8, len) };
+    if !UserSlice::new(current_user_token(), ti as *const u8, len).write(bytes) {
+        return -1;
+    }
+    len as isize
+}
+
+/// One task as `sys_task_usage` reports it
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct TaskUsageInfo {
+    pub pid: usize,
+    /// nul-padded
+    pub name: [u8; TASK_NAME_LEN + 1],
+    /// [`TaskStatus`] as a number
+    pub status: usize,
+    pub nice: isize,
+    /// share of a CPU used lately, in hundredths of a percent
+    pub cpu_usage: usize,
+    /// CPU time used in all
+    pub cpu_time_ms: usize,
+    pub resident_pages: usize,
+}
+
+/// Copy the usage of up to `count` tasks, in pid order, to `buf`, returning
+/// how many tasks there are, or -1 if `buf` is not writable.
+pub fn sys_task_usage(buf: *mut TaskUsageInfo, count: usize) -> isize {
+    let tasks = task_usage();
+    let infos: Vec<TaskUsageInfo> = tasks
+        .iter()
+        .take(count)
+        .map(|task| {
+            let mut name = [0u8; TASK_NAME_LEN + 1];
+            name[..task.name.len()].copy_from_slice(task.name.as_bytes());
+            TaskUsageInfo {
+                pid: task.pid,
+                name,
+                status: task.status as usize,
+                nice: task.nice,
+                cpu_usage: task.cpu_usage / 100,
+                cpu_time_ms: (task.counters.cpu_time as u64 * 1000 / CLOCK_FREQ as u64) as usize,
+                resident_pages: task.resident_pages,
+            }
+        })
+        .collect();
+    if !UserSlice::new(current_user_token(), buf, infos.len()).write(&infos) {
+        return -1;
+    }
+    tasks.len() as isize
+}
+
+/// `prctl` option: name the current task after the nul-terminated string at
+/// `arg2`, cut to `TASK_NAME_LEN` bytes
+const PR_SET_NAME: usize = 15;
+/// `prctl` option: copy the name of the current task to the
+/// `TASK_NAME_LEN + 1` bytes at `arg2`, padded with nuls
+const PR_GET_NAME: usize = 16;
+
+/// Process control, of which only naming the task is supported.
+pub fn sys_prctl(option: usize, arg2: usize) -> isize {
+    let token = current_user_token();
+    match option {
+        PR_SET_NAME => {
+            let mut name = Vec::new();
+            // a longer name is cut, and what follows it need not be mapped
+            for i in 0..TASK_NAME_LEN {
+                match UserPtr::new(token, arg2.wrapping_add(i) as *const u8).read() {
+                    Some(0) => break,
+                    Some(byte) => name.push(byte),
+                    None => return -EFAULT,
+                }
+            }
+            match core::str::from_utf8(&name) {
+                Ok(name) => set_current_task_name(name),
+                Err(_) => return -1,
+            }
+            0
+        }
+        PR_GET_NAME => {
+            let mut name = [0u8; TASK_NAME_LEN + 1];
+            let current = current_task_name();
+            name[..current.len()].copy_from_slice(current.as_bytes());
+            if !UserPtr::new(token, arg2 as *const [u8; TASK_NAME_LEN + 1]).write(name) {
+                return -1;
+            }
+            0
+        }
+        _ => -1,
+    }
+}
+
+/// Run the application called by the NUL-terminated `name` as a child of
+/// the current task, returning its pid.
+pub fn sys_spawn(name: *const u8) -> isize {
+    let app = match read_user_str(current_user_token(), name, MAX_APP_NAME_LEN) {
+        Some(name) => find_app(&name),
+        None => None,
+    };
+    match app.and_then(spawn) {
+        Some(pid) => pid as isize,
+        None => -1,
+    }
+}
+
+/// Wait for the child `pid` of the current task, or any child if `pid` is
+/// -1, to exit, storing its exit code at `exit_code` unless it is null and
+/// returning its pid. Returns -2 if it has not exited yet, and -1 if there
+/// is no such child or `exit_code` is not writable.
+pub fn sys_waitpid(pid: isize, exit_code: *mut i32) -> isize {
+    let (pid, code) = match wait_child(pid) {
+        Some(Some(child)) => child,
+        Some(None) => return -2,
+        None => return -1,
+    };
+    // the child stays a zombie if its status cannot be written
+    let exit_code = UserPtr::new(current_user_token(), exit_code);
+    if !exit_code.is_null() && !exit_code.write(code) {
+        return -1;
+    }
+    collect_child(pid);
+    pid as isize
+}
+
+/// Report the result of the user test called by the NUL-terminated `name`,
+/// for the summary at shutdown.
+pub fn sys_test_report(name: *const u8, passed: usize) -> isize {
+    match read_user_str(current_user_token(), name, MAX_TEST_NAME_LEN) {
+        Some(name) => {
+            crate::ktest::report(name, passed != 0);
+            0
+        }
+        None => -1,
+    }
+}
+
+pub fn sys_getpid() -> isize {
+    current_task_id() as isize
+}
+
+/// Move task `pid` (0 for the current task) to process group `pgid` (0 for
+/// a group of its own, named after it). The task must be the current task
+/// or one of its children, or fails with `-ESRCH`, and the group must
+/// exist unless it is named after the task, or fails with `-EPERM`.
+pub fn sys_setpgid(pid: usize, pgid: usize) -> isize {
+    let current = current_task_id();
+    let pid = if pid == 0 { current } else { pid };
+    let pgid = if pgid == 0 { pid } else { pgid };
+    if pid != current && task_parent(pid) != Some(current) {
+        return -ESRCH;
+    }
+    if pgid != pid && !group_exists(pgid) {
+        return -EPERM;
+    }
+    match set_task_pgid(pid, pgid) {
+        true => 0,
+        false => -ESRCH,
+    }
+}
+
+/// Get the process group of task `pid` (0 for the current task), failing
+/// with `-ESRCH` if there is no such task.
+pub fn sys_getpgid(pid: usize) -> isize {
+    let pid = if pid == 0 { current_task_id() } else { pid };
+    match task_pgid(pid) {
+        Some(pgid) => pgid as isize,
+        None => -ESRCH,
+    }
+}
+
+/// Make the control group at the NUL-terminated `path`, such as `/batch`
+/// or `/batch/low`, in the group its path is in, with CPU weight `weight`
+/// from 1 to `MAX_CGROUP_WEIGHT`. Returns its id. Only root may do so.
+pub fn sys_cgroup_create(path: *const u8, weight: usize) -> isize {
+    if !current_is_privileged() {
+        return -1;
+    }
+    match read_user_str(current_user_token(), path, MAX_CGROUP_PATH_LEN)
+        .and_then(|path| cgroup_create(&path, weight))
+    {
+        Some(id) => id as isize,
+        None => -1,
+    }
+}
+
+/// Move task `pid` (0 for the current task) to control group `cgroup`.
+/// Only root may do so.
+pub fn sys_cgroup_attach(pid: usize, cgroup: usize) -> isize {
+    if !current_is_privileged() {
+        return -1;
+    }
+    let pid = if pid == 0 { current_task_id() } else { pid };
+    match set_task_cgroup(pid, cgroup) {
+        true => 0,
+        false => -1,
+    }
+}
+
+/// Set the CPU weight of control group `cgroup`. Only root may do so.
+pub fn sys_cgroup_set_weight(cgroup: usize, weight: usize) -> isize {
+    if !current_is_privileged() {
+        return -1;
+    }
+    match cgroup_set_weight(cgroup, weight) {
+        true => 0,
+        false => -1,
+    }
+}
+
+/// Remove control group `cgroup`, which must hold no group and no task.
+/// Only root may do so.
+pub fn sys_cgroup_remove(cgroup: usize) -> isize {
+    if !current_is_privileged() {
+        return -1;
+    }
+    match cgroup_remove(cgroup) {
+        true => 0,
+        false => -1,
+    }
+}
+
+/// Write the checkpoint image of task `pid`, which may be the caller, to
+/// the `len` bytes at `buf` if it fits, returning its size whether it fits
+/// or not. The image may then be written to a file, or restored with
+/// `sys_restore` as it is. -1 if there is no such task, if it is another
+/// user's and the caller is not root, if it cannot be checkpointed or if
+/// the kernel heap cannot hold the image.
+///
+/// A task checkpointing itself gets the size of the image, and the task
+/// restored from it gets 0.
+pub fn sys_checkpoint(pid: usize, buf: *mut u8, len: usize) -> isize {
+    if !current_may_access(pid) {
+        return -1;
+    }
+    match checkpoint_task(pid, len) {
+        Some((size, Some(image))) => {
+            if !UserSlice::new(current_user_token(), buf, len).write(&image) {
+                return -EFAULT;
+            }
+            size as isize
+        }
+        Some((size, None)) => size as isize,
+        None => -1,
+    }
+}
+
+/// Restore the checkpoint image of `len` bytes at `buf` as a child of the
+/// caller, returning its id. The task runs as the caller, with its
+/// capabilities, limits and groups. -1 if the image is not valid or the
+/// task table is full.
+pub fn sys_restore(buf: *const u8, len: usize) -> isize {
+    let mut image = Vec::new();
+    if image.try_reserve_exact(len).is_err() {
+        return -ENOMEM;
+    }
+    image.resize(len, 0);
+    if !UserSlice::new(current_user_token(), buf, len).read_into(&mut image) {
+        return -EFAULT;
+    }
+    match restore_task(&image) {
+        Some(pid) => pid as isize,
+        None => -1,
+    }
+}
+
+/// Query the personality of the caller, 0 for the native syscall ABI and 1
+/// for Linux's, with `0xffffffff`, or switch it and the tasks it spawns
+/// from now on to `personality`. Returns the personality before.
+pub fn sys_personality(personality: usize) -> isize {
+    let current = current_personality();
+    if personality as u32 != u32::MAX {
+        match Personality::from_usize(personality) {
+            Some(personality) => set_current_personality(personality),
+            None => return -EINVAL,
+        }
+    }
+    current as isize
+}
+
+pub fn sys_getuid() -> isize {
+    current_credentials().0 as isize
+}
+
+pub fn sys_getgid() -> isize {
+    current_credentials().1 as isize
+}
+
+/// Run as user `uid`. Root may become anyone, and once it has become
+/// someone else it cannot come back; other users may only set their own.
+pub fn sys_setuid(uid: u32) -> isize {
+    let (current, gid) = current_credentials();
+    if current != 0 && uid != current {
+        audit::record(AuditEvent::Denied, [0, uid as usize]);
+        return -1;
+    }
+    set_current_credentials(uid, gid);
+    audit::record(AuditEvent::SetUid, [current as usize, uid as usize]);
+    0
+}
+
+/// Run as group `gid`, which only root may change.
+pub fn sys_setgid(gid: u32) -> isize {
+    let (uid, current) = current_credentials();
+    if uid != 0 && gid != current {
+        audit::record(AuditEvent::Denied, [0, gid as usize]);
+        return -1;
+    }
+    set_current_credentials(uid, gid);
+    audit::record(AuditEvent::SetGid, [current as usize, gid as usize]);
+    0
+}
+
+/// Get the capabilities of the current task.
+pub fn sys_capget() -> isize {
+    current_capabilities().bits() as isize
+}
+
+/// Keep only the capabilities in `capabilities`. They can be given up but
+/// never regained, so asking for one the task lacks fails.
+pub fn sys_capset(capabilities: u32) -> isize {
+    match Capabilities::from_bits(capabilities) {
+        Some(capabilities) if restrict_current_capabilities(capabilities) => 0,
+        _ => -1,
+    }
+}
+
+/// Get the limits of task `pid` (0 for the current task) on `resource` into
+/// `old_limit`, then replace them with `new_limit`; either may be null.
+/// Only tasks of the same user may be changed, except by root.
+pub fn sys_prlimit(
+    pid: usize,
+    resource: usize,
+    new_limit: *const RLimit,
+    old_limit: *mut RLimit,
+) -> isize {
+    let pid = if pid == 0 { current_task_id() } else { pid };
+    let resource = match Resource::from_number(resource) {
+        Some(resource) => resource,
+        None => return -1,
+    };
+    if !current_may_access(pid) {
+        return -1;
+    }
+    let token = current_user_token();
+    let old_limit = UserPtr::new(token, old_limit);
+    if !old_limit.is_null() {
+        match task_limit(pid, resource) {
+            Some(limit) if old_limit.write(limit) => {}
+            _ => return -1,
+        }
+    }
+    let new_limit = UserPtr::new(token, new_limit);
+    if !new_limit.is_null() {
+        match new_limit.read() {
+            Some(limit) if set_task_limit(pid, resource, limit) => {}
+            _ => return -1,
+        }
+    }
     0
 }
+
+/// Set the nice value of task `pid` (0 for the current task), which scales
+/// its stride scheduling weight, so only matters with `sched=stride`: each
+/// step from 0 to 19 gives it about 10% less CPU time, each step down to -20
+/// about 10% more. Only tasks of the same user may be reniced, and only root
+/// may lower a nice value.
+pub fn sys_renice(pid: usize, nice: isize) -> isize {
+    let pid = if pid == 0 { current_task_id() } else { pid };
+    if !(MIN_NICE..=MAX_NICE).contains(&nice) || !current_may_access(pid) {
+        return -1;
+    }
+    if !set_task_nice(pid, nice) {
+        return -1;
+    }
+    0
+}
+
+/// `sched_getscheduler` policies, same values as Linux
+const SCHED_OTHER: isize = 0;
+const SCHED_RR: isize = 2;
+
+/// The scheduling parameters of a task, as Linux `struct sched_param`
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SchedParam {
+    pub sched_priority: i32,
+}
+
+/// The stride scheduling priority of task `pid` (0 for the current task),
+/// or None if there is no such task.
+fn sched_priority(pid: usize) -> Option<usize> {
+    task_priority(if pid == 0 { current_task_id() } else { pid })
+}
+
+/// The scheduling policy of task `pid` (0 for the current task), the same
+/// for all tasks as `sched=` picks it: `SCHED_RR` for round robin, and
+/// `SCHED_OTHER` for stride scheduling, which shares the CPU by weight.
+/// Fails with `-ESRCH` if there is no such task.
+pub fn sys_sched_getscheduler(pid: usize) -> isize {
+    if sched_priority(pid).is_none() {
+        return -ESRCH;
+    }
+    match scheduler() {
+        Scheduler::RoundRobin => SCHED_RR,
+        Scheduler::Stride => SCHED_OTHER,
+    }
+}
+
+/// Write the scheduling parameters of task `pid` (0 for the current task)
+/// to `param`: its priority under stride scheduling, 0 under round robin,
+/// which treats all tasks alike. Fails with `-ESRCH` if there is no such
+/// task.
+pub fn sys_sched_getparam(pid: usize, param: *mut SchedParam) -> isize {
+    let priority = match (sched_priority(pid), scheduler()) {
+        (None, _) => return -ESRCH,
+        (Some(_), Scheduler::RoundRobin) => 0,
+        (Some(priority), Scheduler::Stride) => priority as i32,
+    };
+    let param_value = SchedParam {
+        sched_priority: priority,
+    };
+    match UserPtr::new(current_user_token(), param).write(param_value) {
+        true => 0,
+        false => -EFAULT,
+    }
+}
+
+/// Write the time slice of task `pid` (0 for the current task) to
+/// `interval`: a timer tick, whichever the scheduler, as a task that has
+/// not given up the CPU is preempted at each. Fails with `-ESRCH` if there
+/// is no such task.
+pub fn sys_sched_rr_get_interval(pid: usize, interval: *mut TimeSpec) -> isize {
+    if sched_priority(pid).is_none() {
+        return -ESRCH;
+    }
+    let slice = TimeSpec::from_cycles(CLOCK_FREQ / TICKS_PER_SEC);
+    match UserPtr::new(current_user_token(), interval).write(slice) {
+        true => 0,
+        false => -EFAULT,
+    }
+}
+
+/// Confine the mappings of task `pid` (0 for the current task) to the
+/// ranges and size of `profile`. The task must not have run yet, unless it
+/// is the current one, and cannot be confined twice. Only tasks of the same
+/// user may be confined, except by root.
+pub fn sys_sandbox(pid: usize, profile: *const SandboxProfile) -> isize {
+    let pid = if pid == 0 { current_task_id() } else { pid };
+    if !current_may_access(pid) {
+        return -1;
+    }
+    match UserPtr::new(current_user_token(), profile).read() {
+        Some(profile) if profile.is_valid() && set_task_sandbox(pid, profile) => {}
+        _ => return -1,
+    }
+    0
+}
+
+/// Send signal `signum` to task `pid`; signal 0 only checks that it exists.
+/// Only tasks of the same user may be signalled, except by root.
+pub fn sys_kill(pid: usize, signum: usize) -> isize {
+    if !current_may_access(pid) {
+        return -1;
+    }
+    if signum == 0 {
+        return if send_signal(pid, SignalFlags::empty()) { 0 } else { -1 };
+    }
+    match SignalFlags::from_signum(signum) {
+        Some(signal) if send_signal(pid, signal) => {
+            audit::record(AuditEvent::Kill, [pid, signum]);
+            0
+        }
+        _ => -1,
+    }
+}
+
+/// Set the blocked signal set of the current task, returning the old one.
+pub fn sys_sigprocmask(mask: u32) -> isize {
+    set_current_signal_mask(SignalFlags::from_bits_truncate(mask)).bits() as isize
+}
+
+/// Power off the machine. Only root may do so.
+pub fn sys_shutdown() -> isize {
+    if !current_is_privileged() {
+        return -1;
+    }
+    crate::power::shutdown(false)
+}
+
+/// Restart the machine, or only the kernel without going through the
+/// firmware if `warm` is not 0. Only root may do so.
+pub fn sys_reboot(warm: usize) -> isize {
+    if !current_is_privileged() {
+        return -1;
+    }
+    if warm != 0 {
+        crate::power::warm_reboot()
+    }
+    crate::power::reboot()
+}
+
+/// Pet the watchdog daemon deadline, requiring the next pet within
+/// `timeout_ms`; 0 disarms it. Only root may do so.
+pub fn sys_watchdog(timeout_ms: usize) -> isize {
+    if !current_is_privileged() {
+        return -1;
+    }
+    crate::watchdog::pet_daemon(timeout_ms);
+    0
+}
+
+/// Suspend the machine to RAM, returning once it resumed. Only root may do
+/// so; -1 if the firmware cannot suspend.
+pub fn sys_suspend() -> isize {
+    if !current_is_privileged() {
+        return -1;
+    }
+    if crate::power::suspend() {
+        0
+    } else {
+        -1
+    }
+}
+
+/// One segment of a scattered buffer, layout compatible with `struct iovec`
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct IoVec {
+    pub base: *mut u8,
+    pub len: usize,
+}
+
+/// most segments a scattered buffer may have, as Linux `IOV_MAX`
+const IOV_MAX: usize = 1024;
+
+/// Translate every segment of the `iovcnt` iovecs at `iov` (read through
+/// `iov_token`) in the address space `token`, for reading them, or writing
+/// them if `writable`, keeping only the accessible prefix of the whole
+/// vector as soon as one page is not. None if the iovecs cannot be read.
+fn translated_iovecs(
+    iov_token: usize,
+    iov: *const IoVec,
+    iovcnt: usize,
+    token: usize,
+    writable: bool,
+) -> Option<Vec<&'static mut [u8]>> {
+    let iovecs = UserSlice::new(iov_token, iov, iovcnt).read()?;
+    let mut buffers = Vec::new();
+    for iovec in iovecs {
+        let segment = UserSlice::new(token, iovec.base as *const u8, iovec.len);
+        let segment = segment.prefix_buffer(writable);
+        let complete = segment.len() == iovec.len;
+        buffers.extend(segment.buffers);
+        if !complete {
+            break;
+        }
+    }
+    Some(buffers)
+}
+
+/// Copy the scattered bytes of `src` into those of `dst`, as far as the
+/// shorter goes, returning how many were copied.
+fn copy_scattered(dst: &mut [&'static mut [u8]], src: &[&'static mut [u8]]) -> usize {
+    let mut copied = 0;
+    let (mut index, mut offset) = (0, 0);
+    for chunk in src {
+        let mut chunk: &[u8] = chunk;
+        while !chunk.is_empty() {
+            let target = match dst.get_mut(index) {
+                Some(target) => target,
+                None => return copied,
+            };
+            let len = chunk.len().min(target.len() - offset);
+            target[offset..offset + len].copy_from_slice(&chunk[..len]);
+            chunk = &chunk[len..];
+            offset += len;
+            copied += len;
+            if offset == target.len() {
+                index += 1;
+                offset = 0;
+            }
+        }
+    }
+    copied
+}
+
+/// Copy between the current task's `local` segments and task `pid`'s
+/// `remote` segments, returning the number of bytes transferred. More than
+/// `IOV_MAX` segments fail with `-EINVAL`, and iovecs that cannot be read
+/// with `-EFAULT`.
+fn process_vm_copy(
+    pid: usize,
+    local: (*const IoVec, usize),
+    remote: (*const IoVec, usize),
+    flags: usize,
+    write_remote: bool,
+) -> isize {
+    if flags != 0 || !current_may_access(pid) {
+        return -1;
+    }
+    if local.1 > IOV_MAX || remote.1 > IOV_MAX {
+        return -EINVAL;
+    }
+    let remote_token = match task_user_token(pid) {
+        Some(token) => token,
+        None => return -1,
+    };
+    let token = current_user_token();
+    let local = translated_iovecs(token, local.0, local.1, token, !write_remote);
+    let remote = translated_iovecs(token, remote.0, remote.1, remote_token, write_remote);
+    let (mut local, mut remote) = match (local, remote) {
+        (Some(local), Some(remote)) => (local, remote),
+        _ => return -EFAULT,
+    };
+    let copied = if write_remote {
+        copy_scattered(&mut remote, &local)
+    } else {
+        copy_scattered(&mut local, &remote)
+    };
+    copied as isize
+}
+
+/// Read task `pid`'s memory described by `remote_iov` into `local_iov`.
+///
+/// Only user pages readable in the target are read; the copy stops at the
+/// first inaccessible page and the number of bytes read is returned.
+pub fn sys_process_vm_readv(
+    pid: usize,
+    local_iov: *const IoVec,
+    liovcnt: usize,
+    remote_iov: *const IoVec,
+    riovcnt: usize,
+    flags: usize,
+) -> isize {
+    process_vm_copy(pid, (local_iov, liovcnt), (remote_iov, riovcnt), flags, false)
+}
+
+/// Write `local_iov` into task `pid`'s memory described by `remote_iov`.
+///
+/// Only user pages writable in the target are written; the copy stops at
+/// the first inaccessible page and the number of bytes written is returned.
+pub fn sys_process_vm_writev(
+    pid: usize,
+    local_iov: *const IoVec,
+    liovcnt: usize,
+    remote_iov: *const IoVec,
+    riovcnt: usize,
+    flags: usize,
+) -> isize {
+    process_vm_copy(pid, (local_iov, liovcnt), (remote_iov, riovcnt), flags, true)
+}
@@ -0,0 +1,46 @@
+//! Profiling syscalls
+
+use super::errno::EFAULT;
+use crate::mm::{UserPtr, UserSlice};
+use crate::perf::{self, PerfCounters};
+use crate::profile::{self, ProfileSample};
+use crate::task::{
+    current_is_privileged, current_task_counters, current_task_id, current_user_token,
+};
+
+/// Sample the running task every `interval` timer interrupts, discarding
+/// the samples taken so far, or stop sampling if `interval` is 0.
+/// Privileged; returns 0 or -1.
+pub fn sys_profile(interval: usize) -> isize {
+    if !current_is_privileged() {
+        return -1;
+    }
+    profile::start(interval);
+    0
+}
+
+/// Copy up to `count` histogram entries of task `pid` to `buf` and return
+/// how many the histogram has, which may be more. Reading another task's
+/// profile is privileged. Fails with `-EFAULT` if `buf` is not writable.
+pub fn sys_profile_read(pid: usize, buf: *mut ProfileSample, count: usize) -> isize {
+    if pid != current_task_id() && !current_is_privileged() {
+        return -1;
+    }
+    let samples = profile::samples(pid);
+    let copied = &samples[..count.min(samples.len())];
+    if !UserSlice::new(current_user_token(), buf, copied.len()).write(copied) {
+        return -EFAULT;
+    }
+    samples.len() as isize
+}
+
+/// Write the hardware counts of the current task so far to `counters`.
+/// Fails with `-EFAULT` if it is not writable.
+pub fn sys_perf_read(counters: *mut PerfCounters) -> isize {
+    let mut perf = current_task_counters().0.perf;
+    perf.add(&perf::since_switch());
+    if !UserPtr::new(current_user_token(), counters).write(perf) {
+        return -EFAULT;
+    }
+    0
+}
@@ -0,0 +1,82 @@
+//! Kernel log syscalls
+
+use super::errno::EFAULT;
+use crate::logging::{clear_kernel_log, kernel_log, kernel_log_capacity, set_log_level};
+use crate::mm::{read_user_str, UserSlice};
+use crate::task::{current_is_privileged, current_user_token};
+use alloc::string::String;
+use log::LevelFilter;
+
+/// `syslog` actions, same values as Linux
+const SYSLOG_ACTION_READ_ALL: usize = 3;
+const SYSLOG_ACTION_READ_CLEAR: usize = 4;
+const SYSLOG_ACTION_CLEAR: usize = 5;
+const SYSLOG_ACTION_SIZE_BUFFER: usize = 10;
+/// longest module path `sys_log_level` reads
+const MAX_MODULE_LEN: usize = 128;
+
+/// The `syslog` syscall as `dmesg` uses it. `SYSLOG_ACTION_READ_ALL` copies
+/// the latest `len` bytes of the kernel log to `buf` and returns how many,
+/// `SYSLOG_ACTION_READ_CLEAR` also clears it afterwards and
+/// `SYSLOG_ACTION_CLEAR` only clears it; clearing is privileged.
+/// `SYSLOG_ACTION_SIZE_BUFFER` returns the size of the ring. A `buf` not
+/// writable for as much of the log as is asked fails with `-EFAULT`.
+pub fn sys_dmesg(action: usize, buf: *mut u8, len: usize) -> isize {
+    let clear = matches!(action, SYSLOG_ACTION_READ_CLEAR | SYSLOG_ACTION_CLEAR);
+    if clear && !current_is_privileged() {
+        return -1;
+    }
+    let copied = match action {
+        SYSLOG_ACTION_READ_ALL | SYSLOG_ACTION_READ_CLEAR => {
+            // no more than the ring holds is ever copied
+            let len = len.min(kernel_log_capacity());
+            let log = kernel_log();
+            let log = &log[log.len().saturating_sub(len)..];
+            if !UserSlice::new(current_user_token(), buf, log.len()).write(log) {
+                return -EFAULT;
+            }
+            log.len() as isize
+        }
+        SYSLOG_ACTION_CLEAR => 0,
+        SYSLOG_ACTION_SIZE_BUFFER => return kernel_log_capacity() as isize,
+        _ => return -1,
+    };
+    if clear {
+        clear_kernel_log();
+    }
+    copied
+}
+
+/// Show kernel log records up to `level` (0 none, then error, warn, info,
+/// debug and trace up to 5) from `module`, a NUL-terminated module path
+/// such as `task` or `mm::page_table`, or from all modules without a level
+/// of their own if `module` is null. A `level` of -1 makes `module` follow
+/// the default again. Privileged; returns 0 or -1.
+pub fn sys_log_level(module: *const u8, level: isize) -> isize {
+    if !current_is_privileged() {
+        return -1;
+    }
+    let level = match level {
+        -1 => None,
+        0 => Some(LevelFilter::Off),
+        1 => Some(LevelFilter::Error),
+        2 => Some(LevelFilter::Warn),
+        3 => Some(LevelFilter::Info),
+        4 => Some(LevelFilter::Debug),
+        5 => Some(LevelFilter::Trace),
+        _ => return -1,
+    };
+    let module = if module.is_null() {
+        String::new()
+    } else {
+        match read_user_str(current_user_token(), module, MAX_MODULE_LEN) {
+            Some(module) => module,
+            None => return -1,
+        }
+    };
+    if module.is_empty() && level.is_none() {
+        return -1;
+    }
+    set_log_level(&module, level);
+    0
+}
@@ -0,0 +1,36 @@
+//! Tracepoint syscalls
+
+use super::errno::EFAULT;
+use crate::mm::UserSlice;
+use crate::task::{current_is_privileged, current_user_token};
+use crate::trace::{self, TraceRecord};
+use core::mem::size_of;
+
+/// Enable the tracepoint events whose bit is set in `mask` and disable the
+/// others. Privileged; returns the previous mask or -1.
+pub fn sys_trace_ctl(mask: u32) -> isize {
+    if !current_is_privileged() {
+        return -1;
+    }
+    trace::set_enabled(mask) as isize
+}
+
+/// Move as many whole [`TraceRecord`]s as fit in the `len` bytes at `buf`
+/// out of the trace ring, oldest first, and return the bytes copied.
+/// Privileged; fails with `-EFAULT`, the records lost, if `buf` is not
+/// writable.
+pub fn sys_trace_read(buf: *mut u8, len: usize) -> isize {
+    if !current_is_privileged() {
+        return -1;
+    }
+    let records = trace::drain(len / size_of::<TraceRecord>());
+    let buf = UserSlice::new(
+        current_user_token(),
+        buf as *const TraceRecord,
+        records.len(),
+    );
+    if !buf.write(&records) {
+        return -EFAULT;
+    }
+    (records.len() * size_of::<TraceRecord>()) as isize
+}
@@ -0,0 +1,76 @@
+//! Magic SysRq
+//!
+//! A break on the console UART (`Ctrl-A b` under QEMU with `-nographic`)
+//! followed by a key runs one of the commands below from the interrupt
+//! handler, which needs neither a shell nor a responsive task. Like the
+//! crash dump, the commands do not wait for anything that is in use.
+
+use crate::console::Stdout;
+use crate::drivers::chardev::VIRTIO_CONSOLE;
+use crate::drivers::GPU_DEVICE;
+use crate::mm::{frame_stats, KERNEL_SPACE};
+use crate::task::{dump_tasks, task_table_in_use};
+
+/// Locks of the kernel and whether they are held. Nothing records who holds
+/// them; on a hung kernel it is whatever the current task was doing.
+fn locks() -> [(&'static str, bool); 5] {
+    [
+        ("task table", task_table_in_use()),
+        ("kernel space", KERNEL_SPACE.is_locked()),
+        ("frame allocator", frame_stats().is_none()),
+        (
+            "virtio console",
+            VIRTIO_CONSOLE.try_exclusive_access().is_none(),
+        ),
+        ("gpu", GPU_DEVICE.try_exclusive_access().is_none()),
+    ]
+}
+
+fn show_memory() {
+    match frame_stats() {
+        Some(stats) => {
+            println!(
+                "frames: {} total, {} free, {} never allocated",
+                stats.total, stats.free, stats.never_allocated
+            );
+        }
+        None => {
+            println!("frames: allocator in use");
+        }
+    }
+}
+
+fn show_locks() {
+    for (name, held) in locks().iter() {
+        println!("{}: {}", name, if *held { "held" } else { "free" });
+    }
+}
+
+/// Print the trace ring as a Chrome trace, between marker lines to cut it
+/// out of the console log at.
+fn export_trace() {
+    println!("--- chrome trace begin ---");
+    if crate::trace::write_chrome_trace(&mut Stdout).is_err() {
+        println!("trace ring in use");
+    }
+    println!("--- chrome trace end ---");
+}
+
+/// Run the SysRq command `key`.
+pub fn handle(key: u8) {
+    println!("[kernel] sysrq: {}", key as char);
+    match key {
+        b't' => dump_tasks(),
+        b'm' => show_memory(),
+        b'l' => show_locks(),
+        b'e' => export_trace(),
+        b'b' => crate::power::reboot(),
+        b'w' => crate::power::warm_reboot(),
+        b'o' => crate::power::shutdown(false),
+        _ => {
+            println!(
+                "t: show tasks, m: show memory, l: show locks, e: export trace, b: reboot, w: warm reboot, o: power off"
+            );
+        }
+    }
+}
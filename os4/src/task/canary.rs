@@ -0,0 +1,58 @@
+//! Kernel stack canaries
+//!
+//! The lowest word of every kernel stack holds a canary, derived from a
+//! per-boot secret and the stack's address. A kernel stack that grows down
+//! past its last page hits the guard page below and faults, but one that
+//! only reaches its last bytes silently corrupts whatever lives there; the
+//! canary is checked on trap entry, trap return and task switch, so such an
+//! overflow panics with the task id close to where it happened.
+
+use crate::config::kernel_stack_position;
+use crate::mm::{VirtAddr, KERNEL_SPACE};
+use crate::random::fill_random;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Per-boot secret, 0 until the first canary is planted
+static SECRET: AtomicUsize = AtomicUsize::new(0);
+
+fn secret() -> usize {
+    let secret = SECRET.load(Ordering::Relaxed);
+    if secret != 0 {
+        return secret;
+    }
+    let mut bytes = [0; core::mem::size_of::<usize>()];
+    fill_random(&mut bytes);
+    // the pool may not be seeded this early, so the time is mixed in too
+    let secret = (usize::from_ne_bytes(bytes) ^ crate::timer::get_time()) | 1;
+    SECRET.store(secret, Ordering::Relaxed);
+    secret
+}
+
+fn canary(bottom: usize) -> usize {
+    secret() ^ bottom
+}
+
+/// Write the canary of the kernel stack of task `app_id`, which must be
+/// mapped already. It is written through the frame, as the new mapping may
+/// not be visible to the TLB yet.
+pub fn plant(app_id: usize) {
+    let (bottom, _) = kernel_stack_position(app_id);
+    let ppn = KERNEL_SPACE
+        .lock()
+        .translate(VirtAddr::from(bottom).floor())
+        .unwrap()
+        .ppn();
+    *ppn.get_mut::<usize>() = canary(bottom);
+}
+
+/// Panic if the canary of the kernel stack of task `app_id` was clobbered.
+pub fn check(app_id: usize) {
+    let (bottom, _) = kernel_stack_position(app_id);
+    let found = unsafe { core::ptr::read_volatile(bottom as *const usize) };
+    if found != canary(bottom) {
+        panic!(
+            "kernel stack of task {} overflowed: canary {:#x} clobbered",
+            app_id, found
+        );
+    }
+}
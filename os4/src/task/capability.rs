@@ -0,0 +1,20 @@
+//! Capabilities
+//!
+//! Each task holds a set of capabilities, all of them at creation, and
+//! needs the one of a syscall to make it, on top of any ownership check.
+//! A task can only give capabilities up, so a test harness can run
+//! de-privileged, even as root.
+
+bitflags! {
+    /// A set of capabilities
+    pub struct Capabilities: u32 {
+        /// send signals with `kill`
+        const CAP_KILL       = 1 << 0;
+        /// power off, reboot and arm the watchdog
+        const CAP_SHUTDOWN   = 1 << 1;
+        /// profile, trace and collect coverage, read other tasks' memory
+        const CAP_TRACE      = 1 << 2;
+        /// map memory at a chosen address, which `mmap` always does here
+        const CAP_MMAP_FIXED = 1 << 3;
+    }
+}
@@ -0,0 +1,353 @@
+//! Control groups, sharing the CPU out among groups of tasks
+//!
+//! Groups nest as paths do: `/` is the root group every task starts in,
+//! `/batch` a group in it and `/batch/low` one in that. A spawned task
+//! joins the group of its parent, and root may move a task to another. Each
+//! group has a CPU weight, 1 to `MAX_CGROUP_WEIGHT` as `cpu.weight` in
+//! Linux, and the groups in a group that have tasks ready share the CPU in
+//! proportion to their weights. The tasks directly in a group share as much
+//! as one more group of the default weight would.
+//!
+//! Picking the next task starts with its group: from the root down, the
+//! group with the least pass among the siblings with ready tasks, a group
+//! advancing by `BIG_STRIDE` over its weight when picked. A group with
+//! nothing ready for a while starts again from the pass of the sibling
+//! picked last instead of catching up on the time it left. Within the group
+//! the scheduler then picks among its ready tasks as it would among all, so
+//! task priorities and niceness only weigh against the tasks of the same
+//! group. With the root group alone, picking is as without groups.
+//!
+//! The CPU is the only resource groups share out so far. The CPU time of
+//! each group counts that of its descendants.
+
+use crate::config::{BIG_STRIDE, DEFAULT_CGROUP_WEIGHT, MAX_CGROUPS, MAX_CGROUP_WEIGHT};
+use crate::sync::UPSafeCell;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use lazy_static::*;
+
+/// the group every task starts in
+pub const ROOT_CGROUP: usize = 0;
+
+struct Group {
+    /// full path, `/` for the root group
+    path: String,
+    parent: Option<usize>,
+    weight: usize,
+    /// stride scheduling progress among the siblings
+    pass: usize,
+    /// progress of the tasks directly in the group, as one more child
+    own_pass: usize,
+    /// the pass the child or own tasks picked last started from
+    last_pass: usize,
+    /// CPU time of the tasks in the group and its descendants, in timer
+    /// cycles
+    cpu_time: usize,
+}
+
+impl Group {
+    fn new(path: String, parent: Option<usize>, weight: usize) -> Self {
+        Self {
+            path,
+            parent,
+            weight,
+            pass: 0,
+            own_pass: 0,
+            last_pass: 0,
+            cpu_time: 0,
+        }
+    }
+}
+
+/// Whether pass `a` is behind pass `b`. Passes wrap, but stay within half
+/// the range of each other.
+fn before(a: usize, b: usize) -> bool {
+    (a.wrapping_sub(b) as isize) < 0
+}
+
+/// The groups, by id
+pub struct Groups {
+    /// the root group first; removed groups leave a hole, none at the end
+    groups: Vec<Option<Group>>,
+}
+
+impl Groups {
+    fn new() -> Self {
+        Self {
+            groups: vec![Some(Group::new(
+                String::from("/"),
+                None,
+                DEFAULT_CGROUP_WEIGHT,
+            ))],
+        }
+    }
+
+    fn get(&self, id: usize) -> Option<&Group> {
+        self.groups.get(id).and_then(Option::as_ref)
+    }
+
+    fn group(&mut self, id: usize) -> &mut Group {
+        self.groups[id].as_mut().unwrap()
+    }
+
+    fn find(&self, path: &str) -> Option<usize> {
+        self.groups
+            .iter()
+            .position(|group| group.as_ref().map_or(false, |group| group.path == path))
+    }
+
+    /// Make the group at `path`, in the group its path is in, returning its
+    /// id. None if the path is not absolute and without empty components,
+    /// if the group exists or the one it would be in does not, or if there
+    /// are `MAX_CGROUPS` already.
+    fn create(&mut self, path: &str, weight: usize) -> Option<usize> {
+        if !(1..=MAX_CGROUP_WEIGHT).contains(&weight)
+            || !path.starts_with('/')
+            || path[1..].split('/').any(str::is_empty)
+            || self.find(path).is_some()
+        {
+            return None;
+        }
+        let parent = match path.rfind('/')? {
+            0 => ROOT_CGROUP,
+            end => self.find(&path[..end])?,
+        };
+        let mut group = Group::new(String::from(path), Some(parent), weight);
+        // from where its siblings are, not ahead of them
+        group.pass = self.group(parent).last_pass;
+        match self.groups.iter().position(Option::is_none) {
+            Some(id) => {
+                self.groups[id] = Some(group);
+                Some(id)
+            }
+            None if self.groups.len() < MAX_CGROUPS => {
+                self.groups.push(Some(group));
+                Some(self.groups.len() - 1)
+            }
+            None => None,
+        }
+    }
+
+    /// Remove group `id` if it is not the root and has no child groups.
+    /// Its tasks are the caller's to check.
+    fn remove(&mut self, id: usize) -> bool {
+        if id == ROOT_CGROUP
+            || self.get(id).is_none()
+            || self
+                .groups
+                .iter()
+                .flatten()
+                .any(|group| group.parent == Some(id))
+        {
+            return false;
+        }
+        self.groups[id] = None;
+        while let Some(None) = self.groups.last() {
+            self.groups.pop();
+        }
+        true
+    }
+
+    /// Pick the group whose tasks run next, given the group of each ready
+    /// task, and advance the passes along the way. None if there is no
+    /// ready task, or no group but the root to pick from.
+    fn pick(&mut self, ready: impl Iterator<Item = usize>) -> Option<usize> {
+        if self.groups.len() == 1 {
+            return None;
+        }
+        // groups with ready tasks, directly or in their descendants
+        let mut active = vec![false; self.groups.len()];
+        // groups with ready tasks directly in them
+        let mut own = vec![false; self.groups.len()];
+        for id in ready {
+            own[id] = true;
+            let mut next = Some(id);
+            while let Some(id) = next {
+                if active[id] {
+                    break;
+                }
+                active[id] = true;
+                next = self.group(id).parent;
+            }
+        }
+        let mut node = ROOT_CGROUP;
+        if !active[node] {
+            return None;
+        }
+        loop {
+            // a child group, or None for the tasks of the node itself
+            let mut best = if own[node] {
+                Some((None, self.group(node).own_pass))
+            } else {
+                None
+            };
+            for (id, group) in self.groups.iter().enumerate() {
+                let group = match group {
+                    Some(group) if active[id] && group.parent == Some(node) => group,
+                    _ => continue,
+                };
+                if best.map_or(true, |(_, pass)| before(group.pass, pass)) {
+                    best = Some((Some(id), group.pass));
+                }
+            }
+            let (child, pass) = best?;
+            let last_pass = self.group(node).last_pass;
+            let start = if before(pass, last_pass) {
+                last_pass
+            } else {
+                pass
+            };
+            self.group(node).last_pass = start;
+            match child {
+                Some(child) => {
+                    let group = self.group(child);
+                    group.pass = start.wrapping_add(BIG_STRIDE / group.weight);
+                    node = child;
+                }
+                None => {
+                    let group = self.group(node);
+                    group.own_pass = start.wrapping_add(BIG_STRIDE / DEFAULT_CGROUP_WEIGHT);
+                    return Some(node);
+                }
+            }
+        }
+    }
+
+    /// Charge `time` the tasks of group `id` ran to it and its ancestors.
+    fn charge(&mut self, id: usize, time: usize) {
+        let mut next = Some(id);
+        while let Some(id) = next {
+            let group = self.group(id);
+            group.cpu_time += time;
+            next = group.parent;
+        }
+    }
+}
+
+lazy_static! {
+    static ref GROUPS: UPSafeCell<Groups> = unsafe { UPSafeCell::new(Groups::new()) };
+}
+
+/// What `/proc/cgroupstats` shows of a group
+pub struct CgroupStat {
+    pub id: usize,
+    pub path: String,
+    pub weight: usize,
+    /// tasks directly in the group that have not exited
+    pub tasks: usize,
+    /// CPU time of the group and its descendants, in timer cycles
+    pub cpu_time: usize,
+}
+
+/// Make the group at `path` with CPU weight `weight`, returning its id.
+pub fn cgroup_create(path: &str, weight: usize) -> Option<usize> {
+    GROUPS.exclusive_access().create(path, weight)
+}
+
+/// Set the CPU weight of group `id`, from 1 to `MAX_CGROUP_WEIGHT`.
+pub fn cgroup_set_weight(id: usize, weight: usize) -> bool {
+    if !(1..=MAX_CGROUP_WEIGHT).contains(&weight) {
+        return false;
+    }
+    match GROUPS.exclusive_access().groups.get_mut(id) {
+        Some(Some(group)) => {
+            group.weight = weight;
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Whether group `id` exists.
+pub fn cgroup_exists(id: usize) -> bool {
+    GROUPS.exclusive_access().get(id).is_some()
+}
+
+/// Remove group `id`, which must have no tasks, as the caller checked.
+pub(super) fn remove(id: usize) -> bool {
+    GROUPS.exclusive_access().remove(id)
+}
+
+/// Scheduler path: the group whose ready tasks are to be picked from, if
+/// one is.
+pub(super) fn pick(ready: impl Iterator<Item = usize>) -> Option<usize> {
+    GROUPS.exclusive_access().pick(ready)
+}
+
+/// Charge `time` a task of group `id` ran to the group.
+pub(super) fn charge(id: usize, time: usize) {
+    GROUPS.exclusive_access().charge(id, time);
+}
+
+/// Each group, given the group of each task that has not exited.
+pub(super) fn stats(members: impl Iterator<Item = usize>) -> Vec<CgroupStat> {
+    let groups = GROUPS.exclusive_access();
+    let mut stats: Vec<CgroupStat> = groups
+        .groups
+        .iter()
+        .enumerate()
+        .filter_map(|(id, group)| {
+            group.as_ref().map(|group| CgroupStat {
+                id,
+                path: group.path.clone(),
+                weight: group.weight,
+                tasks: 0,
+                cpu_time: group.cpu_time,
+            })
+        })
+        .collect();
+    for member in members {
+        if let Some(stat) = stats.iter_mut().find(|stat| stat.id == member) {
+            stat.tasks += 1;
+        }
+    }
+    stats
+}
+
+/// Check that groups share the CPU by weight, down the hierarchy: with
+/// `/a` of weight 300 and `/b` of 100, `/b` split evenly between `/b/x`
+/// and `/b/y`, and a task ready in each leaf, `/a` is picked 6 times for
+/// each of the other two, give or take one as strides round.
+pub fn cgroup_test() {
+    let mut groups = Groups::new();
+    let a = groups.create("/a", 300).unwrap();
+    let b = groups.create("/b", 100).unwrap();
+    let x = groups.create("/b/x", 50).unwrap();
+    let y = groups.create("/b/y", 50).unwrap();
+    assert!(groups.create("/b/x", 100).is_none(), "made a group twice");
+    assert!(
+        groups.create("/c/z", 100).is_none(),
+        "made a group out of any"
+    );
+    assert!(
+        groups.create("/b/", 100).is_none(),
+        "made a group without a name"
+    );
+    assert!(groups.create("/d", 0).is_none(), "made a group of weight 0");
+    let mut picks = [0; 5];
+    for _ in 0..800 {
+        let group = groups.pick([a, x, y].iter().copied()).unwrap();
+        picks[group] += 1;
+    }
+    let near = |picks: usize, expected: usize| picks + 1 >= expected && picks <= expected + 1;
+    assert!(near(picks[a], 600) && near(picks[x], 100) && near(picks[y], 100));
+    // the tasks of the root group weigh as one more group of the default
+    // weight, as much as `/b`
+    let mut picks = [0; 5];
+    for _ in 0..500 {
+        let group = groups.pick([ROOT_CGROUP, a, x].iter().copied()).unwrap();
+        picks[group] += 1;
+    }
+    assert!(near(picks[ROOT_CGROUP], 100) && near(picks[a], 300) && near(picks[x], 100));
+    assert!(!groups.remove(b), "removed a group that has children");
+    assert!(groups.remove(x) && groups.remove(y) && groups.remove(b));
+    assert!(!groups.remove(ROOT_CGROUP), "removed the root group");
+    assert_eq!(
+        groups.create("/b", 100),
+        Some(b),
+        "the id of a removed group was not reused"
+    );
+    assert_eq!(groups.pick(core::iter::empty()), None);
+    info!("cgroup_test passed!");
+}
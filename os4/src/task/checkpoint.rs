@@ -0,0 +1,287 @@
+//! Checkpoint and restore
+//!
+//! A checkpoint is an image of a task that is not running: its registers,
+//! its user memory area by area, with the contents of each page that has a
+//! frame and the pages still reserved for a lazy mapping, and what was
+//! counted for it, from CPU time to the syscalls it made. Restoring the
+//! image makes a new task, a child of the one restoring it, that goes on
+//! from where the image was taken.
+//!
+//! A task may checkpoint itself: the copy restored returns 0 from the
+//! checkpoint, where the task gets the size of the image. A task stopped in
+//! the middle of a syscall, blocked or preempted in the kernel, makes the
+//! syscall again once restored, as Linux restarts one a signal interrupted.
+//!
+//! What lives outside of the task is not in the image: open files, pipes
+//! and sockets, semaphores, process and control groups, and credentials.
+//! The restored task has the standard input and outputs only, and runs as
+//! the one restoring it with its capabilities, limits and groups, as a task
+//! it spawned would; the sandbox of the image is kept unless that task has
+//! one of its own, and a negative nice value is only kept for root. A task
+//! that uses the vector registers, or that maps memory owned elsewhere such
+//! as a framebuffer, cannot be checkpointed.
+//!
+//! An image is made of words in the byte order of the machine, and only
+//! meant to be restored by the kernel build that made it. Restoring checks
+//! all of it, as any other input from user space.
+
+use super::{Personality, SignalFlags, TaskControlBlock, TaskCounters};
+use crate::config::{MAX_NICE, MAX_SYSCALL_NUM, MIN_NICE, PAGE_SIZE, USER_SPACE_END};
+use crate::mm::{MapPermission, MemorySet, SandboxProfile, VirtPageNum};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::convert::TryInto;
+use core::mem::size_of;
+
+/// "CKPT"
+const IMAGE_MAGIC: usize = 0x5450_4b43;
+/// 2 added the program break and the personality
+const IMAGE_VERSION: usize = 2;
+const WORD: usize = size_of::<usize>();
+
+/// Bytes to pad `len` bytes with to a whole word
+fn padding(len: usize) -> usize {
+    (WORD - len % WORD) % WORD
+}
+
+/// Writes an image, or only counts its bytes
+struct Writer {
+    image: Vec<u8>,
+    len: usize,
+    counting: bool,
+}
+
+impl Writer {
+    fn put(&mut self, bytes: &[u8]) {
+        self.len += bytes.len();
+        if !self.counting {
+            self.image.extend_from_slice(bytes);
+        }
+    }
+    fn word(&mut self, word: usize) {
+        self.put(&word.to_ne_bytes());
+    }
+    /// `bytes`, after their length, padded to a whole word
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.word(bytes.len());
+        self.put(bytes);
+        self.put(&[0; WORD][..padding(bytes.len())]);
+    }
+    /// `value`, a structure of integers only, as it is in memory
+    fn plain<T: Copy>(&mut self, value: &T) {
+        let bytes =
+            unsafe { core::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) };
+        self.bytes(bytes);
+    }
+}
+
+struct Reader<'a> {
+    image: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if len > self.image.len() {
+            return None;
+        }
+        let (taken, rest) = self.image.split_at(len);
+        self.image = rest;
+        Some(taken)
+    }
+    fn word(&mut self) -> Option<usize> {
+        Some(usize::from_ne_bytes(self.take(WORD)?.try_into().ok()?))
+    }
+    fn bytes(&mut self) -> Option<&'a [u8]> {
+        let len = self.word()?;
+        let bytes = self.take(len)?;
+        self.take(padding(len))?;
+        Some(bytes)
+    }
+    fn str(&mut self) -> Option<&'a str> {
+        core::str::from_utf8(self.bytes()?).ok()
+    }
+    /// A structure of integers only, which any bytes of its size make.
+    fn plain<T: Copy>(&mut self) -> Option<T> {
+        let bytes = self.bytes()?;
+        if bytes.len() != size_of::<T>() {
+            return None;
+        }
+        Some(unsafe { core::ptr::read_unaligned(bytes.as_ptr() as *const T) })
+    }
+}
+
+/// Write the image of `task`, which is the task asking if `itself`. None
+/// if it cannot be checkpointed.
+fn write(task: &TaskControlBlock, itself: bool, out: &mut Writer) -> Option<()> {
+    if task.vector.is_some() {
+        return None;
+    }
+    let memory_set = &task.memory_set;
+    let areas = memory_set.user_areas()?;
+    out.word(IMAGE_MAGIC);
+    out.word(IMAGE_VERSION);
+    out.bytes(task.name.as_bytes());
+    out.bytes(task.cwd.as_bytes());
+    let trap_cx = task.get_trap_cx();
+    let mut regs = trap_cx.x;
+    let mut pc = trap_cx.sepc;
+    if itself {
+        // what the copy returns from the checkpoint
+        regs[10] = 0;
+    } else if task.in_syscall {
+        // back to the `ecall`, whose arguments are still in the registers
+        pc -= 4;
+    }
+    for &reg in regs.iter() {
+        out.word(reg);
+    }
+    out.word(pc);
+    out.word(task.signals.bits() as usize);
+    out.word(task.signal_mask.bits() as usize);
+    out.word(task.priority);
+    out.word(task.nice as usize);
+    out.plain(&task.counters);
+    out.plain(&task.syscall_times);
+    out.word(task.base_size);
+    out.word(task.program_brk);
+    out.word(task.personality as usize);
+    out.word(memory_set.allow_wx() as usize);
+    match memory_set.sandbox() {
+        Some(profile) => {
+            out.word(1);
+            out.plain(&profile);
+        }
+        None => out.word(0),
+    }
+    out.word(areas.len());
+    for (start, end, perm) in areas {
+        out.word(start.0);
+        out.word(end.0);
+        out.word(perm.bits() as usize);
+    }
+    let lazy_pages = memory_set.lazy_pages();
+    out.word(lazy_pages.len());
+    for (vpn, _) in lazy_pages {
+        out.word(vpn.0);
+    }
+    let pages = memory_set.user_pages();
+    out.word(pages.len());
+    for (vpn, ppn) in pages {
+        out.word(vpn.0);
+        out.put(ppn.get_bytes_array());
+    }
+    Some(())
+}
+
+/// The size of the image of `task`, with the image if it is at most
+/// `max_len` bytes. None if the task cannot be checkpointed, or if the
+/// image does not fit in the kernel heap.
+pub(super) fn save(
+    task: &TaskControlBlock,
+    itself: bool,
+    max_len: usize,
+) -> Option<(usize, Option<Vec<u8>>)> {
+    let mut out = Writer {
+        image: Vec::new(),
+        len: 0,
+        counting: true,
+    };
+    write(task, itself, &mut out)?;
+    let len = out.len;
+    if len > max_len {
+        return Some((len, None));
+    }
+    let mut image = Vec::new();
+    image.try_reserve_exact(len).ok()?;
+    let mut out = Writer {
+        image,
+        len: 0,
+        counting: false,
+    };
+    write(task, itself, &mut out)?;
+    Some((len, Some(out.image)))
+}
+
+/// A task made from `image`, confined to `sandbox` if there is one rather
+/// than to the sandbox of the image, which its areas must then fit in. None if the image is not one this
+/// kernel made, or if frames run out.
+pub(super) fn load(image: &[u8], sandbox: Option<SandboxProfile>) -> Option<TaskControlBlock> {
+    let mut input = Reader { image };
+    if input.word()? != IMAGE_MAGIC || input.word()? != IMAGE_VERSION {
+        return None;
+    }
+    let name = input.str()?;
+    let cwd = input.str()?;
+    if !cwd.starts_with('/') {
+        return None;
+    }
+    let mut regs = [0; 32];
+    for reg in regs.iter_mut() {
+        *reg = input.word()?;
+    }
+    regs[0] = 0;
+    let pc = input.word()?;
+    let signals = SignalFlags::from_bits_truncate(input.word()? as u32);
+    let signal_mask = SignalFlags::from_bits_truncate(input.word()? as u32);
+    let priority = input.word()?;
+    let nice = input.word()? as isize;
+    if priority < 2 || !(MIN_NICE..=MAX_NICE).contains(&nice) {
+        return None;
+    }
+    let counters: TaskCounters = input.plain()?;
+    let syscall_times: [u32; MAX_SYSCALL_NUM] = input.plain()?;
+    let base_size = input.word()?;
+    let program_brk = input.word()?;
+    let personality = Personality::from_usize(input.word()?)?;
+    if !(base_size..=USER_SPACE_END).contains(&program_brk) {
+        return None;
+    }
+    let allow_wx = input.word()? != 0;
+    let image_sandbox = match input.word()? {
+        0 => None,
+        1 => Some(input.plain::<SandboxProfile>()?).filter(SandboxProfile::is_valid),
+        _ => return None,
+    };
+    let mut memory_set = MemorySet::new_trap_only();
+    memory_set.set_allow_wx(allow_wx);
+    if let Some(profile) = sandbox.or(image_sandbox) {
+        memory_set.set_sandbox(profile);
+    }
+    for _ in 0..input.word()? {
+        let start = VirtPageNum(input.word()?);
+        let end = VirtPageNum(input.word()?);
+        let perm = MapPermission::from_bits(input.word()?.try_into().ok()?)?;
+        if !memory_set.restore_area(start, end, perm) {
+            return None;
+        }
+    }
+    for _ in 0..input.word()? {
+        if !memory_set.restore_lazy_page(VirtPageNum(input.word()?)) {
+            return None;
+        }
+    }
+    for _ in 0..input.word()? {
+        let vpn = VirtPageNum(input.word()?);
+        if !memory_set.restore_page(vpn, input.take(PAGE_SIZE)?.try_into().ok()?) {
+            return None;
+        }
+    }
+    if !input.image.is_empty() {
+        return None;
+    }
+    let mut task = TaskControlBlock::with_memory_set(memory_set, base_size, pc, false);
+    task.set_name(name);
+    task.cwd = String::from(cwd);
+    task.get_trap_cx().x = regs;
+    task.signals = signals;
+    task.signal_mask = signal_mask - SignalFlags::unblockable();
+    task.priority = priority;
+    task.nice = nice;
+    let resident_pages = task.counters.peak_resident_pages;
+    task.counters = counters;
+    task.counters.peak_resident_pages = counters.peak_resident_pages.max(resident_pages);
+    task.syscall_times = syscall_times;
+    task.program_brk = program_brk;
+    task.personality = personality;
+    Some(task)
+}
@@ -9,25 +9,67 @@
 //! Be careful when you see [`__switch`]. Control flow around this function
 //! might not be what you expect.
 
+mod canary;
+mod capability;
+mod cgroup;
+mod checkpoint;
 mod context;
+mod personality;
+mod replay;
+mod rlimit;
+mod signal;
+mod sleep;
 mod switch;
+mod table;
 #[allow(clippy::module_inception)]
 mod task;
+mod vector;
+mod wait_queue;
 
-use crate::{loader::{get_app_data, get_num_app}, mm::VirtAddr};
+use crate::{
+    loader::{find_app, get_app_data, get_app_name, get_num_app},
+    mm::VirtAddr,
+};
+use crate::cmdline::{self, Scheduler};
+use crate::mm::{frame_stats, heap_used, MapPermission, PhysPageNum, SandboxProfile};
+use crate::mm::{VirtPageNum, KERNEL_SPACE};
+use crate::mm::trim_free_frames;
+use crate::fs::File;
+use crate::audit::{self, AuditEvent};
+use crate::ipc::sem_exit;
+use crate::ktest::KernelTest;
 use crate::sync::UPSafeCell;
 use crate::trap::TrapContext;
+use crate::console::Stdout;
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
 use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, Ordering};
 use lazy_static::*;
 pub use switch::__switch;
-pub use task::{TaskControlBlock, TaskStatus};
+pub use task::{TaskControlBlock, TaskCounters, TaskStatus};
 
+pub use capability::Capabilities;
+pub use cgroup::{cgroup_create, cgroup_exists, cgroup_set_weight, CgroupStat, ROOT_CGROUP};
 pub use context::TaskContext;
+pub use personality::Personality;
+pub use replay::{count_syscall, should_preempt};
+pub use rlimit::{RLimit, Resource, ResourceLimits, RLIM_INFINITY};
+pub use signal::{SignalFlags, MAX_SIG};
+pub use sleep::{block_until, sleep_until, wake_sleepers};
+pub use table::TaskTable;
+pub use vector::VectorState;
+pub use wait_queue::WaitQueue;
 
-use crate::config::CLOCK_FREQ;
+use crate::config::{CLOCK_FREQ, CPU_USAGE_WINDOW_SECS};
 use crate::timer::TICKS_PER_SEC;
-use crate::timer::get_time_ms;
-use crate::config::MAX_SYSCALL_NUM;
+use crate::timer::{get_time, get_time_ms};
+use crate::config::{kernel_stack_position, MAX_SYSCALL_NUM, PAGE_SIZE, RESCHED_BATCH_PAGES};
+use crate::config::{LINUX_MMAP_BASE, USER_SPACE_END};
+use crate::trap::cond_resched;
 
 /// The task manager, where all the tasks are managed.
 ///
@@ -39,8 +81,6 @@ use crate::config::MAX_SYSCALL_NUM;
 /// borrowing checks to runtime. You can see examples on how to use `inner` in
 /// existing functions on `TaskManager`.
 pub struct TaskManager {
-    /// total number of tasks
-    num_app: usize,
     /// use inner value to get mutable access
     inner: UPSafeCell<TaskManagerInner>,
 }
@@ -48,27 +88,105 @@ pub struct TaskManager {
 /// The task manager inner in 'UPSafeCell'
 struct TaskManagerInner {
     /// task list
-    tasks: Vec<TaskControlBlock>,
+    tasks: TaskTable,
     /// id of current `Running` task
     current_task: usize,
+    /// ids of the `Ready` tasks, in the order round robin runs them
+    ready: VecDeque<usize>,
+    /// ids of the `Exited` tasks to reap, zombies left out until collected
+    exited: Vec<usize>,
+    /// pid, name and exit code of every task that has exited, for the
+    /// summary at shutdown
+    exit_codes: Vec<(usize, String, i32)>,
+    /// time of the last task switch, to charge CPU time
+    switched_at: usize,
+    /// time the current task last entered the kernel, or was switched to,
+    /// to charge system time
+    kernel_entered_at: usize,
+    /// time CPU usage was last updated
+    usage_sampled_at: usize,
 }
 
+impl TaskManagerInner {
+    /// Charge the current task, and its control group, for the time it ran
+    /// since the last charge, up to `now`.
+    fn charge_cpu_time(&mut self, now: usize) {
+        let current = self.current_task;
+        let time = now - self.switched_at;
+        self.tasks[current].counters.cpu_time += time;
+        cgroup::charge(self.tasks[current].cgroup, time);
+        self.switched_at = now;
+    }
+}
+
+/// set once the first task runs
+static TASKS_STARTED: AtomicBool = AtomicBool::new(false);
+
 lazy_static! {
     /// a `TaskManager` instance through lazy_static!
     pub static ref TASK_MANAGER: TaskManager = {
         info!("init TASK_MANAGER");
         let num_app = get_num_app();
         info!("num_app = {}", num_app);
-        let mut tasks: Vec<TaskControlBlock> = Vec::new();
-        for i in 0..num_app {
-            tasks.push(TaskControlBlock::new(get_app_data(i), i));
+        let init = match cmdline::init_app() {
+            Some(name) => find_app(&name).unwrap_or_else(|| {
+                warn!("[kernel] no application {:?} to run first", name);
+                0
+            }),
+            None => 0,
+        };
+        #[cfg(feature = "hypervisor")]
+        let guest = cmdline::guest_app().and_then(|name| {
+            let app = find_app(&name);
+            if app.is_none() {
+                warn!("[kernel] no application {:?} to run as a guest", name);
+            }
+            app
+        });
+        // a test harness runs alone, as init, and spawns the others
+        let harness = cmdline::harness_app().and_then(|name| {
+            let app = find_app(&name);
+            if app.is_none() {
+                warn!("[kernel] no application {:?} to run as the test harness", name);
+            }
+            app
+        });
+        let (apps, init): (Vec<usize>, usize) = match harness {
+            Some(app) => (vec![app], 0),
+            None => ((0..num_app).collect(), init),
+        };
+        // the table is empty, so each app gets its place in `apps` as id
+        let mut tasks = TaskTable::new();
+        for (id, &i) in apps.iter().enumerate() {
+            // a guest that cannot be loaded runs as an application
+            #[cfg(feature = "hypervisor")]
+            if guest == Some(i) {
+                if let Some(mut task) = TaskControlBlock::new_guest(get_app_data(i)) {
+                    task.set_name(get_app_name(i));
+                    let id = tasks.insert(task).expect("too many applications");
+                    tasks[id].pgid = id;
+                    crate::hypervisor::set_guest_task(id);
+                    continue;
+                }
+            }
+            let mut task = TaskControlBlock::new(get_app_data(i), id == init);
+            task.set_name(get_app_name(i));
+            // each leads a process group of its own
+            task.pgid = id;
+            tasks.insert(task).expect("too many applications");
         }
         TaskManager {
-            num_app,
             inner: unsafe {
                 UPSafeCell::new(TaskManagerInner {
                     tasks,
-                    current_task: 0,
+                    current_task: init,
+                    // the others in task list order, from the one after init
+                    ready: (init + 1..init + apps.len()).map(|id| id % apps.len()).collect(),
+                    exited: Vec::new(),
+                    exit_codes: Vec::new(),
+                    switched_at: 0,
+                    kernel_entered_at: 0,
+                    usage_sampled_at: 0,
                 })
             },
         }
@@ -76,17 +194,29 @@ lazy_static! {
 }
 
 impl TaskManager {
-    /// Run the first task in task list.
+    /// Run the init task, the first in task list unless `init=` named another.
     ///
     /// Generally, the first task in task list is an idle task (we call it zero process later).
     /// But in ch4, we load apps statically, so the first task is a real app.
     fn run_first_task(&self) -> ! {
         let mut inner = self.inner.exclusive_access();
-        let next_task = &mut inner.tasks[0];
+        let init = inner.current_task;
+        let next_task = &mut inner.tasks[init];
         next_task.task_status = TaskStatus::Running;
+        next_task.charge_stride();
+        // nothing ran before, so there is nothing to charge
+        crate::perf::on_switch();
+        #[cfg(feature = "page-poison")]
+        crate::mm::page_poison_on_switch(init);
+        #[cfg(feature = "fault-inject")]
+        crate::fault::on_switch(init);
+        inner.switched_at = get_time();
+        inner.usage_sampled_at = inner.switched_at;
+        inner.kernel_entered_at = inner.switched_at;
+        let next_task = &mut inner.tasks[init];
         next_task.first_time = get_time_ms();
         next_task.dispatched = true;
-        info!("set task {} dispatched time: {}", 0, next_task.first_time);
+        info!("set task {} ({}) dispatched time: {}", init, next_task.name, next_task.first_time);
         let next_task_cx_ptr = &next_task.task_cx as *const TaskContext;
         drop(inner);
         let mut _unused = TaskContext::zero_init();
@@ -97,29 +227,388 @@ impl TaskManager {
         panic!("unreachable in run_first_task!");
     }
 
-    /// Change the status of current `Running` task into `Ready`.
-    fn mark_current_suspended(&self) {
+    /// Change the status of current `Running` task into `Ready`, counting
+    /// the switch as involuntary if it was `preempted`.
+    fn mark_current_suspended(&self, preempted: bool) {
         let mut inner = self.inner.exclusive_access();
         let current = inner.current_task;
-        inner.tasks[current].task_status = TaskStatus::Ready;
+        inner.ready.push_back(current);
+        let task = &mut inner.tasks[current];
+        task.task_status = TaskStatus::Ready;
+        if preempted {
+            replay::on_preempt();
+            task.counters.involuntary_switches += 1;
+        } else {
+            task.counters.voluntary_switches += 1;
+        }
     }
 
-    /// Change the status of current `Running` task into `Exited`.
-    fn mark_current_exited(&self) {
+    /// Change the status of current `Running` task into `Blocked`, out of the
+    /// ready queue until [`wake_task`](Self::wake_task).
+    fn mark_current_blocked(&self) {
         let mut inner = self.inner.exclusive_access();
         let current = inner.current_task;
-        inner.tasks[current].task_status = TaskStatus::Exited;
+        let task = &mut inner.tasks[current];
+        task.task_status = TaskStatus::Blocked;
+        task.counters.voluntary_switches += 1;
+    }
+
+    /// Make task `id` `Ready` if it is `Blocked`, returning whether it was.
+    fn wake_task(&self, id: usize) -> bool {
+        let mut inner = self.inner.exclusive_access();
+        match inner.tasks.get_mut(id) {
+            Some(task) if task.task_status == TaskStatus::Blocked => {
+                task.task_status = TaskStatus::Ready;
+            }
+            _ => return false,
+        }
+        inner.ready.push_back(id);
+        true
+    }
+
+    /// Load application `app` as a child of the current task, running as
+    /// the same user with the same capabilities, limits, sandbox, working
+    /// directory, process group, control group and personality.
+    /// Returns its id, or None if the task table is full.
+    fn spawn(&self, app: usize) -> Option<usize> {
+        let mut task = TaskControlBlock::new(get_app_data(app), false);
+        task.set_name(get_app_name(app));
+        let mut inner = self.inner.exclusive_access();
+        let current = inner.current_task;
+        let parent = &inner.tasks[current];
+        task.uid = parent.uid;
+        task.gid = parent.gid;
+        task.capabilities = parent.capabilities;
+        task.limits = parent.limits;
+        task.cwd = parent.cwd.clone();
+        task.pgid = parent.pgid;
+        task.cgroup = parent.cgroup;
+        if let Some(profile) = parent.memory_set.sandbox() {
+            task.memory_set.set_sandbox(profile);
+        }
+        if parent.personality == Personality::Linux
+            && !personality::start_linux(&mut task, get_app_data(app))
+        {
+            return None;
+        }
+        task.parent = Some(current);
+        let id = inner.tasks.insert(task)?;
+        inner.ready.push_back(id);
+        inner.tasks[current].children.push(id);
+        Some(id)
+    }
+
+    /// The checkpoint image of task `pid`, as [`checkpoint::save`] makes
+    /// it. None if there is no such task that has not exited.
+    fn checkpoint_task(&self, pid: usize, max_len: usize) -> Option<(usize, Option<Vec<u8>>)> {
+        let inner = self.inner.exclusive_access();
+        match inner.tasks.get(pid) {
+            Some(task) if task.task_status != TaskStatus::Exited => {
+                checkpoint::save(task, pid == inner.current_task, max_len)
+            }
+            _ => None,
+        }
     }
 
-    /// Find next task to run and return task id.
+    /// Make a task of `image` a child of the current task, as [`spawn`]
+    /// would, keeping its name and working directory.
+    /// Returns its id, or None if the image is not valid, if frames run out
+    /// or if the task table is full.
     ///
-    /// In this case, we only return the first `Ready` task in task list.
-    fn find_next_task(&self) -> Option<usize> {
+    /// [`spawn`]: Self::spawn
+    fn restore_task(&self, image: &[u8]) -> Option<usize> {
+        let sandbox = {
+            let inner = self.inner.exclusive_access();
+            inner.tasks[inner.current_task].memory_set.sandbox()
+        };
+        let mut task = checkpoint::load(image, sandbox)?;
+        let mut inner = self.inner.exclusive_access();
+        let current = inner.current_task;
+        let parent = &inner.tasks[current];
+        task.uid = parent.uid;
+        task.gid = parent.gid;
+        task.capabilities = parent.capabilities;
+        task.limits = parent.limits;
+        task.pgid = parent.pgid;
+        task.cgroup = parent.cgroup;
+        if task.uid != 0 {
+            // only root may raise the priority of a task
+            task.nice = task.nice.max(0);
+        }
+        task.parent = Some(current);
+        let id = inner.tasks.insert(task)?;
+        inner.ready.push_back(id);
+        inner.tasks[current].children.push(id);
+        Some(id)
+    }
+
+    /// The id and exit code of the child `pid` of the current task, or of
+    /// any child if `pid` is -1, if it has exited. None if there is no such
+    /// child, Some(None) if it has not exited yet. The child stays a zombie
+    /// until [`Self::collect_child`].
+    fn wait_child(&self, pid: isize) -> Option<Option<(usize, i32)>> {
         let inner = self.inner.exclusive_access();
+        let task = &inner.tasks[inner.current_task];
+        let is_pid = |id: usize| pid == -1 || id as isize == pid;
+        if let Some(&child) = task.exited_children.iter().find(|&&(id, _)| is_pid(id)) {
+            return Some(Some(child));
+        }
+        if task.children.iter().any(|&id| is_pid(id)) {
+            Some(None)
+        } else {
+            None
+        }
+    }
+
+    /// Forget the exit code of the exited child `id` of the current task,
+    /// so that the zombie is reaped and its id may be given again.
+    fn collect_child(&self, id: usize) {
+        let mut inner = self.inner.exclusive_access();
+        let current = inner.current_task;
+        let exited_children = &mut inner.tasks[current].exited_children;
+        let before = exited_children.len();
+        exited_children.retain(|&(child, _)| child != id);
+        if exited_children.len() < before {
+            inner.exited.push(id);
+        }
+    }
+
+    /// Add `task` to the task table, `Ready`, leading a process group of
+    /// its own, returning its id, or None if the table is full.
+    fn add_task(&self, mut task: TaskControlBlock) -> Option<usize> {
+        let mut inner = self.inner.exclusive_access();
+        task.task_status = TaskStatus::Ready;
+        let id = inner.tasks.insert(task)?;
+        inner.tasks[id].pgid = id;
+        inner.ready.push_back(id);
+        Some(id)
+    }
+
+    /// Take task `id` out of the task table if it has exited and is not the
+    /// one whose kernel stack is in use.
+    fn remove_task(&self, id: usize) -> Option<TaskControlBlock> {
+        let mut inner = self.inner.exclusive_access();
+        if id == inner.current_task || inner.tasks.get(id)?.task_status != TaskStatus::Exited {
+            return None;
+        }
+        inner.tasks.remove(id)
+    }
+
+    /// Whether some task waits to be woken.
+    fn any_blocked(&self) -> bool {
+        self.inner
+            .exclusive_access()
+            .tasks
+            .iter()
+            .any(|(_, task)| task.task_status == TaskStatus::Blocked)
+    }
+
+    /// Change the status of current `Running` task into `Exited`, returning
+    /// its files for the caller to close.
+    fn mark_current_exited(&self, exit_code: i32) -> Vec<Option<Arc<dyn File + Send + Sync>>> {
+        let mut inner = self.inner.exclusive_access();
+        let current = inner.current_task;
+        inner.tasks[current].task_status = TaskStatus::Exited;
+        let files = core::mem::take(&mut inner.tasks[current].fd_table);
+        // leave the exit code to the parent, keeping the id as a zombie
+        // until it is collected, and the children without one
+        let parent = inner.tasks[current].parent.take();
+        match parent.and_then(|parent| inner.tasks.get_mut(parent)) {
+            Some(parent) => {
+                parent.children.retain(|&child| child != current);
+                parent.exited_children.push((current, exit_code));
+            }
+            None => inner.exited.push(current),
+        }
+        let zombies = core::mem::take(&mut inner.tasks[current].exited_children);
+        inner.exited.extend(zombies.into_iter().map(|(id, _)| id));
+        for child in core::mem::take(&mut inner.tasks[current].children) {
+            if let Some(child) = inner.tasks.get_mut(child) {
+                child.parent = None;
+            }
+        }
+        let name = inner.tasks[current].name.clone();
+        inner.exit_codes.push((current, name, exit_code));
+        // charge the time up to now, so that the record is complete
+        let now = get_time();
+        inner.charge_cpu_time(now);
+        inner.tasks[current].counters.system_time += now - inner.kernel_entered_at;
+        inner.kernel_entered_at = now;
+        crate::acct::record(current, &inner.tasks[current], exit_code);
+        files
+    }
+
+    /// Print how each task exited, returning whether they all exited with
+    /// code 0.
+    fn print_exit_summary(&self) -> bool {
+        let inner = self.inner.exclusive_access();
+        println!("[kernel] Exit codes:");
+        for (pid, name, exit_code) in &inner.exit_codes {
+            println!("[kernel]   pid {:>2} {:<16} {}", pid, name, exit_code);
+        }
+        inner.exit_codes.iter().all(|&(_, _, exit_code)| exit_code == 0)
+    }
+
+    /// Take the exited tasks out of the task table, but the current one,
+    /// whose kernel stack is in use until the next switch.
+    fn take_exited(&self) -> Vec<(usize, TaskControlBlock)> {
+        let mut inner = self.inner.exclusive_access();
         let current = inner.current_task;
-        (current + 1..current + self.num_app + 1)
-            .map(|id| id % self.num_app)
-            .find(|id| inner.tasks[*id].task_status == TaskStatus::Ready)
+        let exited: Vec<usize> = inner.exited.drain(..).collect();
+        let mut reaped = Vec::new();
+        for id in exited {
+            if id == current {
+                inner.exited.push(id);
+            } else if let Some(task) = inner.tasks.remove(id) {
+                reaped.push((id, task));
+            }
+        }
+        reaped
+    }
+
+    /// Check the transitions of a task: added `Ready` at the back of the
+    /// ready queue, `Blocked` out of it, woken once, then exited and reaped.
+    fn sched_test(&self) {
+        if get_num_app() == 0 {
+            return;
+        }
+        let task = TaskControlBlock::new(get_app_data(0), false);
+        let id = self.add_task(task).expect("the task table is full");
+        let status = |id| self.inner.exclusive_access().tasks[id].task_status;
+        assert_eq!(status(id), TaskStatus::Ready);
+        assert_eq!(self.inner.exclusive_access().ready.back(), Some(&id));
+        // as if it had run and waited
+        let mut inner = self.inner.exclusive_access();
+        inner.ready.retain(|&ready| ready != id);
+        inner.tasks[id].task_status = TaskStatus::Blocked;
+        drop(inner);
+        assert!(self.remove_task(id).is_none(), "removed a task that has not exited");
+        assert!(self.wake_task(id));
+        assert_eq!(status(id), TaskStatus::Ready);
+        assert_eq!(self.inner.exclusive_access().ready.back(), Some(&id));
+        assert!(!self.wake_task(id), "woke a task that is not blocked");
+        // as if it had run and exited
+        let mut inner = self.inner.exclusive_access();
+        inner.ready.retain(|&ready| ready != id);
+        inner.tasks[id].task_status = TaskStatus::Exited;
+        inner.exited.push(id);
+        drop(inner);
+        reap_exited_tasks();
+        assert!(self.inner.exclusive_access().tasks.get(id).is_none(), "an exited task was not reaped");
+        assert!(!self.wake_task(id), "woke a task that is gone");
+        info!("sched_test passed!");
+    }
+
+    /// Check that reaping an exited task gives back every frame it had.
+    fn reap_test(&self) {
+        if get_num_app() == 0 {
+            return;
+        }
+        let free = frame_stats().unwrap().free;
+        let task = TaskControlBlock::new(get_app_data(0), false);
+        let mut inner = self.inner.exclusive_access();
+        let id = inner.tasks.insert(task).expect("the task table is full");
+        inner.tasks[id].task_status = TaskStatus::Exited;
+        inner.exited.push(id);
+        drop(inner);
+        reap_exited_tasks();
+        assert_eq!(frame_stats().unwrap().free, free, "a reaped task kept frames");
+        info!("reap_test passed!");
+    }
+
+    /// Add and remove tasks by the thousand, as exiting and as killed, and
+    /// check that ids and kernel stacks are used again and that frames and
+    /// heap go back to what they were.
+    fn stress_test(&self) {
+        const ROUNDS: usize = 250;
+        const BATCH: usize = 8;
+        if get_num_app() == 0 {
+            return;
+        }
+        let stack_mapped = |id| {
+            let (bottom, _) = kernel_stack_position(id);
+            let vpn: VirtPageNum = VirtAddr::from(bottom).floor();
+            KERNEL_SPACE.lock().translate(vpn).map_or(false, |pte| pte.is_valid())
+        };
+        let mut baseline = None;
+        let mut ids = Vec::new();
+        for round in 0..ROUNDS {
+            for _ in 0..BATCH {
+                let task = TaskControlBlock::new(get_app_data(0), false);
+                match self.add_task(task) {
+                    Some(id) => ids.push(id),
+                    None => break,
+                }
+            }
+            assert!(!ids.is_empty(), "the task table is full");
+            if let Some((first_ids, _, _)) = &baseline {
+                assert_eq!(&ids, first_ids, "task ids were not used again");
+            }
+            assert!(ids.iter().all(|&id| stack_mapped(id)), "a task has no kernel stack");
+            let mut inner = self.inner.exclusive_access();
+            inner.ready.retain(|id| !ids.contains(id));
+            for &id in &ids {
+                inner.tasks[id].task_status = TaskStatus::Exited;
+            }
+            // half exit and are reaped, half are removed at once
+            let (exited, killed) = ids.split_at(ids.len() / 2);
+            inner.exited.extend_from_slice(exited);
+            drop(inner);
+            for &id in killed {
+                drop(self.remove_task(id).expect("a task could not be removed"));
+            }
+            reap_exited_tasks();
+            assert!(ids.iter().all(|&id| !stack_mapped(id)), "a kernel stack was kept");
+            // the first round may grow the task table and the queues
+            let usage = (frame_stats().unwrap().free, heap_used());
+            match &baseline {
+                Some((_, free, heap)) => {
+                    assert_eq!(usage.0, *free, "round {} kept frames", round);
+                    assert_eq!(usage.1, *heap, "round {} kept heap", round);
+                }
+                None => baseline = Some((ids.clone(), usage.0, usage.1)),
+            }
+            ids.clear();
+        }
+        info!("stress_test passed!");
+    }
+
+    /// Take next task to run off the ready queue and return task id.
+    ///
+    /// Round robin takes the `Ready` task that has waited longest, in
+    /// constant time; stride the one with the least pass, the one that has
+    /// waited longest on a tie. With control groups, either picks among
+    /// the tasks of the group picked first. Exited tasks are never looked
+    /// at. When replaying, it is the task recorded.
+    fn find_next_task(&self) -> Option<usize> {
+        let mut inner = self.inner.exclusive_access();
+        let inner = &mut *inner;
+        if let Some(next) = replay::replayed_switch() {
+            match inner.ready.iter().position(|&id| id == next) {
+                Some(position) => return inner.ready.remove(position),
+                None => replay::diverged(),
+            }
+        }
+        let tasks = &inner.tasks;
+        let group = cgroup::pick(inner.ready.iter().map(|&id| tasks[id].cgroup));
+        let in_group = |id: usize| group.map_or(true, |group| tasks[id].cgroup == group);
+        let next = match cmdline::scheduler() {
+            Scheduler::RoundRobin => {
+                let position = inner.ready.iter().position(|&id| in_group(id))?;
+                inner.ready.remove(position)
+            }
+            Scheduler::Stride => {
+                let (position, _) = inner.ready.iter().enumerate().filter(|(_, &id)| in_group(id)).min_by(|(_, &a), (_, &b)| {
+                    // passes wrap, but stay within half the range of each other
+                    (tasks[a].pass.wrapping_sub(tasks[b].pass) as isize).cmp(&0)
+                })?;
+                inner.ready.remove(position)
+            }
+        };
+        if let Some(next) = next {
+            replay::record_switch(next);
+        }
+        next
     }
 
     /// Get the current 'Running' task's token.
@@ -142,12 +631,30 @@ impl TaskManager {
             let mut inner = self.inner.exclusive_access();
             let current = inner.current_task;
             inner.tasks[next].task_status = TaskStatus::Running;
+            inner.tasks[next].charge_stride();
             if  inner.tasks[next].dispatched == false {
                 inner.tasks[next].first_time = get_time_ms();
                 inner.tasks[next].dispatched = true;
-                info!("set task {} dispatched time: {}", next, inner.tasks[next].first_time);
+                let next_task = &inner.tasks[next];
+                info!("set task {} ({}) dispatched time: {}", next, next_task.name, next_task.first_time);
             }
             inner.current_task = next;
+            trace_event!(SchedSwitch, current, next, inner.tasks[current].task_status);
+            canary::check(current);
+            let perf = crate::perf::on_switch();
+            inner.tasks[current].counters.perf.add(&perf);
+            let now = get_time();
+            inner.charge_cpu_time(now);
+            inner.tasks[current].counters.system_time += now - inner.kernel_entered_at;
+            inner.kernel_entered_at = now;
+            #[cfg(feature = "kcov")]
+            crate::kcov::on_switch(next);
+            #[cfg(feature = "page-poison")]
+            crate::mm::page_poison_on_switch(next);
+            #[cfg(feature = "fault-inject")]
+            crate::fault::on_switch(next);
+            crate::watchdog::pet_kernel();
+            vector::on_switch(&mut inner.tasks, current, next);
             let current_task_cx_ptr = &mut inner.tasks[current].task_cx as *mut TaskContext;
             let next_task_cx_ptr = &inner.tasks[next].task_cx as *const TaskContext;
             drop(inner);
@@ -156,8 +663,30 @@ impl TaskManager {
                 __switch(current_task_cx_ptr, next_task_cx_ptr);
             }
             // go back to user mode
+        } else if sleep::next_deadline().is_some()
+            || crate::fs::has_interrupt_waiters()
+            || crate::random::has_seed_waiters()
+            || crate::net::has_watchers()
+        {
+            // only an interrupt wakes a task now, and there is nothing else
+            // to do
+            while self.inner.exclusive_access().ready.is_empty() {
+                crate::trap::wait_for_interrupt();
+            }
+            self.run_next_task();
+        } else if self.any_blocked() {
+            // no task sleeps, so this is a deadlock
+            println!("[kernel] All remaining tasks are blocked!");
+            self.print_exit_summary();
+            crate::ktest::print_reports();
+            crate::power::shutdown(true);
         } else {
-            panic!("All applications completed!");
+            println!("[kernel] All applications completed!");
+            // a failed application fails the run as QEMU sees it
+            let success = self.print_exit_summary();
+            // as does a failed test, if a harness ran any
+            let passed = crate::ktest::print_reports().unwrap_or(true);
+            crate::power::shutdown(!(success && passed));
         }
     }
 
@@ -171,19 +700,29 @@ impl TaskManager {
         let mut inner = self.inner.exclusive_access();
         let current = inner.current_task;
         let now = get_time_ms();
-        info!("task {:?} now time is {:?}", current, now);
-        info!("task {:?} first time is {:?}", current, inner.tasks[current].first_time);
+        let name = &inner.tasks[current].name;
+        info!("task {} ({}) now time is {:?}", current, name, now);
+        info!("task {} ({}) first time is {:?}", current, name, inner.tasks[current].first_time);
 
         let costs = now - inner.tasks[current].first_time ;
-        info!("task {:?} cost time {:?}", current, costs);
+        info!("task {} ({}) cost time {:?}", current, name, costs);
         costs
 
     }
 
+    fn set_current_in_syscall(&self, in_syscall: bool) {
+        let mut inner = self.inner.exclusive_access();
+        let current = inner.current_task;
+        inner.tasks[current].in_syscall = in_syscall;
+    }
+
     fn add_one_to_current_task(&self, call_id: usize)  {
         let mut inner = self.inner.exclusive_access();
         let current = inner.current_task;
-        inner.tasks[current].syscall_times[call_id] += 1;
+        // syscalls past the `TaskInfo` table are not counted
+        if let Some(times) = inner.tasks[current].syscall_times.get_mut(call_id) {
+            *times += 1;
+        }
         //info!("add task {current} syscall {call_id} to {:?}", inner.tasks[current].syscall_times[call_id]);
     }
 
@@ -196,7 +735,95 @@ impl TaskManager {
     fn mmap(&self, start: usize, len: usize, port: usize) -> isize {
         let mut inner = self.inner.exclusive_access();
         let current = inner.current_task;
-        inner.tasks[current].memory_set.mmap(start, len, port)
+        let task = &mut inner.tasks[current];
+        let ret = task.memory_set.mmap(start, len, port);
+        let resident = task.memory_set.resident_pages();
+        task.counters.peak_resident_pages = task.counters.peak_resident_pages.max(resident);
+        ret
+    }
+
+    fn mprotect(&self, start: usize, len: usize, port: usize) -> isize {
+        let mut inner = self.inner.exclusive_access();
+        let current = inner.current_task;
+        inner.tasks[current].memory_set.mprotect(start, len, port)
+    }
+
+    fn mincore(&self, start: usize, len: usize) -> Option<Vec<u8>> {
+        let inner = self.inner.exclusive_access();
+        inner.tasks[inner.current_task].memory_set.mincore(start, len)
+    }
+
+    fn is_anonymous(&self, start: usize, len: usize) -> bool {
+        let inner = self.inner.exclusive_access();
+        inner.tasks[inner.current_task].memory_set.is_anonymous(start, len)
+    }
+
+    fn set_current_priority(&self, priority: usize) {
+        let mut inner = self.inner.exclusive_access();
+        let current = inner.current_task;
+        inner.tasks[current].priority = priority;
+    }
+
+    fn set_current_allow_wx(&self, allow: bool) {
+        let mut inner = self.inner.exclusive_access();
+        let current = inner.current_task;
+        inner.tasks[current].memory_set.set_allow_wx(allow);
+    }
+
+    fn current_personality(&self) -> Personality {
+        let inner = self.inner.exclusive_access();
+        inner.tasks[inner.current_task].personality
+    }
+
+    fn set_current_personality(&self, personality: Personality) {
+        let mut inner = self.inner.exclusive_access();
+        let current = inner.current_task;
+        inner.tasks[current].personality = personality;
+    }
+
+    /// Map `len` bytes with permission `port` as in `mmap` where there is
+    /// room from `LINUX_MMAP_BASE` up, returning where.
+    fn mmap_anywhere(&self, len: usize, port: usize) -> Option<usize> {
+        let mut inner = self.inner.exclusive_access();
+        let current = inner.current_task;
+        let task = &mut inner.tasks[current];
+        let start = task.memory_set.find_free(LINUX_MMAP_BASE, len)?;
+        if task.memory_set.mmap(start, len, port) != 0 {
+            return None;
+        }
+        let resident = task.memory_set.resident_pages();
+        task.counters.peak_resident_pages = task.counters.peak_resident_pages.max(resident);
+        Some(start)
+    }
+
+    /// Where the heap of the current task starts, and its program break.
+    fn current_heap(&self) -> (usize, usize) {
+        let inner = self.inner.exclusive_access();
+        let task = &inner.tasks[inner.current_task];
+        (task.base_size, task.program_brk)
+    }
+
+    /// Move the program break of the current task to `brk`, which the
+    /// caller checked, mapping or unmapping the pages in between. Fails,
+    /// changing nothing, if the pages cannot be mapped.
+    fn set_current_brk(&self, brk: usize) -> bool {
+        let mut inner = self.inner.exclusive_access();
+        let current = inner.current_task;
+        let task = &mut inner.tasks[current];
+        let page_end = |addr: usize| (addr + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+        let (old_end, new_end) = (page_end(task.program_brk), page_end(brk));
+        if new_end > old_end {
+            // read and write, as `brk` heaps are
+            if task.memory_set.mmap(old_end, new_end - old_end, 0b011) != 0 {
+                return false;
+            }
+        } else if new_end < old_end && task.memory_set.munmap(new_end, old_end - new_end) != 0 {
+            return false;
+        }
+        task.program_brk = brk;
+        let resident = task.memory_set.resident_pages();
+        task.counters.peak_resident_pages = task.counters.peak_resident_pages.max(resident);
+        true
     }
 
     fn munmap(&self, start: usize, len: usize ) -> isize {
@@ -205,84 +832,1117 @@ impl TaskManager {
         inner.tasks[current].memory_set.munmap(start, len)
     }
 
-}
+    fn current_can_munmap(&self, start: usize, len: usize) -> bool {
+        let inner = self.inner.exclusive_access();
+        inner.tasks[inner.current_task].memory_set.can_munmap(start, len)
+    }
 
-/// Run the first task in task list.
-pub fn run_first_task() {
-    TASK_MANAGER.run_first_task();
-}
+    /// Free up to `max` frames of the current task, which is exiting,
+    /// returning whether any are left.
+    fn release_current_frames(&self, max: usize) -> bool {
+        let mut inner = self.inner.exclusive_access();
+        let current = inner.current_task;
+        inner.tasks[current].memory_set.release_frames(max)
+    }
 
-/// Switch current `Running` task to the task we have found,
-/// or there is no `Ready` task and we can exit with all applications completed
-fn run_next_task() {
-    TASK_MANAGER.run_next_task();
-}
+    fn map_current_linear(
+        &self,
+        start: usize,
+        ppn: PhysPageNum,
+        pages: usize,
+        permission: MapPermission,
+    ) -> isize {
+        let mut inner = self.inner.exclusive_access();
+        let current = inner.current_task;
+        inner.tasks[current]
+            .memory_set
+            .map_linear(start, ppn, pages, permission)
+    }
 
-/// Change the status of current `Running` task into `Ready`.
-fn mark_current_suspended() {
-    TASK_MANAGER.mark_current_suspended();
-}
+    /// Get the token of task `pid`, if it exists and has not exited.
+    fn get_task_token(&self, pid: usize) -> Option<usize> {
+        let inner = self.inner.exclusive_access();
+        match inner.tasks.get(pid) {
+            Some(task) if task.task_status != TaskStatus::Exited => Some(task.get_user_token()),
+            _ => None,
+        }
+    }
 
-/// Change the status of current `Running` task into `Exited`.
-fn mark_current_exited() {
-    TASK_MANAGER.mark_current_exited();
-}
+    fn get_current_counters(&self) -> (TaskCounters, usize) {
+        let inner = self.inner.exclusive_access();
+        let task = &inner.tasks[inner.current_task];
+        (task.counters, task.memory_set.resident_pages())
+    }
 
-/// Suspend the current 'Running' task and run the next task in task list.
-pub fn suspend_current_and_run_next() {
-    mark_current_suspended();
-    run_next_task();
-}
+    fn handle_lazy_fault(&self, token: usize, va: usize, access: MapPermission) -> bool {
+        let mut inner = self.inner.exclusive_access();
+        let task = match inner.tasks.iter_mut().find(|(_, task)| task.get_user_token() == token) {
+            Some((_, task)) => task,
+            None => return false,
+        };
+        if !task.memory_set.handle_lazy_fault(va, access) {
+            return false;
+        }
+        let resident = task.memory_set.resident_pages();
+        task.counters.peak_resident_pages = task.counters.peak_resident_pages.max(resident);
+        true
+    }
 
-/// Exit the current 'Running' task and run the next task in task list.
-pub fn exit_current_and_run_next() {
-    mark_current_exited();
-    run_next_task();
-}
+    /// Move the pages of every task down to free frames at lower addresses.
+    /// Returns how many moved, or None if the task table is in use.
+    fn compact_memory(&self) -> Option<usize> {
+        let mut inner = self.inner.try_exclusive_access()?;
+        Some(inner.tasks.iter_mut().map(|(_, task)| task.memory_set.migrate_frames()).sum())
+    }
 
-/// Get the current 'Running' task's token.
-pub fn current_user_token() -> usize {
-    TASK_MANAGER.get_current_token()
-}
+    fn enable_current_vector(&self) -> bool {
+        let mut inner = self.inner.exclusive_access();
+        let current = inner.current_task;
+        vector::enable(current, &mut inner.tasks[current])
+    }
 
-/// Get the current 'Running' task's trap contexts.
-pub fn current_trap_cx() -> &'static mut TrapContext {
-    TASK_MANAGER.get_current_trap_cx()
-}
+    fn count_current_lazy_fault(&self) {
+        let mut inner = self.inner.exclusive_access();
+        let current = inner.current_task;
+        let counters = &mut inner.tasks[current].counters;
+        counters.lazy_faults += 1;
+        counters.minor_faults += 1;
+    }
 
-pub fn add_one_while_syscall(id: usize) {
-    TASK_MANAGER.add_one_to_current_task(id);
-}
+    fn get_current_credentials(&self) -> (u32, u32) {
+        let inner = self.inner.exclusive_access();
+        let task = &inner.tasks[inner.current_task];
+        (task.uid, task.gid)
+    }
+
+    fn set_current_credentials(&self, uid: u32, gid: u32) {
+        let mut inner = self.inner.exclusive_access();
+        let current = inner.current_task;
+        inner.tasks[current].uid = uid;
+        inner.tasks[current].gid = gid;
+    }
 
+    fn get_current_capabilities(&self) -> Capabilities {
+        let inner = self.inner.exclusive_access();
+        inner.tasks[inner.current_task].capabilities
+    }
 
-use super::syscall::TaskInfo;
-pub fn get_task_info_inner(t: *mut TaskInfo) {
-    let a = TASK_MANAGER.get_current_task_status();
-    let b = TASK_MANAGER.get_current_task_syscall_times();
-    let c = TASK_MANAGER.get_current_task_costed_time();
-    unsafe {
-        *t = TaskInfo {
-            
-            status : a,
-            syscall_times: b,
-            time: c,
+    /// Keep only the capabilities of the current task that are in
+    /// `capabilities`, returning false if that would add any.
+    fn restrict_current_capabilities(&self, capabilities: Capabilities) -> bool {
+        let mut inner = self.inner.exclusive_access();
+        let current = inner.current_task;
+        let task = &mut inner.tasks[current];
+        if !task.capabilities.contains(capabilities) {
+            return false;
         }
+        task.capabilities = capabilities;
+        true
     }
 
-}
+    fn get_task_limit(&self, pid: usize, resource: Resource) -> Option<RLimit> {
+        let inner = self.inner.exclusive_access();
+        match inner.tasks.get(pid) {
+            Some(task) if task.task_status != TaskStatus::Exited => Some(task.limits.get(resource)),
+            _ => None,
+        }
+    }
 
-pub fn sys_mmap_inner(start: usize, len: usize, port: usize) -> isize {
-    let va = VirtAddr(start);
-    if ! va.aligned() || port & !0x7 != 0  || port & 0x7 == 0 {
-        return -1;
+    fn set_task_limit(&self, pid: usize, resource: Resource, limit: RLimit, privileged: bool) -> bool {
+        let mut inner = self.inner.exclusive_access();
+        match inner.tasks.get_mut(pid) {
+            Some(task) if task.task_status != TaskStatus::Exited => {
+                task.limits.set(resource, limit, privileged)
+            }
+            _ => false,
+        }
     }
-    TASK_MANAGER.mmap(start, len, port)
-}
 
-pub fn sys_munmap_inner(start: usize, len: usize ) -> isize {
-    let va = VirtAddr(start);
-    if ! va.aligned()  {
-        return -1;
+    /// Confine task `pid` to `profile`, if it has not run yet or is the
+    /// current task, and has no profile yet.
+    fn set_task_sandbox(&self, pid: usize, profile: SandboxProfile) -> bool {
+        let mut inner = self.inner.exclusive_access();
+        let current = inner.current_task;
+        match inner.tasks.get_mut(pid) {
+            Some(task) if pid == current || !task.dispatched => {
+                task.memory_set.set_sandbox(profile)
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether the current task may own `len` more bytes of memory.
+    fn current_may_grow(&self, len: usize) -> bool {
+        let inner = self.inner.exclusive_access();
+        let task = &inner.tasks[inner.current_task];
+        let limit = task.limits.soft(Resource::AddressSpace);
+        let owned = task.memory_set.resident_pages() * PAGE_SIZE;
+        owned.checked_add(len).map_or(false, |total| total <= limit)
+    }
+
+    /// Send `SIGXCPU` to the current task if it has run longer than its soft
+    /// CPU time limit, or `SIGKILL` past its hard one.
+    /// Charge the current task for the time it has run so far, and fold
+    /// what each task ran since the last update into its CPU usage.
+    fn update_cpu_usage(&self) {
+        let mut inner = self.inner.exclusive_access();
+        let inner = &mut *inner;
+        let now = get_time();
+        inner.charge_cpu_time(now);
+        let elapsed = (now - inner.usage_sampled_at) as u64;
+        inner.usage_sampled_at = now;
+        if elapsed == 0 {
+            return;
+        }
+        // one update per tick, so this averages over the window
+        let weight = CPU_USAGE_WINDOW_SECS * TICKS_PER_SEC;
+        for (_, task) in inner.tasks.iter_mut() {
+            let ran = (task.counters.cpu_time - task.cpu_time_sampled) as u64;
+            task.cpu_time_sampled = task.counters.cpu_time;
+            let share = (ran * 1_000_000 / elapsed).min(1_000_000) as usize;
+            task.cpu_usage = task.cpu_usage - task.cpu_usage / weight + share / weight;
+        }
+    }
+
+    /// The current task traps into the kernel.
+    fn enter_kernel(&self) {
+        self.inner.exclusive_access().kernel_entered_at = get_time();
+    }
+
+    /// The current task goes back to user space: charge it the time since
+    /// it entered the kernel.
+    fn leave_kernel(&self) {
+        let mut inner = self.inner.exclusive_access();
+        let current = inner.current_task;
+        let now = get_time();
+        inner.tasks[current].counters.system_time += now - inner.kernel_entered_at;
+        inner.kernel_entered_at = now;
+    }
+
+    fn get_task_usage(&self) -> Vec<TaskUsage> {
+        let inner = self.inner.exclusive_access();
+        inner
+            .tasks
+            .iter()
+            .map(|(pid, task)| TaskUsage {
+                pid,
+                name: task.name.clone(),
+                status: task.task_status,
+                nice: task.nice,
+                cpu_usage: task.cpu_usage,
+                counters: task.counters,
+                resident_pages: task.memory_set.resident_pages(),
+            })
+            .collect()
     }
-    TASK_MANAGER.munmap(start, len)
-}
\ No newline at end of file
+
+    fn check_current_cpu_limit(&self) {
+        let mut inner = self.inner.exclusive_access();
+        let ran = get_time() - inner.switched_at;
+        let current = inner.current_task;
+        let task = &mut inner.tasks[current];
+        let seconds = (task.counters.cpu_time + ran) / CLOCK_FREQ;
+        let limit = task.limits.get(Resource::Cpu);
+        if seconds >= limit.max {
+            task.signals |= SignalFlags::SIGKILL;
+        } else if seconds >= limit.cur {
+            task.signals |= SignalFlags::SIGXCPU;
+        }
+    }
+
+    /// Get the user of task `pid`, if it exists and has not exited.
+    /// Set the nice value of task `pid`; lowering it takes root.
+    fn set_task_nice(&self, pid: usize, nice: isize, privileged: bool) -> bool {
+        let mut inner = self.inner.exclusive_access();
+        match inner.tasks.get_mut(pid) {
+            Some(task) if task.task_status != TaskStatus::Exited => {
+                if nice < task.nice && !privileged {
+                    return false;
+                }
+                task.nice = nice;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn get_task_priority(&self, pid: usize) -> Option<usize> {
+        let inner = self.inner.exclusive_access();
+        match inner.tasks.get(pid) {
+            Some(task) if task.task_status != TaskStatus::Exited => Some(task.priority),
+            _ => None,
+        }
+    }
+
+    fn get_task_nice(&self, pid: usize) -> Option<isize> {
+        let inner = self.inner.exclusive_access();
+        match inner.tasks.get(pid) {
+            Some(task) if task.task_status != TaskStatus::Exited => Some(task.nice),
+            _ => None,
+        }
+    }
+
+    fn get_task_uid(&self, pid: usize) -> Option<u32> {
+        let inner = self.inner.exclusive_access();
+        match inner.tasks.get(pid) {
+            Some(task) if task.task_status != TaskStatus::Exited => Some(task.uid),
+            _ => None,
+        }
+    }
+
+    fn get_task_pgid(&self, pid: usize) -> Option<usize> {
+        let inner = self.inner.exclusive_access();
+        match inner.tasks.get(pid) {
+            Some(task) if task.task_status != TaskStatus::Exited => Some(task.pgid),
+            _ => None,
+        }
+    }
+
+    fn get_task_parent(&self, pid: usize) -> Option<usize> {
+        let inner = self.inner.exclusive_access();
+        match inner.tasks.get(pid) {
+            Some(task) if task.task_status != TaskStatus::Exited => task.parent,
+            _ => None,
+        }
+    }
+
+    fn set_task_pgid(&self, pid: usize, pgid: usize) -> bool {
+        let mut inner = self.inner.exclusive_access();
+        match inner.tasks.get_mut(pid) {
+            Some(task) if task.task_status != TaskStatus::Exited => {
+                task.pgid = pgid;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The tasks in process group `pgid` that have not exited.
+    fn group_members(&self, pgid: usize) -> Vec<usize> {
+        let inner = self.inner.exclusive_access();
+        inner
+            .tasks
+            .iter()
+            .filter(|(_, task)| task.pgid == pgid && task.task_status != TaskStatus::Exited)
+            .map(|(id, _)| id)
+            .collect()
+    }
+
+    fn set_task_cgroup(&self, pid: usize, cgroup: usize) -> bool {
+        let mut inner = self.inner.exclusive_access();
+        match inner.tasks.get_mut(pid) {
+            Some(task) if task.task_status != TaskStatus::Exited => {
+                task.cgroup = cgroup;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The control group of each task that has not exited.
+    fn task_cgroups(&self) -> Vec<usize> {
+        let inner = self.inner.exclusive_access();
+        inner
+            .tasks
+            .iter()
+            .filter(|(_, task)| task.task_status != TaskStatus::Exited)
+            .map(|(_, task)| task.cgroup)
+            .collect()
+    }
+
+    fn get_current_task_id(&self) -> usize {
+        self.inner.exclusive_access().current_task
+    }
+
+    fn get_current_task_name(&self) -> String {
+        let inner = self.inner.exclusive_access();
+        inner.tasks[inner.current_task].name.clone()
+    }
+
+    fn set_current_task_name(&self, name: &str) {
+        let mut inner = self.inner.exclusive_access();
+        let current = inner.current_task;
+        inner.tasks[current].set_name(name);
+    }
+
+    fn get_current_cwd(&self) -> String {
+        let inner = self.inner.exclusive_access();
+        inner.tasks[inner.current_task].cwd.clone()
+    }
+
+    fn set_current_cwd(&self, cwd: String) {
+        let mut inner = self.inner.exclusive_access();
+        let current = inner.current_task;
+        inner.tasks[current].cwd = cwd;
+    }
+
+    fn get_current_file(&self, fd: usize) -> Option<Arc<dyn File + Send + Sync>> {
+        let inner = self.inner.exclusive_access();
+        let current = inner.current_task;
+        inner.tasks[current].fd_table.get(fd)?.clone()
+    }
+
+    fn add_current_file(&self, file: Arc<dyn File + Send + Sync>) -> Option<usize> {
+        let mut inner = self.inner.exclusive_access();
+        let current = inner.current_task;
+        let task = &mut inner.tasks[current];
+        let fd = task.alloc_fd();
+        if fd >= task.limits.soft(Resource::NoFile) {
+            return None;
+        }
+        task.fd_table[fd] = Some(file);
+        Some(fd)
+    }
+
+    fn close_current_file(&self, fd: usize) -> Option<Arc<dyn File + Send + Sync>> {
+        let mut inner = self.inner.exclusive_access();
+        let current = inner.current_task;
+        inner.tasks[current].fd_table.get_mut(fd)?.take()
+    }
+
+    /// Mark `signal` pending on task `pid`, failing if it does not exist or has exited.
+    /// A signal that would interrupt a wait wakes the task if it is blocked.
+    fn send_signal(&self, pid: usize, signal: SignalFlags) -> bool {
+        let mut inner = self.inner.exclusive_access();
+        let inner = &mut *inner;
+        match inner.tasks.get_mut(pid) {
+            Some(task) if task.task_status != TaskStatus::Exited => {
+                task.signals |= signal;
+                let interrupting = signal - task.signal_mask - SignalFlags::default_ignored();
+                if task.task_status == TaskStatus::Blocked && !interrupting.is_empty() {
+                    task.task_status = TaskStatus::Ready;
+                    inner.ready.push_back(pid);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn set_current_signal_mask(&self, mask: SignalFlags) -> SignalFlags {
+        let mut inner = self.inner.exclusive_access();
+        let current = inner.current_task;
+        let old_mask = inner.tasks[current].signal_mask;
+        inner.tasks[current].signal_mask = mask - SignalFlags::unblockable();
+        old_mask
+    }
+
+    fn get_current_pending_signals(&self) -> SignalFlags {
+        let inner = self.inner.exclusive_access();
+        inner.tasks[inner.current_task].signals
+    }
+
+    fn current_signal_interrupted(&self) -> bool {
+        let inner = self.inner.exclusive_access();
+        let task = &inner.tasks[inner.current_task];
+        !(task.signals - task.signal_mask - SignalFlags::default_ignored()).is_empty()
+    }
+
+    /// Remove the lowest pending signal in `mask` from the current task.
+    fn take_current_signal(&self, mask: SignalFlags) -> Option<usize> {
+        let mut inner = self.inner.exclusive_access();
+        let current = inner.current_task;
+        let task = &mut inner.tasks[current];
+        let signum = (task.signals & mask).first()?;
+        task.signals.remove(SignalFlags::from_signum(signum).unwrap());
+        Some(signum)
+    }
+
+    /// Write the state of every task to `out`. This may run after the kernel
+    /// failed, so it only notes that the task table is in use if it is.
+    fn dump(&self, out: &mut dyn Write) -> fmt::Result {
+        let inner = match self.inner.try_exclusive_access() {
+            Some(inner) => inner,
+            None => return writeln!(out, "task table in use"),
+        };
+        let now = get_time_ms();
+        for (id, task) in inner.tasks.iter() {
+            let current = if id == inner.current_task { "*" } else { " " };
+            let running_ms = if task.dispatched { now - task.first_time } else { 0 };
+            let syscalls: u32 = task.syscall_times.iter().sum();
+            writeln!(
+                out,
+                "{} task {} ({}): {:?}, pc {:#x}, {} ms since first run, {} syscalls, {} pages, \
+                 pending signals {:#x}",
+                current,
+                id,
+                task.name,
+                task.task_status,
+                task.get_trap_cx().sepc,
+                running_ms,
+                syscalls,
+                task.memory_set.resident_pages(),
+                task.signals.bits()
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Handle the pending unblocked signals of the current task with their
+    /// default action: ignored ones are discarded, and the first terminating
+    /// one is returned.
+    fn check_current_signals(&self) -> Option<usize> {
+        let mut inner = self.inner.exclusive_access();
+        let current = inner.current_task;
+        let task = &mut inner.tasks[current];
+        let deliverable = task.signals - task.signal_mask;
+        task.signals -= deliverable & SignalFlags::default_ignored();
+        (deliverable - SignalFlags::default_ignored()).first()
+    }
+}
+
+/// Print the state of every task on the console.
+pub fn dump_tasks() {
+    write_tasks(&mut Stdout).unwrap();
+}
+
+/// Write the state of every task to `out`.
+pub fn write_tasks(out: &mut dyn Write) -> fmt::Result {
+    TASK_MANAGER.dump(out)
+}
+
+/// Whether the task table is borrowed, for reports on a hung kernel.
+pub fn task_table_in_use() -> bool {
+    TASK_MANAGER.inner.try_exclusive_access().is_none()
+}
+
+/// Whether [`run_first_task`] was called, so that there is a current task.
+pub fn tasks_started() -> bool {
+    TASKS_STARTED.load(Ordering::Relaxed)
+}
+
+/// Run the init task.
+pub fn run_first_task() {
+    TASKS_STARTED.store(true, Ordering::Relaxed);
+    replay::init();
+    TASK_MANAGER.run_first_task();
+}
+
+/// Switch current `Running` task to the task we have found,
+/// or there is no `Ready` task and we can exit with all applications completed
+fn run_next_task() {
+    reap_exited_tasks();
+    TASK_MANAGER.run_next_task();
+}
+
+/// Free everything the exited tasks hold: address space, kernel stack and
+/// id. Those whose parent has yet to collect their exit code are left as
+/// zombies until it does.
+fn reap_exited_tasks() {
+    // dropped outside of the task table, as closing files may wake tasks
+    for (id, task) in TASK_MANAGER.take_exited() {
+        drop(task);
+        on_reap(id);
+    }
+}
+
+/// Task `id` is gone, and its id may be given to a new task: every registry
+/// keyed by task id forgets it, so that the new task starts without what
+/// was kept for the old one.
+fn on_reap(id: usize) {
+    vector::on_reap(id);
+    crate::profile::on_reap(id);
+    #[cfg(feature = "kcov")]
+    crate::kcov::on_reap(id);
+    #[cfg(feature = "fault-inject")]
+    crate::fault::on_reap(id);
+    #[cfg(feature = "hypervisor")]
+    crate::hypervisor::on_reap(id);
+}
+
+/// Tests of scheduler transitions and of task teardown, once and by the
+/// thousand, and of control group shares
+pub const TESTS: &[KernelTest] = &[
+    test_case!(sched_test),
+    test_case!(reap_test),
+    test_case!(stress_test),
+    test_case!(cgroup::cgroup_test),
+];
+
+fn sched_test() {
+    TASK_MANAGER.sched_test();
+}
+
+fn reap_test() {
+    TASK_MANAGER.reap_test();
+}
+
+fn stress_test() {
+    TASK_MANAGER.stress_test();
+}
+
+/// Change the status of current `Running` task into `Ready`.
+fn mark_current_suspended(preempted: bool) {
+    TASK_MANAGER.mark_current_suspended(preempted);
+}
+
+/// Change the status of current `Running` task into `Exited`.
+fn mark_current_exited(exit_code: i32) {
+    // closed outside of the task table, as closing a pipe or a socket may
+    // wake tasks
+    drop(TASK_MANAGER.mark_current_exited(exit_code));
+}
+
+/// Block the current 'Running' task and run the next task in task list.
+/// Only a [`WaitQueue`] blocks tasks, so that something can wake them.
+fn block_current_and_run_next() {
+    TASK_MANAGER.mark_current_blocked();
+    run_next_task();
+}
+
+/// Make task `id` `Ready` if it is `Blocked`, returning whether it was.
+fn wake_task(id: usize) -> bool {
+    TASK_MANAGER.wake_task(id)
+}
+
+/// Schedule `task` along with the others, returning its id, or None if
+/// there are `MAX_TASKS` tasks already.
+#[allow(unused)]
+pub fn add_task(task: TaskControlBlock) -> Option<usize> {
+    TASK_MANAGER.add_task(task)
+}
+
+/// Run application `app` as a child of the current task, returning its id,
+/// or None if there are `MAX_TASKS` tasks already.
+pub fn spawn(app: usize) -> Option<usize> {
+    TASK_MANAGER.spawn(app)
+}
+
+/// The id and exit code of the child `pid` of the current task, or of any
+/// child if `pid` is -1, if it has exited. None if there is no such child,
+/// Some(None) if it has not exited yet. The child keeps its id until
+/// [`collect_child`].
+pub fn wait_child(pid: isize) -> Option<Option<(usize, i32)>> {
+    TASK_MANAGER.wait_child(pid)
+}
+
+/// Forget the exit code of the exited child `id` of the current task,
+/// letting its id go.
+pub fn collect_child(id: usize) {
+    TASK_MANAGER.collect_child(id)
+}
+
+/// Remove exited task `id`, freeing its id, its kernel stack and its
+/// address space. Returns false if there is no such task, it has not
+/// exited or it is the one running.
+#[allow(unused)]
+pub fn remove_task(id: usize) -> bool {
+    // dropped once the task table is released, as closing its files may
+    // wake other tasks
+    match TASK_MANAGER.remove_task(id) {
+        Some(task) => {
+            drop(task);
+            on_reap(id);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Suspend the current 'Running' task and run the next task in task list.
+pub fn suspend_current_and_run_next() {
+    mark_current_suspended(false);
+    run_next_task();
+}
+
+/// Like [`suspend_current_and_run_next`], for when the current task has used
+/// up its time slice rather than given up the CPU itself.
+pub fn preempt_current_and_run_next() {
+    mark_current_suspended(true);
+    run_next_task();
+}
+
+/// Exit the current 'Running' task and run the next task in task list.
+/// `exit_code` is negative if the kernel killed the task: -1 for a guest
+/// kernel error, -2 for a page fault, -3 for an illegal instruction, -4 for
+/// a failed kernel assertion and minus the signal number for a fatal signal.
+pub fn exit_current_and_run_next(exit_code: i32) {
+    sem_exit(current_task_id());
+    // free the address space while the task still runs, so that other
+    // tasks can run while a large one is torn down
+    while TASK_MANAGER.release_current_frames(RESCHED_BATCH_PAGES) {
+        cond_resched();
+    }
+    mark_current_exited(exit_code);
+    run_next_task();
+}
+
+/// Get the current 'Running' task's token.
+pub fn current_user_token() -> usize {
+    TASK_MANAGER.get_current_token()
+}
+
+/// Get the current 'Running' task's trap contexts.
+pub fn current_trap_cx() -> &'static mut TrapContext {
+    TASK_MANAGER.get_current_trap_cx()
+}
+
+/// Get the id of the syscall the current task is making, as passed in a7.
+pub fn current_syscall_id() -> usize {
+    current_trap_cx().x[17]
+}
+
+pub fn add_one_while_syscall(id: usize) {
+    TASK_MANAGER.add_one_to_current_task(id);
+}
+
+/// Mark the current task as making a syscall, or done with it.
+pub fn set_current_in_syscall(in_syscall: bool) {
+    TASK_MANAGER.set_current_in_syscall(in_syscall);
+}
+
+
+use super::syscall::TaskInfo;
+/// The [`TaskInfo`] of the current task.
+pub fn get_task_info_inner() -> TaskInfo {
+    TaskInfo {
+        status: TASK_MANAGER.get_current_task_status(),
+        syscall_times: TASK_MANAGER.get_current_task_syscall_times(),
+        time: TASK_MANAGER.get_current_task_costed_time(),
+    }
+}
+
+pub fn sys_mmap_inner(start: usize, len: usize, port: usize) -> isize {
+    let va = VirtAddr(start);
+    if ! va.aligned() || port & !0x7 != 0  || port & 0x7 == 0 {
+        return -1;
+    }
+    TASK_MANAGER.mmap(start, len, port)
+}
+
+pub fn sys_munmap_inner(start: usize, len: usize ) -> isize {
+    let va = VirtAddr(start);
+    if ! va.aligned()  {
+        return -1;
+    }
+    // check it all first, so that a failure changes nothing, then unmap a
+    // batch at a time to let other tasks run in between
+    if !TASK_MANAGER.current_can_munmap(start, len) {
+        return -1;
+    }
+    let batch = RESCHED_BATCH_PAGES * PAGE_SIZE;
+    let end = start + len;
+    let mut chunk = start;
+    while chunk < end {
+        let chunk_len = batch.min(end - chunk);
+        if TASK_MANAGER.munmap(chunk, chunk_len) != 0 {
+            return -1;
+        }
+        chunk += chunk_len;
+        if chunk < end {
+            cond_resched();
+        }
+    }
+    0
+}
+
+/// Panic if the current task has overflowed its kernel stack.
+pub fn check_current_kernel_stack() {
+    canary::check(current_task_id());
+}
+
+/// Change the permission of pages of the current task, `port` being as in
+/// `mmap`.
+pub fn current_mprotect(start: usize, len: usize, port: usize) -> isize {
+    if !VirtAddr(start).aligned() || port & !0x7 != 0 {
+        return -1;
+    }
+    TASK_MANAGER.mprotect(start, len, port)
+}
+
+/// Whether each page of `[start, start + len)` of the current task has a
+/// frame, see `MemorySet::mincore`.
+pub fn current_mincore(start: usize, len: usize) -> Option<Vec<u8>> {
+    TASK_MANAGER.mincore(start, len)
+}
+
+/// Whether `[start, start + len)` of the current task maps only anonymous
+/// memory, see `MemorySet::is_anonymous`.
+pub fn current_is_anonymous(start: usize, len: usize) -> bool {
+    TASK_MANAGER.is_anonymous(start, len)
+}
+
+/// Set the stride scheduling priority of the current task.
+pub fn set_current_priority(priority: usize) {
+    TASK_MANAGER.set_current_priority(priority)
+}
+
+/// The syscall ABI the current task speaks.
+pub fn current_personality() -> Personality {
+    TASK_MANAGER.current_personality()
+}
+
+/// Switch the current task, and the tasks it spawns from now on, to
+/// `personality`.
+pub fn set_current_personality(personality: Personality) {
+    TASK_MANAGER.set_current_personality(personality);
+}
+
+/// Map `len` bytes into the current task wherever there is room, `port`
+/// being as in `mmap`. Returns where, or None if there is no room, frames
+/// run out or the address space limit would be passed.
+pub fn current_mmap_anywhere(len: usize, port: usize) -> Option<usize> {
+    if port & !0x7 != 0 || port & 0x7 == 0 || !current_may_grow(len) {
+        return None;
+    }
+    TASK_MANAGER.mmap_anywhere(len, port)
+}
+
+/// Move the program break of the current task to `brk`, returning where it
+/// is then: where it was if `brk` is below the top of the stack, where the
+/// heap starts, or if the heap cannot grow that far.
+pub fn set_current_brk(brk: usize) -> usize {
+    let (bottom, old) = TASK_MANAGER.current_heap();
+    if brk < bottom || brk > USER_SPACE_END {
+        return old;
+    }
+    if brk > old && !current_may_grow(brk - old) {
+        return old;
+    }
+    match TASK_MANAGER.set_current_brk(brk) {
+        true => brk,
+        false => old,
+    }
+}
+
+/// Let the current task map pages writable and executable at once.
+pub fn set_current_allow_wx(allow: bool) {
+    TASK_MANAGER.set_current_allow_wx(allow)
+}
+
+/// Map `pages` frames starting at `ppn` into the current task at `start`.
+pub fn current_map_linear(
+    start: usize,
+    ppn: PhysPageNum,
+    pages: usize,
+    permission: MapPermission,
+) -> isize {
+    TASK_MANAGER.map_current_linear(start, ppn, pages, permission)
+}
+
+/// Get the token of the address space of task `pid`.
+pub fn task_user_token(pid: usize) -> Option<usize> {
+    TASK_MANAGER.get_task_token(pid)
+}
+
+/// Get the counters of the current task, with the frames it owns now.
+pub fn current_task_counters() -> (TaskCounters, usize) {
+    TASK_MANAGER.get_current_counters()
+}
+
+/// Give a frame to the lazily mapped page of the current task holding `va`,
+/// which a user access lacking `access` faulted on. Returns whether the
+/// access can be retried.
+pub fn current_lazy_fault(va: usize, access: MapPermission) -> bool {
+    TASK_MANAGER.handle_lazy_fault(current_user_token(), va, access)
+}
+
+/// Give a frame to the lazily mapped page holding `va` in the address space
+/// of `token`, before the kernel accesses it. Returns whether it did.
+pub fn fault_in(token: usize, va: usize) -> bool {
+    TASK_MANAGER.handle_lazy_fault(token, va, MapPermission::empty())
+}
+
+/// Compact physical memory: move the pages of the tasks to the lowest free
+/// frames, so that the free ones form long runs for contiguous allocations.
+/// Returns how many pages moved.
+pub fn compact_memory() -> usize {
+    let moved = TASK_MANAGER.compact_memory().unwrap_or(0);
+    trim_free_frames();
+    if moved > 0 {
+        info!("compaction moved {} pages", moved);
+    }
+    moved
+}
+
+/// Let the current task, which trapped on an illegal instruction, use the
+/// vector registers if that is why. Returns whether the instruction can be
+/// retried.
+pub fn enable_current_vector() -> bool {
+    TASK_MANAGER.enable_current_vector()
+}
+
+/// Count a page fault of the current task that gave a lazy page its frame.
+pub fn count_current_lazy_fault() {
+    TASK_MANAGER.count_current_lazy_fault();
+}
+
+/// Get the id of the current 'Running' task.
+pub fn current_task_id() -> usize {
+    TASK_MANAGER.get_current_task_id()
+}
+
+/// Get the name of the current 'Running' task.
+pub fn current_task_name() -> String {
+    TASK_MANAGER.get_current_task_name()
+}
+
+/// Rename the current task, cutting `name` to `TASK_NAME_LEN` bytes.
+pub fn set_current_task_name(name: &str) {
+    TASK_MANAGER.set_current_task_name(name);
+}
+
+/// Absolute path of the working directory of the current task.
+pub fn current_cwd() -> String {
+    TASK_MANAGER.get_current_cwd()
+}
+
+/// Change the working directory of the current task to `cwd`, an absolute
+/// path.
+pub fn set_current_cwd(cwd: String) {
+    TASK_MANAGER.set_current_cwd(cwd);
+}
+
+/// Whether the current task may act on the whole system, that is whether
+/// it runs as root. A refusal is audited.
+pub fn current_is_privileged() -> bool {
+    let privileged = current_credentials().0 == 0;
+    if !privileged {
+        audit::record(AuditEvent::Denied, [0, 0]);
+    }
+    privileged
+}
+
+/// Get the (uid, gid) the current task runs as.
+pub fn current_credentials() -> (u32, u32) {
+    TASK_MANAGER.get_current_credentials()
+}
+
+/// Change the (uid, gid) the current task runs as.
+pub fn set_current_credentials(uid: u32, gid: u32) {
+    TASK_MANAGER.set_current_credentials(uid, gid)
+}
+
+/// Get the capabilities of the current task.
+pub fn current_capabilities() -> Capabilities {
+    TASK_MANAGER.get_current_capabilities()
+}
+
+/// Drop the capabilities of the current task that are not in
+/// `capabilities`. Fails if it asks for any the task does not have.
+pub fn restrict_current_capabilities(capabilities: Capabilities) -> bool {
+    TASK_MANAGER.restrict_current_capabilities(capabilities)
+}
+
+/// Get the limits of task `pid` on `resource`.
+pub fn task_limit(pid: usize, resource: Resource) -> Option<RLimit> {
+    TASK_MANAGER.get_task_limit(pid, resource)
+}
+
+/// Replace the limits of task `pid` on `resource`; raising the hard limit
+/// takes root.
+pub fn set_task_limit(pid: usize, resource: Resource, limit: RLimit) -> bool {
+    TASK_MANAGER.set_task_limit(pid, resource, limit, current_credentials().0 == 0)
+}
+
+/// Confine the mappings task `pid` makes to `profile`. The task must not
+/// have run yet, unless it is the current one.
+pub fn set_task_sandbox(pid: usize, profile: SandboxProfile) -> bool {
+    TASK_MANAGER.set_task_sandbox(pid, profile)
+}
+
+/// Whether the current task may own `len` more bytes of memory under its
+/// address space limit.
+pub fn current_may_grow(len: usize) -> bool {
+    TASK_MANAGER.current_may_grow(len)
+}
+
+/// What a task monitor shows of a task
+pub struct TaskUsage {
+    pub pid: usize,
+    pub name: String,
+    pub status: TaskStatus,
+    pub nice: isize,
+    /// share of a CPU used lately, in millionths
+    pub cpu_usage: usize,
+    /// CPU time, faults and the like over the life of the task
+    pub counters: TaskCounters,
+    pub resident_pages: usize,
+}
+
+/// Trap path: the current task has entered the kernel, for system time.
+pub fn enter_kernel() {
+    TASK_MANAGER.enter_kernel();
+}
+
+/// Trap path: the current task is about to go back to user space.
+pub fn leave_kernel() {
+    TASK_MANAGER.leave_kernel();
+}
+
+/// Timer path: update the CPU usage of every task.
+pub fn update_cpu_usage() {
+    TASK_MANAGER.update_cpu_usage();
+}
+
+/// The CPU usage and size of every task, in pid order.
+pub fn task_usage() -> Vec<TaskUsage> {
+    TASK_MANAGER.get_task_usage()
+}
+
+/// Timer path: signal the current task if it has used up its CPU time.
+pub fn check_current_cpu_limit() {
+    TASK_MANAGER.check_current_cpu_limit()
+}
+
+/// Set the nice value of task `pid`, which must be within `MIN_NICE` and
+/// `MAX_NICE`; lowering it takes root.
+pub fn set_task_nice(pid: usize, nice: isize) -> bool {
+    TASK_MANAGER.set_task_nice(pid, nice, current_credentials().0 == 0)
+}
+
+/// Get the stride scheduling priority of task `pid`.
+pub fn task_priority(pid: usize) -> Option<usize> {
+    TASK_MANAGER.get_task_priority(pid)
+}
+
+/// Get the nice value of task `pid`.
+pub fn task_nice(pid: usize) -> Option<isize> {
+    TASK_MANAGER.get_task_nice(pid)
+}
+
+/// Get the user task `pid` runs as.
+pub fn task_uid(pid: usize) -> Option<u32> {
+    TASK_MANAGER.get_task_uid(pid)
+}
+
+/// Get the process group of task `pid`.
+pub fn task_pgid(pid: usize) -> Option<usize> {
+    TASK_MANAGER.get_task_pgid(pid)
+}
+
+/// Get the task that spawned task `pid`, if it has not exited.
+pub fn task_parent(pid: usize) -> Option<usize> {
+    TASK_MANAGER.get_task_parent(pid)
+}
+
+/// Move task `pid` to process group `pgid`, failing if it does not exist
+/// or has exited.
+pub fn set_task_pgid(pid: usize, pgid: usize) -> bool {
+    TASK_MANAGER.set_task_pgid(pid, pgid)
+}
+
+/// Whether some task that has not exited is in process group `pgid`.
+pub fn group_exists(pgid: usize) -> bool {
+    !TASK_MANAGER.group_members(pgid).is_empty()
+}
+
+/// Move task `pid` to control group `cgroup`, failing if either does not
+/// exist.
+pub fn set_task_cgroup(pid: usize, cgroup: usize) -> bool {
+    cgroup_exists(cgroup) && TASK_MANAGER.set_task_cgroup(pid, cgroup)
+}
+
+/// Remove control group `cgroup`, failing if it is the root group, if it
+/// has groups in it, or tasks that have not exited.
+pub fn cgroup_remove(cgroup: usize) -> bool {
+    !TASK_MANAGER.task_cgroups().contains(&cgroup) && cgroup::remove(cgroup)
+}
+
+/// Each control group, with its tasks and the CPU time they used.
+pub fn cgroup_stats() -> Vec<CgroupStat> {
+    cgroup::stats(TASK_MANAGER.task_cgroups().into_iter())
+}
+
+/// The size of the checkpoint image of task `pid`, with the image if it is
+/// at most `max_len` bytes. None if there is no such task that has not
+/// exited, if it cannot be checkpointed, or if the image does not fit in
+/// the kernel heap.
+pub fn checkpoint_task(pid: usize, max_len: usize) -> Option<(usize, Option<Vec<u8>>)> {
+    TASK_MANAGER.checkpoint_task(pid, max_len)
+}
+
+/// Restore the checkpoint `image` as a child of the current task, returning
+/// its id.
+pub fn restore_task(image: &[u8]) -> Option<usize> {
+    TASK_MANAGER.restore_task(image)
+}
+
+/// Whether the current task may act on task `pid`: it must run as the same
+/// user, or as root. A refusal is audited.
+pub fn current_may_access(pid: usize) -> bool {
+    let uid = match task_uid(pid) {
+        Some(uid) => uid,
+        None => return false,
+    };
+    let current = current_credentials().0;
+    if current != 0 && current != uid {
+        audit::record(AuditEvent::Denied, [pid, 0]);
+        return false;
+    }
+    true
+}
+
+/// Get the file opened as `fd` by the current task.
+pub fn current_file(fd: usize) -> Option<Arc<dyn File + Send + Sync>> {
+    TASK_MANAGER.get_current_file(fd)
+}
+
+/// Install `file` in the lowest free descriptor of the current task, unless
+/// that is past its open file limit.
+pub fn current_add_file(file: Arc<dyn File + Send + Sync>) -> Option<usize> {
+    TASK_MANAGER.add_current_file(file)
+}
+
+/// Close descriptor `fd` of the current task, returning whether it was open.
+pub fn current_close_file(fd: usize) -> bool {
+    // dropped outside of the task table, as closing a pipe or a socket may
+    // wake tasks
+    TASK_MANAGER.close_current_file(fd).is_some()
+}
+
+/// Send `signal` to task `pid`.
+pub fn send_signal(pid: usize, signal: SignalFlags) -> bool {
+    let sent = TASK_MANAGER.send_signal(pid, signal);
+    // a signalfd may have become readable
+    crate::fs::wake_pollers();
+    crate::fs::wake_signalfd_readers();
+    sent
+}
+
+/// Send `signal` to every task in process group `pgid`, returning whether
+/// there was any.
+pub fn signal_group(pgid: usize, signal: SignalFlags) -> bool {
+    let members = TASK_MANAGER.group_members(pgid);
+    for &pid in members.iter() {
+        TASK_MANAGER.send_signal(pid, signal);
+    }
+    crate::fs::wake_pollers();
+    crate::fs::wake_signalfd_readers();
+    !members.is_empty()
+}
+
+/// Replace the blocked signal set of the current task and return the old one.
+pub fn set_current_signal_mask(mask: SignalFlags) -> SignalFlags {
+    TASK_MANAGER.set_current_signal_mask(mask)
+}
+
+/// Get the pending signals of the current task.
+pub fn current_pending_signals() -> SignalFlags {
+    TASK_MANAGER.get_current_pending_signals()
+}
+
+/// Consume one pending signal in `mask` of the current task.
+pub fn take_current_signal(mask: SignalFlags) -> Option<usize> {
+    TASK_MANAGER.take_current_signal(mask)
+}
+
+/// Whether a pending signal would terminate the current task, so that a
+/// blocking syscall should give up and return to user space.
+pub fn current_signal_interrupted() -> bool {
+    TASK_MANAGER.current_signal_interrupted()
+}
+
+/// Act on the pending signals of the current task before it goes back to
+/// user space, exiting it if one of them is fatal.
+pub fn handle_signals() {
+    if let Some(signum) = TASK_MANAGER.check_current_signals() {
+        error!(
+            "[kernel] Application {} (pid {}) killed by signal {}.",
+            current_task_name(),
+            current_task_id(),
+            signum
+        );
+        exit_current_and_run_next(-(signum as i32));
+    }
+}
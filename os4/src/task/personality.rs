@@ -0,0 +1,120 @@
+//! Personalities, the syscall ABI a task speaks
+//!
+//! Tasks speak this kernel's own ABI unless they switch to the Linux one
+//! with `personality`, which is numbered 92 as on Linux in both. A task
+//! spawned gets the personality of its parent: a launcher switches, spawns
+//! a small static binary built for riscv64 Linux, musl's for instance, and
+//! switches back. See `syscall::linux` for what the Linux ABI covers.
+//!
+//! A task spawned with the Linux personality starts as the Linux ELF loader
+//! starts one, on a stack holding `argc`, `argv` with the name of the
+//! application alone, an empty environment and the auxiliary vector, which
+//! tells where the program headers are, the page size, the entry point,
+//! the credentials, and 16 random bytes. Its program break starts at the
+//! top of its stack.
+
+use super::TaskControlBlock;
+use crate::config::PAGE_SIZE;
+use crate::mm::UserSlice;
+use crate::random::fill_random;
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+/// The syscall ABI of a task
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Personality {
+    Native = 0,
+    Linux = 1,
+}
+
+impl Personality {
+    pub fn from_usize(personality: usize) -> Option<Self> {
+        match personality {
+            0 => Some(Self::Native),
+            1 => Some(Self::Linux),
+            _ => None,
+        }
+    }
+}
+
+/// auxiliary vector entries, as in Linux `<elf.h>`
+const AT_NULL: usize = 0;
+const AT_PHDR: usize = 3;
+const AT_PHENT: usize = 4;
+const AT_PHNUM: usize = 5;
+const AT_PAGESZ: usize = 6;
+const AT_ENTRY: usize = 9;
+const AT_UID: usize = 11;
+const AT_EUID: usize = 12;
+const AT_GID: usize = 13;
+const AT_EGID: usize = 14;
+const AT_RANDOM: usize = 25;
+
+/// Where the program headers of `elf_data` are once it is loaded, which a
+/// loaded segment holds if the binary is linked as usual. 0 if none does.
+fn loaded_phdr(elf_data: &[u8]) -> usize {
+    let elf = match xmas_elf::ElfFile::new(elf_data) {
+        Ok(elf) => elf,
+        Err(_) => return 0,
+    };
+    let ph_offset = elf.header.pt2.ph_offset();
+    let ph_size = elf.header.pt2.ph_entry_size() as u64 * elf.header.pt2.ph_count() as u64;
+    elf.program_iter()
+        .filter(|ph| matches!(ph.get_type(), Ok(xmas_elf::program::Type::Load)))
+        .find(|ph| ph.offset() <= ph_offset && ph_offset + ph_size <= ph.offset() + ph.file_size())
+        .map_or(0, |ph| {
+            (ph.virtual_addr() + ph_offset - ph.offset()) as usize
+        })
+}
+
+/// Lay out the stack of `task`, freshly loaded from `elf_data`, as Linux
+/// starts a task, and switch it to the Linux personality. Fails if the
+/// stack is too small to hold it all.
+pub(super) fn start_linux(task: &mut TaskControlBlock, elf_data: &[u8]) -> bool {
+    let elf = match xmas_elf::ElfFile::new(elf_data) {
+        Ok(elf) => elf,
+        Err(_) => return false,
+    };
+    let token = task.memory_set.token();
+    let top = task.base_size;
+    // the name and the random bytes at the top, the words below them
+    let random = top - 16;
+    let name = (random - task.name.len() - 1) & !(size_of::<usize>() - 1);
+    let mut random_bytes = [0; 16];
+    fill_random(&mut random_bytes);
+    let mut name_bytes = Vec::from(task.name.as_bytes());
+    name_bytes.push(0);
+    let auxv = [
+        (AT_PHDR, loaded_phdr(elf_data)),
+        (AT_PHENT, elf.header.pt2.ph_entry_size() as usize),
+        (AT_PHNUM, elf.header.pt2.ph_count() as usize),
+        (AT_PAGESZ, PAGE_SIZE),
+        (AT_ENTRY, elf.header.pt2.entry_point() as usize),
+        (AT_UID, task.uid as usize),
+        (AT_EUID, task.uid as usize),
+        (AT_GID, task.gid as usize),
+        (AT_EGID, task.gid as usize),
+        (AT_RANDOM, random),
+        (AT_NULL, 0),
+    ];
+    // argc, argv and its end, the end of the environment
+    let mut words = Vec::from([1, name, 0, 0]);
+    for (key, value) in auxv {
+        words.push(key);
+        words.push(value);
+    }
+    let sp = (name - words.len() * size_of::<usize>()) & !0xf;
+    if sp < top - PAGE_SIZE
+        || !UserSlice::new(token, random as *const u8, 16).write(&random_bytes)
+        || !UserSlice::new(token, name as *const u8, name_bytes.len()).write(&name_bytes)
+        || !UserSlice::new(token, sp as *const usize, words.len()).write(&words)
+    {
+        return false;
+    }
+    let trap_cx = task.get_trap_cx();
+    trap_cx.x[2] = sp;
+    // no function for the task to register with `atexit`
+    trap_cx.x[10] = 0;
+    task.personality = Personality::Linux;
+    true
+}
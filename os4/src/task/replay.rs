@@ -0,0 +1,212 @@
+//! Scheduling record and replay
+//!
+//! With `schedlog=on` on the command line, every task switch is recorded:
+//! which task was switched to, and, for a switch because a time slice
+//! ended, how many syscalls all tasks had made by then. Tasks share no
+//! memory, so which syscalls each one makes between switches is all that
+//! makes an interleaving; the instruction the timer interrupted at does not
+//! matter.
+//!
+//! The log is kept in the [`SCHED_LOG_SIZE`] bytes of RAM below the crash
+//! dump, which survive a reboot. A boot that finds a log replays it instead
+//! of recording: timer ticks no longer end time slices, tasks are preempted
+//! at the first safe point at the syscall count recorded, and the task
+//! recorded is switched to each time. The log is then forgotten, so the
+//! boot after records again. When the log runs out, or the task recorded
+//! cannot run, tasks are scheduled as usual from there on.
+//!
+//! Anything else that depends on time or on devices, such as a timeout or
+//! an incoming packet, is not replayed and may make a replay diverge.
+
+use crate::cmdline;
+use crate::config::SCHED_LOG_SIZE;
+use crate::mm::sched_log_base;
+use crate::sync::UPSafeCell;
+use core::mem::size_of;
+use lazy_static::*;
+
+/// marks a log a previous boot left, "SCHEDLOG"
+const MAGIC: u64 = 0x474f_4c44_4548_4353;
+/// `task` of a record of a preemption
+const PREEMPTED: usize = usize::MAX;
+
+/// Start of the log; the records follow it
+#[repr(C)]
+struct Header {
+    magic: u64,
+    len: u64,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct Record {
+    /// syscalls made by all tasks before the switch
+    syscalls: usize,
+    /// the task switched to, or `PREEMPTED` if the current one was
+    /// preempted, which the switch follows
+    task: usize,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Mode {
+    Off,
+    Record,
+    Replay,
+}
+
+struct SchedLog {
+    mode: Mode,
+    /// syscalls made by all tasks so far
+    syscalls: usize,
+    /// records written, or replayed
+    next: usize,
+    /// records to replay
+    len: usize,
+}
+
+lazy_static! {
+    static ref SCHED_LOG: UPSafeCell<SchedLog> = unsafe {
+        UPSafeCell::new(SchedLog {
+            mode: Mode::Off,
+            syscalls: 0,
+            next: 0,
+            len: 0,
+        })
+    };
+}
+
+fn header() -> &'static mut Header {
+    unsafe { &mut *(sched_log_base() as *mut Header) }
+}
+
+fn records() -> &'static mut [Record] {
+    let start = sched_log_base() + size_of::<Header>();
+    let len = (SCHED_LOG_SIZE - size_of::<Header>()) / size_of::<Record>();
+    unsafe { core::slice::from_raw_parts_mut(start as *mut Record, len) }
+}
+
+impl SchedLog {
+    fn push(&mut self, task: usize) {
+        let records = records();
+        if self.next == records.len() {
+            warn!("[kernel] scheduling log full, recording stopped");
+            self.mode = Mode::Off;
+            return;
+        }
+        records[self.next] = Record {
+            syscalls: self.syscalls,
+            task,
+        };
+        self.next += 1;
+        // kept up to date, so that a boot ending in a panic leaves its log
+        header().len = self.next as u64;
+    }
+    fn peek(&mut self) -> Option<Record> {
+        if self.next < self.len {
+            return Some(records()[self.next]);
+        }
+        info!("[kernel] replayed {} scheduling decisions", self.len);
+        self.mode = Mode::Off;
+        None
+    }
+    fn diverge(&mut self) {
+        warn!(
+            "[kernel] replay diverged at scheduling decision {}, {} syscalls in",
+            self.next, self.syscalls
+        );
+        self.mode = Mode::Off;
+    }
+}
+
+/// Replay the log the previous boot left, if there is one, or else start
+/// recording one, if `schedlog=on` was given.
+pub fn init() {
+    if !cmdline::sched_log() {
+        return;
+    }
+    let mut log = SCHED_LOG.exclusive_access();
+    let header = header();
+    if header.magic == MAGIC {
+        header.magic = 0;
+        log.mode = Mode::Replay;
+        log.len = (header.len as usize).min(records().len());
+        info!(
+            "[kernel] replaying {} scheduling decisions of the previous boot",
+            log.len
+        );
+    } else {
+        header.len = 0;
+        header.magic = MAGIC;
+        log.mode = Mode::Record;
+        info!("[kernel] recording scheduling decisions");
+    }
+}
+
+/// Syscall entry path: count the syscall.
+pub fn count_syscall() {
+    let mut log = SCHED_LOG.exclusive_access();
+    if log.mode != Mode::Off {
+        log.syscalls += 1;
+    }
+}
+
+/// Whether to preempt the current task at a safe point, `tick` being
+/// whether its time slice is over. When replaying, it is rather whether
+/// it was preempted at this syscall count.
+pub fn should_preempt(tick: bool) -> bool {
+    let mut log = SCHED_LOG.exclusive_access();
+    if log.mode != Mode::Replay {
+        return tick;
+    }
+    let syscalls = log.syscalls;
+    match log.peek() {
+        // late rather than never, if it was missed
+        Some(record) => record.task == PREEMPTED && record.syscalls <= syscalls,
+        None => tick,
+    }
+}
+
+/// The current task is being preempted.
+pub(super) fn on_preempt() {
+    let mut log = SCHED_LOG.exclusive_access();
+    match log.mode {
+        Mode::Record => log.push(PREEMPTED),
+        Mode::Replay => match log.peek() {
+            Some(record) if record.task == PREEMPTED => log.next += 1,
+            Some(_) => log.diverge(),
+            None => {}
+        },
+        Mode::Off => {}
+    }
+}
+
+/// The task to switch to when replaying, if the log has one.
+pub(super) fn replayed_switch() -> Option<usize> {
+    let mut log = SCHED_LOG.exclusive_access();
+    if log.mode != Mode::Replay {
+        return None;
+    }
+    match log.peek()? {
+        record if record.task != PREEMPTED => {
+            log.next += 1;
+            Some(record.task)
+        }
+        _ => {
+            log.diverge();
+            None
+        }
+    }
+}
+
+/// The task recorded by [`replayed_switch`] cannot run.
+pub(super) fn diverged() {
+    SCHED_LOG.exclusive_access().diverge();
+}
+
+/// The scheduler picked task `next` to switch to.
+pub(super) fn record_switch(next: usize) {
+    let mut log = SCHED_LOG.exclusive_access();
+    if log.mode == Mode::Record {
+        log.push(next);
+    }
+}
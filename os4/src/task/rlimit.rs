@@ -0,0 +1,106 @@
+//! Resource limits
+//!
+//! Each task has a soft and a hard limit per resource, with the numbers and
+//! layout of Linux `prlimit`. The soft limit is the one enforced; a task may
+//! move it up to the hard limit, and lower the hard limit, but only root may
+//! raise a hard limit.
+//!
+//! A task loaded at boot starts with the defaults below; one spawned gets
+//! the limits of its parent.
+
+use crate::config::{DEFAULT_NOFILE_LIMIT, USER_STACK_SIZE};
+
+/// No limit
+pub const RLIM_INFINITY: usize = usize::MAX;
+
+/// The resources limited
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Resource {
+    /// CPU time in seconds, past which the task gets `SIGXCPU`, or `SIGKILL`
+    /// past the hard limit
+    Cpu = 0,
+    /// size of the user stack in bytes; the stack is mapped once when the
+    /// task is loaded, so this only reports it
+    Stack = 3,
+    /// one more than the highest file descriptor that can be opened
+    NoFile = 7,
+    /// bytes of memory the address space may own
+    AddressSpace = 9,
+}
+
+impl Resource {
+    pub fn from_number(resource: usize) -> Option<Self> {
+        match resource {
+            0 => Some(Self::Cpu),
+            3 => Some(Self::Stack),
+            7 => Some(Self::NoFile),
+            9 => Some(Self::AddressSpace),
+            _ => None,
+        }
+    }
+    fn index(self) -> usize {
+        match self {
+            Self::Cpu => 0,
+            Self::Stack => 1,
+            Self::NoFile => 2,
+            Self::AddressSpace => 3,
+        }
+    }
+}
+
+/// A soft and a hard limit, layout compatible with `struct rlimit`
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct RLimit {
+    pub cur: usize,
+    pub max: usize,
+}
+
+impl RLimit {
+    const fn new(limit: usize) -> Self {
+        Self {
+            cur: limit,
+            max: limit,
+        }
+    }
+}
+
+/// The limits of one task
+#[derive(Copy, Clone)]
+pub struct ResourceLimits {
+    limits: [RLimit; 4],
+}
+
+impl Default for ResourceLimits {
+    fn default() -> Self {
+        let mut limits = [RLimit::new(RLIM_INFINITY); 4];
+        limits[Resource::Stack.index()] = RLimit::new(USER_STACK_SIZE);
+        limits[Resource::NoFile.index()] = RLimit::new(DEFAULT_NOFILE_LIMIT);
+        Self { limits }
+    }
+}
+
+impl ResourceLimits {
+    pub fn get(&self, resource: Resource) -> RLimit {
+        self.limits[resource.index()]
+    }
+    /// The enforced limit on `resource`.
+    pub fn soft(&self, resource: Resource) -> usize {
+        self.get(resource).cur
+    }
+    /// Replace the limits on `resource`, failing if the soft one would
+    /// exceed the hard one or if the hard one would be raised without
+    /// `privileged`. The stack limit cannot go below the stack mapped
+    /// already.
+    pub fn set(&mut self, resource: Resource, limit: RLimit, privileged: bool) -> bool {
+        let old = self.get(resource);
+        if limit.cur > limit.max
+            || (limit.max > old.max && !privileged)
+            || (resource == Resource::Stack && limit.cur < USER_STACK_SIZE)
+        {
+            return false;
+        }
+        self.limits[resource.index()] = limit;
+        true
+    }
+}
@@ -0,0 +1,79 @@
+//! Signal numbers and default actions
+
+/// Largest valid signal number
+pub const MAX_SIG: usize = 31;
+
+bitflags! {
+    /// A set of signals, bit `n` standing for signal number `n`
+    pub struct SignalFlags: u32 {
+        const SIGHUP    = 1 << 1;
+        const SIGINT    = 1 << 2;
+        const SIGQUIT   = 1 << 3;
+        const SIGILL    = 1 << 4;
+        const SIGTRAP   = 1 << 5;
+        const SIGABRT   = 1 << 6;
+        const SIGBUS    = 1 << 7;
+        const SIGFPE    = 1 << 8;
+        const SIGKILL   = 1 << 9;
+        const SIGUSR1   = 1 << 10;
+        const SIGSEGV   = 1 << 11;
+        const SIGUSR2   = 1 << 12;
+        const SIGPIPE   = 1 << 13;
+        const SIGALRM   = 1 << 14;
+        const SIGTERM   = 1 << 15;
+        const SIGSTKFLT = 1 << 16;
+        const SIGCHLD   = 1 << 17;
+        const SIGCONT   = 1 << 18;
+        const SIGSTOP   = 1 << 19;
+        const SIGTSTP   = 1 << 20;
+        const SIGTTIN   = 1 << 21;
+        const SIGTTOU   = 1 << 22;
+        const SIGURG    = 1 << 23;
+        const SIGXCPU   = 1 << 24;
+        const SIGXFSZ   = 1 << 25;
+        const SIGVTALRM = 1 << 26;
+        const SIGPROF   = 1 << 27;
+        const SIGWINCH  = 1 << 28;
+        const SIGIO     = 1 << 29;
+        const SIGPWR    = 1 << 30;
+        const SIGSYS    = 1 << 31;
+    }
+}
+
+impl SignalFlags {
+    /// The set containing only signal `signum`, if it is a valid signal number.
+    pub fn from_signum(signum: usize) -> Option<Self> {
+        if signum == 0 || signum > MAX_SIG {
+            return None;
+        }
+        Self::from_bits(1 << signum)
+    }
+
+    /// Signals that can be neither blocked nor redirected to a signalfd.
+    pub fn unblockable() -> Self {
+        Self::SIGKILL | Self::SIGSTOP
+    }
+
+    /// Signals whose default action is to do nothing.
+    ///
+    /// Job-control stops are not supported yet, so they are ignored as well.
+    pub fn default_ignored() -> Self {
+        Self::SIGCHLD
+            | Self::SIGCONT
+            | Self::SIGURG
+            | Self::SIGWINCH
+            | Self::SIGSTOP
+            | Self::SIGTSTP
+            | Self::SIGTTIN
+            | Self::SIGTTOU
+    }
+
+    /// Lowest-numbered signal in the set, if any.
+    pub fn first(&self) -> Option<usize> {
+        if self.is_empty() {
+            None
+        } else {
+            Some(self.bits.trailing_zeros() as usize)
+        }
+    }
+}
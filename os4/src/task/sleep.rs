@@ -0,0 +1,85 @@
+//! Sleeping until a deadline
+//!
+//! A sleeping task is `Blocked` with its deadline, in timer cycles since
+//! boot, in the sleep queue. The timer is armed for the first deadline as
+//! well as for the ticks, and its interrupt wakes the tasks whose deadline
+//! passed. A task may also sleep while it waits on a [`WaitQueue`], until
+//! either wakes it, which is how timeouts are made. When every task left is
+//! blocked and some of them sleep, the kernel waits for the first deadline
+//! instead of declaring a deadlock.
+
+use super::{
+    block_current_and_run_next, current_signal_interrupted, current_task_id, wake_task, WaitQueue,
+};
+use crate::sync::UPSafeCell;
+use crate::timer::{get_time, set_wakeup};
+use alloc::collections::BTreeSet;
+use lazy_static::*;
+
+lazy_static! {
+    /// sleeping tasks as (deadline, id), first deadline first
+    static ref SLEEPERS: UPSafeCell<BTreeSet<(usize, usize)>> =
+        unsafe { UPSafeCell::new(BTreeSet::new()) };
+}
+
+/// Block the current task until the timer reaches `deadline`. Returns
+/// false if a signal interrupted the sleep first.
+pub fn sleep_until(deadline: usize) -> bool {
+    let id = current_task_id();
+    loop {
+        if get_time() >= deadline {
+            return true;
+        }
+        if current_signal_interrupted() {
+            return false;
+        }
+        SLEEPERS.exclusive_access().insert((deadline, id));
+        set_wakeup(next_deadline());
+        block_current_and_run_next();
+        // still queued if a signal woke the task
+        SLEEPERS.exclusive_access().remove(&(deadline, id));
+    }
+}
+
+/// Block the current task on `queue` until it is woken, or until the timer
+/// reaches `deadline` if there is one. As with any wait on a queue, the
+/// caller checks again what it waits for.
+pub fn block_until(queue: &WaitQueue, deadline: Option<usize>) {
+    let id = current_task_id();
+    if let Some(deadline) = deadline {
+        SLEEPERS.exclusive_access().insert((deadline, id));
+        set_wakeup(next_deadline());
+    }
+    queue.block_current_and_run_next();
+    if let Some(deadline) = deadline {
+        SLEEPERS.exclusive_access().remove(&(deadline, id));
+    }
+}
+
+/// Timer interrupt: wake the tasks whose deadline passed, and arm the
+/// timer for the next deadline. Returns whether any task woke.
+pub fn wake_sleepers() -> bool {
+    let now = get_time();
+    let mut woken = false;
+    loop {
+        let first = SLEEPERS.exclusive_access().iter().next().copied();
+        match first {
+            Some((deadline, id)) if deadline <= now => {
+                SLEEPERS.exclusive_access().remove(&(deadline, id));
+                woken |= wake_task(id);
+            }
+            _ => break,
+        }
+    }
+    set_wakeup(next_deadline());
+    woken
+}
+
+/// The first deadline of a sleeping task, if any sleeps.
+pub(super) fn next_deadline() -> Option<usize> {
+    SLEEPERS
+        .exclusive_access()
+        .iter()
+        .next()
+        .map(|&(deadline, _)| deadline)
+}
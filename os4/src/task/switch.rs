@@ -5,7 +5,7 @@
 //! language (Do you know why?), so this module really is just a wrapper around
 //! `switch.S`.
 
-core::arch::global_asm!(include_str!("switch.S"));
+global_asm_xlen!(include_str!("switch.S"));
 
 use super::TaskContext;
 
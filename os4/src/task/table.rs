@@ -0,0 +1,87 @@
+//! The task table
+//!
+//! A task's id is its slot in the table, which also picks its kernel
+//! stack. A task added while the kernel runs takes the lowest free slot, so
+//! ids and kernel stacks are used again once a task is removed, and a slot
+//! may be empty.
+
+use super::TaskControlBlock;
+use crate::config::{kernel_stack_position, MAX_TASKS};
+use crate::mm::{VirtAddr, KERNEL_SPACE};
+use alloc::vec::Vec;
+use core::ops::{Index, IndexMut};
+
+/// Every task, by id
+pub struct TaskTable {
+    slots: Vec<Option<TaskControlBlock>>,
+}
+
+impl TaskTable {
+    pub fn new() -> Self {
+        Self { slots: Vec::new() }
+    }
+    /// Give `task` the lowest free id and its kernel stack, and return the
+    /// id, or None if there are `MAX_TASKS` tasks already.
+    pub fn insert(&mut self, mut task: TaskControlBlock) -> Option<usize> {
+        let id = match self.slots.iter().position(Option::is_none) {
+            Some(id) => id,
+            None if self.slots.len() < MAX_TASKS => {
+                self.slots.push(None);
+                self.slots.len() - 1
+            }
+            None => return None,
+        };
+        task.attach_kernel_stack(id);
+        self.slots[id] = Some(task);
+        Some(id)
+    }
+    /// Take task `id` out of the table and unmap its kernel stack, which
+    /// must not be the one in use.
+    pub fn remove(&mut self, id: usize) -> Option<TaskControlBlock> {
+        let task = self.slots.get_mut(id)?.take()?;
+        let (bottom, _) = kernel_stack_position(id);
+        KERNEL_SPACE
+            .lock()
+            .remove_area_with_start_vpn(VirtAddr::from(bottom).floor());
+        Some(task)
+    }
+    pub fn get(&self, id: usize) -> Option<&TaskControlBlock> {
+        self.slots.get(id)?.as_ref()
+    }
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut TaskControlBlock> {
+        self.slots.get_mut(id)?.as_mut()
+    }
+    /// The tasks with their ids, in id order.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &TaskControlBlock)> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(id, slot)| Some((id, slot.as_ref()?)))
+    }
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (usize, &mut TaskControlBlock)> {
+        self.slots
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(id, slot)| Some((id, slot.as_mut()?)))
+    }
+}
+
+impl Default for TaskTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Task `id`, which must exist.
+impl Index<usize> for TaskTable {
+    type Output = TaskControlBlock;
+    fn index(&self, id: usize) -> &TaskControlBlock {
+        self.get(id).expect("no such task")
+    }
+}
+
+impl IndexMut<usize> for TaskTable {
+    fn index_mut(&mut self, id: usize) -> &mut TaskControlBlock {
+        self.get_mut(id).expect("no such task")
+    }
+}
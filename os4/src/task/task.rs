@@ -1,11 +1,34 @@
 //! Types related to task management
-use super::TaskContext;
-use crate::config::{kernel_stack_position, TRAP_CONTEXT, MAX_SYSCALL_NUM};
+use super::{Capabilities, ResourceLimits, SignalFlags, TaskContext, VectorState, ROOT_CGROUP};
+use super::Personality;
+use crate::config::{kernel_stack_position, TRAP_CONTEXT, MAX_SYSCALL_NUM, USER_GID, USER_UID};
+use crate::config::{BIG_STRIDE, DEFAULT_PRIORITY, MIN_NICE, TASK_NAME_LEN};
+use crate::fs::{File, Stdin, Stdout};
+use crate::perf::PerfCounters;
 use crate::mm::{MapPermission, MemorySet, PhysPageNum, VirtAddr, KERNEL_SPACE};
 use crate::trap::{trap_handler, TrapContext};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Weight of nice 0
+const NICE_0_WEIGHT: usize = 1024;
+/// Weight of each nice value from `MIN_NICE`, as in Linux: each step is
+/// about 10% more or less CPU time
+const NICE_WEIGHTS: [usize; 40] = [
+    88761, 71755, 56483, 46273, 36291, 29154, 23254, 18705, 14949, 11916, 9548, 7620, 6100, 4904,
+    3906, 3121, 2501, 1991, 1586, 1277, 1024, 820, 655, 526, 423, 335, 272, 215, 172, 137, 110,
+    87, 70, 56, 45, 36, 29, 23, 18, 15,
+];
 
 /// task control block structure
 pub struct TaskControlBlock {
+    /// what logs and dumps call the task, the application's name unless it
+    /// set another
+    pub name: String,
+    /// absolute path of the working directory, relative paths start from
+    pub cwd: String,
     pub task_status: TaskStatus,
     pub task_cx: TaskContext,
     pub memory_set: MemorySet,
@@ -14,6 +37,80 @@ pub struct TaskControlBlock {
     pub first_time: usize,
     pub dispatched: bool, 
     pub syscall_times: [u32; MAX_SYSCALL_NUM],
+    pub fd_table: Vec<Option<Arc<dyn File + Send + Sync>>>,
+    /// signals received but not yet handled
+    pub signals: SignalFlags,
+    /// signals that stay pending instead of being acted upon
+    pub signal_mask: SignalFlags,
+    /// user and group the task runs as, 0 being root
+    pub uid: u32,
+    pub gid: u32,
+    /// what privileged syscalls the task may make
+    pub capabilities: Capabilities,
+    pub limits: ResourceLimits,
+    pub counters: TaskCounters,
+    /// stride scheduling weight, at least 2
+    pub priority: usize,
+    /// niceness, `MIN_NICE` to `MAX_NICE`, scaling the weight `priority`
+    /// gives
+    pub nice: isize,
+    /// stride scheduling progress, advanced by the stride of the task each
+    /// time it is picked
+    pub pass: usize,
+    /// vector registers, once the task has used them
+    pub vector: Option<VectorState>,
+    /// share of a CPU the task used lately, in millionths, averaged over
+    /// `CPU_USAGE_WINDOW_SECS`
+    pub cpu_usage: usize,
+    /// `counters.cpu_time` when `cpu_usage` was last updated
+    pub cpu_time_sampled: usize,
+    /// the task that spawned this one, while it has not exited
+    pub parent: Option<usize>,
+    /// process group, named after the task that made it, which the console
+    /// signals as a whole
+    pub pgid: usize,
+    /// control group, sharing the CPU with the tasks of other groups by
+    /// weight
+    pub cgroup: usize,
+    /// whether the task is in a syscall, for a checkpoint to make it again
+    pub in_syscall: bool,
+    /// the syscall ABI the task speaks
+    pub personality: Personality,
+    /// end of the heap `brk` grows, which starts at the top of the stack
+    pub program_brk: usize,
+    /// the tasks this one spawned that have not exited
+    pub children: Vec<usize>,
+    /// id and exit code of the children that exited and were not waited
+    /// for yet, kept as zombies so that their ids are not given again
+    pub exited_children: Vec<(usize, i32)>,
+}
+
+/// Events counted over the life of a task
+#[derive(Copy, Clone, Default)]
+pub struct TaskCounters {
+    /// page faults resolved without waiting, `lazy_faults` and `cow_faults`;
+    /// any other fault is fatal and not counted
+    pub minor_faults: usize,
+    /// page faults that had to wait for a backing store to swap the page
+    /// in, of which there is none, so this stays 0
+    pub major_faults: usize,
+    /// page faults that gave a frame to a page `mmap` left lazy
+    pub lazy_faults: usize,
+    /// page faults that copied a page shared copy-on-write; there is no
+    /// fork to share pages, so this stays 0
+    pub cow_faults: usize,
+    /// switches away because the task yielded or waited
+    pub voluntary_switches: usize,
+    /// switches away because its time slice ran out
+    pub involuntary_switches: usize,
+    /// most frames the address space has owned at once
+    pub peak_resident_pages: usize,
+    /// hardware counts while it ran
+    pub perf: PerfCounters,
+    /// time it ran, in `get_time` ticks, kernel work on its behalf included
+    pub cpu_time: usize,
+    /// the part of `cpu_time` spent in the kernel
+    pub system_time: usize,
 }
 
 impl TaskControlBlock {
@@ -23,30 +120,112 @@ impl TaskControlBlock {
     pub fn get_user_token(&self) -> usize {
         self.memory_set.token()
     }
-    pub fn new(elf_data: &[u8], app_id: usize) -> Self {
+    pub fn alloc_fd(&mut self) -> usize {
+        if let Some(fd) = (0..self.fd_table.len()).find(|fd| self.fd_table[*fd].is_none()) {
+            fd
+        } else {
+            self.fd_table.push(None);
+            self.fd_table.len() - 1
+        }
+    }
+    /// Rename the task, keeping the first `TASK_NAME_LEN` bytes of `name`
+    /// that are whole characters.
+    pub fn set_name(&mut self, name: &str) {
+        let mut len = name.len().min(TASK_NAME_LEN);
+        while !name.is_char_boundary(len) {
+            len -= 1;
+        }
+        self.name = String::from(&name[..len]);
+    }
+    /// Advance the pass of the task, which has just been picked to run.
+    pub fn charge_stride(&mut self) {
+        self.pass = self.pass.wrapping_add(self.stride());
+    }
+    /// `BIG_STRIDE` over the weight of the task: its priority, scaled by
+    /// `NICE_WEIGHTS` relative to nice 0. At least 1.
+    pub fn stride(&self) -> usize {
+        let weight = NICE_WEIGHTS[(self.nice - MIN_NICE) as usize] as u64;
+        let stride = BIG_STRIDE as u64 * NICE_0_WEIGHT as u64 / (self.priority as u64 * weight);
+        stride.max(1) as usize
+    }
+    /// Load a task from `elf_data`, running as root if `root` is set. It
+    /// gets its kernel stack when added to the task table.
+    pub fn new(elf_data: &[u8], root: bool) -> Self {
         // memory_set with elf program headers/trampoline/trap context/user stack
         let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data);
+        Self::with_memory_set(memory_set, user_sp, entry_point, root)
+    }
+    /// A task running the guest kernel `elf_data` in VS-mode, or None if it
+    /// cannot be loaded.
+    #[cfg(feature = "hypervisor")]
+    pub fn new_guest(elf_data: &[u8]) -> Option<Self> {
+        let entry_point = crate::hypervisor::load(elf_data)?;
+        // the guest code is not in this address space, which only serves to
+        // take its traps
+        let memory_set = MemorySet::new_trap_only();
+        let task = Self::with_memory_set(memory_set, 0, entry_point, false);
+        let trap_cx = task.get_trap_cx();
+        trap_cx.sstatus.set_spp(riscv::register::sstatus::SPP::Supervisor);
+        // hart id and device tree
+        trap_cx.x[10] = 0;
+        trap_cx.x[11] = 0;
+        Some(task)
+    }
+    pub(super) fn with_memory_set(
+        memory_set: MemorySet,
+        user_sp: usize,
+        entry_point: usize,
+        root: bool,
+    ) -> Self {
         let trap_cx_ppn = memory_set
             .translate(VirtAddr::from(TRAP_CONTEXT).into())
             .unwrap()
             .ppn();
+        let peak_resident_pages = memory_set.resident_pages();
         let task_status = TaskStatus::Ready;
-        // map a kernel-stack in kernel space
-        let (kernel_stack_bottom, kernel_stack_top) = kernel_stack_position(app_id);
-        KERNEL_SPACE.lock().insert_framed_area(
-            kernel_stack_bottom.into(),
-            kernel_stack_top.into(),
-            MapPermission::R | MapPermission::W,
-        );
         let task_control_block = Self {
+            name: String::new(),
+            cwd: String::from("/"),
             task_status,
-            task_cx: TaskContext::goto_trap_return(kernel_stack_top),
+            task_cx: TaskContext::zero_init(),
             memory_set,
             trap_cx_ppn,
             base_size: user_sp,
             first_time: 0,
             dispatched: false,
             syscall_times: [0; MAX_SYSCALL_NUM],
+            fd_table: vec![
+                // 0 -> stdin
+                Some(Arc::new(Stdin::new())),
+                // 1 -> stdout
+                Some(Arc::new(Stdout::new())),
+                // 2 -> stderr
+                Some(Arc::new(Stdout::new())),
+            ],
+            signals: SignalFlags::empty(),
+            signal_mask: SignalFlags::empty(),
+            uid: if root { 0 } else { USER_UID },
+            gid: if root { 0 } else { USER_GID },
+            capabilities: Capabilities::all(),
+            limits: ResourceLimits::default(),
+            counters: TaskCounters {
+                peak_resident_pages,
+                ..TaskCounters::default()
+            },
+            priority: DEFAULT_PRIORITY,
+            nice: 0,
+            pass: 0,
+            vector: None,
+            cpu_usage: 0,
+            cpu_time_sampled: 0,
+            parent: None,
+            pgid: 0,
+            cgroup: ROOT_CGROUP,
+            in_syscall: false,
+            personality: Personality::Native,
+            program_brk: user_sp,
+            children: Vec::new(),
+            exited_children: Vec::new(),
         };
         // prepare TrapContext in user space
         let trap_cx = task_control_block.get_trap_cx();
@@ -54,18 +233,33 @@ impl TaskControlBlock {
             entry_point,
             user_sp,
             KERNEL_SPACE.lock().token(),
-            kernel_stack_top,
+            0,
             trap_handler as usize,
         );
         task_control_block
     }
+    /// Map the kernel stack of task `id` in kernel space and make it the
+    /// stack the task starts on and traps to.
+    pub fn attach_kernel_stack(&mut self, id: usize) {
+        let (kernel_stack_bottom, kernel_stack_top) = kernel_stack_position(id);
+        KERNEL_SPACE.lock().insert_framed_area(
+            kernel_stack_bottom.into(),
+            kernel_stack_top.into(),
+            MapPermission::R | MapPermission::W,
+        );
+        super::canary::plant(id);
+        self.task_cx = TaskContext::goto_trap_return(kernel_stack_top);
+        self.get_trap_cx().kernel_sp = kernel_stack_top;
+    }
 }
 
 #[derive(Copy, Clone, PartialEq, Debug)]
-/// task status: UnInit, Ready, Running, Exited
+/// task status: UnInit, Ready, Running, Exited, Blocked
 pub enum TaskStatus {
     UnInit,
     Ready,
     Running,
     Exited,
+    /// waiting on a [`super::WaitQueue`]
+    Blocked,
 }
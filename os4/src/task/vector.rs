@@ -0,0 +1,163 @@
+//! Vector extension state
+//!
+//! When the harts have the V extension, user code may use the 32 vector
+//! registers, whose length VLEN is only known at runtime. Every task starts
+//! with `sstatus.VS` Off, so its first vector instruction traps as illegal;
+//! [`enable`] then gives it a save area of `32 * vlenb` bytes, clears the
+//! registers and lets the instruction run again.
+//!
+//! Saving and restoring is lazy, driven by `sstatus.VS`: the registers are
+//! saved when their task is switched away from with VS Dirty, and restored
+//! when a task other than the one whose state they hold is switched to.
+//! Tasks that never use vectors cost nothing.
+//!
+//! Signals have no user handlers in this kernel, so there is no signal frame
+//! to keep vector state in: a signal is either ignored, leaving the state
+//! alone, or ends the task.
+//!
+//! The assembler may not know the V extension, so the vector instructions
+//! are written as their encodings.
+
+use super::{TaskControlBlock, TaskTable};
+use crate::board::{has_extensions, Extensions};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::arch::asm;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use riscv::register::sstatus::FS;
+
+/// `sstatus.VS` Initial, enough for the kernel to access the registers
+const SSTATUS_VS_INITIAL: usize = 1 << 9;
+
+/// The task whose state the vector registers hold, `usize::MAX` if none
+static LOADED: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// Vector state of a task that has used vectors
+pub struct VectorState {
+    /// v0 to v31, `vlenb` bytes each
+    regs: Vec<u8>,
+    vl: usize,
+    vtype: usize,
+    vstart: usize,
+    vcsr: usize,
+}
+
+fn vlenb() -> usize {
+    let vlenb: usize;
+    unsafe {
+        asm!("csrs sstatus, {}", "csrr {}, 0xc22", in(reg) SSTATUS_VS_INITIAL, out(reg) vlenb);
+    }
+    vlenb
+}
+
+impl VectorState {
+    fn new() -> Self {
+        Self {
+            regs: vec![0; 32 * vlenb()],
+            vl: 0,
+            vtype: 0,
+            vstart: 0,
+            vcsr: 0,
+        }
+    }
+    /// Copy the vector registers here.
+    fn save(&mut self) {
+        unsafe {
+            asm!(
+                "csrs sstatus, {vs}",
+                "csrr {vl}, 0xc20",
+                "csrr {vtype}, 0xc21",
+                "csrr {vstart}, 0x008",
+                "csrr {vcsr}, 0x00f",
+                // whole register moves start at vstart
+                "csrw 0x008, zero",
+                "csrr {group}, 0xc22",
+                "slli {group}, {group}, 3",
+                ".word 0xe2850027", // vs8r.v v0, (a0)
+                "add a0, a0, {group}",
+                ".word 0xe2850427", // vs8r.v v8, (a0)
+                "add a0, a0, {group}",
+                ".word 0xe2850827", // vs8r.v v16, (a0)
+                "add a0, a0, {group}",
+                ".word 0xe2850c27", // vs8r.v v24, (a0)
+                vs = in(reg) SSTATUS_VS_INITIAL,
+                vl = out(reg) self.vl,
+                vtype = out(reg) self.vtype,
+                vstart = out(reg) self.vstart,
+                vcsr = out(reg) self.vcsr,
+                group = out(reg) _,
+                inout("a0") self.regs.as_mut_ptr() => _,
+            );
+        }
+    }
+    /// Load the vector registers from here.
+    fn restore(&self) {
+        unsafe {
+            asm!(
+                "csrs sstatus, {vs}",
+                "csrw 0x008, zero",
+                "csrr {group}, 0xc22",
+                "slli {group}, {group}, 3",
+                ".word 0xe2850007", // vl8r.v v0, (a0)
+                "add a0, a0, {group}",
+                ".word 0xe2850407", // vl8r.v v8, (a0)
+                "add a0, a0, {group}",
+                ".word 0xe2850807", // vl8r.v v16, (a0)
+                "add a0, a0, {group}",
+                ".word 0xe2850c07", // vl8r.v v24, (a0)
+                ".word 0x80c5f057", // vsetvl zero, a1, a2
+                "csrw 0x00f, {vcsr}",
+                "csrw 0x008, {vstart}",
+                vs = in(reg) SSTATUS_VS_INITIAL,
+                vcsr = in(reg) self.vcsr,
+                vstart = in(reg) self.vstart,
+                group = out(reg) _,
+                inout("a0") self.regs.as_ptr() => _,
+                in("a1") self.vl,
+                in("a2") self.vtype,
+            );
+        }
+    }
+}
+
+/// Let task `id`, which trapped on an illegal instruction with vectors off,
+/// use them. Returns false, leaving the task alone, if vectors were on
+/// already or the harts have none, so that the instruction was illegal
+/// after all.
+pub fn enable(id: usize, task: &mut TaskControlBlock) -> bool {
+    let cx = task.get_trap_cx();
+    if cx.vector_state() != FS::Off || !has_extensions(Extensions::V) {
+        return false;
+    }
+    let state = task.vector.get_or_insert_with(VectorState::new);
+    // the registers may hold the state of another task, saved already
+    state.restore();
+    LOADED.store(id, Ordering::Relaxed);
+    cx.set_vector_state(FS::Clean);
+    true
+}
+
+/// Reaping path: task `id` is gone, and its id may be given to a new task.
+pub fn on_reap(id: usize) {
+    let _ = LOADED.compare_exchange(id, usize::MAX, Ordering::Relaxed, Ordering::Relaxed);
+}
+
+/// Task switch path, from task `current` to task `next`: save the registers
+/// if the current task changed them, and load those of the next task if it
+/// uses vectors and they hold another state.
+pub fn on_switch(tasks: &mut TaskTable, current: usize, next: usize) {
+    let task = &mut tasks[current];
+    let cx = task.get_trap_cx();
+    if cx.vector_state() == FS::Dirty {
+        if let Some(state) = task.vector.as_mut() {
+            state.save();
+            LOADED.store(current, Ordering::Relaxed);
+            cx.set_vector_state(FS::Clean);
+        }
+    }
+    if let Some(state) = tasks[next].vector.as_ref() {
+        if LOADED.swap(next, Ordering::Relaxed) != next {
+            state.restore();
+        }
+    }
+}
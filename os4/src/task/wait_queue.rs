@@ -0,0 +1,75 @@
+//! Wait queues
+//!
+//! A task waiting for something another task or a device will do blocks on
+//! a [`WaitQueue`] instead of yielding in a loop: it is `Blocked`, out of
+//! the ready queue, until it is woken. Whoever makes the condition true
+//! wakes the waiters.
+//!
+//! A wake-up only means the condition may hold: a signal also wakes a
+//! blocked task, and a woken task may find another one got there first. So
+//! waiters check their condition again in a loop:
+//!
+//! ```ignore
+//! while !condition() {
+//!     QUEUE.block_current_and_run_next();
+//! }
+//! ```
+
+use super::{block_current_and_run_next, current_task_id, wake_task};
+use crate::sync::UPSafeCell;
+use alloc::collections::VecDeque;
+
+/// Tasks waiting for the same thing, in the order they started waiting
+pub struct WaitQueue {
+    waiters: UPSafeCell<VecDeque<usize>>,
+}
+
+impl WaitQueue {
+    pub fn new() -> Self {
+        Self {
+            waiters: unsafe { UPSafeCell::new(VecDeque::new()) },
+        }
+    }
+    /// Block the current task on this queue and run the next task, until
+    /// another task wakes it.
+    pub fn block_current_and_run_next(&self) {
+        let id = current_task_id();
+        self.waiters.exclusive_access().push_back(id);
+        block_current_and_run_next();
+        // still queued if a signal woke the task, which must not be woken
+        // again for this queue once it waits elsewhere
+        self.waiters
+            .exclusive_access()
+            .retain(|&waiter| waiter != id);
+    }
+    /// Wake the task that has waited longest. Returns whether there was one.
+    pub fn wake_one(&self) -> bool {
+        loop {
+            let id = match self.waiters.exclusive_access().pop_front() {
+                Some(id) => id,
+                None => return false,
+            };
+            // skip tasks a signal woke already, not yet run
+            if wake_task(id) {
+                return true;
+            }
+        }
+    }
+    /// Whether no task waits.
+    pub fn is_empty(&self) -> bool {
+        self.waiters.exclusive_access().is_empty()
+    }
+    /// Wake every waiting task.
+    pub fn wake_all(&self) {
+        let waiters = core::mem::take(&mut *self.waiters.exclusive_access());
+        for id in waiters {
+            wake_task(id);
+        }
+    }
+}
+
+impl Default for WaitQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,13 +1,69 @@
 //! RISC-V timer-related functionality
+//!
+//! With the Sstc extension the next timer interrupt is programmed by
+//! writing `stimecmp` directly, which costs a CSR write instead of a trip
+//! through the firmware. OpenSBI lets supervisor mode write it whenever the
+//! harts have Sstc. Without it, or before [`init`] has probed for it,
+//! the SBI timer call is used.
+//!
+//! Besides the periodic tick, the timer is armed for the earliest wake-up
+//! asked for with [`set_wakeup`], so that a sleep or a poll timeout ends
+//! when due rather than at the tick after. While the CPU idles with
+//! nothing the tick is needed for, [`stop_tick`] leaves only the wake-up
+//! armed, until [`restart_tick`].
 
+use crate::board::{has_extensions, Extensions};
 use crate::config::CLOCK_FREQ;
 use crate::sbi::set_timer;
+use core::arch::asm;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use riscv::register::time;
 
 pub const TICKS_PER_SEC: usize = 100;
 const MICRO_PER_SEC: usize = 1_000_000;
 const MILLI_PER_SEC: usize = 1000;
 
+/// Whether `stimecmp` is written instead of calling the firmware
+static SSTC: AtomicBool = AtomicBool::new(false);
+/// when the next tick is due
+static NEXT_TICK: AtomicUsize = AtomicUsize::new(0);
+/// the wake-up asked for besides the ticks, `usize::MAX` for none
+static NEXT_WAKEUP: AtomicUsize = AtomicUsize::new(usize::MAX);
+/// Whether the ticks are left out of the timer, while the CPU idles
+static TICK_STOPPED: AtomicBool = AtomicBool::new(false);
+
+/// Pick how the timer is programmed, once the board is known.
+pub fn init() {
+    if has_extensions(Extensions::SSTC) {
+        SSTC.store(true, Ordering::Relaxed);
+        info!("[kernel] timer: programming stimecmp directly");
+    }
+}
+
+/// Write `stimecmp`, which raises the timer interrupt once `time` reaches it.
+#[cfg(target_pointer_width = "64")]
+fn set_stimecmp(timer: u64) {
+    unsafe {
+        asm!("csrw 0x14d, {}", in(reg) timer);
+    }
+}
+
+#[cfg(target_pointer_width = "32")]
+fn set_stimecmp(timer: u64) {
+    // make the low half as late as it goes first, so that no value between
+    // the old and the new one can fire
+    unsafe {
+        asm!(
+            "csrw 0x14d, {max}",
+            "csrw 0x15d, {high}",
+            "csrw 0x14d, {low}",
+            max = in(reg) u32::MAX,
+            high = in(reg) (timer >> 32) as u32,
+            low = in(reg) timer as u32,
+        );
+    }
+}
+
 /// read the `mtime` register
 pub fn get_time() -> usize {
     time::read()
@@ -22,7 +78,67 @@ pub fn get_time_ms() -> usize {
     get_time_us() / 1000
 }
 
+/// The timer value `ms` milliseconds from now, as a deadline to sleep to.
+pub fn deadline_after_ms(ms: usize) -> usize {
+    get_time().saturating_add(ms.saturating_mul(CLOCK_FREQ / MILLI_PER_SEC))
+}
+
+/// Raise the timer interrupt at the next tick or wake-up, whichever comes
+/// first, or at the wake-up only if the tick is stopped.
+fn arm_timer() {
+    let tick = if TICK_STOPPED.load(Ordering::Relaxed) {
+        usize::MAX
+    } else {
+        NEXT_TICK.load(Ordering::Relaxed)
+    };
+    program_timer(tick.min(NEXT_WAKEUP.load(Ordering::Relaxed)));
+}
+
+/// Raise the timer interrupt once `time` reaches `next`.
+fn program_timer(next: usize) {
+    if SSTC.load(Ordering::Relaxed) {
+        set_stimecmp(next as u64);
+    } else {
+        set_timer(next);
+    }
+}
+
 /// set the next timer interrupt
 pub fn set_next_trigger() {
-    set_timer(get_time() + CLOCK_FREQ / TICKS_PER_SEC);
+    NEXT_TICK.store(get_time() + CLOCK_FREQ / TICKS_PER_SEC, Ordering::Relaxed);
+    arm_timer();
+}
+
+/// Leave the ticks out of the timer, which then only raises its interrupt
+/// for the wake-up asked for, if any.
+pub fn stop_tick() {
+    TICK_STOPPED.store(true, Ordering::Relaxed);
+    arm_timer();
+}
+
+/// Start ticking again after [`stop_tick`], a full period from now.
+pub fn restart_tick() {
+    if TICK_STOPPED.swap(false, Ordering::Relaxed) {
+        set_next_trigger();
+    }
+}
+
+/// Raise no timer interrupt at all, neither tick nor wake-up, until
+/// [`set_next_trigger`] arms the timer again.
+pub fn disarm() {
+    program_timer(usize::MAX);
+}
+
+/// Raise the timer interrupt at `deadline` too, in timer cycles, or only
+/// for the ticks if None. Replaces the wake-up asked for before.
+pub fn set_wakeup(deadline: Option<usize>) {
+    let deadline = deadline.unwrap_or(usize::MAX);
+    if NEXT_WAKEUP.swap(deadline, Ordering::Relaxed) != deadline {
+        arm_timer();
+    }
+}
+
+/// Whether the timer interrupt is for a tick rather than only a wake-up.
+pub fn tick_due() -> bool {
+    get_time() >= NEXT_TICK.load(Ordering::Relaxed)
 }
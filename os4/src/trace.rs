@@ -0,0 +1,199 @@
+//! Static tracepoints
+//!
+//! [`trace_event!`] records a timestamped [`TraceRecord`] into a ring of
+//! the hart it runs on, if its event is enabled. The kernel only runs on
+//! the boot hart, so there is a single ring. Once the ring is full the
+//! oldest records are overwritten.
+//!
+//! Events are enabled at runtime by a mask of `1 << event`, all of them from
+//! boot in a `debug` build, and the ring is drained as raw records by
+//! `sys_trace_read`, or as a Chrome trace by [`write_chrome_trace`], which
+//! SysRq `e` prints. Saved to a file, the latter opens in `chrome://tracing`
+//! or Perfetto: one row shows the task running on the hart, with the
+//! interrupts taken, and one row per task its syscalls and page faults.
+
+use crate::config::{TRACE_AT_BOOT, TRACE_BUFFER_RECORDS};
+use crate::sync::UPSafeCell;
+use crate::timer::get_time_us;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU32, Ordering};
+use lazy_static::*;
+
+/// Events a tracepoint can record, with the meaning of their arguments
+#[derive(Copy, Clone)]
+#[repr(u32)]
+pub enum TraceEvent {
+    /// the next task, and the [`crate::task::TaskStatus`] the previous one
+    /// is left in
+    SchedSwitch = 0,
+    /// the syscall id and its first argument
+    SyscallEnter = 1,
+    /// the syscall id and its return value
+    SyscallExit = 2,
+    /// the faulting address and pc
+    PageFault = 3,
+    /// the interrupt cause, and the PLIC source of an external interrupt
+    Irq = 4,
+}
+
+/// A record as drained to user space
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct TraceRecord {
+    pub time_us: usize,
+    pub event: u32,
+    /// task running when the event happened
+    pub pid: u32,
+    pub args: [usize; 2],
+}
+
+struct TraceRing {
+    records: VecDeque<TraceRecord>,
+    /// records overwritten before being drained
+    lost: usize,
+}
+
+/// Mask of enabled events, checked before anything else is done
+static ENABLED: AtomicU32 = AtomicU32::new(if TRACE_AT_BOOT { u32::MAX } else { 0 });
+
+lazy_static! {
+    static ref RING: UPSafeCell<TraceRing> = unsafe {
+        UPSafeCell::new(TraceRing {
+            records: VecDeque::with_capacity(TRACE_BUFFER_RECORDS),
+            lost: 0,
+        })
+    };
+}
+
+/// Record `event` of task `pid`, if it is enabled.
+#[macro_export]
+macro_rules! trace_event {
+    ($event: ident, $pid: expr, $arg0: expr, $arg1: expr) => {
+        if $crate::trace::enabled($crate::trace::TraceEvent::$event) {
+            $crate::trace::record(
+                $crate::trace::TraceEvent::$event,
+                $pid,
+                [$arg0 as usize, $arg1 as usize],
+            );
+        }
+    };
+}
+
+pub fn enabled(event: TraceEvent) -> bool {
+    ENABLED.load(Ordering::Relaxed) & (1 << event as u32) != 0
+}
+
+pub fn record(event: TraceEvent, pid: usize, args: [usize; 2]) {
+    let mut ring = RING.exclusive_access();
+    if ring.records.len() == TRACE_BUFFER_RECORDS {
+        ring.records.pop_front();
+        ring.lost += 1;
+    }
+    ring.records.push_back(TraceRecord {
+        time_us: get_time_us(),
+        event: event as u32,
+        pid: pid as u32,
+        args,
+    });
+}
+
+/// Enable the events in `mask`, disabling the others, and return the
+/// previous mask.
+pub fn set_enabled(mask: u32) -> u32 {
+    ENABLED.swap(mask, Ordering::Relaxed)
+}
+
+/// Take up to `count` of the oldest records from the ring.
+pub fn drain(count: usize) -> Vec<TraceRecord> {
+    let mut ring = RING.exclusive_access();
+    let count = count.min(ring.records.len());
+    let drained = ring.records.drain(..count).collect();
+    if ring.lost != 0 {
+        warn!("[kernel] {} trace records lost", ring.lost);
+        ring.lost = 0;
+    }
+    drained
+}
+
+/// The Chrome trace-event process ids of the rows
+const HART_ROW: usize = 0;
+const TASKS_ROW: usize = 1;
+
+/// Drain the ring to `out` as a Chrome trace-event JSON document, with
+/// times in microseconds. Returns an error, with the ring as it was, if
+/// the ring is in use.
+pub fn write_chrome_trace(out: &mut dyn Write) -> fmt::Result {
+    let records: Vec<TraceRecord> = match RING.try_exclusive_access() {
+        Some(mut ring) => {
+            if ring.lost != 0 {
+                warn!("[kernel] {} trace records lost", ring.lost);
+                ring.lost = 0;
+            }
+            ring.records.drain(..).collect()
+        }
+        None => return Err(fmt::Error),
+    };
+    write!(out, "{{\"traceEvents\":[")?;
+    write!(
+        out,
+        "\n{{\"name\":\"process_name\",\"ph\":\"M\",\"pid\":{},\"args\":{{\"name\":\"hart\"}}}}",
+        HART_ROW
+    )?;
+    write!(
+        out,
+        ",\n{{\"name\":\"process_name\",\"ph\":\"M\",\"pid\":{},\"args\":{{\"name\":\"tasks\"}}}}",
+        TASKS_ROW
+    )?;
+    // the task running since the last switch, and since when
+    let mut running: Option<(u32, usize)> = None;
+    for record in records.iter() {
+        let (time, pid) = (record.time_us, record.pid);
+        match record.event {
+            e if e == TraceEvent::SchedSwitch as u32 => {
+                if let Some((task, since)) = running {
+                    write!(
+                        out,
+                        ",\n{{\"name\":\"task {}\",\"ph\":\"X\",\"ts\":{},\"dur\":{},\"pid\":{},\"tid\":0}}",
+                        task, since, time - since, HART_ROW
+                    )?;
+                }
+                running = Some((record.args[0] as u32, time));
+            }
+            e if e == TraceEvent::SyscallEnter as u32 => write!(
+                out,
+                ",\n{{\"name\":\"syscall {}\",\"ph\":\"B\",\"ts\":{},\"pid\":{},\"tid\":{},\"args\":{{\"arg0\":{}}}}}",
+                record.args[0], time, TASKS_ROW, pid, record.args[1]
+            )?,
+            e if e == TraceEvent::SyscallExit as u32 => write!(
+                out,
+                ",\n{{\"ph\":\"E\",\"ts\":{},\"pid\":{},\"tid\":{},\"args\":{{\"ret\":{}}}}}",
+                time, TASKS_ROW, pid, record.args[1] as isize
+            )?,
+            e if e == TraceEvent::PageFault as u32 => write!(
+                out,
+                ",\n{{\"name\":\"page fault\",\"ph\":\"i\",\"s\":\"t\",\"ts\":{},\"pid\":{},\"tid\":{},\"args\":{{\"addr\":\"{:#x}\",\"pc\":\"{:#x}\"}}}}",
+                time, TASKS_ROW, pid, record.args[0], record.args[1]
+            )?,
+            e if e == TraceEvent::Irq as u32 => write!(
+                out,
+                ",\n{{\"name\":\"irq {}\",\"ph\":\"i\",\"s\":\"t\",\"ts\":{},\"pid\":{},\"tid\":0,\"args\":{{\"source\":{}}}}}",
+                record.args[0], time, HART_ROW, record.args[1]
+            )?,
+            _ => {}
+        }
+    }
+    // the slice of the task still running ends with the trace
+    if let (Some((task, since)), Some(last)) = (running, records.last()) {
+        write!(
+            out,
+            ",\n{{\"name\":\"task {}\",\"ph\":\"X\",\"ts\":{},\"dur\":{},\"pid\":{},\"tid\":0}}",
+            task,
+            since,
+            last.time_us - since,
+            HART_ROW
+        )?;
+    }
+    writeln!(out, "\n]}}")
+}
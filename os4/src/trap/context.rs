@@ -1,6 +1,9 @@
 //! Implementation of [`TrapContext`]
 
-use riscv::register::sstatus::{self, Sstatus, SPP};
+use riscv::register::sstatus::{self, Sstatus, FS, SPP};
+
+/// lowest bit of `sstatus.VS`, the state of the vector registers
+const SSTATUS_VS_SHIFT: usize = 9;
 
 #[repr(C)]
 /// trap context structure containing sstatus, sepc and registers
@@ -17,6 +20,24 @@ impl TrapContext {
     pub fn set_sp(&mut self, sp: usize) {
         self.x[2] = sp;
     }
+    /// State of the vector registers of the user, as `sstatus.VS` records
+    /// it.
+    pub fn vector_state(&self) -> FS {
+        match (self.sstatus.bits() >> SSTATUS_VS_SHIFT) & 3 {
+            0 => FS::Off,
+            1 => FS::Initial,
+            2 => FS::Clean,
+            _ => FS::Dirty,
+        }
+    }
+    pub fn set_vector_state(&mut self, state: FS) {
+        let bits =
+            self.sstatus.bits() & !(3 << SSTATUS_VS_SHIFT) | (state as usize) << SSTATUS_VS_SHIFT;
+        // `Sstatus` is a plain word without a setter for these bits
+        unsafe {
+            *(&mut self.sstatus as *mut Sstatus as *mut usize) = bits;
+        }
+    }
     pub fn app_init_context(
         entry: usize,
         sp: usize,
@@ -35,6 +56,8 @@ impl TrapContext {
             trap_handler,
         };
         cx.set_sp(sp);
+        // vectors are off until the task first uses them
+        cx.set_vector_state(FS::Off);
         cx
     }
 }
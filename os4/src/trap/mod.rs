@@ -6,29 +6,64 @@
 //! All traps go through `__alltraps`, which is defined in `trap.S`. The
 //! assembly language code does just enough work restore the kernel space
 //! context, ensuring that Rust code safely runs, and transfers control to
-//! [`trap_handler()`].
+//! [`trap_handler()`]. Unless `KSTACK_RANDOMIZE` is off, [`trap_return()`]
+//! moves the kernel stack pointer the next trap starts from by a random
+//! offset, so that the layout of the kernel stack differs from trap to trap.
 //!
 //! It then calls different functionality based on what exactly the exception
 //! was. For example, timer interrupts trigger task preemption, and syscalls go
 //! to [`syscall()`].
+//!
+//! The kernel runs with interrupts off, so a timer interrupt that comes
+//! during a long kernel operation stays pending until the task goes back to
+//! user space. Such operations call [`cond_resched()`] at safe points, which
+//! takes a pending tick as the trap would and lets other tasks run. With
+//! no task to run, [`wait_for_interrupt()`] takes interrupts in their place.
 mod context;
 
-use crate::config::{TRAMPOLINE, TRAP_CONTEXT};
+use crate::config::{kernel_stack_position, TRAMPOLINE, TRAP_CONTEXT};
+use crate::config::{KSTACK_MAX_OFFSET, KSTACK_RANDOMIZE, LAZY_MMAP};
+use crate::mm::MapPermission;
+use crate::drivers::irq_handler;
 use crate::syscall::syscall;
 use crate::task::{
-    current_trap_cx, current_user_token, exit_current_and_run_next, suspend_current_and_run_next,add_one_while_syscall,
+    current_trap_cx, current_user_token, exit_current_and_run_next, add_one_while_syscall,
+    set_current_in_syscall, preempt_current_and_run_next, count_current_lazy_fault, current_task_id, current_task_name,
+    count_syscall, should_preempt,
+    handle_signals, check_current_kernel_stack, check_current_cpu_limit, current_lazy_fault,
+    enable_current_vector,
 };
-use crate::timer::set_next_trigger;
+use crate::timer::{set_next_trigger, tick_due};
 use riscv::register::{
     mtvec::TrapMode,
     scause::{self, Exception, Interrupt, Trap},
-    sie, stval, stvec,
+    sie, sip, sstatus::SPP, stval, stvec,
 };
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+global_asm_xlen!(include_str!("trap.S"));
 
-core::arch::global_asm!(include_str!("trap.S"));
+/// State of the generator of kernel stack offsets. They only need to be
+/// unpredictable enough to break layout assumptions, so a xorshift seeded
+/// once is fast enough for every trap.
+static KSTACK_SEED: AtomicUsize = AtomicUsize::new(0);
 
 pub fn init() {
     set_kernel_trap_entry();
+    let mut seed = [0; core::mem::size_of::<usize>()];
+    crate::random::fill_random(&mut seed);
+    KSTACK_SEED.store(usize::from_ne_bytes(seed) | 1, Ordering::Relaxed);
+}
+
+/// A random multiple of 16 bytes below `KSTACK_MAX_OFFSET`, mixed with the
+/// time so that it depends on when the trap happens too.
+fn kernel_stack_offset() -> usize {
+    let mut x = KSTACK_SEED.load(Ordering::Relaxed);
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    KSTACK_SEED.store(x, Ordering::Relaxed);
+    ((x ^ crate::timer::get_time()) % (KSTACK_MAX_OFFSET / 16)) * 16
 }
 
 fn set_kernel_trap_entry() {
@@ -49,31 +84,107 @@ pub fn enable_timer_interrupt() {
     }
 }
 
+pub fn enable_external_interrupt() {
+    unsafe {
+        sie::set_sext();
+    }
+}
+
+/// The permission a page fault of cause `trap` shows to be lacking.
+fn fault_access(trap: Trap) -> MapPermission {
+    match trap {
+        Trap::Exception(Exception::StorePageFault) => MapPermission::W,
+        Trap::Exception(Exception::InstructionPageFault) => MapPermission::X,
+        _ => MapPermission::R,
+    }
+}
+
 #[no_mangle]
 pub fn trap_handler() -> ! {
     set_kernel_trap_entry();
+    check_current_kernel_stack();
+    crate::task::enter_kernel();
     let cx = current_trap_cx();
     let scause = scause::read();
     let stval = stval::read();
+    crate::random::add_sample(crate::timer::get_time() ^ scause.bits());
+    #[cfg(feature = "hypervisor")]
+    if !scause.is_interrupt() && crate::hypervisor::is_guest(current_task_id()) {
+        if !crate::hypervisor::handle_exception(cx, scause.bits(), stval) {
+            exit_current_and_run_next(-1);
+        }
+        trap_return();
+    }
     match scause.cause() {
         Trap::Exception(Exception::UserEnvCall) => {
+            #[cfg(feature = "syscall-fuzz")]
+            if crate::syscall::is_fuzz_target(current_task_id()) {
+                crate::syscall::run_fuzzer();
+            }
+            // when replaying, where the task was preempted before this syscall
+            if should_preempt(false) {
+                preempt_current_and_run_next();
+            }
+            count_syscall();
             cx.sepc += 4;
             add_one_while_syscall(cx.x[17]);
-            cx.x[10] = syscall(cx.x[17], [cx.x[10], cx.x[11], cx.x[12]]) as usize;
+            trace_event!(SyscallEnter, current_task_id(), cx.x[17], cx.x[10]);
+            let id = cx.x[17];
+            #[cfg(feature = "kcov")]
+            crate::kcov::on_syscall(current_task_id(), true);
+            set_current_in_syscall(true);
+            cx.x[10] = syscall(
+                cx.x[17],
+                [cx.x[10], cx.x[11], cx.x[12], cx.x[13], cx.x[14], cx.x[15]],
+            ) as usize;
+            set_current_in_syscall(false);
+            #[cfg(feature = "kcov")]
+            crate::kcov::on_syscall(current_task_id(), false);
+            trace_event!(SyscallExit, current_task_id(), id, cx.x[10]);
+            cond_resched();
+        }
+        Trap::Exception(Exception::StorePageFault)
+        | Trap::Exception(Exception::LoadPageFault)
+        | Trap::Exception(Exception::InstructionPageFault)
+            if LAZY_MMAP && current_lazy_fault(stval, fault_access(scause.cause())) =>
+        {
+            count_current_lazy_fault();
+            trace_event!(PageFault, current_task_id(), stval, cx.sepc);
         }
         Trap::Exception(Exception::StoreFault)
         | Trap::Exception(Exception::StorePageFault)
-        | Trap::Exception(Exception::LoadPageFault) => {
-            error!("[kernel] PageFault in application, bad addr = {:#x}, bad instruction = {:#x}, core dumped.", stval, cx.sepc);
-            exit_current_and_run_next();
+        | Trap::Exception(Exception::LoadPageFault)
+        | Trap::Exception(Exception::InstructionPageFault) => {
+            trace_event!(PageFault, current_task_id(), stval, cx.sepc);
+            error!(
+                "[kernel] PageFault in application {} (pid {}), bad addr = {:#x}, bad instruction = {:#x}, core dumped.",
+                current_task_name(),
+                current_task_id(),
+                stval,
+                cx.sepc
+            );
+            exit_current_and_run_next(-2);
+        }
+        Trap::Exception(Exception::IllegalInstruction) if enable_current_vector() => {
+            // the first vector instruction of the task, run it again
         }
         Trap::Exception(Exception::IllegalInstruction) => {
-            error!("[kernel] IllegalInstruction in application, core dumped.");
-            exit_current_and_run_next();
+            error!(
+                "[kernel] IllegalInstruction in application {} (pid {}), core dumped.",
+                current_task_name(),
+                current_task_id()
+            );
+            exit_current_and_run_next(-3);
         }
         Trap::Interrupt(Interrupt::SupervisorTimer) => {
-            set_next_trigger();
-            suspend_current_and_run_next();
+            trace_event!(Irq, current_task_id(), scause.code(), 0);
+            let tick = timer_tick(cx.sepc, cx.sstatus.spp() == SPP::Supervisor);
+            if should_preempt(tick) {
+                preempt_current_and_run_next();
+            }
+        }
+        Trap::Interrupt(Interrupt::SupervisorExternal) => {
+            device_interrupt();
         }
         _ => {
             panic!(
@@ -83,12 +194,105 @@ pub fn trap_handler() -> ! {
             );
         }
     }
+    handle_signals();
     trap_return();
 }
 
+/// What a timer interrupt does before the current task is preempted; `pc`
+/// is where the task was, in the kernel if `kernel` is set. The interrupt
+/// may only be for a sleeping task to wake. Returns whether it is time to
+/// switch tasks: a tick is due or a task woke.
+fn timer_tick(pc: usize, kernel: bool) -> bool {
+    let woken = crate::task::wake_sleepers();
+    if !tick_due() {
+        return woken;
+    }
+    tick();
+    crate::profile::on_timer(current_task_id(), pc, kernel);
+    check_current_cpu_limit();
+    true
+}
+
+/// The work of a tick that is not about the task running.
+fn tick() {
+    set_next_trigger();
+    crate::watchdog::on_timer();
+    crate::net::poll();
+    // what changes without a device interrupt, such as network timers and
+    // memory pressure, is looked at again by the pollers once a tick
+    crate::fs::wake_pollers();
+    crate::fs::pressure_tick();
+    if crate::fs::tty_needs_tick() {
+        crate::fs::tty_interrupt();
+    }
+    crate::task::update_cpu_usage();
+}
+
+/// Handle the devices that raised an interrupt.
+fn device_interrupt() {
+    irq_handler();
+    crate::fs::tty_interrupt();
+    crate::fs::input_interrupt();
+    crate::net::poll();
+    crate::fs::wake_pollers();
+}
+
+/// With no task ready to run, wait for an interrupt and handle it, from the
+/// kernel and for no task, idling as [`crate::power::idle()`] sees fit.
+/// Returns once one was handled, whether or not it woke a task.
+pub fn wait_for_interrupt() {
+    loop {
+        let pending = sip::read();
+        if pending.stimer() || pending.sext() {
+            // interrupts are the only entropy source left while idle
+            crate::random::add_sample(crate::timer::get_time() ^ pending.bits());
+        }
+        if pending.stimer() {
+            crate::task::wake_sleepers();
+            if tick_due() {
+                tick();
+            }
+            return;
+        }
+        if pending.sext() {
+            device_interrupt();
+            return;
+        }
+        crate::power::idle();
+    }
+}
+
+/// Whether the current task has used up its time slice, or a sleeping task
+/// is to wake: a timer interrupt is pending, having come while the kernel
+/// ran.
+pub fn need_resched() -> bool {
+    sip::read().stimer()
+}
+
+/// A safe point in a long kernel operation: if the time slice is over, take
+/// the timer tick and let the other tasks run before going on. The caller
+/// must hold no borrow of the task manager or of anything else another task
+/// may use.
+pub fn cond_resched() {
+    // where the syscall was made from
+    let tick = need_resched() && timer_tick(current_trap_cx().sepc, true);
+    if should_preempt(tick) {
+        preempt_current_and_run_next();
+    }
+}
+
 #[no_mangle]
 pub fn trap_return() -> ! {
+    check_current_kernel_stack();
+    if KSTACK_RANDOMIZE {
+        // where `__alltraps` will put the stack on the next trap
+        let (_, top) = kernel_stack_position(current_task_id());
+        current_trap_cx().kernel_sp = top - kernel_stack_offset();
+    }
+    crate::task::leave_kernel();
     set_user_trap_entry();
+    #[cfg(feature = "hypervisor")]
+    crate::hypervisor::prepare_entry(current_task_id());
     let trap_cx_ptr = TRAP_CONTEXT;
     let user_satp = current_user_token();
     extern "C" {
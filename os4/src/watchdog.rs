@@ -0,0 +1,80 @@
+//! Software watchdog
+//!
+//! The kernel is petted whenever it shows progress: on every timer
+//! interrupt and every task switch. Busy-wait loops that neither schedule
+//! nor take interrupts call [`check`], which bites once the kernel has gone
+//! unpetted for [`WATCHDOG_TIMEOUT_MS`].
+//!
+//! A privileged user daemon may also arm a deadline of its own with
+//! `sys_watchdog`, and must then pet it again before it expires.
+//!
+//! Biting dumps the state of every task and powers off through SBI with a
+//! failure reason, so that a hung automated run ends with a diagnosis.
+
+use crate::config::WATCHDOG_TIMEOUT_MS;
+use crate::sync::UPSafeCell;
+use crate::timer::get_time_ms;
+use lazy_static::*;
+
+struct Watchdog {
+    /// when the kernel must have been petted again, 0 before the first pet
+    kernel_deadline: usize,
+    /// (timeout, deadline) of the user daemon, if one armed the watchdog
+    daemon: Option<(usize, usize)>,
+}
+
+lazy_static! {
+    static ref WATCHDOG: UPSafeCell<Watchdog> = unsafe {
+        UPSafeCell::new(Watchdog {
+            kernel_deadline: 0,
+            daemon: None,
+        })
+    };
+}
+
+/// Record that the kernel is making progress.
+pub fn pet_kernel() {
+    WATCHDOG.exclusive_access().kernel_deadline = get_time_ms() + WATCHDOG_TIMEOUT_MS;
+}
+
+/// Bite if the kernel has not been petted in time.
+pub fn check() {
+    let now = get_time_ms();
+    let deadline = WATCHDOG.exclusive_access().kernel_deadline;
+    if deadline != 0 && now > deadline {
+        bite("the kernel made no progress");
+    }
+}
+
+/// Timer interrupt path: pet the kernel and check the daemon deadline.
+pub fn on_timer() {
+    pet_kernel();
+    let now = get_time_ms();
+    let daemon = WATCHDOG.exclusive_access().daemon;
+    if let Some((_, deadline)) = daemon {
+        if now > deadline {
+            bite("the watchdog daemon stopped petting");
+        }
+    }
+}
+
+/// Pet the daemon deadline, expecting the next pet within `timeout_ms`; a
+/// timeout of 0 disarms it.
+pub fn pet_daemon(timeout_ms: usize) {
+    WATCHDOG.exclusive_access().daemon = if timeout_ms == 0 {
+        None
+    } else {
+        Some((timeout_ms, get_time_ms() + timeout_ms))
+    };
+}
+
+/// Whether the user daemon has a deadline armed.
+pub fn daemon_armed() -> bool {
+    WATCHDOG.exclusive_access().daemon.is_some()
+}
+
+fn bite(reason: &str) -> ! {
+    println!("[kernel] watchdog: {}, resetting", reason);
+    crate::task::dump_tasks();
+    crate::power::shutdown(true)
+}